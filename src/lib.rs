@@ -1,18 +1,86 @@
 #![no_std]
-#![no_main]
-#![feature(alloc_error_handler)]
 
-use bare_test::{print, println, time::since_boot};
+#[cfg(any(feature = "mock", feature = "sim"))]
+extern crate alloc;
+#[cfg(feature = "sim")]
+extern crate std;
+
+use log::warn;
+
+use crate::sdhci_err::MmcError;
 
 pub mod sdhci_reg;
 pub mod sdhci_cmd;
 pub mod sdhci;
+pub mod sdhci_err;
+pub mod sdhci_ext_csd;
+pub mod sdhci_cqe;
+pub mod sdhci_recovery;
+pub mod sdhci_timer;
+pub mod sdhci_pci;
+pub mod sdhci_sd;
+pub mod sdhci_tuning;
+pub mod sdhci_hotplug;
+pub mod sdhci_erase;
+pub mod sdhci_hpi;
+pub mod sdhci_cache;
+pub mod sdhci_reliable;
+pub mod sdhci_boot;
+pub mod sdhci_wp;
+pub mod sdhci_partition;
+pub mod sdhci_health;
+pub mod sdhci_ffu;
+pub mod sdhci_bkops;
+#[cfg(feature = "async")]
+mod sdhci_async;
+#[cfg(feature = "mock")]
+pub mod sdhci_mock;
+#[cfg(feature = "sim")]
+pub mod sdhci_sim;
 
+/// Wait for `us` microseconds with the installed `Timer`.
 pub fn delay_us(us: u64) {
-    let start = since_boot();
-    let duration = core::time::Duration::from_micros(us);
-    
-    while since_boot() - start < duration {
+    sdhci_timer::timer().delay(core::time::Duration::from_micros(us));
+}
+
+/// A point in time after which a wait is abandoned.
+#[derive(Clone, Copy)]
+pub struct Deadline {
+    start: core::time::Duration,
+    timeout: core::time::Duration,
+}
+
+impl Deadline {
+    pub fn after_us(timeout_us: u64) -> Self {
+        Self { start: sdhci_timer::timer().now(), timeout: core::time::Duration::from_micros(timeout_us) }
+    }
+
+    pub fn expired(&self) -> bool {
+        sdhci_timer::timer().now() - self.start > self.timeout
+    }
+}
+
+/// Call `f` until it returns `Some`, for at most `timeout_us` microseconds.
+///
+/// `f` is called once more after the deadline, so a late result is not mistaken for a timeout.
+/// On timeout `MmcError::Timeout(what)` is returned.
+pub fn poll_timeout<T>(what: &'static str, timeout_us: u64, mut f: impl FnMut() -> Option<T>) -> Result<T, MmcError> {
+    let deadline = Deadline::after_us(timeout_us);
+
+    loop {
+        let expired = deadline.expired();
+        if let Some(value) = f() {
+            return Ok(value);
+        }
+        if expired {
+            warn!("emmc timeout after {} us waiting for {}", timeout_us, what);
+            return Err(MmcError::Timeout(what));
+        }
         core::hint::spin_loop();
     }
 }
+
+/// Wait until `done` returns true, for at most `timeout_us` microseconds.
+pub fn wait_timeout(what: &'static str, timeout_us: u64, mut done: impl FnMut() -> bool) -> Result<(), MmcError> {
+    poll_timeout(what, timeout_us, || done().then_some(()))
+}
@@ -1,108 +1,1247 @@
-use log::info;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use core::task::Poll;
+#[cfg(feature = "async")]
+use core::task::Waker;
+
+use kspin::SpinNoIrq;
+use log::{debug, info, warn};
 use rk3568_clk::cru::CRU;
 use rk3568_clk::cru::cru_clksel_con28_bits::{*};
 
-use crate::delay_us;
-use crate::sdhci_reg::Reg;
+use crate::{delay_us, poll_timeout, wait_timeout};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_ocr_bits::*;
+use crate::sdhci_cmd::mmc_r1_bits::*;
+use crate::sdhci_cmd::mmc_resp_type::*;
+use crate::sdhci_cmd::sd_cmd_idx::*;
+use crate::sdhci_cqe::Cqe;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_reg::emmc_error_int_stat_bits::EMMC_AUTO_CMD_ERR;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_ext_csd::ExtCsd;
+use crate::sdhci_hotplug::Hotplug;
+#[cfg(feature = "async")]
+use crate::sdhci_async::CLAIM_WAKERS;
+use crate::sdhci_recovery::RecoveryStats;
+use crate::sdhci_sd::{Scr, SdCapacity, SdStatus, SdTiming};
+use crate::sdhci_tuning::Tuning;
+use crate::sdhci_reg::{Mmio, MmioPtr, Reg};
 use crate::sdhci_reg::emmc_cmd_bits::{*};
 use crate::sdhci_reg::emmc_normal_int_en_bits::{*};
+use crate::sdhci_reg::emmc_normal_int_stat_bits::{*};
+use crate::sdhci_reg::emmc_host_ctrl_ver_bits::EMMC_SPEC_VERSION_V300;
+use crate::sdhci_reg::emmc_pwr_ctrl_bits::EMMC_SD_BUS_VOL_VDD1_3V3;
 use crate::sdhci_reg::emmc_dll_ctrl_bits::{*};
+use crate::sdhci_reg::emmc_tout_ctrl_bits::EMMC_TOUT_CNT_MAX;
+use crate::sdhci_reg::emmc_xfer_mode_bits::{*};
 // use crate::sdhci_cmd::Cmd;
 
-pub struct SDHCI {
-    reg: Reg,
-    clk: CRU,
+/// Size of a data block in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Relative card address assigned to the device with CMD3.
+const MMC_RCA: u16 = 1;
+
+/// Software reset of the controller or of the CMD/DAT lines.
+pub const RESET_TIMEOUT_US: u64 = 100_000;
+/// Internal clock becoming stable.
+pub const CLK_STABLE_TIMEOUT_US: u64 = 150_000;
+/// CMD and DAT inhibit clearing before a command is issued.
+pub const INHIBIT_TIMEOUT_US: u64 = 10_000;
+/// Command without data, on top of the data and busy timeouts of the request.
+pub const CMD_TIMEOUT_US: u64 = 100_000;
+/// Device power up, repeating CMD1 until the OCR busy bit is set.
+pub const OCR_TIMEOUT_US: u64 = 1_000_000;
+/// Read access time of one block used when the CSD asks for less.
+pub const DEFAULT_READ_TIMEOUT_US: u64 = 100_000;
+/// Programming time of one block used when the CSD asks for less.
+pub const DEFAULT_WRITE_TIMEOUT_US: u64 = 250_000;
+/// Busy time of CMD6 when EXT_CSD GENERIC_CMD6_TIME is not set.
+pub const DEFAULT_SWITCH_TIMEOUT_US: u64 = 500_000;
+/// First block of a boot operation after it started.
+pub const BOOT_DATA_TIMEOUT_US: u64 = 1_000_000;
+
+/// SD clock during card identification, programmed through the divider on generic hosts.
+pub(crate) const IDENT_CLK_HZ: u32 = 400_000;
+/// SD clock of eMMC high speed timing, for devices supporting 26 MHz only.
+pub const MMC_HS_26_CLK_HZ: u32 = 26_000_000;
+/// SD clock of eMMC high speed timing.
+pub const MMC_HS_52_CLK_HZ: u32 = 52_000_000;
+
+/// The controller the driver runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostKind {
+    /// The RK3568 DesignWare controller, clocked by the CRU and with the vendor DLL registers.
+    Dwcmshc,
+    /// A standard SDHCI controller, e.g. QEMU's `sdhci-pci`, clocked through its divider.
+    Generic,
+}
+
+/// The kind of card identified after CMD0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardType {
+    Mmc,
+    Sd,
+}
+
+/// The card identified by `SDHCI::init`.
+#[derive(Clone)]
+pub struct Card {
+    pub card_type: CardType,
+    /// Relative card address.
+    pub rca: u16,
+    /// The card is addressed in sectors instead of bytes.
+    pub high_capacity: bool,
+    pub cid: [u32; 4],
+    pub csd: [u32; 4],
+    pub ext_csd: ExtCsd,
+    /// Capacity in blocks of `BLOCK_SIZE` bytes.
+    pub blocks: u64,
+    /// Data bus width in use, 1, 4 or 8.
+    pub bus_width: u8,
+    /// SD Configuration Register, only read from SD cards.
+    pub scr: Scr,
+    /// SD Status, only read from SD cards.
+    pub sd_status: SdStatus,
+    /// Bus speed mode of an SD card.
+    pub sd_timing: SdTiming,
+}
+
+impl Card {
+    pub(crate) const fn empty() -> Self {
+        Self {
+            card_type: CardType::Mmc,
+            rca: 0,
+            high_capacity: false,
+            cid: [0; 4],
+            csd: [0; 4],
+            ext_csd: ExtCsd::empty(),
+            blocks: 0,
+            bus_width: 1,
+            scr: Scr::empty(),
+            sd_status: SdStatus::empty(),
+            sd_timing: SdTiming::Default,
+        }
+    }
+
+    /// CMD23 is supported, by every eMMC device and by SD cards listing it in the SCR CMD_SUPPORT.
+    pub fn supports_cmd23(&self) -> bool {
+        match self.card_type {
+            CardType::Mmc => true,
+            CardType::Sd => self.scr.cmd_support() & 0x02 != 0,
+        }
+    }
+
+    /// Capacity class of an SD card, `None` for an eMMC device.
+    pub fn sd_capacity(&self) -> Option<SdCapacity> {
+        (self.card_type == CardType::Sd).then(|| SdCapacity::new(self.high_capacity, self.blocks))
+    }
+
+    /// Read access timeout of one block: 100 times TAAC from the CSD, at least `DEFAULT_READ_TIMEOUT_US`.
+    pub fn read_timeout_us(&self) -> u64 {
+        // TAAC time value in tenths, indexed by bits 6:3.
+        const TAAC_MULT: [u64; 16] = [0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80];
+        let taac = csd_bits(&self.csd, 112, 8);
+        let taac_ns = TAAC_MULT[(taac >> 3 & 0x0f) as usize] * 10u64.pow(taac & 0x07) / 10;
+        (taac_ns * 100 / 1000).max(DEFAULT_READ_TIMEOUT_US)
+    }
+
+    /// Programming timeout of one block: the read timeout times R2W_FACTOR from the CSD,
+    /// at least `DEFAULT_WRITE_TIMEOUT_US`.
+    pub fn write_timeout_us(&self) -> u64 {
+        let r2w_factor = csd_bits(&self.csd, 26, 3);
+        (self.read_timeout_us() << r2w_factor).max(DEFAULT_WRITE_TIMEOUT_US)
+    }
+
+    /// Busy timeout of CMD6 from EXT_CSD GENERIC_CMD6_TIME, in units of 10 ms.
+    pub fn switch_timeout_us(&self) -> u64 {
+        match self.ext_csd.byte(EXT_CSD_GENERIC_CMD6_TIME) {
+            0 => DEFAULT_SWITCH_TIMEOUT_US,
+            time => time as u64 * 10_000,
+        }
+    }
+}
+
+/// Data moved through the buffer data port during a request.
+pub(crate) enum Data<'a> {
+    None,
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for command complete of the CMD23 preceding the command.
+    Sbc,
+    /// Waiting for command complete.
+    Cmd,
+    /// Moving data until transfer complete.
+    Data,
+    /// Waiting for the end of the busy signal of an R1b response.
+    Busy,
+    Done,
+}
+
+/// A command, with its optional data phase, in flight on the controller.
+pub(crate) struct Request<'a> {
+    pub(crate) idx: u16,
+    ctype: u16,
+    resp_type: u16,
+    arg: u32,
+    data: Data<'a>,
+    /// Size of the data blocks, `BLOCK_SIZE` except for registers read as data such as the SD SCR.
+    block_size: usize,
+    /// Blocks moved through the buffer data port so far.
+    done_blocks: usize,
+    /// Stop the transfer with CMD12 once the data phase is over.
+    stop: bool,
+    /// Argument of the CMD23 setting the block count before the command, which then needs no CMD12.
+    sbc: Option<u32>,
+    /// Start the data phase by holding the CMD line low instead of sending the command.
+    pub(crate) boot: bool,
+    /// Fail the request when the R1 card status reports an error.
+    check_status: bool,
+    /// Longest busy signal of an R1b response, `switch_timeout_us` of the card if `None`.
+    busy_timeout_us: Option<u64>,
+    phase: Phase,
+    resp: u32,
+}
+
+impl<'a> Request<'a> {
+    pub(crate) fn new(idx: u16, ctype: u16, resp_type: u16, arg: u32) -> Self {
+        Self {
+            idx,
+            ctype,
+            resp_type,
+            arg,
+            data: Data::None,
+            block_size: BLOCK_SIZE,
+            done_blocks: 0,
+            stop: false,
+            sbc: None,
+            boot: false,
+            check_status: true,
+            busy_timeout_us: None,
+            phase: Phase::Cmd,
+            resp: 0,
+        }
+    }
+
+    pub(crate) fn with_data(mut self, data: Data<'a>) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub(crate) fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Precede the command with CMD23 setting the block count, with the flags in `sbc` such as
+    /// reliable write, instead of stopping it with CMD12.
+    pub(crate) fn with_sbc(mut self, sbc: u32) -> Self {
+        self.sbc = Some(sbc);
+        self
+    }
+
+    /// Do not check the response as a card status, for the R6 and R7 responses of SD cards.
+    pub(crate) fn unchecked(mut self) -> Self {
+        self.check_status = false;
+        self
+    }
+
+    /// Allow the busy signal of an R1b response to last `timeout_us`, e.g. for an erase.
+    pub(crate) fn with_busy_timeout(mut self, timeout_us: u64) -> Self {
+        self.busy_timeout_us = Some(timeout_us);
+        self
+    }
+
+    fn blocks(&self) -> usize {
+        match &self.data {
+            Data::None => 0,
+            Data::Read(buf) => buf.len() / self.block_size,
+            Data::Write(buf) => buf.len() / self.block_size,
+        }
+    }
+}
+
+/// Exclusive use of the controller for one request.
+///
+/// Dropping it before the request finished successfully, e.g. on error or when an async
+/// request is cancelled, resets the CMD and DAT lines so the next request starts clean.
+pub(crate) struct InFlight<'a, M: Mmio> {
+    sdhci: &'a SDHCI<M>,
+    pub(crate) finished: bool,
+}
+
+impl<M: Mmio> Drop for InFlight<'_, M> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // A wedged controller is reported by the next request.
+            let _ = self.sdhci.reset_lines();
+        }
+        self.sdhci.busy.store(false, Ordering::Release);
+        #[cfg(feature = "async")]
+        self.sdhci.wake_claim_waiters();
+    }
+}
+
+pub struct SDHCI<M: Mmio = MmioPtr> {
+    pub(crate) reg: Reg<M>,
+    /// Clock unit feeding cclk_emmc, absent when the controller is not behind the RK3568 CRU.
+    pub(crate) clk: Option<CRU>,
+    host_kind: HostKind,
+    /// Completion is signalled by `handle_irq` instead of polling the status registers.
+    irq_enabled: AtomicBool,
+    /// Called while waiting for a completion, e.g. to execute `wfi` or yield to the scheduler.
+    pub(crate) idle: fn(),
+    /// Normal interrupt status latched (and acknowledged) but not yet consumed.
+    normal_int: AtomicU16,
+    /// Error interrupt status latched (and acknowledged) but not yet consumed.
+    error_int: AtomicU16,
+    /// A card insertion or removal was latched. Unlike `normal_int` it is not cleared by the
+    /// next request, only `poll_card_detect` consumes it.
+    pub(crate) card_detect_int: AtomicBool,
+    /// A request owns the controller.
+    busy: AtomicBool,
+    pub(crate) card: SpinNoIrq<Card>,
+    /// Command queuing engine state, see `cqe_enable`.
+    pub(crate) cqe: Cqe,
+    pub(crate) recovery: SpinNoIrq<RecoveryStats>,
+    /// Sampling clock tuning of the UHS-I mode in use, see `execute_tuning`.
+    pub(crate) tuning: SpinNoIrq<Tuning>,
+    /// Card detect of a removable slot, see `poll_card_detect`.
+    pub(crate) hotplug: SpinNoIrq<Hotplug>,
+    /// Enable the volatile cache of the device in `init`, see `set_cache`.
+    pub(crate) use_cache: bool,
+    /// Let the controller issue CMD12 and CMD23 of multiple block transfers, see `set_auto_cmd`.
+    auto_cmd: bool,
+    /// CMD56 argument reading the vendor health report in `health`, see `set_health_report`.
+    pub(crate) health_report_arg: Option<u32>,
+    /// Enable AUTO_EN of the device in `init`, see `set_auto_bkops`.
+    pub(crate) auto_bkops: bool,
+    /// An R1 status reported EXCEPTION_EVENT, handled by `bkops_idle`.
+    pub(crate) exception_event: AtomicBool,
+    /// Background operations started with `start_bkops` may still keep the device busy.
+    pub(crate) bkops_running: AtomicBool,
+    /// A write or an erase waits for the device to leave the programming state, see
+    /// `interrupt_current_operation`.
+    pub(crate) long_op: AtomicBool,
+    /// `interrupt_current_operation` asked to interrupt the operation waited for with HPI.
+    pub(crate) hpi_request: AtomicBool,
+    /// Waker of the async request in flight, woken by `handle_irq`.
+    #[cfg(feature = "async")]
+    pub(crate) waker: SpinNoIrq<Option<Waker>>,
+    /// Wakers of the async requests waiting for the controller, woken when it is released.
+    #[cfg(feature = "async")]
+    pub(crate) claim_wakers: SpinNoIrq<[Option<Waker>; CLAIM_WAKERS]>,
 }
 
 impl SDHCI {
     pub fn new (base_addr: u64, clk_addr: u64) -> Self {
-        Self { reg: Reg::new(base_addr as u64), clk: CRU::new(clk_addr as u64) }
+        let mut sdhci = Self::new_with_mmio(base_addr, MmioPtr);
+        sdhci.clk = Some(CRU::new(clk_addr));
+        sdhci
     }
 
-    pub fn init(&self) {
-        self.reg.emmc_reset_all();
+    /// Create a driver for a standard SDHCI controller, without the RK3568 clock unit and DLL.
+    ///
+    /// The slot is taken as removable, see `set_non_removable`.
+    pub fn new_generic(base_addr: u64) -> Self {
+        let mut sdhci = Self::new_with_mmio(base_addr, MmioPtr);
+        sdhci.host_kind = HostKind::Generic;
+        sdhci.set_non_removable(false);
+        sdhci
+    }
+}
 
-        while !self.reg.emmc_reset_all_is_finished() {
-            info!("emmc reset all is not finished!");
+impl<M: Mmio> SDHCI<M> {
+    /// Create a driver accessing the registers through `mmio`, without a clock unit.
+    ///
+    /// This is how host tests run the driver against `sdhci_mock::MockMmio`.
+    pub fn new_with_mmio(base_addr: u64, mmio: M) -> Self {
+        Self {
+            reg: Reg::with_mmio(base_addr, mmio),
+            clk: None,
+            host_kind: HostKind::Dwcmshc,
+            irq_enabled: AtomicBool::new(false),
+            idle: core::hint::spin_loop,
+            normal_int: AtomicU16::new(0),
+            error_int: AtomicU16::new(0),
+            card_detect_int: AtomicBool::new(false),
+            busy: AtomicBool::new(false),
+            card: SpinNoIrq::new(Card::empty()),
+            cqe: Cqe::new(),
+            recovery: SpinNoIrq::new(RecoveryStats::new()),
+            tuning: SpinNoIrq::new(Tuning::new()),
+            hotplug: SpinNoIrq::new(Hotplug::new(true)),
+            use_cache: false,
+            auto_cmd: true,
+            health_report_arg: None,
+            auto_bkops: false,
+            exception_event: AtomicBool::new(false),
+            bkops_running: AtomicBool::new(false),
+            long_op: AtomicBool::new(false),
+            hpi_request: AtomicBool::new(false),
+            #[cfg(feature = "async")]
+            waker: SpinNoIrq::new(None),
+            #[cfg(feature = "async")]
+            claim_wakers: SpinNoIrq::new([const { None }; CLAIM_WAKERS]),
         }
+    }
+
+    /// Set the function called while a command or transfer is in flight.
+    ///
+    /// The default spins. In interrupt mode this is where the caller sleeps (`wfi`)
+    /// or yields to its scheduler until `handle_irq` has run.
+    pub fn set_idle(&mut self, idle: fn()) {
+        self.idle = idle;
+    }
+
+    /// Set the kind of controller, `HostKind::Dwcmshc` unless created with `new_generic`.
+    pub fn set_host_kind(&mut self, host_kind: HostKind) {
+        self.host_kind = host_kind;
+    }
+
+    pub fn host_kind(&self) -> HostKind {
+        self.host_kind
+    }
+
+    /// Let the controller issue the CMD23 before, or the CMD12 after, a multiple block transfer.
+    ///
+    /// On by default, saving a command round-trip per request. When off both are sent as
+    /// separate commands, for controllers whose Auto CMD is broken.
+    pub fn set_auto_cmd(&mut self, enabled: bool) {
+        self.auto_cmd = enabled;
+    }
+
+    /// Switch to interrupt driven completion.
+    ///
+    /// Every enabled status bit is routed to the interrupt line (GIC SPI 0x13 on RK3568),
+    /// and the integrator must call `handle_irq` from its handler for that line.
+    pub fn enable_irq(&self) {
+        self.irq_enabled.store(true, Ordering::Release);
+        self.apply_int_sig();
+    }
+
+    /// Switch back to polling the status registers.
+    pub fn disable_irq(&self) {
+        self.irq_enabled.store(false, Ordering::Release);
+        self.apply_int_sig();
+    }
+
+    /// Interrupt handler entry point.
+    ///
+    /// Reads and acknowledges the normal and error interrupt status and hands them to the
+    /// context waiting on the pending command or transfer.
+    ///
+    /// # Returns
+    ///
+    /// - true if the controller had a pending interrupt
+    /// - false if the interrupt was not raised by this controller
+    pub fn handle_irq(&self) -> bool {
+        let handled = self.latch_int();
+
+        #[cfg(feature = "async")]
+        if handled {
+            let waker = self.waker.lock().take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+
+        handled
+    }
+
+    /// Return the register interface of the controller.
+    pub fn reg(&self) -> &Reg<M> {
+        &self.reg
+    }
+
+    /// Return a copy of the information about the identified card.
+    pub fn card(&self) -> Card {
+        self.card.lock().clone()
+    }
+
+    pub fn init(&self) -> Result<(), MmcError> {
+        self.init_host()?;
+        self.init_card()
+    }
+
+    /// Reset the controller and power and clock the slot at the identification frequency,
+    /// leaving the card untouched.
+    pub(crate) fn init_host(&self) -> Result<(), MmcError> {
+        *self.tuning.lock() = Tuning::new();
+        self.reg.emmc_reset_all();
+        wait_timeout("reset all", RESET_TIMEOUT_US, || self.reg.emmc_reset_all_is_finished())?;
 
         info!("emmc host version: {:#x}", self.reg.emmc_get_host_ctrl_ver());
         info!("emmc spec version: {:#x}", self.reg.emmc_get_spec_version());
         info!("emmc vendor version: {:#x}", self.reg.emmc_get_vendor_version());
-        info!("emmc version type: {:#x}", self.reg.emmc_get_ver_type());
-        info!("emmc version id: {:#x}", self.reg.emmc_get_ver_id());
-
-        self.clk.cru_clksel_set_cclk_emmc(CRU_CLKSEL_CCLK_EMMC_SOC0_375K);
-        info!("clock.cru_clksel_get_cclk_emmc(): {:#x}", self.clk.cru_clksel_get_cclk_emmc());
+        if self.host_kind == HostKind::Dwcmshc {
+            info!("emmc version type: {:#x}", self.reg.emmc_get_ver_type());
+            info!("emmc version id: {:#x}", self.reg.emmc_get_ver_id());
+        }
 
+        if let Some(clk) = &self.clk {
+            clk.cru_clksel_set_cclk_emmc(CRU_CLKSEL_CCLK_EMMC_SOC0_375K);
+            info!("clock.cru_clksel_get_cclk_emmc(): {:#x}", clk.cru_clksel_get_cclk_emmc());
+        }
 
 
+        self.detect_card()?;
+        if self.host_kind == HostKind::Generic {
+            self.reg.emmc_set_sd_bus_vol_vdd1(EMMC_SD_BUS_VOL_VDD1_3V3);
+        }
         self.reg.emmc_pwr_on();
 
-        self.reg.emmc_set_normal_int_en(EMMC_CMD_COMPLETE_EN
-                                        | EMMC_XFER_COMPLETE_EN
-                                        | EMMC_DMA_INTERRUPT_EN
-                                        | EMMC_BUF_WR_READY_EN
-                                        | EMMC_BUF_RD_READY_EN);
-        // self.reg.emmc_set_error_int_en(0x027f);
+        let mut normal_int_en = EMMC_CMD_COMPLETE_EN
+                                | EMMC_XFER_COMPLETE_EN
+                                | EMMC_DMA_INTERRUPT_EN
+                                | EMMC_BUF_WR_READY_EN
+                                | EMMC_BUF_RD_READY_EN;
+        if !self.hotplug.lock().non_removable {
+            normal_int_en |= EMMC_CARD_INSERTION_EN | EMMC_CARD_REMOVAL_EN;
+        }
+        self.reg.emmc_set_normal_int_en(normal_int_en);
+        // Without the error status enabled a failed command never completes.
+        self.reg.emmc_enable_all_error_int();
 
-        // self.reg.emmc_clear_all_error_int_flags();
-        // self.reg.emmc_clear_all_normal_int_flags();
-        self.reg.emmc_disable_all_normal_int_sig();
-        self.reg.emmc_disable_all_error_int_sig();
+        self.reg.emmc_clear_all_error_int_flags();
+        self.reg.emmc_clear_all_normal_int_flags();
+        self.apply_int_sig();
 
         self.reg.emmc_enable_data_xfer_width_1bit();
+        // The busy signal of R1b commands is detected through the data timeout.
+        self.reg.emmc_set_tout_cnt(EMMC_TOUT_CNT_MAX);
 
-        self.reg.emmc_set_dll_ctrl(0);
+        match self.host_kind {
+            HostKind::Dwcmshc => {
+                self.reg.emmc_set_dll_ctrl(0);
 
-        info!("DWCMSHC_EMMC_DLL_CTRL: {:#x}", self.reg.emmc_get_dll_ctrl());
-        info!("DWCMSHC_EMMC_DLL_RXCLK: {:#x}", self.reg.emmc_get_dll_rxclk());
-        info!("DWCMSHC_EMMC_DLL_TXCLK: {:#x}", self.reg.emmc_get_dll_txclk());
-        self.reg.emmc_set_dll_ctrl(EMMC_DLL_START | EMMC_DLL_BYPASS);
-        self.reg.emmc_set_dll_rxclk(1 << 31);
-        self.reg.emmc_set_dll_txclk(0);
-        info!("DWCMSHC_EMMC_DLL_CTRL: {:#x}", self.reg.emmc_get_dll_ctrl());
-        info!("DWCMSHC_EMMC_DLL_RXCLK: {:#x}", self.reg.emmc_get_dll_rxclk());
-        info!("DWCMSHC_EMMC_DLL_TXCLK: {:#x}", self.reg.emmc_get_dll_txclk());
+                info!("DWCMSHC_EMMC_DLL_CTRL: {:#x}", self.reg.emmc_get_dll_ctrl());
+                info!("DWCMSHC_EMMC_DLL_RXCLK: {:#x}", self.reg.emmc_get_dll_rxclk());
+                info!("DWCMSHC_EMMC_DLL_TXCLK: {:#x}", self.reg.emmc_get_dll_txclk());
+                self.reg.emmc_set_dll_ctrl(EMMC_DLL_START | EMMC_DLL_BYPASS);
+                self.reg.emmc_set_dll_rxclk(1 << 31);
+                self.reg.emmc_set_dll_txclk(0);
+                info!("DWCMSHC_EMMC_DLL_CTRL: {:#x}", self.reg.emmc_get_dll_ctrl());
+                info!("DWCMSHC_EMMC_DLL_RXCLK: {:#x}", self.reg.emmc_get_dll_rxclk());
+                info!("DWCMSHC_EMMC_DLL_TXCLK: {:#x}", self.reg.emmc_get_dll_txclk());
 
-        self.reg.emmc_disable_cmd_conflict_check();
-        info!("emmc_get_host_ctrl3: {:#x}", self.reg.emmc_get_host_ctrl3());
+                self.reg.emmc_disable_cmd_conflict_check();
+                info!("emmc_get_host_ctrl3: {:#x}", self.reg.emmc_get_host_ctrl3());
+            }
+            HostKind::Generic => self.set_clk_divider(IDENT_CLK_HZ),
+        }
 
         self.reg.emmc_enable_internal_clk();
-        while !self.reg.emmc_internal_clk_is_stable() {
-            info!("emmc internal clk is not stable!");
-        }
+        wait_timeout("internal clock stable", CLK_STABLE_TIMEOUT_US, || self.reg.emmc_internal_clk_is_stable())?;
         self.reg.emmc_enable_sd_clk();
 
         info!("emmc enable sd clk: {:#x}", self.reg.emmc_get_clk_ctrl());
         delay_us(10000);
+        Ok(())
+    }
 
-        self.sdhci_send_cmd(0, 0, 0, 0); // CMD0
-        info!("CMD0 response: {:#x}", self.reg.emmc_get_resp01());
-        delay_us(10000);
-        self.sdhci_send_cmd(1, 0, 2, 0); // CMD1
-        info!("CMD1 response: {:#x}", self.reg.emmc_get_resp01());
-        delay_us(10000);
+    /// Program the SD clock divider for at most `hz`, from the base clock in the capabilities.
+    ///
+    /// Version 3.00 controllers divide by any even number up to 2046, older ones by a power of two up to 256.
+    fn set_clk_divider(&self, hz: u32) {
+        let div = self.clk_divider(hz);
+        info!("sd clock: base {} Hz, divider {:#x}", self.base_clk_hz(), div);
+        self.reg.emmc_set_freq(div);
+    }
+
+    /// Divider of the SD clock for at most `hz`, see `set_clk_divider`.
+    pub(crate) fn clk_divider(&self, hz: u32) -> u16 {
+        let base_hz = self.base_clk_hz();
+        let div = if base_hz <= hz {
+            0
+        } else if self.reg.emmc_get_spec_version() >= EMMC_SPEC_VERSION_V300 {
+            base_hz.div_ceil(2 * hz).min(0x3ff)
+        } else {
+            base_hz.div_ceil(2 * hz).next_power_of_two().min(0x80)
+        };
+        div as u16
+    }
+
+    fn base_clk_hz(&self) -> u32 {
+        match self.reg.emmc_get_base_clk_freq() {
+            // Unknown base clock, assume the fastest one of the specification.
+            0 => 255_000_000,
+            mhz => mhz * 1_000_000,
+        }
+    }
+
+    /// Change the SD clock of a running generic host to at most `hz`, stopping it meanwhile.
+    pub(crate) fn change_clk_divider(&self, hz: u32) -> Result<(), MmcError> {
+        self.reg.emmc_disable_sd_clk();
+        self.set_clk_divider(hz);
+        wait_timeout("internal clock stable", CLK_STABLE_TIMEOUT_US, || self.reg.emmc_internal_clk_is_stable())?;
+        self.reg.emmc_enable_sd_clk();
+        Ok(())
+    }
+
+    /// Identify the card and bring it to the transfer state.
+    ///
+    /// After CMD0 an SD card of version 2.00 or later answers CMD8, an older one only CMD55
+    /// and an eMMC device neither of them.
+    fn init_card(&self) -> Result<(), MmcError> {
+        self.sdhci_send_cmd(MMC_GO_IDLE_STATE, 0, MMC_RESP_NONE, 0)?;
+
+        let if_cond = SD_IF_COND_VHS_27_36 | SD_IF_COND_CHECK_PATTERN;
+        match self.execute(&mut Request::new(SD_SEND_IF_COND, 0, MMC_RESP_R7, if_cond).unchecked()) {
+            Ok(resp) if resp & 0xfff == if_cond => return self.init_sd(true, true),
+            Ok(resp) => info!("CMD8 response {:#x} does not echo the interface condition", resp),
+            Err(err) => debug!("no response to CMD8: {:?}", err),
+        }
+
+        // Cards that did not answer report CMD8 as an illegal command with the next status, start over.
+        self.sdhci_send_cmd(MMC_GO_IDLE_STATE, 0, MMC_RESP_NONE, 0)?;
+        match self.sdhci_send_cmd(SD_APP_CMD, 0, MMC_RESP_R1, 0) {
+            // eMMC devices answer CMD55 too, without expecting an application command.
+            Ok(status) if status & MMC_R1_APP_CMD == 0 => debug!("CMD55 status {:#x} without APP_CMD", status),
+            // An inquiry without a voltage window does not start the power up of an SD card.
+            Ok(_) => match self.execute(&mut Request::new(SD_APP_SEND_OP_COND, 0, MMC_RESP_R3, 0)) {
+                Ok(_) => return self.init_sd(false, false),
+                Err(err) => debug!("no response to ACMD41: {:?}", err),
+            },
+            Err(err) => debug!("no response to CMD55: {:?}", err),
+        }
+
+        self.sdhci_send_cmd(MMC_GO_IDLE_STATE, 0, MMC_RESP_NONE, 0)?;
+        self.init_mmc()
+    }
+
+    /// Identify the eMMC device and bring it to the transfer state.
+    fn init_mmc(&self) -> Result<(), MmcError> {
+        let ocr = poll_timeout("device power up (CMD1)", OCR_TIMEOUT_US, || {
+            match self.sdhci_send_cmd(MMC_SEND_OP_COND, 0, MMC_RESP_R3,
+                                      MMC_OCR_ACCESS_MODE_SECTOR | MMC_OCR_VDD_27_36 | MMC_OCR_VDD_170_195) {
+                Ok(ocr) if ocr & MMC_OCR_BUSY == 0 => {
+                    delay_us(1000);
+                    None
+                }
+                result => Some(result),
+            }
+        })??;
+        info!("CMD1 response: {:#x}", ocr);
+
+        self.sdhci_send_cmd(MMC_ALL_SEND_CID, 0, MMC_RESP_R2, 0)?;
+        let cid = self.sdhci_get_resp136();
+        self.sdhci_send_cmd(MMC_SET_RELATIVE_ADDR, 0, MMC_RESP_R1, (MMC_RCA as u32) << 16)?;
+        self.sdhci_send_cmd(MMC_SEND_CSD, 0, MMC_RESP_R2, (MMC_RCA as u32) << 16)?;
+        let csd = self.sdhci_get_resp136();
+        self.sdhci_send_cmd(MMC_SELECT_CARD, 0, MMC_RESP_R1B, (MMC_RCA as u32) << 16)?;
+
+        let ext_csd = self.mmc_send_ext_csd()?;
+
+        let high_capacity = ocr & MMC_OCR_ACCESS_MODE_SECTOR != 0;
+        let blocks = if high_capacity {
+            ext_csd.sec_count() as u64
+        } else {
+            let c_size = csd_bits(&csd, 62, 12) as u64;
+            let c_size_mult = csd_bits(&csd, 47, 3);
+            let read_bl_len = csd_bits(&csd, 80, 4);
+            ((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE as u64
+        };
+        info!("emmc cid: {:08x?}, ext_csd rev: {}, blocks: {}", cid, ext_csd.rev(), blocks);
+
+        *self.card.lock() = Card {
+            card_type: CardType::Mmc,
+            rca: MMC_RCA,
+            high_capacity,
+            cid,
+            csd,
+            ext_csd,
+            blocks,
+            bus_width: 1,
+            scr: Scr::empty(),
+            sd_status: SdStatus::empty(),
+            sd_timing: SdTiming::Default,
+        };
+        self.cache_init()?;
+        self.hpi_enable()?;
+        self.bkops_init();
+        Ok(())
+    }
+
+    /// Issue a command and wait until it has completed, including the busy signal of R1b.
+    ///
+    /// # Returns
+    ///
+    /// - The `EMMC_RESP01` register, i.e. the card status for R1/R1b and the OCR for R3.
+    pub fn sdhci_send_cmd(&self, idx: u16, ctype: u16, resp_type: u16, arg: u32) -> Result<u32, MmcError> {
+        self.execute(&mut Request::new(idx, ctype, resp_type, arg))
+    }
+
+    /// Return the 136-bit response with the CRC stripped, `resp[0]` holding bits 127:96.
+    pub fn sdhci_get_resp136(&self) -> [u32; 4] {
+        let r0 = self.reg.emmc_get_resp01();
+        let r1 = self.reg.emmc_get_resp23();
+        let r2 = self.reg.emmc_get_resp45();
+        let r3 = self.reg.emmc_get_resp67();
+        [
+            (r3 << 8) | (r2 >> 24),
+            (r2 << 8) | (r1 >> 24),
+            (r1 << 8) | (r0 >> 24),
+            r0 << 8,
+        ]
+    }
+
+    /// Ask the card for its status with CMD13.
+    pub fn sdhci_send_status(&self) -> Result<u32, MmcError> {
+        self.execute(&mut self.status_request())
+    }
+
+    /// Read `buf.len() / BLOCK_SIZE` blocks starting at `lba`.
+    ///
+    /// Transient bus errors are handled by the recovery ladder, see `recover`.
+    pub fn read_blocks(&self, lba: u32, buf: &mut [u8]) -> Result<(), MmcError> {
+        self.with_recovery(|| {
+            let mut req = self.rw_request(lba, Data::Read(&mut *buf))?;
+            self.execute(&mut req).map(|_| ())
+        })
+    }
+
+    /// Write `buf.len() / BLOCK_SIZE` blocks starting at `lba`.
+    ///
+    /// Returns once the device has finished programming the data, or has taken it into its
+    /// volatile cache if that is enabled, see `flush_cache`. Transient bus errors are handled
+    /// by the recovery ladder, see `recover`. Programming interrupted with
+    /// `interrupt_current_operation` fails with `MmcError::Interrupted`, see `resume_write`.
+    pub fn write_blocks(&self, lba: u32, buf: &[u8]) -> Result<(), MmcError> {
+        self.with_recovery(|| {
+            let mut req = self.rw_request(lba, Data::Write(buf))?;
+            self.execute(&mut req)?;
+            let timeout = self.card.lock().write_timeout_us();
+            self.wait_ready_interruptible(timeout)
+        })
+    }
+
+    /// Write `buf` at `lba` with CMD25, CMD23 setting the block count together with `flags`
+    /// such as forced programming, instead of stopping the transfer with CMD12.
+    pub(crate) fn write_blocks_sbc(&self, lba: u32, buf: &[u8], flags: u32) -> Result<(), MmcError> {
+        let mut req = self.rw_request(lba, Data::Write(buf))?;
+        req.idx = MMC_WRITE_MULTIPLE_BLOCK;
+        req.stop = false;
+        req.sbc = Some(req.blocks() as u32 | flags);
+        self.execute(&mut req)?;
+        let timeout = self.card.lock().write_timeout_us();
+        self.wait_ready(timeout)
+    }
+
+    /// Poll CMD13 until the device is back in the transfer state and ready for data.
+    pub(crate) fn wait_ready(&self, timeout_us: u64) -> Result<(), MmcError> {
+        poll_timeout("transfer state (CMD13)", timeout_us, || match self.sdhci_send_status() {
+            Ok(status) if !status_is_ready(status) => {
+                (self.idle)();
+                None
+            }
+            result => Some(result),
+        })?
+        .map(|_| ())
+    }
+
+    pub(crate) fn status_request(&self) -> Request<'static> {
+        Request::new(MMC_SEND_STATUS, 0, MMC_RESP_R1, (self.card.lock().rca as u32) << 16)
+    }
+
+    /// Build the CMD17/18/24/25 request moving `data` from or to `lba`.
+    ///
+    /// A multiple block transfer is preceded by CMD23 if the card supports it, otherwise stopped with CMD12.
+    pub(crate) fn rw_request<'a>(&self, lba: u32, data: Data<'a>) -> Result<Request<'a>, MmcError> {
+        let (len, read) = match &data {
+            Data::Read(buf) => (buf.len(), true),
+            Data::Write(buf) => (buf.len(), false),
+            Data::None => (0, false),
+        };
+        let blocks = len / BLOCK_SIZE;
+        if len == 0 || len % BLOCK_SIZE != 0 || blocks > u16::MAX as usize {
+            return Err(MmcError::InvalidArgument);
+        }
+        self.check_card()?;
+
+        let card = self.card.lock();
+        if lba as u64 + blocks as u64 > card.blocks {
+            return Err(MmcError::InvalidArgument);
+        }
+        let arg = if card.high_capacity { lba } else { lba * BLOCK_SIZE as u32 };
+        let idx = match (read, blocks > 1) {
+            (true, false) => MMC_READ_SINGLE_BLOCK,
+            (true, true) => MMC_READ_MULTIPLE_BLOCK,
+            (false, false) => MMC_WRITE_BLOCK,
+            (false, true) => MMC_WRITE_MULTIPLE_BLOCK,
+        };
+
+        let mut req = Request::new(idx, 0, MMC_RESP_R1, arg).with_data(data);
+        if blocks > 1 && card.supports_cmd23() {
+            req.sbc = Some(blocks as u32);
+        } else {
+            req.stop = blocks > 1;
+        }
+        Ok(req)
+    }
+
+    /// Write `value` to the EXT_CSD byte at `index` with CMD6 and wait until the device is back in
+    /// the transfer state.
+    ///
+    /// A rejected switch is reported by CMD13 as `MmcError::CardStatus` with `MMC_R1_SWITCH_ERROR` set.
+    pub fn mmc_switch(&self, index: usize, value: u8) -> Result<(), MmcError> {
+        let timeout = self.card.lock().switch_timeout_us();
+        self.switch_busy(index, value, timeout)?;
+
+        self.card.lock().ext_csd.0[index] = value;
+        Ok(())
+    }
+
+    /// Read the EXT_CSD of the device with CMD8.
+    pub fn mmc_send_ext_csd(&self) -> Result<ExtCsd, MmcError> {
+        let mut ext_csd = ExtCsd::empty();
+        self.execute(&mut Request::new(MMC_SEND_EXT_CSD, 0, MMC_RESP_R1, 0).with_data(Data::Read(&mut ext_csd.0)))?;
+        Ok(ext_csd)
+    }
+
+    /// Set the data bus width of the device with BUS_WIDTH, then the one of the controller.
+    ///
+    /// Only 1, 4 and 8 are valid, at single data rate.
+    pub fn mmc_set_bus_width(&self, width: u8) -> Result<(), MmcError> {
+        if self.card.lock().card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        }
+        let value = match width {
+            1 => 0,
+            4 => 1,
+            8 => 2,
+            _ => return Err(MmcError::InvalidArgument),
+        };
+        self.mmc_switch(EXT_CSD_BUS_WIDTH, value)?;
+        if width == 8 {
+            self.reg.emmc_enable_ext_data_xfre();
+        } else {
+            self.reg.emmc_disable_ext_data_xfre();
+            if width == 4 {
+                self.reg.emmc_enable_data_xfer_width_4bit();
+            } else {
+                self.reg.emmc_enable_data_xfer_width_1bit();
+            }
+        }
+        self.card.lock().bus_width = width;
+        debug!("emmc bus width: {}", width);
+        Ok(())
+    }
+
+    /// Switch the device with HS_TIMING, then the controller, to high speed timing, clocked at
+    /// the fastest rate CARD_TYPE allows on hosts whose clock the driver controls.
+    pub fn mmc_set_high_speed(&self) -> Result<(), MmcError> {
+        let (card_type, modes) = {
+            let card = self.card.lock();
+            (card.card_type, card.ext_csd.byte(EXT_CSD_CARD_TYPE))
+        };
+        let hz = if card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        } else if modes & EXT_CSD_CARD_TYPE_HS_52 != 0 {
+            MMC_HS_52_CLK_HZ
+        } else if modes & EXT_CSD_CARD_TYPE_HS_26 != 0 {
+            MMC_HS_26_CLK_HZ
+        } else {
+            return Err(MmcError::Unsupported);
+        };
+        self.mmc_switch(EXT_CSD_HS_TIMING, 1)?;
+        self.reg.emmc_enable_high_speed();
+        self.set_bus_clock(hz)?;
+        info!("emmc: high speed at {} Hz", hz);
+        Ok(())
+    }
+
+    /// Write `value` to the EXT_CSD byte at `index` with `mmc_switch`, then read the EXT_CSD back
+    /// and check the device took it.
+    ///
+    /// For settings the device may silently ignore, e.g. once they are locked. The copy of the
+    /// EXT_CSD is refreshed with what was read.
+    pub(crate) fn mmc_switch_verify(&self, index: usize, value: u8) -> Result<(), MmcError> {
+        self.mmc_switch(index, value)?;
+        let ext_csd = self.mmc_send_ext_csd()?;
+        let read = ext_csd.byte(index);
+        self.card.lock().ext_csd = ext_csd;
+        if read != value {
+            warn!("EXT_CSD[{}] reads back {:#x} instead of {:#x}", index, read, value);
+            return Err(MmcError::VerifyFailed);
+        }
+        Ok(())
+    }
+
+    /// Write `value` to the EXT_CSD byte at `index` with CMD6, waiting up to `timeout_us` for the
+    /// busy signal, without updating the copy of the EXT_CSD. For the bytes triggering an
+    /// operation, such as FLUSH_CACHE.
+    pub(crate) fn switch_busy(&self, index: usize, value: u8, timeout_us: u64) -> Result<(), MmcError> {
+        self.execute(&mut Request::new(MMC_SWITCH, 0, MMC_RESP_R1B, switch_arg(index, value)).with_busy_timeout(timeout_us))?;
+        self.wait_ready(timeout_us)
+    }
+
+    /// Legacy commands cannot be issued while the command queuing engine owns the bus.
+    pub(crate) fn check_legacy(&self) -> Result<(), MmcError> {
+        if self.reg.emmc_cqe_is_enabled() && !self.reg.emmc_cqe_is_halted() {
+            return Err(MmcError::CqeActive);
+        }
+        Ok(())
+    }
+
+    /// Claim the controller for one request, or return `None` if another one is in flight.
+    pub(crate) fn try_claim(&self) -> Option<InFlight<'_, M>> {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| InFlight { sdhci: self, finished: false })
+    }
+
+    /// A request owns the controller, e.g. when called from the idle function.
+    pub(crate) fn in_flight(&self) -> bool {
+        self.busy.load(Ordering::Acquire)
+    }
+
+    /// Claim the controller, calling the idle function while another request is in flight.
+    pub(crate) fn claim(&self) -> InFlight<'_, M> {
+        loop {
+            if let Some(inflight) = self.try_claim() {
+                return inflight;
+            }
+            (self.idle)();
+        }
+    }
+
+    /// Run a request to completion, calling the idle function while waiting.
+    pub(crate) fn execute(&self, req: &mut Request) -> Result<u32, MmcError> {
+        self.check_legacy()?;
+        self.check_card()?;
+        self.retune_if_needed()?;
+        self.preempt_bkops(req.idx)?;
+        let mut inflight = self.claim();
+
+        let timeout = self.request_timeout_us(req);
+        let result = self.issue(req).and_then(|_| {
+            poll_timeout("request completion", timeout, || match self.poll_request(req) {
+                Poll::Ready(result) => Some(result),
+                Poll::Pending => {
+                    (self.idle)();
+                    None
+                }
+            })?
+        });
+
+        inflight.finished = result.is_ok();
+        result
     }
 
-    pub fn sdhci_send_cmd(&self, idx: u16, ctype: u16, resp_type: u16, arg: u32) {
+    /// Upper bound of the time `req` takes, derived from the CSD and EXT_CSD of the card.
+    pub(crate) fn request_timeout_us(&self, req: &Request) -> u64 {
+        let card = self.card.lock();
+        let blocks = req.blocks() as u64;
+        let mut timeout = CMD_TIMEOUT_US;
+        match req.data {
+            Data::Read(_) => timeout += blocks * card.read_timeout_us(),
+            Data::Write(_) => timeout += blocks * card.write_timeout_us(),
+            Data::None => {}
+        }
+        if req.idx == MMC_GO_IDLE_STATE && blocks > 0 {
+            timeout += BOOT_DATA_TIMEOUT_US;
+        }
+        if req.resp_type == MMC_RESP_R1B {
+            timeout += req.busy_timeout_us.unwrap_or_else(|| card.switch_timeout_us());
+        }
+        timeout
+    }
+
+    /// The controller can issue CMD23 by itself, Auto CMD23 is defined from version 3.00.
+    fn auto_cmd23(&self) -> bool {
+        self.auto_cmd && self.reg.emmc_get_spec_version() >= EMMC_SPEC_VERSION_V300
+    }
+
+    /// Program the controller for `req` and start the command, or the CMD23 preceding it.
+    pub(crate) fn issue(&self, req: &mut Request) -> Result<(), MmcError> {
         self.reg.emmc_clear_all_error_int_flags();
-        self.reg.emmc_clear_all_normal_int_flags();
+        // Card detect events are left for `latch_int` to move into `card_detect_int`.
+        let normal = self.reg.emmc_get_normal_int_stat();
+        self.reg.emmc_set_normal_int_stat(normal & !(EMMC_CARD_INSERTION | EMMC_CARD_REMOVAL));
+        self.normal_int.store(0, Ordering::Release);
+        self.error_int.store(0, Ordering::Release);
+        req.done_blocks = 0;
+
+        match req.sbc {
+            Some(arg) if !self.auto_cmd23() => {
+                wait_timeout("CMD line inhibit", INHIBIT_TIMEOUT_US, || self.reg.emmc_cmd_is_ready())?;
+                self.reg.emmc_set_xfer_mode(0);
+                self.reg.emmc_set_argument(arg);
+                self.reg.emmc_set_cmd(MMC_SET_BLOCK_COUNT << EMMC_CMD_INDEX_POS | MMC_RESP_R1);
+                req.phase = Phase::Sbc;
+                Ok(())
+            }
+            _ => self.issue_cmd(req),
+        }
+    }
+
+    /// Program the controller for the command of `req` and start it.
+    fn issue_cmd(&self, req: &mut Request) -> Result<(), MmcError> {
+        wait_timeout("CMD line inhibit", INHIBIT_TIMEOUT_US, || self.reg.emmc_cmd_is_ready())?;
+
+        let blocks = req.blocks();
+        let mut cmd = req.idx << EMMC_CMD_INDEX_POS | req.ctype | req.resp_type;
+        if blocks > 0 || req.resp_type == MMC_RESP_R1B {
+            wait_timeout("DAT line inhibit", INHIBIT_TIMEOUT_US, || self.reg.emmc_cmd_data_is_ready())?;
+        }
+        if blocks > 0 {
+            let mut xfer_mode = EMMC_BLOCK_COUNT_ENABLE;
+            if matches!(req.data, Data::Read(_)) {
+                xfer_mode |= EMMC_DATA_XFER_DIR_READ;
+            }
+            if blocks > 1 || req.sbc.is_some() {
+                xfer_mode |= EMMC_MULTI_BLK_SEL;
+            }
+            match req.sbc {
+                Some(arg) if self.auto_cmd23() => {
+                    self.reg.emmc_set_sdmasa(arg);
+                    xfer_mode |= EMMC_AUTO_CMD23_ENABLED;
+                }
+                None if req.stop && self.auto_cmd => xfer_mode |= EMMC_AUTO_CMD12_ENABLED,
+                _ => {}
+            }
+            self.reg.emmc_set_xfer_block_size(req.block_size as u16);
+            self.reg.emmc_set_blockcount(blocks as u16);
+            self.reg.emmc_set_xfer_mode(xfer_mode);
+            cmd |= EMMC_DATA_PRESENT;
+            if req.boot {
+                self.reg.emmc_start_mandatory_boot();
+                req.phase = Phase::Data;
+                return Ok(());
+            }
+        } else {
+            self.reg.emmc_set_xfer_mode(0);
+        }
 
+        self.reg.emmc_set_argument(req.arg);
+
+        debug!("emmc set argument: {:#x}", req.arg);
+        debug!("emmc set cmd: {:#x}", cmd);
+        self.reg.emmc_set_cmd(cmd);
+
+        req.phase = Phase::Cmd;
+        Ok(())
+    }
+
+    /// Advance `req` with whatever the controller has reported since the last call.
+    pub(crate) fn poll_request(&self, req: &mut Request) -> Poll<Result<u32, MmcError>> {
+        if !self.irq_enabled.load(Ordering::Acquire) {
+            self.latch_int();
+        }
+
+        if req.phase != Phase::Done && self.card_is_gone() {
+            req.phase = Phase::Done;
+            return Poll::Ready(Err(MmcError::NoCard));
+        }
+        if req.phase != Phase::Done && let Some(err) = self.take_error() {
+            req.phase = Phase::Done;
+            return Poll::Ready(Err(err));
+        }
+
+        loop {
+            match req.phase {
+                Phase::Sbc => {
+                    if self.take_int(EMMC_CMD_COMPLETE) == 0 {
+                        return Poll::Pending;
+                    }
+                    let status = self.reg.emmc_get_resp01();
+                    self.note_exception_event(status);
+                    if status & MMC_R1_ERROR_MASK != 0 {
+                        req.phase = Phase::Done;
+                        return Poll::Ready(Err(MmcError::from_card_status(status)));
+                    }
+                    if let Err(err) = self.issue_cmd(req) {
+                        req.phase = Phase::Done;
+                        return Poll::Ready(Err(err));
+                    }
+                }
+                Phase::Cmd => {
+                    if self.take_int(EMMC_CMD_COMPLETE) == 0 {
+                        return Poll::Pending;
+                    }
+                    req.resp = self.reg.emmc_get_resp01();
+                    // Reading up to the last block makes CMD12 report OUT_OF_RANGE.
+                    let r1 = req.resp_type == MMC_RESP_R1 || req.resp_type == MMC_RESP_R1B;
+                    if r1 && req.check_status {
+                        self.note_exception_event(req.resp);
+                    }
+                    if r1 && req.check_status && req.idx != MMC_STOP_TRANSMISSION && req.resp & MMC_R1_ERROR_MASK != 0 {
+                        req.phase = Phase::Done;
+                        return Poll::Ready(Err(MmcError::from_card_status(req.resp)));
+                    }
+                    req.phase = match (&req.data, req.resp_type) {
+                        (Data::Read(_) | Data::Write(_), _) => Phase::Data,
+                        (Data::None, MMC_RESP_R1B) => Phase::Busy,
+                        (Data::None, _) => Phase::Done,
+                    };
+                }
+                Phase::Data => {
+                    self.pio(req);
+                    if self.take_int(EMMC_XFER_COMPLETE) == 0 {
+                        return Poll::Pending;
+                    }
+                    if req.stop && !self.auto_cmd {
+                        *req = Request::new(MMC_STOP_TRANSMISSION, EMMC_CMD_TYPE_ABORT, MMC_RESP_R1B, 0);
+                        if let Err(err) = self.issue(req) {
+                            req.phase = Phase::Done;
+                            return Poll::Ready(Err(err));
+                        }
+                    } else {
+                        req.phase = Phase::Done;
+                    }
+                }
+                Phase::Busy => {
+                    if self.take_int(EMMC_XFER_COMPLETE) == 0 {
+                        return Poll::Pending;
+                    }
+                    req.phase = Phase::Done;
+                }
+                Phase::Done => return Poll::Ready(Ok(req.resp)),
+            }
+        }
+    }
+
+    /// Remember an exception event reported by the R1 status `status` for `bkops_idle`.
+    fn note_exception_event(&self, status: u32) {
+        if status & MMC_R1_EXCEPTION_EVENT != 0 {
+            self.exception_event.store(true, Ordering::Release);
+        }
+    }
+
+    /// Move as many blocks as the packet buffer allows once it signalled ready.
+    fn pio(&self, req: &mut Request) {
+        if self.take_int(EMMC_BUF_RD_READY | EMMC_BUF_WR_READY) == 0 {
+            return;
+        }
+
+        let Request { data, block_size, done_blocks, .. } = req;
+        let block_size = *block_size;
+        match data {
+            Data::Read(buf) => {
+                while *done_blocks * block_size < buf.len() && self.reg.emmc_buf_rd_is_enabled() {
+                    let block = &mut buf[*done_blocks * block_size..][..block_size];
+                    for word in block.as_chunks_mut::<4>().0 {
+                        *word = self.reg.emmc_read_buf_data().to_le_bytes();
+                    }
+                    *done_blocks += 1;
+                }
+            }
+            Data::Write(buf) => {
+                while *done_blocks * block_size < buf.len() && self.reg.emmc_buf_wr_is_enabled() {
+                    let block = &buf[*done_blocks * block_size..][..block_size];
+                    for word in block.as_chunks::<4>().0 {
+                        self.reg.emmc_write_buf_data(u32::from_le_bytes(*word));
+                    }
+                    *done_blocks += 1;
+                }
+            }
+            Data::None => {}
+        }
+    }
+
+    /// Reset the CMD and DAT lines after a failed or abandoned request.
+    pub(crate) fn reset_lines(&self) -> Result<(), MmcError> {
         self.reg.emmc_reset_cmd();
-        while !self.reg.emmc_reset_cmd_is_finished() {
-            info!("emmc reset cmd is not finished!");
+        self.reg.emmc_reset_data();
+        wait_timeout("CMD/DAT line reset", RESET_TIMEOUT_US, || {
+            self.reg.emmc_reset_cmd_is_finished() && self.reg.emmc_reset_data_is_finished()
+        })
+    }
+
+    pub(crate) fn irq_enabled(&self) -> bool {
+        self.irq_enabled.load(Ordering::Acquire)
+    }
+
+    /// Route the enabled status bits to the interrupt line, or mask them all when polling.
+    pub(crate) fn apply_int_sig(&self) {
+        if self.irq_enabled.load(Ordering::Acquire) {
+            self.reg.emmc_set_normal_int_sig_en(self.reg.emmc_get_normal_int_en());
+            self.reg.emmc_set_error_int_sig_en(self.reg.emmc_get_error_int_en());
+        } else {
+            self.reg.emmc_disable_all_normal_int_sig();
+            self.reg.emmc_disable_all_error_int_sig();
         }
+    }
 
-        while !self.reg.emmc_cmd_is_ready() {
-            info!("emmc cmd is not ready!");
+    /// Read and acknowledge the interrupt status, accumulating it for `poll_request`.
+    pub(crate) fn latch_int(&self) -> bool {
+        let normal = self.reg.emmc_get_normal_int_stat();
+        if normal == 0 {
+            return false;
+        }
+
+        if normal & EMMC_ERROR_INT != 0 {
+            let error = self.reg.emmc_get_error_int_stat();
+            self.reg.emmc_set_error_int_stat(error);
+            self.error_int.fetch_or(error, Ordering::AcqRel);
+        }
+        if normal & EMMC_CQE_EVENT != 0 {
+            self.cqe_irq();
         }
-        self.reg.emmc_set_argument(arg);
+        // ERROR_INT and CQE_EVENT summarise the error and CQIS registers and CARD_INTERRUPT
+        // is level triggered, none of them is write-1-to-clear.
+        self.reg.emmc_set_normal_int_stat(normal & !(EMMC_ERROR_INT | EMMC_CQE_EVENT | EMMC_CARD_INTERRUPT));
+        if normal & (EMMC_CARD_INSERTION | EMMC_CARD_REMOVAL) != 0 {
+            self.card_detect_int.store(true, Ordering::Release);
+        }
+        self.normal_int.fetch_or(normal & !(EMMC_CARD_INSERTION | EMMC_CARD_REMOVAL), Ordering::AcqRel);
+
+        true
+    }
+
+    /// Consume the latched `mask` bits, returning those that were set.
+    pub(crate) fn take_int(&self, mask: u16) -> u16 {
+        self.normal_int.fetch_and(!mask, Ordering::AcqRel) & mask
+    }
+
+    /// Consume the latched error status, decoded with the Auto CMD error status if it is involved.
+    pub(crate) fn take_error(&self) -> Option<MmcError> {
+        let error = self.error_int.swap(0, Ordering::AcqRel);
+        let auto_cmd = if error & EMMC_AUTO_CMD_ERR != 0 { self.reg.emmc_get_auto_cmd_stat() } else { 0 };
+        MmcError::from_error_int_stat(error, auto_cmd)
+    }
 
-        info!("emmc set argument: {:#x}", arg);
-        info!("emmc set cmd: {:#x}", idx << EMMC_CMD_INDEX_POS | ctype | resp_type);
-        self.reg.emmc_set_cmd(idx << EMMC_CMD_INDEX_POS | ctype | resp_type);
+    /// Wait for one of the normal interrupt status bits of `mask`, or for an error.
+    pub(crate) fn wait_int(&self, what: &'static str, mask: u16, timeout_us: u64) -> Result<(), MmcError> {
+        poll_timeout(what, timeout_us, || {
+            if !self.irq_enabled() {
+                self.latch_int();
+            }
+            if let Some(err) = self.take_error() {
+                return Some(Err(err));
+            }
+            if self.take_int(mask) != 0 {
+                return Some(Ok(()));
+            }
+            (self.idle)();
+            None
+        })?
+    }
+}
+
+/// The card is in the transfer state and ready for the next data command.
+pub(crate) fn status_is_ready(status: u32) -> bool {
+    status & MMC_R1_READY_FOR_DATA != 0 && mmc_r1_current_state(status) == MMC_R1_STATE_TRAN
+}
 
-        info!("emmc cmd: {:#x}", self.reg.emmc_get_cmd());
+/// CMD6 argument writing `value` to the EXT_CSD byte at `index`.
+pub(crate) fn switch_arg(index: usize, value: u8) -> u32 {
+    MMC_SWITCH_MODE_WRITE_BYTE << 24 | (index as u32) << 16 | (value as u32) << 8
+}
+
+/// Extract `len` bits starting at bit `start` of a 128-bit CSD/CID register.
+pub(crate) fn csd_bits(reg: &[u32; 4], start: usize, len: usize) -> u32 {
+    let word = 3 - start / 32;
+    let offset = start % 32;
+    let mut value = reg[word] >> offset;
+    if offset + len > 32 {
+        value |= reg[word - 1] << (32 - offset);
     }
-}
\ No newline at end of file
+    value & ((1u64 << len) - 1) as u32
+}
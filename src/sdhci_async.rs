@@ -0,0 +1,359 @@
+use core::future::poll_fn;
+use core::sync::atomic::Ordering;
+use core::task::{Context, Poll};
+
+use log::{debug, info, warn};
+
+use crate::Deadline;
+use crate::sdhci::{CMD_TIMEOUT_US, Data, InFlight, Request, SDHCI, status_is_ready, switch_arg};
+use crate::sdhci_bkops::BKOPS_TIMEOUT_US;
+use crate::sdhci_cache::CACHE_FLUSH_TIMEOUT_US;
+use crate::sdhci_cmd::mmc_cmd_idx::{MMC_STOP_TRANSMISSION, MMC_SWITCH};
+use crate::sdhci_cmd::mmc_resp_type::MMC_RESP_R1B;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::{EXT_CSD_FLUSH, EXT_CSD_FLUSH_CACHE};
+use crate::sdhci_recovery::{RECOVERY_RETRIES, RECOVERY_STATUS_POLLS, RecoveryState};
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_cmd_bits::EMMC_CMD_TYPE_ABORT;
+use crate::sdhci_reg::emmc_normal_int_stat_bits::EMMC_BUF_RD_READY;
+use crate::sdhci_tuning::TUNING_TIMEOUT_US;
+
+/// Async requests waiting for the controller that are woken when it is released. Any further
+/// one polls again right away.
+pub(crate) const CLAIM_WAKERS: usize = 4;
+
+/// Request API for async executors, enabled with the `async` feature.
+///
+/// The futures are woken from `SDHCI::handle_irq`, so `enable_irq` should be called after
+/// `init`. In polling mode they re-schedule themselves on every poll instead. Nothing blocks
+/// the executor: re-tuning, the end of background operations and the retries of the recovery
+/// ladder are awaited like requests.
+impl<M: Mmio> SDHCI<M> {
+    /// Read `buf.len() / BLOCK_SIZE` blocks starting at `lba`.
+    ///
+    /// A transient error is retried `RECOVERY_RETRIES` times as by the first step of `recover`,
+    /// without the backoff. The further steps block, so the error is then returned and the
+    /// caller may run `recover` outside of the executor.
+    pub async fn read(&self, lba: u32, buf: &mut [u8]) -> Result<(), MmcError> {
+        let mut attempt = 0;
+        loop {
+            let mut req = self.rw_request(lba, Data::Read(&mut *buf))?;
+            match self.execute_async(&mut req).await {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    attempt += 1;
+                    self.recover_async(err, attempt).await?;
+                }
+            }
+        }
+    }
+
+    /// Write `buf.len() / BLOCK_SIZE` blocks starting at `lba`.
+    ///
+    /// Completes once the device has finished programming the data, or has taken it into its
+    /// volatile cache. Errors are handled as by `read`. Programming interrupted with
+    /// `interrupt_current_operation` fails with `MmcError::Interrupted`, as by `write_blocks`.
+    pub async fn write(&self, lba: u32, buf: &[u8]) -> Result<(), MmcError> {
+        let mut attempt = 0;
+        loop {
+            let mut req = self.rw_request(lba, Data::Write(buf))?;
+            let result = match self.execute_async(&mut req).await {
+                Ok(_) => {
+                    let timeout = self.card.lock().write_timeout_us();
+                    self.wait_ready_interruptible_async(timeout).await
+                }
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    attempt += 1;
+                    self.recover_async(err, attempt).await?;
+                }
+            }
+        }
+    }
+
+    /// Wait until every completed write is durable, see `flush_cache`.
+    ///
+    /// The wait can be cut short with `interrupt_current_operation` like `write`.
+    pub async fn flush(&self) -> Result<(), MmcError> {
+        let (cache, timeout) = {
+            let card = self.card.lock();
+            (card.cache_enabled(), card.write_timeout_us())
+        };
+        if !cache {
+            self.check_card()?;
+            return self.wait_ready_interruptible_async(timeout).await;
+        }
+        let arg = switch_arg(EXT_CSD_FLUSH_CACHE, EXT_CSD_FLUSH);
+        let mut req = Request::new(MMC_SWITCH, 0, MMC_RESP_R1B, arg).with_busy_timeout(CACHE_FLUSH_TIMEOUT_US);
+        self.execute_async(&mut req).await?;
+        self.wait_ready_interruptible_async(CACHE_FLUSH_TIMEOUT_US).await
+    }
+
+    /// Take the first step of `recover` for the `attempt`th consecutive failure of a request,
+    /// returning `err` once it is exhausted.
+    async fn recover_async(&self, err: MmcError, attempt: u32) -> Result<(), MmcError> {
+        if !err.is_transient() {
+            return Err(err);
+        }
+        warn!("emmc request failed: {:?}, recovery attempt {}", err, attempt);
+        // A drifted sampling point shows as CRC errors.
+        if matches!(err, MmcError::CmdCrc | MmcError::DataCrc | MmcError::Tuning) {
+            self.request_retune();
+        }
+        if attempt > RECOVERY_RETRIES {
+            return Err(err);
+        }
+        if self.recovery_reset_lines() {
+            self.abort_async().await;
+            let deadline = Deadline::after_us(RECOVERY_STATUS_POLLS as u64 * 1000);
+            loop {
+                match self.recovery_state(self.run_async(&mut self.status_request()).await) {
+                    RecoveryState::Ready => break,
+                    RecoveryState::Abort => self.abort_async().await,
+                    RecoveryState::Wait => {}
+                }
+                if deadline.expired() {
+                    warn!("emmc recovery: card did not return to the transfer state");
+                    break;
+                }
+            }
+        }
+        self.recovery.lock().retries += 1;
+        Ok(())
+    }
+
+    async fn abort_async(&self) {
+        self.note_abort();
+        // The device rejects CMD12 if no transfer was in progress, the lines are then reset.
+        let mut req = Request::new(MMC_STOP_TRANSMISSION, EMMC_CMD_TYPE_ABORT, MMC_RESP_R1B, 0);
+        if let Err(err) = self.run_async(&mut req).await {
+            debug!("emmc recovery: CMD12 failed: {:?}", err);
+        }
+    }
+
+    /// Poll CMD13 until the device is back in the transfer state, see `wait_ready`.
+    async fn wait_ready_async(&self, timeout_us: u64) -> Result<(), MmcError> {
+        let deadline = Deadline::after_us(timeout_us);
+        loop {
+            let status = self.run_async(&mut self.status_request()).await?;
+            if status_is_ready(status) {
+                return Ok(());
+            }
+            if deadline.expired() {
+                return Err(MmcError::Timeout("transfer state (CMD13)"));
+            }
+        }
+    }
+
+    /// Poll CMD13 as `wait_ready_interruptible`, sending HPI and failing with
+    /// `MmcError::Interrupted` once `interrupt_current_operation` asks for it.
+    async fn wait_ready_interruptible_async(&self, timeout_us: u64) -> Result<(), MmcError> {
+        self.hpi_request.store(false, Ordering::Release);
+        let result = {
+            let _long_op = LongOp::start(self);
+            let deadline = Deadline::after_us(timeout_us);
+            loop {
+                let status = match self.run_async(&mut self.status_request()).await {
+                    Ok(status) => status,
+                    Err(err) => break Err(err),
+                };
+                if status_is_ready(status) {
+                    break Ok(());
+                }
+                if self.hpi_request.swap(false, Ordering::AcqRel) {
+                    break Err(MmcError::Interrupted);
+                }
+                if deadline.expired() {
+                    break Err(MmcError::Timeout("transfer state (CMD13)"));
+                }
+            }
+        };
+
+        if result == Err(MmcError::Interrupted) {
+            warn!("operation interrupted with HPI");
+            let (mut req, timeout) = self.hpi_request();
+            self.run_async(&mut req).await?;
+            self.wait_ready_async(timeout).await?;
+        }
+        result
+    }
+
+    /// Run a request after re-tuning and ending background operations if needed, as `execute`.
+    async fn execute_async(&self, req: &mut Request<'_>) -> Result<u32, MmcError> {
+        self.check_legacy()?;
+        self.check_card()?;
+        if let Some(opcode) = self.retune_opcode() {
+            self.execute_tuning_async(opcode).await?;
+        }
+        if self.bkops_in_the_way(req.idx) {
+            self.end_bkops_async().await?;
+        }
+        self.run_async(req).await
+    }
+
+    /// Run a request, yielding to the executor instead of calling the idle function.
+    ///
+    /// The deadline of the request is checked whenever the future is polled, so in interrupt
+    /// mode a controller that never raises its interrupt is only noticed on the next wake up.
+    async fn run_async(&self, req: &mut Request<'_>) -> Result<u32, MmcError> {
+        let mut inflight = self.claim_async().await;
+
+        let deadline = Deadline::after_us(self.request_timeout_us(req));
+        let result = match self.issue(req) {
+            Ok(()) => poll_fn(|cx| match self.poll_request_async(req, cx) {
+                Poll::Pending if deadline.expired() => Poll::Ready(Err(MmcError::Timeout("request completion"))),
+                poll => poll,
+            })
+            .await,
+            Err(err) => Err(err),
+        };
+
+        inflight.finished = result.is_ok();
+        result
+    }
+
+    /// Claim the controller, waiting to be woken when another request releases it.
+    async fn claim_async(&self) -> InFlight<'_, M> {
+        poll_fn(|cx| {
+            if let Some(inflight) = self.try_claim() {
+                return Poll::Ready(inflight);
+            }
+            {
+                let mut wakers = self.claim_wakers.lock();
+                if !wakers.iter().flatten().any(|waker| waker.will_wake(cx.waker())) {
+                    match wakers.iter_mut().find(|waker| waker.is_none()) {
+                        Some(slot) => *slot = Some(cx.waker().clone()),
+                        None => cx.waker().wake_by_ref(),
+                    }
+                }
+            }
+            // The controller may have been released before the waker was stored.
+            match self.try_claim() {
+                Some(inflight) => Poll::Ready(inflight),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wake the async requests waiting for the controller, once it is released.
+    pub(crate) fn wake_claim_waiters(&self) {
+        let wakers = core::mem::take(&mut *self.claim_wakers.lock());
+        for waker in wakers.into_iter().flatten() {
+            waker.wake();
+        }
+    }
+
+    /// Run the tuning procedure with `opcode` as `execute_tuning`, awaiting each tuning block.
+    async fn execute_tuning_async(&self, opcode: u16) -> Result<(), MmcError> {
+        let mut inflight = self.claim_async().await;
+
+        self.reg.emmc_start_tuning();
+        let deadline = Deadline::after_us(TUNING_TIMEOUT_US);
+        let mut loops = 0;
+        let result = loop {
+            if let Some(result) = self.tuning_result(loops, &deadline) {
+                break result;
+            }
+            loops += 1;
+            if let Err(err) = self.issue_tuning_block(opcode) {
+                break Err(err);
+            }
+            let block_deadline = Deadline::after_us(CMD_TIMEOUT_US);
+            let block = poll_fn(|cx| self.poll_int_async("tuning block", EMMC_BUF_RD_READY, &block_deadline, cx));
+            if let Err(err) = block.await {
+                break Err(err);
+            }
+        };
+
+        inflight.finished = result.is_ok();
+        self.tuning_done(opcode, loops, result)
+    }
+
+    /// End the background operations that may still run, as `preempt_bkops` but awaiting
+    /// the device.
+    async fn end_bkops_async(&self) -> Result<(), MmcError> {
+        if status_is_ready(self.run_async(&mut self.status_request()).await?) {
+            return Ok(());
+        }
+        if !self.card.lock().hpi_enabled() {
+            return self.wait_ready_async(BKOPS_TIMEOUT_US).await;
+        }
+        info!("background operations interrupted with HPI");
+        let (mut req, timeout) = self.hpi_request();
+        self.run_async(&mut req).await?;
+        self.wait_ready_async(timeout).await
+    }
+
+    fn poll_request_async(&self, req: &mut Request<'_>, cx: &mut Context<'_>) -> Poll<Result<u32, MmcError>> {
+        if let Poll::Ready(result) = self.poll_request(req) {
+            return Poll::Ready(result);
+        }
+        if !self.register_waker(cx) {
+            return Poll::Pending;
+        }
+        // The interrupt may have been handled before the waker was stored.
+        self.poll_request(req)
+    }
+
+    /// Wait for one of the normal interrupt status bits of `mask`, or for an error, as `wait_int`.
+    fn poll_int_async(&self, what: &'static str, mask: u16, deadline: &Deadline, cx: &mut Context<'_>) -> Poll<Result<(), MmcError>> {
+        let poll = || {
+            if !self.irq_enabled() {
+                self.latch_int();
+            }
+            if let Some(err) = self.take_error() {
+                return Poll::Ready(Err(err));
+            }
+            if self.take_int(mask) != 0 {
+                return Poll::Ready(Ok(()));
+            }
+            Poll::Pending
+        };
+        if let Poll::Ready(result) = poll() {
+            return Poll::Ready(result);
+        }
+        if deadline.expired() {
+            return Poll::Ready(Err(MmcError::Timeout(what)));
+        }
+        if !self.register_waker(cx) {
+            return Poll::Pending;
+        }
+        poll()
+    }
+
+    /// Have `handle_irq` wake the task of `cx`, or wake it right away in polling mode.
+    ///
+    /// Returns whether the waker was stored, after which the caller must poll once more.
+    fn register_waker(&self, cx: &mut Context<'_>) -> bool {
+        if !self.irq_enabled() {
+            cx.waker().wake_by_ref();
+            return false;
+        }
+        let mut waker = self.waker.lock();
+        if !waker.as_ref().is_some_and(|waker| waker.will_wake(cx.waker())) {
+            *waker = Some(cx.waker().clone());
+        }
+        true
+    }
+}
+
+/// A long operation `interrupt_current_operation` may interrupt is awaited, until dropped, also
+/// when the future awaiting it is cancelled.
+struct LongOp<'a, M: Mmio>(&'a SDHCI<M>);
+
+impl<'a, M: Mmio> LongOp<'a, M> {
+    fn start(sdhci: &'a SDHCI<M>) -> Self {
+        sdhci.long_op.store(true, Ordering::Release);
+        Self(sdhci)
+    }
+}
+
+impl<M: Mmio> Drop for LongOp<'_, M> {
+    fn drop(&mut self) {
+        self.0.long_op.store(false, Ordering::Release);
+    }
+}
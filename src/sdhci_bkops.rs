@@ -0,0 +1,201 @@
+use core::sync::atomic::Ordering;
+
+use log::{info, warn};
+
+use crate::sdhci::{Card, CardType, SDHCI, status_is_ready, switch_arg};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_resp_type::MMC_RESP_R1;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_wp::Irreversible;
+
+/// Background operations taking longer are waited for no more, the EXT_CSD gives no timeout for them.
+pub const BKOPS_TIMEOUT_US: u64 = 120_000_000;
+
+/// Urgency of the background operations the device has pending, BKOPS_STATUS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BkopsStatus {
+    None,
+    NonCritical,
+    PerformanceImpacted,
+    Critical,
+}
+
+impl Card {
+    /// Whether the device has background operations, from BKOPS_SUPPORT.
+    pub fn bkops_supported(&self) -> bool {
+        self.card_type == CardType::Mmc && self.ext_csd.byte(EXT_CSD_BKOPS_SUPPORT) & 0x01 != 0
+    }
+
+    /// The host may start background operations with BKOPS_START.
+    pub fn manual_bkops_enabled(&self) -> bool {
+        self.bkops_supported() && self.ext_csd.byte(EXT_CSD_BKOPS_EN) & EXT_CSD_MANUAL_BKOPS_EN != 0
+    }
+
+    /// The device runs background operations by itself when idle, from eMMC 5.1.
+    pub fn auto_bkops_enabled(&self) -> bool {
+        self.bkops_supported() && self.ext_csd.byte(EXT_CSD_BKOPS_EN) & EXT_CSD_AUTO_BKOPS_EN != 0
+    }
+
+    pub fn bkops_status(&self) -> BkopsStatus {
+        match self.ext_csd.byte(EXT_CSD_BKOPS_STATUS) & 0x03 {
+            0 => BkopsStatus::None,
+            1 => BkopsStatus::NonCritical,
+            2 => BkopsStatus::PerformanceImpacted,
+            _ => BkopsStatus::Critical,
+        }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Enable AUTO_EN of the device in `init` where it is supported, letting it run background
+    /// operations by itself when idle. Off by default, as the setting is kept by the device.
+    pub fn set_auto_bkops(&mut self, enabled: bool) {
+        self.auto_bkops = enabled;
+    }
+
+    /// Enable AUTO_EN as asked with `set_auto_bkops`, once the EXT_CSD is read.
+    ///
+    /// The device is usable without it, so a failure is only logged.
+    pub(crate) fn bkops_init(&self) {
+        self.exception_event.store(false, Ordering::Release);
+        let (supported, bkops_en, rev) = {
+            let card = self.card.lock();
+            (card.bkops_supported(), card.ext_csd.byte(EXT_CSD_BKOPS_EN), card.ext_csd.rev())
+        };
+        if !supported {
+            return;
+        }
+        if self.auto_bkops && rev >= 8 && bkops_en & EXT_CSD_AUTO_BKOPS_EN == 0
+            && let Err(err) = self.mmc_switch(EXT_CSD_BKOPS_EN, bkops_en | EXT_CSD_AUTO_BKOPS_EN)
+        {
+            warn!("enabling automatic background operations failed: {:?}", err);
+        }
+        let card = self.card.lock();
+        info!("background operations, manual: {}, auto: {}", card.manual_bkops_enabled(), card.auto_bkops_enabled());
+    }
+
+    /// Let the host start background operations with `start_bkops`, setting MANUAL_EN of BKOPS_EN.
+    ///
+    /// MANUAL_EN can never be cleared again. The device then counts on the host to start its
+    /// background operations, see `bkops_idle`. It reports urgent ones with the URGENT_BKOPS
+    /// exception event, which has no enable bit in EXCEPTION_EVENTS_CTRL.
+    pub fn enable_manual_bkops(&self, _confirm: Irreversible) -> Result<(), MmcError> {
+        self.check_card()?;
+        let bkops_en = {
+            let card = self.card.lock();
+            if !card.bkops_supported() {
+                return Err(MmcError::Unsupported);
+            }
+            card.ext_csd.byte(EXT_CSD_BKOPS_EN)
+        };
+        self.mmc_switch_verify(EXT_CSD_BKOPS_EN, bkops_en | EXT_CSD_MANUAL_BKOPS_EN)
+    }
+
+    /// Handle background operations while the device is idle, e.g. from the idle loop of the
+    /// integrator between bursts of I/O. Returns whether they were started.
+    ///
+    /// Once an R1 status reported EXCEPTION_EVENT, EXCEPTION_EVENTS_STATUS and BKOPS_STATUS are
+    /// read and the background operations started with `start_bkops` if they impact performance
+    /// and manual BKOPS is enabled. The next command other than CMD13 interrupts them.
+    pub fn bkops_idle(&self) -> Result<bool, MmcError> {
+        self.check_card()?;
+        if self.bkops_running.load(Ordering::Acquire) {
+            if !status_is_ready(self.sdhci_send_status()?) {
+                return Ok(false);
+            }
+            self.bkops_running.store(false, Ordering::Release);
+            info!("background operations done");
+        }
+        if !self.exception_event.swap(false, Ordering::AcqRel) {
+            return Ok(false);
+        }
+
+        let ext_csd = self.with_recovery(|| self.mmc_send_ext_csd())?;
+        let (events, status, manual) = {
+            let mut card = self.card.lock();
+            card.ext_csd = ext_csd;
+            let events = card.ext_csd.byte(EXT_CSD_EXCEPTION_EVENTS_STATUS);
+            (events, card.bkops_status(), card.manual_bkops_enabled())
+        };
+        if events & !EXT_CSD_URGENT_BKOPS != 0 {
+            warn!("exception events {:#x} not handled", events);
+        }
+        if status < BkopsStatus::PerformanceImpacted {
+            return Ok(false);
+        }
+        if !manual {
+            warn!("background operations {:?} but manual BKOPS is disabled", status);
+            return Ok(false);
+        }
+        info!("starting background operations, {:?}", status);
+        self.start_bkops()?;
+        Ok(true)
+    }
+
+    /// Start the pending background operations with BKOPS_START, without waiting for them.
+    ///
+    /// The device is busy until they are done. The next command other than CMD13 interrupts them
+    /// with HPI, or waits for them if the device has no HPI. Fails with `MmcError::Unsupported`
+    /// unless manual BKOPS is enabled, see `enable_manual_bkops`.
+    pub fn start_bkops(&self) -> Result<(), MmcError> {
+        self.check_card()?;
+        if !self.card.lock().manual_bkops_enabled() {
+            return Err(MmcError::Unsupported);
+        }
+        self.hpi_enable()?;
+        // Without busy detection, so other commands can interrupt them.
+        self.sdhci_send_cmd(MMC_SWITCH, 0, MMC_RESP_R1, switch_arg(EXT_CSD_BKOPS_START, 1))?;
+        self.bkops_running.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Before issuing command `idx`, get the device out of the background operations started
+    /// by `start_bkops` if they still run.
+    pub(crate) fn preempt_bkops(&self, idx: u16) -> Result<(), MmcError> {
+        if self.bkops_in_the_way(idx) {
+            self.end_bkops()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the background operations started by `start_bkops` may keep the device from
+    /// taking command `idx`, which must then end them. They are no longer tracked afterwards.
+    pub(crate) fn bkops_in_the_way(&self, idx: u16) -> bool {
+        match idx {
+            // CMD13 polls them.
+            MMC_SEND_STATUS => false,
+            // CMD0 ends them anyway.
+            MMC_GO_IDLE_STATE => {
+                self.bkops_running.store(false, Ordering::Release);
+                false
+            }
+            _ => self.bkops_running.swap(false, Ordering::AcqRel),
+        }
+    }
+
+    /// Get the device out of the background operations started by `start_bkops`. Returns
+    /// whether they were still running.
+    pub(crate) fn interrupt_bkops(&self) -> Result<bool, MmcError> {
+        if !self.bkops_running.swap(false, Ordering::AcqRel) {
+            return Ok(false);
+        }
+        self.end_bkops()
+    }
+
+    /// End the background operations that may still run, with HPI if enabled or by waiting
+    /// for them. Returns whether they were still running.
+    fn end_bkops(&self) -> Result<bool, MmcError> {
+        if status_is_ready(self.sdhci_send_status()?) {
+            return Ok(false);
+        }
+        if self.card.lock().hpi_enabled() {
+            info!("background operations interrupted with HPI");
+            self.send_hpi()?;
+        } else {
+            self.wait_ready(BKOPS_TIMEOUT_US)?;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,236 @@
+use log::info;
+
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, Data, HostKind, Request, SDHCI};
+use crate::sdhci_cmd::mmc_cmd_idx::{MMC_BOOT_INITIATION_ARG, MMC_GO_IDLE_STATE};
+use crate::sdhci_cmd::mmc_resp_type::MMC_RESP_NONE;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_boot_ctrl_bits::{EMMC_BOOT_ACK_ENABLE, EMMC_BOOT_TOUT_CNT_MAX};
+use crate::sdhci_wp::Irreversible;
+
+/// Partition the device boots from, BOOT_PARTITION_ENABLE of PARTITION_CONFIG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootPartition {
+    /// Booting is disabled.
+    Disabled,
+    Boot1,
+    Boot2,
+    /// The user data area.
+    User,
+}
+
+impl BootPartition {
+    fn bits(self) -> u8 {
+        match self {
+            BootPartition::Disabled => 0,
+            BootPartition::Boot1 => 1,
+            BootPartition::Boot2 => 2,
+            BootPartition::User => 7,
+        }
+    }
+}
+
+/// Data bus width of the boot operation, BOOT_BUS_WIDTH of BOOT_BUS_CONDITIONS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootBusWidth {
+    X1,
+    X4,
+    X8,
+}
+
+/// Timing of the boot operation, BOOT_MODE of BOOT_BUS_CONDITIONS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootMode {
+    /// Single data rate with the backward compatible timing.
+    SdrCompat,
+    /// Single data rate with the high speed timing.
+    SdrHighSpeed,
+    /// Dual data rate.
+    Ddr,
+}
+
+/// What the device does in the boot operation, from PARTITION_CONFIG and BOOT_BUS_CONDITIONS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootConfig {
+    pub partition: BootPartition,
+    /// Send the boot acknowledge pattern before the boot data.
+    pub ack: bool,
+    pub bus_width: BootBusWidth,
+    pub mode: BootMode,
+    /// Keep the boot bus width and mode after the boot operation instead of going back to 1-bit
+    /// backward compatible timing.
+    pub retain_bus: bool,
+}
+
+impl BootConfig {
+    /// PARTITION_CONFIG with the boot fields of the configuration and the partition access `config` has.
+    fn partition_config(&self, config: u8) -> u8 {
+        let ack = if self.ack { EXT_CSD_BOOT_ACK } else { 0 };
+        ack | self.partition.bits() << 3 | config & 0x07
+    }
+
+    fn bus_conditions(&self) -> u8 {
+        let width = match self.bus_width {
+            BootBusWidth::X1 => 0,
+            BootBusWidth::X4 => 1,
+            BootBusWidth::X8 => 2,
+        };
+        let mode = match self.mode {
+            BootMode::SdrCompat => 0,
+            BootMode::SdrHighSpeed => 1,
+            BootMode::Ddr => 2,
+        };
+        let retain = if self.retain_bus { EXT_CSD_BOOT_BUS_RETAIN } else { 0 };
+        mode << 3 | retain | width
+    }
+}
+
+/// How long the boot configuration stays locked, BOOT_CONFIG_PROT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootLock {
+    /// Until the device is power cycled.
+    UntilPowerCycle,
+    /// For good, it can never be changed again.
+    Permanent(Irreversible),
+}
+
+/// Lock of the boot configuration, as reported by the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootLockStatus {
+    Unlocked,
+    UntilPowerCycle,
+    Permanent,
+}
+
+/// How `SDHCI::boot_read` starts the boot operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootMethod {
+    /// The alternative boot operation, CMD0 with the argument 0xFFFFFFFA.
+    Alternative,
+    /// Holding the CMD line low, through BOOT_CTRL of the DWC MSHC.
+    CmdLow,
+}
+
+impl Card {
+    /// The boot configuration, `None` for an SD card or a value not defined by the standard.
+    pub fn boot_config(&self) -> Option<BootConfig> {
+        if self.card_type != CardType::Mmc {
+            return None;
+        }
+        let config = self.ext_csd.byte(EXT_CSD_PARTITION_CONFIG);
+        let conditions = self.ext_csd.byte(EXT_CSD_BOOT_BUS_CONDITIONS);
+        Some(BootConfig {
+            partition: match config >> 3 & 0x07 {
+                0 => BootPartition::Disabled,
+                1 => BootPartition::Boot1,
+                2 => BootPartition::Boot2,
+                7 => BootPartition::User,
+                _ => return None,
+            },
+            ack: config & EXT_CSD_BOOT_ACK != 0,
+            bus_width: match conditions & 0x03 {
+                0 => BootBusWidth::X1,
+                1 => BootBusWidth::X4,
+                2 => BootBusWidth::X8,
+                _ => return None,
+            },
+            mode: match conditions >> 3 & 0x03 {
+                0 => BootMode::SdrCompat,
+                1 => BootMode::SdrHighSpeed,
+                2 => BootMode::Ddr,
+                _ => return None,
+            },
+            retain_bus: conditions & EXT_CSD_BOOT_BUS_RETAIN != 0,
+        })
+    }
+
+    /// The lock of the boot configuration.
+    pub fn boot_lock(&self) -> BootLockStatus {
+        let prot = self.ext_csd.byte(EXT_CSD_BOOT_CONFIG_PROT);
+        if prot & EXT_CSD_PERM_BOOT_CONFIG_PROT != 0 {
+            BootLockStatus::Permanent
+        } else if prot & EXT_CSD_PWR_BOOT_CONFIG_PROT != 0 {
+            BootLockStatus::UntilPowerCycle
+        } else {
+            BootLockStatus::Unlocked
+        }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Write the boot configuration to BOOT_BUS_CONDITIONS and PARTITION_CONFIG, keeping the
+    /// partition currently accessed, and read each back.
+    ///
+    /// Fails with `MmcError::VerifyFailed` if the device did not take it, e.g. because the
+    /// configuration is locked.
+    pub fn set_boot_config(&self, boot: &BootConfig) -> Result<(), MmcError> {
+        let (card_type, config) = {
+            let card = self.card.lock();
+            (card.card_type, card.ext_csd.byte(EXT_CSD_PARTITION_CONFIG))
+        };
+        if card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        }
+        self.mmc_switch_verify(EXT_CSD_BOOT_BUS_CONDITIONS, boot.bus_conditions())?;
+        self.mmc_switch_verify(EXT_CSD_PARTITION_CONFIG, boot.partition_config(config))?;
+        info!("boot configuration set to {:?}", boot);
+        Ok(())
+    }
+
+    /// Lock the boot configuration with BOOT_CONFIG_PROT.
+    ///
+    /// A `BootLock::Permanent` lock cannot be undone, not even by the vendor, so it takes an
+    /// `Irreversible` confirmation.
+    pub fn lock_boot_config(&self, lock: BootLock) -> Result<(), MmcError> {
+        let (card_type, prot) = {
+            let card = self.card.lock();
+            (card.card_type, card.ext_csd.byte(EXT_CSD_BOOT_CONFIG_PROT))
+        };
+        if card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        }
+        let bit = match lock {
+            BootLock::UntilPowerCycle => EXT_CSD_PWR_BOOT_CONFIG_PROT,
+            BootLock::Permanent(_) => EXT_CSD_PERM_BOOT_CONFIG_PROT,
+        };
+        self.mmc_switch_verify(EXT_CSD_BOOT_CONFIG_PROT, prot | bit)?;
+        info!("boot configuration locked: {:?}", lock);
+        Ok(())
+    }
+
+    /// Read the first `buf.len() / BLOCK_SIZE` blocks of the boot partition enabled in
+    /// PARTITION_CONFIG with a boot operation, without identifying the device.
+    ///
+    /// The bus is 1-bit wide with the backward compatible timing, as BOOT_BUS_CONDITIONS are
+    /// after a reset. With `ack` the boot acknowledge pattern is checked, failing with
+    /// `MmcError::BootAck` if the device does not send it, which needs the BOOT_CTRL register
+    /// of the DWC MSHC as does `BootMethod::CmdLow`. The device is left in the idle state with
+    /// CMD0, `init` must run before any other access.
+    pub fn boot_read(&self, method: BootMethod, ack: bool, buf: &mut [u8]) -> Result<(), MmcError> {
+        let dwcmshc = self.host_kind() == HostKind::Dwcmshc;
+        if !dwcmshc && (ack || method == BootMethod::CmdLow) {
+            return Err(MmcError::Unsupported);
+        }
+        let blocks = buf.len() / BLOCK_SIZE;
+        if blocks == 0 || !buf.len().is_multiple_of(BLOCK_SIZE) || blocks > u16::MAX as usize {
+            return Err(MmcError::InvalidArgument);
+        }
+
+        self.init_host()?;
+        if dwcmshc {
+            self.reg.emmc_enable_card_is_emmc();
+            self.reg.emmc_set_boot_ctrl(if ack { EMMC_BOOT_ACK_ENABLE | EMMC_BOOT_TOUT_CNT_MAX } else { 0 });
+        }
+        let mut req = Request::new(MMC_GO_IDLE_STATE, 0, MMC_RESP_NONE, MMC_BOOT_INITIATION_ARG).with_data(Data::Read(buf));
+        req.boot = method == BootMethod::CmdLow;
+        let result = self.execute(&mut req);
+
+        if method == BootMethod::CmdLow {
+            self.reg.emmc_stop_mandatory_boot();
+        }
+        let reset = self.sdhci_send_cmd(MMC_GO_IDLE_STATE, 0, MMC_RESP_NONE, 0);
+        info!("boot operation read {} blocks: {:?}", blocks, result);
+        result.and(reset).map(|_| ())
+    }
+}
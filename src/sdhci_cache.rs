@@ -0,0 +1,111 @@
+use log::info;
+
+use crate::sdhci::{Card, CardType, SDHCI};
+use crate::sdhci_cmd::mmc_cmd_idx::MMC_CMD23_FORCED_PRG;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+/// Writing the volatile cache back, the EXT_CSD gives no timeout for it.
+pub const CACHE_FLUSH_TIMEOUT_US: u64 = 30_000_000;
+
+impl Card {
+    /// Size of the volatile cache from CACHE_SIZE, 0 without one.
+    pub fn cache_size(&self) -> u32 {
+        if self.card_type == CardType::Mmc { self.ext_csd.u32(EXT_CSD_CACHE_SIZE) } else { 0 }
+    }
+
+    /// The volatile cache is enabled: written data is only durable after a flush.
+    pub fn cache_enabled(&self) -> bool {
+        self.ext_csd.byte(EXT_CSD_CACHE_CTRL) & 0x01 != 0
+    }
+
+    /// Cache barriers are enabled with BARRIER_CTRL.
+    pub fn barrier_enabled(&self) -> bool {
+        self.ext_csd.byte(EXT_CSD_BARRIER_CTRL) & 0x01 != 0
+    }
+
+    /// CMD23 accepts the forced programming bit, from eMMC 4.5.
+    pub fn supports_forced_programming(&self) -> bool {
+        self.card_type == CardType::Mmc && self.ext_csd.rev() >= 6
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Enable the volatile cache of the device and, where supported, cache barriers in `init`.
+    ///
+    /// Off by default: with the cache enabled `write_blocks` returns before the data is
+    /// durable, and `flush_cache` or `write_blocks_fua` must be used where that matters.
+    pub fn set_cache(&mut self, enabled: bool) {
+        self.use_cache = enabled;
+    }
+
+    /// Enable the cache and the barriers as asked with `set_cache`, once the EXT_CSD is read.
+    pub(crate) fn cache_init(&self) -> Result<(), MmcError> {
+        let (size, barrier) = {
+            let card = self.card.lock();
+            (card.cache_size(), card.ext_csd.byte(EXT_CSD_BARRIER_SUPPORT) & 0x01 != 0)
+        };
+        if !self.use_cache || size == 0 {
+            return Ok(());
+        }
+        self.mmc_switch(EXT_CSD_CACHE_CTRL, 1)?;
+        if barrier {
+            self.mmc_switch(EXT_CSD_BARRIER_CTRL, 1)?;
+        }
+        info!("volatile cache of size {} enabled, barriers: {}", size, barrier);
+        Ok(())
+    }
+
+    /// Wait until every completed write is durable.
+    ///
+    /// With the volatile cache enabled its content is written back with FLUSH_CACHE. Without
+    /// it this is the case once the device has left the programming state, which is polled
+    /// with CMD13.
+    pub fn flush_cache(&self) -> Result<(), MmcError> {
+        let (cache, timeout) = {
+            let card = self.card.lock();
+            (card.cache_enabled(), card.write_timeout_us())
+        };
+        if cache {
+            self.switch_busy(EXT_CSD_FLUSH_CACHE, EXT_CSD_FLUSH, CACHE_FLUSH_TIMEOUT_US)
+        } else {
+            self.wait_ready(timeout)
+        }
+    }
+
+    /// Make the device write the data cached so far back before any data written afterwards,
+    /// without waiting for it to be durable, e.g. between a journal and its commit block.
+    ///
+    /// Flushes the cache if barriers are not enabled.
+    pub fn cache_barrier(&self) -> Result<(), MmcError> {
+        let (cache, barrier) = {
+            let card = self.card.lock();
+            (card.cache_enabled(), card.barrier_enabled())
+        };
+        match (cache, barrier) {
+            (true, true) => self.switch_busy(EXT_CSD_FLUSH_CACHE, EXT_CSD_BARRIER, CACHE_FLUSH_TIMEOUT_US),
+            _ => self.flush_cache(),
+        }
+    }
+
+    /// Write `buf.len() / BLOCK_SIZE` blocks starting at `lba` and return once they are
+    /// durable, as a forced unit access write.
+    ///
+    /// With the volatile cache enabled the data bypasses it with the forced programming bit of
+    /// CMD23, or the cache is flushed after the write on devices older than eMMC 4.5.
+    pub fn write_blocks_fua(&self, lba: u32, buf: &[u8]) -> Result<(), MmcError> {
+        let (cache, forced) = {
+            let card = self.card.lock();
+            (card.cache_enabled(), card.supports_forced_programming())
+        };
+        match (cache, forced) {
+            (false, _) => self.write_blocks(lba, buf),
+            (true, true) => self.with_recovery(|| self.write_blocks_sbc(lba, buf, MMC_CMD23_FORCED_PRG)),
+            (true, false) => {
+                self.write_blocks(lba, buf)?;
+                self.flush_cache()
+            }
+        }
+    }
+}
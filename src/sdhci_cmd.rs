@@ -15,3 +15,199 @@ impl Cmd {
     }
 
 }
+
+/// Command indexes used by the driver, as defined by the JEDEC eMMC specification.
+pub mod mmc_cmd_idx {
+    pub const MMC_GO_IDLE_STATE: u16 = 0;
+    pub const MMC_SEND_OP_COND: u16 = 1;
+    pub const MMC_ALL_SEND_CID: u16 = 2;
+    pub const MMC_SET_RELATIVE_ADDR: u16 = 3;
+    pub const MMC_SWITCH: u16 = 6;
+    pub const MMC_SELECT_CARD: u16 = 7;
+    pub const MMC_SEND_EXT_CSD: u16 = 8;
+    pub const MMC_SEND_CSD: u16 = 9;
+    pub const MMC_STOP_TRANSMISSION: u16 = 12;
+    pub const MMC_SEND_STATUS: u16 = 13;
+    pub const MMC_SET_BLOCKLEN: u16 = 16;
+    pub const MMC_READ_SINGLE_BLOCK: u16 = 17;
+    pub const MMC_READ_MULTIPLE_BLOCK: u16 = 18;
+    pub const MMC_SET_BLOCK_COUNT: u16 = 23;
+    pub const MMC_WRITE_BLOCK: u16 = 24;
+    pub const MMC_WRITE_MULTIPLE_BLOCK: u16 = 25;
+    pub const MMC_SET_WRITE_PROT: u16 = 28;
+    pub const MMC_CLR_WRITE_PROT: u16 = 29;
+    pub const MMC_SEND_WRITE_PROT: u16 = 30;
+    pub const MMC_SEND_WRITE_PROT_TYPE: u16 = 31;
+    pub const MMC_ERASE_GROUP_START: u16 = 35;
+    pub const MMC_ERASE_GROUP_END: u16 = 36;
+    pub const MMC_ERASE: u16 = 38;
+    pub const MMC_CMDQ_TASK_MGMT: u16 = 48;
+    pub const MMC_GEN_CMD: u16 = 56;
+
+    /// CMD6 access mode setting the bits of the value byte in the EXT_CSD field.
+    pub const MMC_SWITCH_MODE_SET_BITS: u32 = 0x01;
+    /// CMD6 access mode clearing the bits of the value byte in the EXT_CSD field.
+    pub const MMC_SWITCH_MODE_CLEAR_BITS: u32 = 0x02;
+    /// CMD6 access mode writing the value byte to the EXT_CSD field.
+    pub const MMC_SWITCH_MODE_WRITE_BYTE: u32 = 0x03;
+    /// CMD48 TM op-code discarding every task queued in the device.
+    pub const MMC_CMDQ_DISCARD_QUEUE: u32 = 0x01;
+    /// CMD23 argument bit making the following CMD25 a reliable write.
+    pub const MMC_CMD23_REL_WRITE: u32 = 1 << 31;
+    /// CMD23 argument bit programming the data of the following CMD25 past the volatile cache.
+    pub const MMC_CMD23_FORCED_PRG: u32 = 1 << 24;
+    /// CMD12 and CMD13 argument bit turning the command into a High Priority Interrupt.
+    pub const MMC_HPI_BIT: u32 = 0x01;
+    /// CMD56 argument bit reading the data block from the device instead of writing it.
+    pub const MMC_GEN_CMD_RD: u32 = 0x01;
+    /// CMD0 argument starting the alternative boot operation.
+    pub const MMC_BOOT_INITIATION_ARG: u32 = 0xffff_fffa;
+
+    /// CMD38 argument erasing the erase groups of the range.
+    pub const MMC_ERASE_ARG: u32 = 0x0000_0000;
+    /// CMD38 argument erasing only the write blocks of the range.
+    pub const MMC_TRIM_ARG: u32 = 0x0000_0001;
+    /// CMD38 argument marking the write blocks of the range as unused.
+    pub const MMC_DISCARD_ARG: u32 = 0x0000_0003;
+    /// CMD38 argument of a secure erase, also purging every copy of the erased data.
+    pub const MMC_SECURE_ERASE_ARG: u32 = 0x8000_0000;
+    /// CMD38 arguments of the two steps of a secure trim: the first one marks the write
+    /// blocks, the second one purges every block marked so far.
+    pub const MMC_SECURE_TRIM1_ARG: u32 = 0x8000_0001;
+    pub const MMC_SECURE_TRIM2_ARG: u32 = 0x8000_8000;
+}
+
+/// Command indexes of the SD Physical Layer specification that differ from the eMMC ones.
+///
+/// Application specific commands (ACMD) must be preceded by `SD_APP_CMD`.
+pub mod sd_cmd_idx {
+    pub const SD_SEND_RELATIVE_ADDR: u16 = 3;
+    pub const SD_SWITCH_FUNC: u16 = 6;
+    pub const SD_SEND_IF_COND: u16 = 8;
+    pub const SD_VOLTAGE_SWITCH: u16 = 11;
+    pub const SD_SEND_TUNING_BLOCK: u16 = 19;
+    pub const SD_ERASE_WR_BLK_START: u16 = 32;
+    pub const SD_ERASE_WR_BLK_END: u16 = 33;
+    pub const SD_APP_CMD: u16 = 55;
+    pub const SD_APP_SET_BUS_WIDTH: u16 = 6;
+    pub const SD_APP_SD_STATUS: u16 = 13;
+    pub const SD_APP_SEND_OP_COND: u16 = 41;
+    pub const SD_APP_SEND_SCR: u16 = 51;
+
+    /// CMD8 argument: 2.7V - 3.6V supplied, check pattern 0xaa echoed back in the R7 response.
+    pub const SD_IF_COND_VHS_27_36: u32 = 0x01 << 8;
+    pub const SD_IF_COND_CHECK_PATTERN: u32 = 0xaa;
+
+    /// ACMD6 argument for the 1-bit bus.
+    pub const SD_BUS_WIDTH_1: u32 = 0x00;
+    /// ACMD6 argument for the 4-bit bus.
+    pub const SD_BUS_WIDTH_4: u32 = 0x02;
+
+    /// CMD6 mode switching to the functions of the argument, instead of checking them.
+    pub const SD_SWITCH_MODE_SWITCH: u32 = 0x01 << 31;
+    /// CMD6 function group 1, the access mode (bus speed).
+    pub const SD_SWITCH_GROUP_ACCESS_MODE: u8 = 1;
+    pub const SD_SWITCH_ACCESS_MODE_DEFAULT: u8 = 0;
+    pub const SD_SWITCH_ACCESS_MODE_HS: u8 = 1;
+    /// UHS-I access modes, selectable once the signaling voltage is 1.8V. SDR12 and SDR25 share
+    /// the function numbers of default speed and high speed.
+    pub const SD_SWITCH_ACCESS_MODE_SDR12: u8 = 0;
+    pub const SD_SWITCH_ACCESS_MODE_SDR25: u8 = 1;
+    pub const SD_SWITCH_ACCESS_MODE_SDR50: u8 = 2;
+    pub const SD_SWITCH_ACCESS_MODE_SDR104: u8 = 3;
+    pub const SD_SWITCH_ACCESS_MODE_DDR50: u8 = 4;
+
+    /// CMD38 argument erasing the write blocks of the range.
+    pub const SD_ERASE_ARG: u32 = 0x0000_0000;
+    /// CMD38 argument marking the write blocks of the range as unused, from SD 5.0.
+    pub const SD_DISCARD_ARG: u32 = 0x0000_0001;
+
+    /// Size of the tuning block sent with CMD19 on a 4-bit bus.
+    pub const SD_TUNING_BLOCK_SIZE: usize = 64;
+}
+
+/// Response formats, expressed as the `EMMC_CMD` bits the controller needs for each of them.
+pub mod mmc_resp_type {
+    use crate::sdhci_reg::emmc_cmd_bits::*;
+
+    pub const MMC_RESP_NONE: u16 = EMMC_RESP_TYPE_NONE;
+    pub const MMC_RESP_R1: u16 = EMMC_RESP_TYPE_LEN_48 | EMMC_CMD_CRC_CHK | EMMC_CMD_IDX_CHK;
+    pub const MMC_RESP_R1B: u16 = EMMC_RESP_TYPE_LEN_48_CHECK | EMMC_CMD_CRC_CHK | EMMC_CMD_IDX_CHK;
+    pub const MMC_RESP_R2: u16 = EMMC_RESP_TYPE_LEN_136 | EMMC_CMD_CRC_CHK;
+    pub const MMC_RESP_R3: u16 = EMMC_RESP_TYPE_LEN_48;
+    /// Published RCA response of SD CMD3.
+    pub const MMC_RESP_R6: u16 = EMMC_RESP_TYPE_LEN_48 | EMMC_CMD_CRC_CHK | EMMC_CMD_IDX_CHK;
+    /// Card interface condition response of SD CMD8.
+    pub const MMC_RESP_R7: u16 = EMMC_RESP_TYPE_LEN_48 | EMMC_CMD_CRC_CHK | EMMC_CMD_IDX_CHK;
+}
+
+/// Bits of the card status returned in the R1 response.
+pub mod mmc_r1_bits {
+    pub const MMC_R1_OUT_OF_RANGE: u32 = 1 << 31;
+    pub const MMC_R1_ADDRESS_ERROR: u32 = 1 << 30;
+    pub const MMC_R1_BLOCK_LEN_ERROR: u32 = 1 << 29;
+    pub const MMC_R1_ERASE_SEQ_ERROR: u32 = 1 << 28;
+    pub const MMC_R1_ERASE_PARAM: u32 = 1 << 27;
+    pub const MMC_R1_WP_VIOLATION: u32 = 1 << 26;
+    pub const MMC_R1_CARD_IS_LOCKED: u32 = 1 << 25;
+    pub const MMC_R1_LOCK_UNLOCK_FAILED: u32 = 1 << 24;
+    pub const MMC_R1_COM_CRC_ERROR: u32 = 1 << 23;
+    pub const MMC_R1_ILLEGAL_COMMAND: u32 = 1 << 22;
+    pub const MMC_R1_CARD_ECC_FAILED: u32 = 1 << 21;
+    pub const MMC_R1_CC_ERROR: u32 = 1 << 20;
+    pub const MMC_R1_ERROR: u32 = 1 << 19;
+    pub const MMC_R1_CID_CSD_OVERWRITE: u32 = 1 << 16;
+    pub const MMC_R1_WP_ERASE_SKIP: u32 = 1 << 15;
+    pub const MMC_R1_ERASE_RESET: u32 = 1 << 13;
+    pub const MMC_R1_CURRENT_STATE_POS: u32 = 9;
+    pub const MMC_R1_CURRENT_STATE_MASK: u32 = 0x0f << MMC_R1_CURRENT_STATE_POS;
+    pub const MMC_R1_STATE_IDLE: u32 = 0;
+    pub const MMC_R1_STATE_READY: u32 = 1;
+    pub const MMC_R1_STATE_IDENT: u32 = 2;
+    pub const MMC_R1_STATE_STBY: u32 = 3;
+    pub const MMC_R1_STATE_TRAN: u32 = 4;
+    pub const MMC_R1_STATE_DATA: u32 = 5;
+    pub const MMC_R1_STATE_RCV: u32 = 6;
+    pub const MMC_R1_STATE_PRG: u32 = 7;
+    pub const MMC_R1_STATE_DIS: u32 = 8;
+    pub const MMC_R1_READY_FOR_DATA: u32 = 1 << 8;
+    pub const MMC_R1_SWITCH_ERROR: u32 = 1 << 7;
+    pub const MMC_R1_EXCEPTION_EVENT: u32 = 1 << 6;
+    pub const MMC_R1_APP_CMD: u32 = 1 << 5;
+    /// Every bit that reports a failed command.
+    pub const MMC_R1_ERROR_MASK: u32 = MMC_R1_OUT_OF_RANGE
+        | MMC_R1_ADDRESS_ERROR
+        | MMC_R1_BLOCK_LEN_ERROR
+        | MMC_R1_ERASE_SEQ_ERROR
+        | MMC_R1_ERASE_PARAM
+        | MMC_R1_WP_VIOLATION
+        | MMC_R1_LOCK_UNLOCK_FAILED
+        | MMC_R1_COM_CRC_ERROR
+        | MMC_R1_ILLEGAL_COMMAND
+        | MMC_R1_CARD_ECC_FAILED
+        | MMC_R1_CC_ERROR
+        | MMC_R1_ERROR
+        | MMC_R1_CID_CSD_OVERWRITE
+        | MMC_R1_WP_ERASE_SKIP
+        | MMC_R1_SWITCH_ERROR;
+
+    /// Extract the CURRENT_STATE field of the card status.
+    pub const fn mmc_r1_current_state(status: u32) -> u32 {
+        (status & MMC_R1_CURRENT_STATE_MASK) >> MMC_R1_CURRENT_STATE_POS
+    }
+}
+
+/// Bits of the OCR register returned by CMD1 and ACMD41 in the R3 response.
+pub mod mmc_ocr_bits {
+    /// 2.7V - 3.6V
+    pub const MMC_OCR_VDD_27_36: u32 = 0x1ff << 15;
+    /// 1.70V - 1.95V
+    pub const MMC_OCR_VDD_170_195: u32 = 0x01 << 7;
+    pub const MMC_OCR_ACCESS_MODE_SECTOR: u32 = 0x02 << 29;
+    /// Cleared while the card is still powering up.
+    pub const MMC_OCR_BUSY: u32 = 0x01 << 31;
+    /// ACMD41 Host Capacity Support, and Card Capacity Status in the response.
+    pub const SD_OCR_CCS: u32 = 0x01 << 30;
+    /// ACMD41 switching to 1.8V request, and accepted in the response.
+    pub const SD_OCR_S18R: u32 = 0x01 << 24;
+}
@@ -0,0 +1,433 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(target_arch = "aarch64")]
+use aarch64_cpu::asm::barrier;
+use kspin::SpinNoIrq;
+use log::{info, warn};
+
+use crate::sdhci::{BLOCK_SIZE, RESET_TIMEOUT_US, SDHCI};
+use crate::wait_timeout;
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_resp_type::*;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_cmd_bits::EMMC_CMD_TYPE_ABORT;
+use crate::sdhci_reg::emmc_cqcfg_bits::*;
+use crate::sdhci_reg::emmc_cqctl_bits::*;
+use crate::sdhci_reg::emmc_cqis_bits::*;
+use crate::sdhci_reg::emmc_cqterri_bits::*;
+use crate::sdhci_reg::emmc_host_ctrl1_bits::EMMC_DMA_SEL_ADMA2;
+use crate::sdhci_reg::emmc_normal_int_en_bits::EMMC_CQE_EVENT_EN;
+use crate::sdhci_reg::emmc_xfer_mode_bits::EMMC_BLOCK_COUNT_ENABLE;
+
+/// Number of task slots of the command queuing engine.
+pub const CQE_NUM_SLOTS: usize = 32;
+/// Number of transfer descriptors reserved for each task.
+pub const CQE_MAX_SEGS: usize = 16;
+/// Largest data length of one transfer descriptor.
+pub const CQE_SEG_SIZE: usize = 64 * 1024;
+/// Largest number of blocks of one task.
+pub const CQE_MAX_BLOCKS: usize = CQE_MAX_SEGS * CQE_SEG_SIZE / BLOCK_SIZE;
+
+const CQE_TASK_DESC_LEN: usize = 8;
+const CQE_LINK_DESC_LEN: usize = 8;
+const CQE_SLOT_LEN: usize = CQE_TASK_DESC_LEN + CQE_LINK_DESC_LEN;
+const CQE_TRAN_DESC_LEN: usize = 8;
+/// Offset of the transfer descriptors behind the task descriptor list.
+const CQE_TRAN_OFFSET: usize = CQE_NUM_SLOTS * CQE_SLOT_LEN;
+
+/// Halting waits for the task in progress on the bus to finish.
+pub const CQE_HALT_TIMEOUT_US: u64 = 1_000_000;
+
+/// Size of the descriptor memory passed to `SDHCI::cqe_enable`.
+pub const CQE_DESC_MEM_SIZE: usize = CQE_TRAN_OFFSET + CQE_NUM_SLOTS * CQE_MAX_SEGS * CQE_TRAN_DESC_LEN;
+/// Required alignment of the descriptor memory.
+pub const CQE_DESC_MEM_ALIGN: u64 = 1024;
+
+/// Fields of the 64-bit task, link and ADMA2 transfer descriptors.
+pub mod cqe_desc_bits {
+    pub const CQE_DESC_VALID: u64 = 0x01 << 0;
+    pub const CQE_DESC_END: u64 = 0x01 << 1;
+    pub const CQE_DESC_INT: u64 = 0x01 << 2;
+    pub const CQE_DESC_ACT_POS: u64 = 3;
+    pub const CQE_DESC_ACT_TRAN: u64 = 0x04 << CQE_DESC_ACT_POS;
+    pub const CQE_DESC_ACT_TASK: u64 = 0x05 << CQE_DESC_ACT_POS;
+    pub const CQE_DESC_ACT_LINK: u64 = 0x06 << CQE_DESC_ACT_POS;
+    /// Transfer descriptor: data length in bytes, 0 meaning 65536.
+    pub const CQE_DESC_LEN_POS: u64 = 16;
+    /// Transfer and link descriptor: 32-bit physical address.
+    pub const CQE_DESC_ADDR_POS: u64 = 32;
+
+    /// Task descriptor fields.
+    pub const CQE_TASK_FORCED_PROG: u64 = 0x01 << 6;
+    pub const CQE_TASK_CONTEXT_POS: u64 = 7;
+    pub const CQE_TASK_DATA_TAG: u64 = 0x01 << 11;
+    pub const CQE_TASK_DATA_DIR_READ: u64 = 0x01 << 12;
+    pub const CQE_TASK_PRIORITY: u64 = 0x01 << 13;
+    pub const CQE_TASK_QBR: u64 = 0x01 << 14;
+    pub const CQE_TASK_REL_WRITE: u64 = 0x01 << 15;
+    pub const CQE_TASK_BLK_COUNT_POS: u64 = 16;
+    pub const CQE_TASK_BLK_ADDR_POS: u64 = 32;
+}
+
+use cqe_desc_bits::*;
+
+/// Complete the descriptor writes before the engine is pointed at them.
+fn desc_barrier() {
+    #[cfg(target_arch = "aarch64")]
+    barrier::dsb(barrier::SY);
+    // Host builds, e.g. for the tests, only need the writes kept in order.
+    #[cfg(not(target_arch = "aarch64"))]
+    core::sync::atomic::fence(Ordering::SeqCst);
+}
+
+/// A read or write handed to the command queuing engine.
+///
+/// The engine moves the data with ADMA2, so the buffer is given by its physical address
+/// and must be physically contiguous, word aligned and below 4 GiB. Cache maintenance of
+/// the buffer is left to the caller.
+#[derive(Debug, Clone, Copy)]
+pub struct CqeTask {
+    pub lba: u32,
+    /// Number of blocks, at most `CQE_MAX_BLOCKS`.
+    pub blocks: u16,
+    /// Physical address of the data buffer.
+    pub buf_phys: u64,
+    pub read: bool,
+    /// Reliable write.
+    pub reliable: bool,
+    /// Forced programming, the data bypasses the volatile cache.
+    pub forced_prog: bool,
+    /// High priority task.
+    pub priority: bool,
+}
+
+/// Tasks finished since the last call to `SDHCI::cqe_take_completed`, bit `n` standing for tag `n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CqeCompletion {
+    pub done: u32,
+    /// Tasks that failed or were cleared. They may be submitted again.
+    pub failed: u32,
+}
+
+/// Descriptor memory owned by the engine while it is enabled.
+struct CqeMem {
+    virt: &'static mut [u8],
+}
+
+impl CqeMem {
+    fn write_desc(&mut self, offset: usize, desc: u64) {
+        let ptr = self.virt[offset..offset + 8].as_mut_ptr() as *mut u64;
+        unsafe { ptr.write_volatile(desc.to_le()) }
+    }
+}
+
+/// Command queuing engine state kept in `SDHCI`.
+pub(crate) struct Cqe {
+    mem: SpinNoIrq<Option<CqeMem>>,
+    /// Tags usable with the queue depth of the device.
+    slots: AtomicU32,
+    /// Tags submitted and not reported yet.
+    used: AtomicU32,
+    done: AtomicU32,
+    failed: AtomicU32,
+    /// Called from `handle_irq` when tasks completed or failed.
+    notify: fn(),
+}
+
+impl Cqe {
+    pub(crate) const fn new() -> Self {
+        Self {
+            mem: SpinNoIrq::new(None),
+            slots: AtomicU32::new(0),
+            used: AtomicU32::new(0),
+            done: AtomicU32::new(0),
+            failed: AtomicU32::new(0),
+            notify: || {},
+        }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Set the function called from `handle_irq` when queued tasks completed or failed.
+    ///
+    /// It runs in interrupt context and should only wake whoever calls `cqe_take_completed`.
+    pub fn set_cqe_notify(&mut self, notify: fn()) {
+        self.cqe.notify = notify;
+    }
+
+    /// Enable command queuing on the device (CMDQ_MODE_EN) and hand the bus to the engine.
+    ///
+    /// `mem` holds the task descriptor list and the transfer descriptors. It must be at least
+    /// `CQE_DESC_MEM_SIZE` bytes, mapped non-cacheable, and `phys` must be its physical
+    /// address, aligned to `CQE_DESC_MEM_ALIGN` and below 4 GiB.
+    ///
+    /// Legacy commands fail with `MmcError::CqeActive` until the engine is halted or disabled.
+    pub fn cqe_enable(&self, mem: &'static mut [u8], phys: u64) -> Result<(), MmcError> {
+        if mem.len() < CQE_DESC_MEM_SIZE
+            || !phys.is_multiple_of(CQE_DESC_MEM_ALIGN)
+            || phys + CQE_DESC_MEM_SIZE as u64 > 1 << 32
+        {
+            return Err(MmcError::InvalidArgument);
+        }
+        if self.reg.emmc_cqe_is_enabled() {
+            return Err(MmcError::CqeActive);
+        }
+
+        let (rca, support, depth) = {
+            let card = self.card.lock();
+            (card.rca, card.ext_csd.byte(EXT_CSD_CMDQ_SUPPORT) & 0x01 != 0,
+             (card.ext_csd.byte(EXT_CSD_CMDQ_DEPTH) & 0x1f) as usize + 1)
+        };
+        if !support {
+            return Err(MmcError::Unsupported);
+        }
+        self.mmc_switch(EXT_CSD_CMDQ_MODE_EN, 1)?;
+        info!("emmc cqe version: {:#x}, queue depth: {}", self.reg.emmc_get_cqver(), depth);
+
+        let mut mem = CqeMem { virt: mem };
+        for tag in 0..CQE_NUM_SLOTS {
+            let tran = phys + (CQE_TRAN_OFFSET + tag * CQE_MAX_SEGS * CQE_TRAN_DESC_LEN) as u64;
+            mem.write_desc(tag * CQE_SLOT_LEN, 0);
+            mem.write_desc(tag * CQE_SLOT_LEN + CQE_TASK_DESC_LEN,
+                           CQE_DESC_VALID | CQE_DESC_ACT_LINK | tran << CQE_DESC_ADDR_POS);
+        }
+        desc_barrier();
+
+        self.reg.emmc_select_dma(EMMC_DMA_SEL_ADMA2);
+        self.reg.emmc_set_xfer_block_size(BLOCK_SIZE as u16);
+        self.reg.emmc_set_xfer_mode(EMMC_BLOCK_COUNT_ENABLE);
+        self.reg.emmc_set_normal_int_en(self.reg.emmc_get_normal_int_en() | EMMC_CQE_EVENT_EN);
+        self.apply_int_sig();
+
+        self.reg.emmc_set_cqcfg(0);
+        self.reg.emmc_set_cqtdlba(phys);
+        self.reg.emmc_set_cqssc2_rca(rca);
+        let cqis = EMMC_CQ_HAC | EMMC_CQ_TCC | EMMC_CQ_RED | EMMC_CQ_TCL | EMMC_CQ_GCE | EMMC_CQ_ICCE;
+        self.reg.emmc_set_cqis(cqis);
+        self.reg.emmc_set_cqiste(cqis);
+        self.reg.emmc_set_cqisge(cqis);
+
+        *self.cqe.mem.lock() = Some(mem);
+        self.cqe.slots.store(if depth >= CQE_NUM_SLOTS { u32::MAX } else { (1 << depth) - 1 }, Ordering::Release);
+        self.cqe.used.store(0, Ordering::Release);
+        self.cqe.done.store(0, Ordering::Release);
+        self.cqe.failed.store(0, Ordering::Release);
+
+        self.reg.emmc_set_cqcfg(EMMC_CQ_EN);
+        self.reg.emmc_set_cqctl(0);
+        Ok(())
+    }
+
+    /// Stop the engine and switch the device back to legacy commands.
+    ///
+    /// Tasks still queued are cleared and reported as failed. Returns the descriptor memory.
+    pub fn cqe_disable(&self) -> Result<&'static mut [u8], MmcError> {
+        if self.cqe.mem.lock().is_none() {
+            return Err(MmcError::Unsupported);
+        }
+
+        self.cqe_halt()?;
+        self.cqe_clear_all()?;
+        self.reg.emmc_set_cqcfg(0);
+        self.reg.emmc_set_normal_int_en(self.reg.emmc_get_normal_int_en() & !EMMC_CQE_EVENT_EN);
+        self.apply_int_sig();
+
+        // A concurrent call may have disabled the engine in the meantime.
+        let Some(mem) = self.cqe.mem.lock().take() else {
+            return Err(MmcError::Unsupported);
+        };
+        self.mmc_switch(EXT_CSD_CMDQ_MODE_EN, 0)?;
+        Ok(mem.virt)
+    }
+
+    /// Queue `task` and ring its doorbell.
+    ///
+    /// # Returns
+    ///
+    /// - The tag of the task, reported in `CqeCompletion` once it finished.
+    pub fn cqe_submit(&self, task: &CqeTask) -> Result<u8, MmcError> {
+        let len = task.blocks as usize * BLOCK_SIZE;
+        if task.blocks == 0
+            || task.blocks as usize > CQE_MAX_BLOCKS
+            || !task.buf_phys.is_multiple_of(4)
+            || task.buf_phys + len as u64 > 1 << 32
+        {
+            return Err(MmcError::InvalidArgument);
+        }
+
+        let addr = {
+            let card = self.card.lock();
+            if task.lba as u64 + task.blocks as u64 > card.blocks {
+                return Err(MmcError::InvalidArgument);
+            }
+            if card.high_capacity { task.lba } else { task.lba * BLOCK_SIZE as u32 }
+        };
+
+        let mut mem = self.cqe.mem.lock();
+        let Some(mem) = mem.as_mut() else {
+            return Err(MmcError::Unsupported);
+        };
+
+        let slots = self.cqe.slots.load(Ordering::Acquire);
+        let tag = loop {
+            let used = self.cqe.used.load(Ordering::Acquire);
+            let free = slots & !used;
+            if free == 0 {
+                return Err(MmcError::QueueFull);
+            }
+            let tag = free.trailing_zeros();
+            if self.cqe.used
+                .compare_exchange(used, used | 1 << tag, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                break tag as usize;
+            }
+        };
+
+        let segs = len.div_ceil(CQE_SEG_SIZE);
+        let tran = CQE_TRAN_OFFSET + tag * CQE_MAX_SEGS * CQE_TRAN_DESC_LEN;
+        for seg in 0..segs {
+            let seg_len = (len - seg * CQE_SEG_SIZE).min(CQE_SEG_SIZE);
+            let seg_addr = task.buf_phys + (seg * CQE_SEG_SIZE) as u64;
+            let end = if seg + 1 == segs { CQE_DESC_END } else { 0 };
+            mem.write_desc(tran + seg * CQE_TRAN_DESC_LEN,
+                           CQE_DESC_VALID | end | CQE_DESC_ACT_TRAN
+                           | (seg_len as u64 & 0xffff) << CQE_DESC_LEN_POS
+                           | seg_addr << CQE_DESC_ADDR_POS);
+        }
+
+        let mut desc = CQE_DESC_VALID | CQE_DESC_END | CQE_DESC_INT | CQE_DESC_ACT_TASK
+            | (task.blocks as u64) << CQE_TASK_BLK_COUNT_POS
+            | (addr as u64) << CQE_TASK_BLK_ADDR_POS;
+        if task.read {
+            desc |= CQE_TASK_DATA_DIR_READ;
+        }
+        if task.reliable {
+            desc |= CQE_TASK_REL_WRITE;
+        }
+        if task.forced_prog {
+            desc |= CQE_TASK_FORCED_PROG;
+        }
+        if task.priority {
+            desc |= CQE_TASK_PRIORITY;
+        }
+        mem.write_desc(tag * CQE_SLOT_LEN, desc);
+
+        // The descriptors must be visible to the engine before the doorbell rings.
+        desc_barrier();
+        self.reg.emmc_set_cqtdbr(1 << tag);
+        Ok(tag as u8)
+    }
+
+    /// Return and forget the tasks that finished since the last call.
+    ///
+    /// In polling mode this also reads the interrupt status. A non-zero `failed` mask means the
+    /// engine stopped on an error, `cqe_recover` must be called before submitting again.
+    pub fn cqe_take_completed(&self) -> CqeCompletion {
+        if !self.irq_enabled() {
+            self.latch_int();
+        }
+
+        let done = self.cqe.done.swap(0, Ordering::AcqRel);
+        let failed = self.cqe.failed.swap(0, Ordering::AcqRel);
+        self.cqe.used.fetch_and(!(done | failed), Ordering::AcqRel);
+        CqeCompletion { done, failed }
+    }
+
+    /// Halt the engine, e.g. to send legacy commands in between queued tasks.
+    ///
+    /// The task in progress on the bus is finished first.
+    pub fn cqe_halt(&self) -> Result<(), MmcError> {
+        if !self.reg.emmc_cqe_is_enabled() {
+            return Err(MmcError::Unsupported);
+        }
+
+        self.reg.emmc_set_cqctl(self.reg.emmc_get_cqctl() | EMMC_CQ_HALT);
+        wait_timeout("cqe halt", CQE_HALT_TIMEOUT_US, || self.reg.emmc_cqe_is_halted())
+    }
+
+    /// Let a halted engine continue with the queued tasks.
+    pub fn cqe_resume(&self) {
+        self.reg.emmc_set_cqctl(self.reg.emmc_get_cqctl() & !EMMC_CQ_HALT);
+    }
+
+    /// Remove the `tasks` from a halted engine and report them as failed.
+    ///
+    /// The device may still hold them in its queue, so the engine must be recovered with
+    /// `cqe_recover` if any of them was already sent.
+    pub fn cqe_clear_tasks(&self, tasks: u32) -> Result<(), MmcError> {
+        if !self.reg.emmc_cqe_is_halted() {
+            return Err(MmcError::CqeActive);
+        }
+
+        self.reg.emmc_set_cqtclr(tasks);
+        wait_timeout("cqe task clear", RESET_TIMEOUT_US, || self.reg.emmc_get_cqtclr() & tasks == 0)?;
+        self.cqe.failed.fetch_or(tasks & self.cqe.used.load(Ordering::Acquire), Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Recover from a failed task.
+    ///
+    /// Halts the engine, aborts the transfer on the bus with CMD12, discards the device queue
+    /// with CMD48 and clears every task in the engine. Every task not completed yet is
+    /// reported as failed, then the engine is resumed.
+    pub fn cqe_recover(&self) -> Result<(), MmcError> {
+        warn!("emmc cqe recovery, cqterri: {:#x}", self.reg.emmc_get_cqterri());
+        self.cqe_halt()?;
+        self.reset_lines()?;
+
+        // The device may not be in a data state, an abort failing here is expected.
+        let _ = self.sdhci_send_cmd(MMC_STOP_TRANSMISSION, EMMC_CMD_TYPE_ABORT, MMC_RESP_R1B, 0);
+        self.sdhci_send_cmd(MMC_CMDQ_TASK_MGMT, 0, MMC_RESP_R1B, MMC_CMDQ_DISCARD_QUEUE)?;
+        self.cqe_clear_all()?;
+        let timeout = self.card.lock().write_timeout_us();
+        self.wait_ready(timeout)?;
+
+        self.cqe_resume();
+        Ok(())
+    }
+
+    /// Clear every task of the halted engine and report the outstanding ones as failed.
+    fn cqe_clear_all(&self) -> Result<(), MmcError> {
+        self.reg.emmc_set_cqctl(self.reg.emmc_get_cqctl() | EMMC_CQ_CLR_ALL_TASKS);
+        wait_timeout("cqe clear all tasks", RESET_TIMEOUT_US, || self.reg.emmc_cqe_clear_all_tasks_is_finished())?;
+
+        let done = self.cqe.done.load(Ordering::Acquire);
+        self.cqe.failed.fetch_or(self.cqe.used.load(Ordering::Acquire) & !done, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Handle `EMMC_CQE_EVENT`, called when latching the interrupt status.
+    pub(crate) fn cqe_irq(&self) {
+        let cqis = self.reg.emmc_get_cqis();
+        self.reg.emmc_set_cqis(cqis);
+
+        let mut notify = false;
+        if cqis & EMMC_CQ_TCC != 0 {
+            let tcn = self.reg.emmc_get_cqtcn();
+            self.reg.emmc_set_cqtcn(tcn);
+            self.cqe.done.fetch_or(tcn, Ordering::AcqRel);
+            notify = true;
+        }
+
+        if cqis & (EMMC_CQ_RED | EMMC_CQ_GCE | EMMC_CQ_ICCE) != 0 {
+            let terri = self.reg.emmc_get_cqterri();
+            let mut failed = 0;
+            if terri & EMMC_CQ_RMEFV != 0 {
+                failed |= 1 << ((terri & EMMC_CQ_RMETID_MASK) >> EMMC_CQ_RMETID_POS);
+            }
+            if terri & EMMC_CQ_DTEFV != 0 {
+                failed |= 1 << ((terri & EMMC_CQ_DTETID_MASK) >> EMMC_CQ_DTETID_POS);
+            }
+            warn!("emmc cqe error, cqis: {:#x}, cqterri: {:#x}", cqis, terri);
+            self.cqe.failed.fetch_or(failed, Ordering::AcqRel);
+            notify = true;
+        }
+
+        if notify {
+            (self.cqe.notify)();
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use core::ops::Range;
+
+use core::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::delay_us;
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, Request, SDHCI, csd_bits, status_is_ready, switch_arg};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_resp_type::{MMC_RESP_R1, MMC_RESP_R1B};
+use crate::sdhci_cmd::sd_cmd_idx::*;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_timer::timer;
+
+/// Unit of the ERASE_TIMEOUT_MULT and TRIM_MULT fields of the EXT_CSD.
+pub const ERASE_TIMEOUT_UNIT_US: u64 = 300_000;
+/// Erase timeout of one write block of an SD card without an erase timeout in its SD Status.
+pub const SD_ERASE_BLOCK_TIMEOUT_US: u64 = 250_000;
+/// Discard timeout of an SD card, independent of the size of the range.
+pub const SD_DISCARD_TIMEOUT_US: u64 = 250_000;
+/// Suggested sanitize timeout, the EXT_CSD gives none and it depends on the unmapped data.
+pub const SANITIZE_TIMEOUT_US: u64 = 240_000_000;
+/// Interval of the progress messages while a sanitize runs.
+pub const SANITIZE_PROGRESS_INTERVAL_US: u64 = 10_000_000;
+/// Interval of the CMD13 polls while a sanitize runs.
+pub const SANITIZE_POLL_US: u64 = 10_000;
+
+/// How `SDHCI::erase` removes the data of a range of blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EraseKind {
+    /// Erase whole erase groups, the content becomes ERASED_MEM_CONT.
+    Erase,
+    /// Erase only the write blocks of the range, if SEC_GB_CL_EN is supported.
+    Trim,
+    /// Mark the write blocks of the range as unused, their content becomes undefined.
+    /// Supported from eMMC 4.5, and by SD cards reporting DISCARD_SUPPORT.
+    Discard,
+    /// Erase whole erase groups and purge every copy of their data, if SEC_ER_EN is supported.
+    SecureErase,
+    /// Trim the write blocks and purge every copy of their data, if SEC_ER_EN and
+    /// SEC_GB_CL_EN are supported.
+    SecureTrim,
+}
+
+impl EraseKind {
+    /// CMD38 arguments issued in turn, each with its own CMD35/CMD36 range.
+    fn args(self, card_type: CardType) -> &'static [u32] {
+        match (card_type, self) {
+            (CardType::Mmc, EraseKind::Erase) => &[MMC_ERASE_ARG],
+            (CardType::Mmc, EraseKind::Trim) => &[MMC_TRIM_ARG],
+            (CardType::Mmc, EraseKind::Discard) => &[MMC_DISCARD_ARG],
+            (CardType::Mmc, EraseKind::SecureErase) => &[MMC_SECURE_ERASE_ARG],
+            (CardType::Mmc, EraseKind::SecureTrim) => &[MMC_SECURE_TRIM1_ARG, MMC_SECURE_TRIM2_ARG],
+            (CardType::Sd, EraseKind::Discard) => &[SD_DISCARD_ARG],
+            (CardType::Sd, _) => &[SD_ERASE_ARG],
+        }
+    }
+}
+
+impl Card {
+    /// Blocks of `BLOCK_SIZE` bytes in an erase group, 1 for SD cards which erase write blocks.
+    ///
+    /// HC_ERASE_GRP_SIZE of the EXT_CSD applies once ERASE_GROUP_DEF is set, ERASE_GRP_SIZE
+    /// and ERASE_GRP_MULT of the CSD otherwise.
+    pub fn erase_group_blocks(&self) -> u32 {
+        if self.card_type == CardType::Sd {
+            return 1;
+        }
+        let hc_size = self.ext_csd.byte(EXT_CSD_HC_ERASE_GRP_SIZE) as u32;
+        if self.ext_csd.byte(EXT_CSD_ERASE_GROUP_DEF) & 0x01 != 0 && hc_size != 0 {
+            // Units of 512 KiB.
+            return hc_size * 1024;
+        }
+        let size = csd_bits(&self.csd, 42, 5) + 1;
+        let mult = csd_bits(&self.csd, 37, 5) + 1;
+        let write_bl_len = csd_bits(&self.csd, 22, 4);
+        (((size * mult) << write_bl_len) / BLOCK_SIZE as u32).max(1)
+    }
+
+    /// Whether the card accepts CMD38 for `kind`.
+    pub fn supports_erase(&self, kind: EraseKind) -> bool {
+        let sec = self.ext_csd.byte(EXT_CSD_SEC_FEATURE_SUPPORT);
+        match (self.card_type, kind) {
+            (_, EraseKind::Erase) => true,
+            (CardType::Mmc, EraseKind::Trim) => sec & EXT_CSD_SEC_GB_CL_EN != 0,
+            (CardType::Mmc, EraseKind::Discard) => self.ext_csd.rev() >= 6,
+            (CardType::Mmc, EraseKind::SecureErase) => sec & EXT_CSD_SEC_ER_EN != 0,
+            (CardType::Mmc, EraseKind::SecureTrim) => {
+                sec & (EXT_CSD_SEC_ER_EN | EXT_CSD_SEC_GB_CL_EN) == EXT_CSD_SEC_ER_EN | EXT_CSD_SEC_GB_CL_EN
+            }
+            (CardType::Sd, EraseKind::Discard) => self.sd_status.discard_support(),
+            (CardType::Sd, _) => false,
+        }
+    }
+
+    /// Busy timeout of CMD38 for `kind` over `groups` erase groups.
+    ///
+    /// eMMC devices give it in units of 300 ms per erase group, with TRIM_MULT for trim and
+    /// discard and ERASE_TIMEOUT_MULT for erases with the high capacity erase group size;
+    /// the write timeout is used per group otherwise. Secure operations take SEC_ERASE_MULT
+    /// or SEC_TRIM_MULT times longer. SD cards give it in the SD Status.
+    pub fn erase_timeout_us(&self, kind: EraseKind, groups: u32) -> u64 {
+        let groups = groups as u64;
+        if self.card_type == CardType::Sd {
+            return match kind {
+                EraseKind::Discard => SD_DISCARD_TIMEOUT_US,
+                _ => self.sd_erase_timeout_us(groups),
+            };
+        }
+
+        let mult = |offset| self.ext_csd.byte(offset) as u64;
+        let high_capacity = mult(EXT_CSD_ERASE_GROUP_DEF) & 0x01 != 0;
+        let per_group = match kind {
+            EraseKind::Trim | EraseKind::Discard if mult(EXT_CSD_TRIM_MULT) != 0 => {
+                mult(EXT_CSD_TRIM_MULT) * ERASE_TIMEOUT_UNIT_US
+            }
+            _ if high_capacity && mult(EXT_CSD_ERASE_TIMEOUT_MULT) != 0 => {
+                mult(EXT_CSD_ERASE_TIMEOUT_MULT) * ERASE_TIMEOUT_UNIT_US
+            }
+            _ => self.write_timeout_us(),
+        };
+        let secure = match kind {
+            EraseKind::SecureErase => mult(EXT_CSD_SEC_ERASE_MULT),
+            EraseKind::SecureTrim => mult(EXT_CSD_SEC_TRIM_MULT),
+            _ => 1,
+        };
+        per_group * secure.max(1) * groups
+    }
+
+    /// Erase timeout of `blocks` write blocks from ERASE_SIZE, ERASE_TIMEOUT and ERASE_OFFSET
+    /// of the SD Status, at least one second.
+    fn sd_erase_timeout_us(&self, blocks: u64) -> u64 {
+        let ssr = &self.sd_status;
+        let au_size = ssr.au_size() as u64;
+        if ssr.erase_size() == 0 || au_size == 0 {
+            return blocks * SD_ERASE_BLOCK_TIMEOUT_US;
+        }
+        let aus = (blocks * BLOCK_SIZE as u64).div_ceil(au_size);
+        let secs = ssr.erase_timeout() as u64 * aus / ssr.erase_size() as u64 + ssr.erase_offset() as u64;
+        secs.max(1) * 1_000_000
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Erase the blocks of `lba` in the user data area with CMD35/CMD36/CMD38, CMD32/CMD33/CMD38
+    /// for SD cards.
+    ///
+    /// `EraseKind::Erase` only erases the erase groups fully inside the range, and returns at
+    /// once if there is none. `EraseKind::SecureErase` fails with `MmcError::InvalidArgument`
+    /// unless the range is aligned to erase groups, and a kind the card does not support fails
+    /// with `MmcError::Unsupported`. Returns once the busy signal of CMD38 ended, see
+    /// `Card::erase_timeout_us`. With HPI the erase is polled with CMD13 instead, and an erase
+    /// interrupted with `interrupt_current_operation` fails with `MmcError::Interrupted`.
+    pub fn erase(&self, lba: Range<u32>, kind: EraseKind) -> Result<(), MmcError> {
+        self.check_card()?;
+        let (range, args, sd, high_capacity, timeout) = {
+            let card = self.card.lock();
+            if lba.start > lba.end || lba.end as u64 > card.blocks {
+                return Err(MmcError::InvalidArgument);
+            }
+            if !card.supports_erase(kind) {
+                return Err(MmcError::Unsupported);
+            }
+
+            let group = card.erase_group_blocks();
+            let range = match kind {
+                EraseKind::Erase => lba.start.next_multiple_of(group)..lba.end / group * group,
+                EraseKind::SecureErase if !lba.start.is_multiple_of(group) || !lba.end.is_multiple_of(group) => {
+                    return Err(MmcError::InvalidArgument);
+                }
+                _ => lba,
+            };
+            if range.is_empty() {
+                debug!("{:?}: no erase group of {} blocks inside the range", kind, group);
+                return Ok(());
+            }
+            let groups = (range.end - 1) / group - range.start / group + 1;
+            let timeout = card.erase_timeout_us(kind, groups);
+            (range, kind.args(card.card_type), card.card_type == CardType::Sd, card.high_capacity, timeout)
+        };
+
+        let hpi = self.hpi_enable()?;
+        info!("{:?} of blocks {:#x}..{:#x}, timeout {} ms", kind, range.start, range.end, timeout / 1000);
+        let (start_idx, end_idx) =
+            if sd { (SD_ERASE_WR_BLK_START, SD_ERASE_WR_BLK_END) } else { (MMC_ERASE_GROUP_START, MMC_ERASE_GROUP_END) };
+        let addr = |lba: u32| if high_capacity { lba } else { lba * BLOCK_SIZE as u32 };
+        self.with_recovery(|| {
+            for &arg in args {
+                self.sdhci_send_cmd(start_idx, 0, MMC_RESP_R1, addr(range.start))?;
+                self.sdhci_send_cmd(end_idx, 0, MMC_RESP_R1, addr(range.end - 1))?;
+                if hpi {
+                    // Without busy detection, so it can be interrupted while CMD13 polls it.
+                    self.sdhci_send_cmd(MMC_ERASE, 0, MMC_RESP_R1, arg)?;
+                    self.wait_ready_interruptible(timeout)?;
+                } else {
+                    self.execute(&mut Request::new(MMC_ERASE, 0, MMC_RESP_R1B, arg).with_busy_timeout(timeout))?;
+                    self.wait_ready(timeout)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Discard `count` blocks starting at `lba`, for the discard (TRIM) requests of a block
+    /// device such as virtio-blk so the flash can reclaim them.
+    ///
+    /// Uses discard if the card supports it, then trim, then an erase of the erase groups
+    /// fully inside the range. The content of the blocks is undefined afterwards.
+    pub fn discard_blocks(&self, lba: u32, count: u32) -> Result<(), MmcError> {
+        let end = lba.checked_add(count).ok_or(MmcError::InvalidArgument)?;
+        let kind = {
+            let card = self.card.lock();
+            [EraseKind::Discard, EraseKind::Trim]
+                .into_iter()
+                .find(|&kind| card.supports_erase(kind))
+                .unwrap_or(EraseKind::Erase)
+        };
+        self.erase(lba..end, kind)
+    }
+
+    /// Purge the unmapped data of the device, i.e. every copy left behind by writes, erases
+    /// and discards, by writing SANITIZE_START with CMD6.
+    ///
+    /// The device is busy until the sanitize is done, which is polled with CMD13 for up to
+    /// `timeout_us`, e.g. `SANITIZE_TIMEOUT_US`, logging the progress every
+    /// `SANITIZE_PROGRESS_INTERVAL_US`. `abort` is called between the polls: once it returns
+    /// true the sanitize is interrupted with HPI and `MmcError::Interrupted` is returned, if
+    /// the device supports HPI. Errors of the sanitize are reported in the R1 status as
+    /// `MmcError::CardStatus`.
+    pub fn sanitize(&self, timeout_us: u64, mut abort: impl FnMut() -> bool) -> Result<(), MmcError> {
+        self.check_card()?;
+        {
+            let card = self.card.lock();
+            if card.card_type != CardType::Mmc || card.ext_csd.byte(EXT_CSD_SEC_FEATURE_SUPPORT) & EXT_CSD_SEC_SANITIZE == 0 {
+                return Err(MmcError::Unsupported);
+            }
+        }
+        let hpi = self.hpi_enable()?;
+
+        info!("sanitize started, timeout {} s", timeout_us / 1_000_000);
+        // Without busy detection, so the sanitize can be interrupted while CMD13 polls it.
+        self.sdhci_send_cmd(MMC_SWITCH, 0, MMC_RESP_R1, switch_arg(EXT_CSD_SANITIZE_START, 1))?;
+
+        let start = timer().now();
+        let interval = Duration::from_micros(SANITIZE_PROGRESS_INTERVAL_US);
+        let mut progress = interval;
+        let mut abort_ignored = false;
+        loop {
+            let status = self.sdhci_send_status()?;
+            let elapsed = timer().now() - start;
+            if status_is_ready(status) {
+                info!("sanitize done after {} ms", elapsed.as_millis());
+                return Ok(());
+            }
+            if !abort_ignored && abort() {
+                if hpi {
+                    warn!("sanitize interrupted after {} ms", elapsed.as_millis());
+                    self.send_hpi()?;
+                    return Err(MmcError::Interrupted);
+                }
+                warn!("sanitize cannot be interrupted without HPI");
+                abort_ignored = true;
+            }
+            if elapsed > Duration::from_micros(timeout_us) {
+                warn!("sanitize still running after {} s", elapsed.as_secs());
+                return Err(MmcError::Timeout("sanitize"));
+            }
+            if elapsed >= progress {
+                info!("sanitize in progress, {} s elapsed", elapsed.as_secs());
+                progress += interval;
+            }
+            (self.idle)();
+            delay_us(SANITIZE_POLL_US);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::sdhci_cmd::mmc_r1_bits::*;
+use crate::sdhci_reg::emmc_auto_cmd_stat_bits::*;
+use crate::sdhci_reg::emmc_error_int_stat_bits::*;
+
+/// Errors reported by the SDHCI driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmcError {
+    /// No response was returned within 64 SD clock cycles.
+    CmdTimeout,
+    /// CRC error in the command response.
+    CmdCrc,
+    /// End bit of the command response was 0.
+    CmdEndBit,
+    /// Command index in the response did not match the command.
+    CmdIndex,
+    /// Busy or read data timeout on the DAT lines.
+    DataTimeout,
+    /// CRC error in the read data or in the write CRC status.
+    DataCrc,
+    /// End bit of the read data or of the write CRC status was 0.
+    DataEndBit,
+    /// Error reported for Auto CMD12 / Auto CMD23.
+    AutoCmd(AutoCmdError),
+    /// Error during an ADMA based data transfer.
+    Adma,
+    /// Error during the tuning procedure, or no sampling point was found.
+    Tuning,
+    /// The card or the controller did not switch the signaling voltage to 1.8V.
+    VoltageSwitch,
+    /// The slot is empty, or the card was removed during the request.
+    NoCard,
+    /// Error detected by the response check function.
+    Response,
+    /// Boot acknowledge error in boot operation mode.
+    BootAck,
+    /// A wait on the controller or the device did not finish in time, naming what was waited for.
+    Timeout(&'static str),
+    /// The R1 card status of the response has error bits set.
+    CardStatus(u32),
+    /// The write or erase hit a write protected group or boot area, reported with WP_VIOLATION.
+    WriteProtected,
+    /// The request is malformed, e.g. an unaligned buffer length or an LBA past the end.
+    InvalidArgument,
+    /// The device or the controller does not support the operation, or it is not enabled.
+    Unsupported,
+    /// A long operation such as a sanitize was interrupted with HPI before it completed.
+    Interrupted,
+    /// A setting written to the device reads back different, e.g. because it is locked.
+    VerifyFailed,
+    /// The partitioning was already made final with PARTITION_SETTING_COMPLETED.
+    PartitioningCompleted,
+    /// The firmware update failed, with the FFU_STATUS the device reports, see `sdhci_ffu::FfuStatus`.
+    FfuFailed(u8),
+    /// A legacy command was issued while the command queuing engine is running.
+    CqeActive,
+    /// Every task slot of the command queue is in use.
+    QueueFull,
+}
+
+/// Failure of the CMD12 or CMD23 the controller issued by itself, from `EMMC_AUTO_CMD_STAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoCmdError {
+    /// Auto CMD12 was not issued because the data transfer failed first.
+    NotExecuted,
+    /// No response was returned within 64 SD clock cycles.
+    Timeout,
+    /// CRC error in the response.
+    Crc,
+    /// End bit of the response was 0.
+    EndBit,
+    /// Command index in the response did not match the command.
+    Index,
+    /// The response check function found an error in the R1 card status.
+    Response,
+    /// The command queued after a failed Auto CMD12 was not issued.
+    NotIssued,
+}
+
+impl AutoCmdError {
+    /// Decode the value of the `EMMC_AUTO_CMD_STAT` register.
+    ///
+    /// The other bits are meaningless when Auto CMD12 was not executed, and a status without
+    /// any known bit is taken as such.
+    pub fn from_auto_cmd_stat(stat: u16) -> Self {
+        const DECODE: [(u16, AutoCmdError); 6] = [
+            (EMMC_AUTO_CMD_TOUT_ERR, AutoCmdError::Timeout),
+            (EMMC_AUTO_CMD_CRC_ERR, AutoCmdError::Crc),
+            (EMMC_AUTO_CMD_EBIT_ERR, AutoCmdError::EndBit),
+            (EMMC_AUTO_CMD_IDX_ERR, AutoCmdError::Index),
+            (EMMC_AUTO_CMD_RESP_ERR, AutoCmdError::Response),
+            (EMMC_CMD_NOT_ISSUED_AUTO_CMD12, AutoCmdError::NotIssued),
+        ];
+
+        if stat & EMMC_AUTO_CMD12_NOT_EXEC != 0 {
+            return AutoCmdError::NotExecuted;
+        }
+        DECODE.iter().find(|(bit, _)| stat & bit != 0).map_or(AutoCmdError::NotExecuted, |&(_, err)| err)
+    }
+}
+
+impl MmcError {
+    /// Decode the value of the `EMMC_ERROR_INT_STAT` register, with the value of the
+    /// `EMMC_AUTO_CMD_STAT` register telling which Auto CMD error is reported.
+    ///
+    /// Command errors take precedence over data errors because a failed command never
+    /// starts its data phase. Returns `None` if no known error bit is set.
+    pub fn from_error_int_stat(stat: u16, auto_cmd_stat: u16) -> Option<Self> {
+        const DECODE: [(u16, MmcError); 12] = [
+            (EMMC_CMD_TOUT_ERR, MmcError::CmdTimeout),
+            (EMMC_CMD_CRC_ERR, MmcError::CmdCrc),
+            (EMMC_CMD_END_BIT_ERR, MmcError::CmdEndBit),
+            (EMMC_CMD_IDX_ERR, MmcError::CmdIndex),
+            (EMMC_DATA_TOUT_ERR, MmcError::DataTimeout),
+            (EMMC_DATA_CRC_ERR, MmcError::DataCrc),
+            (EMMC_DATA_END_BIT_ERR, MmcError::DataEndBit),
+            (EMMC_AUTO_CMD_ERR, MmcError::AutoCmd(AutoCmdError::NotExecuted)),
+            (EMMC_ADMA_ERR, MmcError::Adma),
+            (EMMC_TUNING_ERR, MmcError::Tuning),
+            (EMMC_RESP_ERR, MmcError::Response),
+            (EMMC_BOOT_ACK_ERR, MmcError::BootAck),
+        ];
+
+        DECODE.iter().find(|(bit, _)| stat & bit != 0).map(|&(_, err)| match err {
+            MmcError::AutoCmd(_) => MmcError::AutoCmd(AutoCmdError::from_auto_cmd_stat(auto_cmd_stat)),
+            err => err,
+        })
+    }
+
+    /// The error reported by the R1 card status `status` with error bits set.
+    pub fn from_card_status(status: u32) -> Self {
+        if status & MMC_R1_WP_VIOLATION != 0 {
+            MmcError::WriteProtected
+        } else {
+            MmcError::CardStatus(status)
+        }
+    }
+
+    /// Whether the error may go away by resetting the bus and retrying.
+    ///
+    /// Bus errors are, as are card status errors caused by a corrupted command or by the
+    /// card being left in the wrong state. Addressing and protection errors are not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MmcError::CardStatus(status) => {
+                status & (MMC_R1_COM_CRC_ERROR | MMC_R1_ILLEGAL_COMMAND | MMC_R1_CC_ERROR | MMC_R1_ERROR) != 0
+            }
+            MmcError::InvalidArgument
+            | MmcError::Unsupported
+            | MmcError::VoltageSwitch
+            | MmcError::NoCard
+            | MmcError::Interrupted
+            | MmcError::VerifyFailed
+            | MmcError::PartitioningCompleted
+            | MmcError::FfuFailed(_)
+            | MmcError::WriteProtected
+            | MmcError::CqeActive
+            | MmcError::QueueFull => false,
+            _ => true,
+        }
+    }
+}
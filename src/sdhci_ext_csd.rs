@@ -0,0 +1,236 @@
+/// Byte offsets of the fields in the 512-byte EXT_CSD register.
+pub mod ext_csd_bits {
+    /// Size of the EXT_CSD register in bytes.
+    pub const EXT_CSD_SIZE: usize = 512;
+
+    /// Command queue enable, 1 bit.
+    pub const EXT_CSD_CMDQ_MODE_EN: usize = 15;
+    /// Result of the last firmware update, see `sdhci_ffu::FfuStatus`.
+    pub const EXT_CSD_FFU_STATUS: usize = 26;
+    /// Writing `EXT_CSD_FFU_INSTALL` installs the downloaded firmware, in FFU mode.
+    pub const EXT_CSD_MODE_OPERATION_CODES: usize = 29;
+    /// 0 for the normal mode, 1 for FFU mode.
+    pub const EXT_CSD_MODE_CONFIG: usize = 30;
+    /// Bit 0 enables cache barriers.
+    pub const EXT_CSD_BARRIER_CTRL: usize = 31;
+    /// Bit 0 flushes the volatile cache, bit 1 sets a barrier.
+    pub const EXT_CSD_FLUSH_CACHE: usize = 32;
+    /// Bit 0 enables the volatile cache.
+    pub const EXT_CSD_CACHE_CTRL: usize = 33;
+    /// Attributes of GP1 to GP4 in 4 bits each from bit 3:0 of the first byte, 2 bytes.
+    pub const EXT_CSD_EXT_PARTITIONS_ATTRIBUTE: usize = 52;
+    /// Exception events the device raises, see `EXT_CSD_URGENT_BKOPS`. 2 bytes, little endian.
+    pub const EXT_CSD_EXCEPTION_EVENTS_STATUS: usize = 54;
+    /// Exception events reported with EXCEPTION_EVENT in the R1 status, 2 bytes, little endian.
+    pub const EXT_CSD_EXCEPTION_EVENTS_CTRL: usize = 56;
+    /// 0 for 512-byte data sectors, 1 for 4 KiB ones.
+    pub const EXT_CSD_DATA_SECTOR_SIZE: usize = 61;
+    /// Start of the enhanced user data area, in bytes or in sectors for high capacity devices.
+    /// 4 bytes, little endian.
+    pub const EXT_CSD_ENH_START_ADDR: usize = 136;
+    /// Size of the enhanced user data area in units of HC_WP_GRP_SIZE × HC_ERASE_GRP_SIZE ×
+    /// 512 KiB. 3 bytes, little endian.
+    pub const EXT_CSD_ENH_SIZE_MULT: usize = 140;
+    /// Sizes of GP1 to GP4 in the units of ENH_SIZE_MULT, 3 bytes each, little endian.
+    pub const EXT_CSD_GP_SIZE_MULT: usize = 143;
+    /// Bit 0 set once the partitioning is final, applied with the next power cycle.
+    pub const EXT_CSD_PARTITION_SETTING_COMPLETED: usize = 155;
+    /// Bit 0 makes the enhanced user data area enhanced, bits 1 to 4 GP1 to GP4.
+    pub const EXT_CSD_PARTITIONS_ATTRIBUTE: usize = 156;
+    /// Most enhanced memory of all partitions together in the units of ENH_SIZE_MULT. 3 bytes,
+    /// little endian.
+    pub const EXT_CSD_MAX_ENH_SIZE_MULT: usize = 157;
+    /// Partitioning features, see `EXT_CSD_PARTITIONING_EN`.
+    pub const EXT_CSD_PARTITIONING_SUPPORT: usize = 160;
+    /// Bit 0 enables HPI.
+    pub const EXT_CSD_HPI_MGMT: usize = 161;
+    /// Background operations enabled, see `EXT_CSD_MANUAL_BKOPS_EN`.
+    pub const EXT_CSD_BKOPS_EN: usize = 163;
+    /// Writing 1 starts the pending background operations, the device is busy until they are done.
+    pub const EXT_CSD_BKOPS_START: usize = 164;
+    /// Writing 1 starts a sanitize, the device is busy until it is done.
+    pub const EXT_CSD_SANITIZE_START: usize = 165;
+    /// Reliable write parameters, see `EXT_CSD_EN_REL_WR`.
+    pub const EXT_CSD_WR_REL_PARAM: usize = 166;
+    /// 128 KiB units, 1 bit.
+    pub const EXT_CSD_RPMB_SIZE_MULT: usize = 168;
+    /// Bit 0 set if firmware updates are disabled for good.
+    pub const EXT_CSD_FFU_CONFIG: usize = 169;
+    /// Protection applied by CMD28 to the user area and what may be applied, see `EXT_CSD_US_PWR_WP_EN`.
+    pub const EXT_CSD_USER_WP: usize = 171;
+    /// Protection of the boot partitions, see `EXT_CSD_B_PWR_WP_EN`.
+    pub const EXT_CSD_BOOT_WP: usize = 173;
+    /// Protection of boot area 1 in bits 1:0 and of area 2 in bits 3:2, 1 until the next power
+    /// cycle and 2 for good.
+    pub const EXT_CSD_BOOT_WP_STATUS: usize = 174;
+    /// Bit 0 selects the high capacity erase group size.
+    pub const EXT_CSD_ERASE_GROUP_DEF: usize = 175;
+    /// Boot mode in bits 4:3, bit 2 to retain the boot bus width and boot bus width in bits 1:0.
+    pub const EXT_CSD_BOOT_BUS_CONDITIONS: usize = 177;
+    /// Locks the boot configuration, see `EXT_CSD_PWR_BOOT_CONFIG_PROT`.
+    pub const EXT_CSD_BOOT_CONFIG_PROT: usize = 178;
+    /// Boot ACK in bit 6, boot partition enable in bits 5:3 and partition access in bits 2:0.
+    pub const EXT_CSD_PARTITION_CONFIG: usize = 179;
+    /// Content of erased memory, 0 or 1 for all ones.
+    pub const EXT_CSD_ERASED_MEM_CONT: usize = 181;
+    /// Bus width mode, 0 for 1-bit, 1 for 4-bit and 2 for 8-bit.
+    pub const EXT_CSD_BUS_WIDTH: usize = 183;
+    pub const EXT_CSD_HS_TIMING: usize = 185;
+    pub const EXT_CSD_REV: usize = 192;
+    pub const EXT_CSD_CARD_TYPE: usize = 196;
+    /// Maximum time to leave an operation interrupted with HPI, in units of 10 ms.
+    pub const EXT_CSD_OUT_OF_INTERRUPT_TIME: usize = 198;
+    /// 4 bytes, little endian.
+    pub const EXT_CSD_SEC_COUNT: usize = 212;
+    /// High capacity write protect group size in erase groups.
+    pub const EXT_CSD_HC_WP_GRP_SIZE: usize = 221;
+    /// Sectors written reliably as one unit in the legacy reliable write mode.
+    pub const EXT_CSD_REL_WR_SEC_C: usize = 222;
+    /// Erase timeout in units of 300 ms per erase group.
+    pub const EXT_CSD_ERASE_TIMEOUT_MULT: usize = 223;
+    /// High capacity erase group size in units of 512 KiB.
+    pub const EXT_CSD_HC_ERASE_GRP_SIZE: usize = 224;
+    /// Size of each boot partition in units of 128 KiB.
+    pub const EXT_CSD_BOOT_SIZE_MULT: usize = 226;
+    /// Secure trim timeout as a multiple of the erase timeout.
+    pub const EXT_CSD_SEC_TRIM_MULT: usize = 229;
+    /// Secure erase timeout as a multiple of the erase timeout.
+    pub const EXT_CSD_SEC_ERASE_MULT: usize = 230;
+    /// Secure operations supported by the device, see the `EXT_CSD_SEC_*_EN` bits.
+    pub const EXT_CSD_SEC_FEATURE_SUPPORT: usize = 231;
+    /// Trim and discard timeout in units of 300 ms per erase group.
+    pub const EXT_CSD_TRIM_MULT: usize = 232;
+    /// Sectors of the last write interrupted with HPI that were programmed. 4 bytes, little endian.
+    pub const EXT_CSD_CORRECTLY_PRG_SECTORS_NUM: usize = 242;
+    /// Urgency of the pending background operations, see `sdhci_bkops::BkopsStatus`.
+    pub const EXT_CSD_BKOPS_STATUS: usize = 246;
+    /// Maximum busy time of CMD6 in units of 10 ms.
+    pub const EXT_CSD_GENERIC_CMD6_TIME: usize = 248;
+    /// Size of the volatile cache, 0 without one. 4 bytes, little endian.
+    pub const EXT_CSD_CACHE_SIZE: usize = 249;
+    /// Version of the firmware running, 8 bytes.
+    pub const EXT_CSD_FIRMWARE_VERSION: usize = 254;
+    /// Consumed reserved blocks, see `sdhci_health::PreEol`.
+    pub const EXT_CSD_PRE_EOL_INFO: usize = 267;
+    /// Life time used of the SLC memory in steps of 10%, 0x0b once exceeded.
+    pub const EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_A: usize = 268;
+    /// Life time used of the MLC memory in steps of 10%, 0x0b once exceeded.
+    pub const EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_B: usize = 269;
+    /// Data sectors of the firmware download received correctly. 4 bytes, little endian.
+    pub const EXT_CSD_NUMBER_OF_FW_SECTORS_CORRECTLY_PROGRAMMED: usize = 302;
+    /// Queue depth minus 1 in bits 4:0.
+    pub const EXT_CSD_CMDQ_DEPTH: usize = 307;
+    /// Bit 0 set if command queuing is supported.
+    pub const EXT_CSD_CMDQ_SUPPORT: usize = 308;
+    /// Bit 0 set if cache barriers are supported.
+    pub const EXT_CSD_BARRIER_SUPPORT: usize = 486;
+    /// Argument of the CMD25 downloading the firmware in FFU mode. 4 bytes, little endian.
+    pub const EXT_CSD_FFU_ARG: usize = 487;
+    /// Longest busy time of MODE_OPERATION_CODES, 100 us × 2^value.
+    pub const EXT_CSD_OPERATION_CODE_TIMEOUT: usize = 491;
+    /// Bit 0 set if the firmware is installed with MODE_OPERATION_CODES instead of a power cycle.
+    pub const EXT_CSD_FFU_FEATURES: usize = 492;
+    /// Bit 0 set if FFU is supported.
+    pub const EXT_CSD_SUPPORTED_MODES: usize = 493;
+    /// Bit 0 set if background operations are supported.
+    pub const EXT_CSD_BKOPS_SUPPORT: usize = 502;
+    /// HPI support in bit 0, and bit 1 set if HPI is sent with CMD12 instead of CMD13.
+    pub const EXT_CSD_HPI_FEATURES: usize = 503;
+
+    /// FLUSH_CACHE: write the content of the volatile cache back.
+    pub const EXT_CSD_FLUSH: u8 = 1 << 0;
+    /// FLUSH_CACHE: write the data cached so far back before any data cached afterwards.
+    pub const EXT_CSD_BARRIER: u8 = 1 << 1;
+    /// SEC_FEATURE_SUPPORT: secure erase and secure trim are supported.
+    pub const EXT_CSD_SEC_ER_EN: u8 = 1 << 0;
+    /// SEC_FEATURE_SUPPORT: trim is supported.
+    pub const EXT_CSD_SEC_GB_CL_EN: u8 = 1 << 4;
+    /// SEC_FEATURE_SUPPORT: sanitize is supported.
+    pub const EXT_CSD_SEC_SANITIZE: u8 = 1 << 6;
+    /// WR_REL_PARAM: enhanced reliable write, a write of any size and alignment is reliable.
+    pub const EXT_CSD_EN_REL_WR: u8 = 1 << 2;
+    /// PARTITION_CONFIG: the device sends a boot acknowledge pattern.
+    pub const EXT_CSD_BOOT_ACK: u8 = 1 << 6;
+    /// BOOT_BUS_CONDITIONS: keep the boot bus width and mode after the boot operation.
+    pub const EXT_CSD_BOOT_BUS_RETAIN: u8 = 1 << 2;
+    /// BOOT_CONFIG_PROT: the boot configuration is locked until the next power cycle.
+    pub const EXT_CSD_PWR_BOOT_CONFIG_PROT: u8 = 1 << 0;
+    /// BOOT_CONFIG_PROT: the boot configuration is locked for good.
+    pub const EXT_CSD_PERM_BOOT_CONFIG_PROT: u8 = 1 << 4;
+    /// PARTITIONING_SUPPORT: general purpose partitions and an enhanced user data area can be made.
+    pub const EXT_CSD_PARTITIONING_EN: u8 = 1 << 0;
+    /// PARTITIONING_SUPPORT: partitions can be made enhanced.
+    pub const EXT_CSD_ENH_ATTRIBUTE_EN: u8 = 1 << 1;
+    /// PARTITIONING_SUPPORT: general purpose partitions can have EXT_PARTITIONS_ATTRIBUTE.
+    pub const EXT_CSD_EXT_ATTRIBUTE_EN: u8 = 1 << 2;
+    /// USER_WP: CMD28 protects until the next power cycle.
+    pub const EXT_CSD_US_PWR_WP_EN: u8 = 1 << 0;
+    /// USER_WP: CMD28 protects for good.
+    pub const EXT_CSD_US_PERM_WP_EN: u8 = 1 << 2;
+    /// USER_WP: power-on protection of the user area is disabled until the next power cycle.
+    pub const EXT_CSD_US_PWR_WP_DIS: u8 = 1 << 3;
+    /// USER_WP: permanent protection of the user area is disabled for good.
+    pub const EXT_CSD_US_PERM_WP_DIS: u8 = 1 << 4;
+    /// BOOT_WP: protect the boot areas until the next power cycle.
+    pub const EXT_CSD_B_PWR_WP_EN: u8 = 1 << 0;
+    /// BOOT_WP: with `EXT_CSD_B_SEC_WP_SEL`, `EXT_CSD_B_PWR_WP_EN` applies to area 2 instead of area 1.
+    pub const EXT_CSD_B_PWR_WP_SEC_SEL: u8 = 1 << 1;
+    /// BOOT_WP: protect the boot areas for good.
+    pub const EXT_CSD_B_PERM_WP_EN: u8 = 1 << 2;
+    /// BOOT_WP: with `EXT_CSD_B_SEC_WP_SEL`, `EXT_CSD_B_PERM_WP_EN` applies to area 2 instead of area 1.
+    pub const EXT_CSD_B_PERM_WP_SEC_SEL: u8 = 1 << 3;
+    /// BOOT_WP: permanent protection of the boot areas is disabled for good.
+    pub const EXT_CSD_B_PERM_WP_DIS: u8 = 1 << 4;
+    /// BOOT_WP: power-on protection of the boot areas is disabled until the next power cycle.
+    pub const EXT_CSD_B_PWR_WP_DIS: u8 = 1 << 6;
+    /// BOOT_WP: the enable bits apply to the area selected by the `*_SEC_SEL` bits instead of both.
+    pub const EXT_CSD_B_SEC_WP_SEL: u8 = 1 << 7;
+    /// MODE_OPERATION_CODES: install the downloaded firmware.
+    pub const EXT_CSD_FFU_INSTALL: u8 = 0x01;
+    /// MODE_OPERATION_CODES: abort the firmware update.
+    pub const EXT_CSD_FFU_ABORT: u8 = 0x02;
+    /// HPI_FEATURES: HPI is supported.
+    pub const EXT_CSD_HPI_SUPPORT: u8 = 1 << 0;
+    /// HPI_FEATURES: HPI is sent with CMD12.
+    pub const EXT_CSD_HPI_IMPL_CMD12: u8 = 1 << 1;
+    /// EXCEPTION_EVENTS_STATUS and _CTRL: background operations are urgent, BKOPS_STATUS 2 or more.
+    pub const EXT_CSD_URGENT_BKOPS: u8 = 1 << 0;
+    /// BKOPS_EN: the host starts background operations with BKOPS_START. One-time programmable.
+    pub const EXT_CSD_MANUAL_BKOPS_EN: u8 = 1 << 0;
+    /// BKOPS_EN: the device runs background operations by itself when idle.
+    pub const EXT_CSD_AUTO_BKOPS_EN: u8 = 1 << 1;
+    /// CARD_TYPE: high speed timing at up to 26 MHz.
+    pub const EXT_CSD_CARD_TYPE_HS_26: u8 = 1 << 0;
+    /// CARD_TYPE: high speed timing at up to 52 MHz.
+    pub const EXT_CSD_CARD_TYPE_HS_52: u8 = 1 << 1;
+}
+
+/// The EXT_CSD register read with CMD8.
+#[derive(Clone)]
+pub struct ExtCsd(pub [u8; ext_csd_bits::EXT_CSD_SIZE]);
+
+impl ExtCsd {
+    pub const fn empty() -> Self {
+        Self([0; ext_csd_bits::EXT_CSD_SIZE])
+    }
+
+    /// Return the raw byte at `offset`.
+    pub fn byte(&self, offset: usize) -> u8 {
+        self.0[offset]
+    }
+
+    /// Return the little endian 32-bit field starting at `offset`.
+    pub fn u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes([self.0[offset], self.0[offset + 1], self.0[offset + 2], self.0[offset + 3]])
+    }
+
+    /// Extended CSD revision, 8 for eMMC 5.1.
+    pub fn rev(&self) -> u8 {
+        self.byte(ext_csd_bits::EXT_CSD_REV)
+    }
+
+    /// Device density in 512-byte sectors, only valid for high capacity devices.
+    pub fn sec_count(&self) -> u32 {
+        self.u32(ext_csd_bits::EXT_CSD_SEC_COUNT)
+    }
+}
@@ -0,0 +1,157 @@
+use log::{info, warn};
+
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, Data, Request, SDHCI};
+use crate::sdhci_cmd::mmc_cmd_idx::MMC_WRITE_MULTIPLE_BLOCK;
+use crate::sdhci_cmd::mmc_resp_type::MMC_RESP_R1;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+/// Result of the last firmware update, FFU_STATUS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FfuStatus {
+    Success,
+    GeneralError,
+    /// The firmware was downloaded but could not be installed.
+    InstallError,
+    /// The firmware download failed.
+    DownloadError,
+    Other(u8),
+}
+
+impl FfuStatus {
+    fn from_ext_csd(value: u8) -> Self {
+        match value {
+            0x00 => FfuStatus::Success,
+            0x10 => FfuStatus::GeneralError,
+            0x11 => FfuStatus::InstallError,
+            0x12 => FfuStatus::DownloadError,
+            value => FfuStatus::Other(value),
+        }
+    }
+}
+
+/// How far `SDHCI::ffu` got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FfuOutcome {
+    /// The new firmware runs, with this FIRMWARE_VERSION.
+    Installed([u8; 8]),
+    /// The firmware is downloaded and the device installs it with the next power cycle, after
+    /// which `init` must run and `Card::ffu_status` tells whether it worked.
+    PowerCycle,
+}
+
+impl Card {
+    /// Whether the firmware can be updated in the field, from SUPPORTED_MODES and FFU_CONFIG.
+    pub fn supports_ffu(&self) -> bool {
+        self.card_type == CardType::Mmc
+            && self.ext_csd.rev() >= 7
+            && self.ext_csd.byte(EXT_CSD_SUPPORTED_MODES) & 0x01 != 0
+            && self.ext_csd.byte(EXT_CSD_FFU_CONFIG) & 0x01 == 0
+    }
+
+    pub fn ffu_status(&self) -> FfuStatus {
+        FfuStatus::from_ext_csd(self.ext_csd.byte(EXT_CSD_FFU_STATUS))
+    }
+
+    /// FIRMWARE_VERSION, vendor specific.
+    pub fn firmware_version(&self) -> [u8; 8] {
+        core::array::from_fn(|i| self.ext_csd.byte(EXT_CSD_FIRMWARE_VERSION + i))
+    }
+
+    /// Bytes in a data sector, which the firmware download is made of.
+    pub fn data_sector_size(&self) -> usize {
+        if self.ext_csd.byte(EXT_CSD_DATA_SECTOR_SIZE) & 0x01 != 0 { 4096 } else { BLOCK_SIZE }
+    }
+
+    /// Busy time of MODE_OPERATION_CODES, the switch timeout if OPERATION_CODE_TIMEOUT is not set.
+    fn operation_code_timeout_us(&self) -> u64 {
+        match self.ext_csd.byte(EXT_CSD_OPERATION_CODE_TIMEOUT) {
+            0 => self.switch_timeout_us(),
+            time => 100 << time.min(0x17),
+        }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Update the firmware of the device with `firmware`, a whole number of data sectors as the
+    /// vendor ships it.
+    ///
+    /// The device enters FFU mode with MODE_CONFIG, the firmware is downloaded with CMD25 to
+    /// FFU_ARG and its sectors checked against NUMBER_OF_FW_SECTORS_CORRECTLY_PROGRAMMED. It is
+    /// then installed with MODE_OPERATION_CODES if FFU_FEATURES supports it, and FFU_STATUS
+    /// checked, failing with `MmcError::FfuFailed`. Otherwise the device is put back in the normal
+    /// mode and installs it with the next power cycle. A failed download leaves the device in the
+    /// normal mode with its old firmware.
+    pub fn ffu(&self, firmware: &[u8]) -> Result<FfuOutcome, MmcError> {
+        self.check_card()?;
+        let (ffu_arg, op_codes, sector_size, old_version) = {
+            let card = self.card.lock();
+            // The download is sized with CMD23.
+            if !card.supports_ffu() || !card.supports_cmd23() {
+                return Err(MmcError::Unsupported);
+            }
+            let ffu_arg = card.ext_csd.u32(EXT_CSD_FFU_ARG);
+            let op_codes = card.ext_csd.byte(EXT_CSD_FFU_FEATURES) & 0x01 != 0;
+            (ffu_arg, op_codes, card.data_sector_size(), card.firmware_version())
+        };
+        if firmware.is_empty() || !firmware.len().is_multiple_of(sector_size) {
+            return Err(MmcError::InvalidArgument);
+        }
+
+        info!("FFU: downloading {} bytes, firmware {:02x?}", firmware.len(), old_version);
+        self.flush_cache()?;
+        self.mmc_switch(EXT_CSD_MODE_CONFIG, 0x01)?;
+        if let Err(err) = self.ffu_download(ffu_arg, firmware, sector_size) {
+            warn!("FFU: download failed: {:?}", err);
+            let _ = self.mmc_switch(EXT_CSD_MODE_CONFIG, 0x00);
+            return Err(err);
+        }
+
+        if !op_codes {
+            self.mmc_switch(EXT_CSD_MODE_CONFIG, 0x00)?;
+            info!("FFU: firmware downloaded, installed with the next power cycle");
+            return Ok(FfuOutcome::PowerCycle);
+        }
+        // The device is back in the normal mode once done.
+        let timeout = self.card.lock().operation_code_timeout_us();
+        self.switch_busy(EXT_CSD_MODE_OPERATION_CODES, EXT_CSD_FFU_INSTALL, timeout)?;
+        let ext_csd = self.mmc_send_ext_csd()?;
+        let (status, version) = {
+            let mut card = self.card.lock();
+            card.ext_csd = ext_csd;
+            (card.ext_csd.byte(EXT_CSD_FFU_STATUS), card.firmware_version())
+        };
+        if status != 0 {
+            warn!("FFU: install failed: {:?}", FfuStatus::from_ext_csd(status));
+            return Err(MmcError::FfuFailed(status));
+        }
+        if version == old_version {
+            warn!("FFU: firmware version unchanged");
+        }
+        info!("FFU: firmware {:02x?} installed", version);
+        Ok(FfuOutcome::Installed(version))
+    }
+
+    /// Download `firmware` in FFU mode, each CMD25 to `ffu_arg`, and check the device took every sector.
+    fn ffu_download(&self, ffu_arg: u32, firmware: &[u8], sector_size: usize) -> Result<(), MmcError> {
+        let timeout = self.card.lock().write_timeout_us();
+        for chunk in firmware.chunks(u16::MAX as usize / (sector_size / BLOCK_SIZE) * sector_size) {
+            let blocks = (chunk.len() / BLOCK_SIZE) as u32;
+            let mut req = Request::new(MMC_WRITE_MULTIPLE_BLOCK, 0, MMC_RESP_R1, ffu_arg)
+                .with_data(Data::Write(chunk))
+                .with_sbc(blocks);
+            self.execute(&mut req)?;
+            self.wait_ready(timeout)?;
+        }
+
+        let ext_csd = self.mmc_send_ext_csd()?;
+        let sectors = ext_csd.u32(EXT_CSD_NUMBER_OF_FW_SECTORS_CORRECTLY_PROGRAMMED) as usize;
+        self.card.lock().ext_csd = ext_csd;
+        if sectors != firmware.len() / sector_size {
+            warn!("FFU: {} of {} sectors programmed", sectors, firmware.len() / sector_size);
+            return Err(MmcError::VerifyFailed);
+        }
+        Ok(())
+    }
+}
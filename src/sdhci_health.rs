@@ -0,0 +1,143 @@
+use log::{info, warn};
+
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, Data, Request, SDHCI};
+use crate::sdhci_cmd::mmc_cmd_idx::{MMC_GEN_CMD, MMC_GEN_CMD_RD};
+use crate::sdhci_cmd::mmc_resp_type::MMC_RESP_R1;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+/// Consumption of the reserved blocks replacing worn out ones, PRE_EOL_INFO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreEol {
+    /// Not reported, e.g. before eMMC 5.0.
+    Undefined,
+    Normal,
+    /// 80% of the reserved blocks are consumed.
+    Warning,
+    /// 90% of the reserved blocks are consumed, the device should be replaced.
+    Urgent,
+}
+
+/// Estimated life time used of a memory type, DEVICE_LIFE_TIME_EST_TYP_A or _B.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LifeTime {
+    /// Not reported, e.g. before eMMC 5.0.
+    Undefined,
+    /// At most this percentage of the life time is used, in steps of 10.
+    Used(u8),
+    /// The estimated life time is exceeded.
+    Exceeded,
+}
+
+impl LifeTime {
+    fn from_ext_csd(value: u8) -> Self {
+        match value {
+            1..=10 => LifeTime::Used(value * 10),
+            11 => LifeTime::Exceeded,
+            _ => LifeTime::Undefined,
+        }
+    }
+
+    /// Wear as a percentage of the estimated life time, the upper bound of the step reported.
+    /// Over 100 once it is exceeded.
+    pub fn wear_percent(&self) -> Option<u8> {
+        match self {
+            LifeTime::Undefined => None,
+            LifeTime::Used(percent) => Some(*percent),
+            LifeTime::Exceeded => Some(110),
+        }
+    }
+}
+
+/// Wear of the device, see `SDHCI::health`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Health {
+    pub pre_eol: PreEol,
+    /// SLC memory, e.g. the enhanced partitions.
+    pub slc: LifeTime,
+    /// MLC memory.
+    pub mlc: LifeTime,
+    /// The vendor health report read with CMD56, if asked for with `SDHCI::set_health_report`.
+    /// Its layout is vendor specific.
+    pub vendor_report: Option<[u8; BLOCK_SIZE]>,
+}
+
+impl Health {
+    /// Whether the device is worn out or close to it, either pre-EOL urgent or past its life time.
+    pub fn is_critical(&self) -> bool {
+        self.pre_eol == PreEol::Urgent || self.slc == LifeTime::Exceeded || self.mlc == LifeTime::Exceeded
+    }
+}
+
+impl Card {
+    /// The health from PRE_EOL_INFO and DEVICE_LIFE_TIME_EST_TYP_A/B of the copy of the EXT_CSD,
+    /// `None` for an SD card.
+    pub fn health(&self) -> Option<Health> {
+        if self.card_type != CardType::Mmc {
+            return None;
+        }
+        Some(Health {
+            pre_eol: match self.ext_csd.byte(EXT_CSD_PRE_EOL_INFO) {
+                1 => PreEol::Normal,
+                2 => PreEol::Warning,
+                3 => PreEol::Urgent,
+                _ => PreEol::Undefined,
+            },
+            slc: LifeTime::from_ext_csd(self.ext_csd.byte(EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_A)),
+            mlc: LifeTime::from_ext_csd(self.ext_csd.byte(EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_B)),
+            vendor_report: None,
+        })
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Read the vendor health report with CMD56 and `arg` in `health`, `None` to skip it.
+    ///
+    /// The argument selecting the report is vendor specific, the read bit is added to it.
+    pub fn set_health_report(&mut self, arg: Option<u32>) {
+        self.health_report_arg = arg;
+    }
+
+    /// Read the wear of the device from a fresh EXT_CSD, and the vendor health report if one is
+    /// set with `set_health_report`.
+    ///
+    /// An urgent pre-EOL or an exceeded life time is logged as a warning. A vendor report the
+    /// device refuses is logged and left out.
+    pub fn health(&self) -> Result<Health, MmcError> {
+        self.check_card()?;
+        if self.card.lock().card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        }
+        let ext_csd = self.with_recovery(|| self.mmc_send_ext_csd())?;
+        let mut health = {
+            let mut card = self.card.lock();
+            card.ext_csd = ext_csd;
+            card.health().ok_or(MmcError::Unsupported)?
+        };
+
+        if let Some(arg) = self.health_report_arg {
+            let mut report = [0; BLOCK_SIZE];
+            match self.gen_cmd_read(arg, &mut report) {
+                Ok(()) => health.vendor_report = Some(report),
+                Err(err) => warn!("vendor health report (CMD56 {:#x}): {:?}", arg, err),
+            }
+        }
+
+        if health.is_critical() {
+            warn!("eMMC worn out: pre-EOL {:?}, SLC {:?}, MLC {:?}", health.pre_eol, health.slc, health.mlc);
+        } else {
+            info!("eMMC health: pre-EOL {:?}, SLC {:?}, MLC {:?}", health.pre_eol, health.slc, health.mlc);
+        }
+        Ok(health)
+    }
+
+    /// Read the data block of the general command CMD56 with `arg`.
+    pub fn gen_cmd_read(&self, arg: u32, buf: &mut [u8; BLOCK_SIZE]) -> Result<(), MmcError> {
+        self.check_card()?;
+        self.with_recovery(|| {
+            let mut req = Request::new(MMC_GEN_CMD, 0, MMC_RESP_R1, arg | MMC_GEN_CMD_RD).with_data(Data::Read(buf));
+            self.execute(&mut req).map(|_| ())
+        })
+    }
+}
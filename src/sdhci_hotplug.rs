@@ -0,0 +1,151 @@
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use log::{info, warn};
+
+use crate::sdhci::{Card, SDHCI};
+use crate::sdhci_err::MmcError;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_timer::timer;
+use crate::sdhci_tuning::Tuning;
+
+/// Time the card detect state must stay unchanged before an insertion or removal is reported.
+pub const CARD_DETECT_DEBOUNCE_US: u64 = 100_000;
+/// Card detect becoming stable after a reset of the controller.
+pub const CARD_STABLE_TIMEOUT_US: u64 = 100_000;
+
+/// A change of the card detect state, reported once it is debounced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardEvent {
+    /// A card was inserted. It has been initialised if `SDHCI::card` reports it.
+    Inserted,
+    /// The card was removed, requests fail with `MmcError::NoCard` until the next insertion.
+    Removed,
+}
+
+/// Card detect state of a removable slot.
+pub(crate) struct Hotplug {
+    /// The card is soldered, as `non-removable` in the device tree: card detect is ignored.
+    pub(crate) non_removable: bool,
+    /// Debounced card detect state.
+    present: bool,
+    /// Card detect state differing from `present`, and since when.
+    candidate: Option<(bool, Duration)>,
+    handler: Option<fn(CardEvent)>,
+}
+
+impl Hotplug {
+    pub(crate) const fn new(non_removable: bool) -> Self {
+        Self { non_removable, present: true, candidate: None, handler: None }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Declare the card soldered, skipping card detect. This is the default for the RK3568
+    /// eMMC controller, and should follow `non-removable` of the device tree node.
+    pub fn set_non_removable(&mut self, non_removable: bool) {
+        self.hotplug.lock().non_removable = non_removable;
+    }
+
+    /// Set the function `poll_card_detect` calls with each event it reports.
+    pub fn set_card_event_handler(&mut self, handler: fn(CardEvent)) {
+        self.hotplug.lock().handler = Some(handler);
+    }
+
+    /// Return whether a card was present at the last debounced card detect.
+    pub fn card_present(&self) -> bool {
+        let hotplug = self.hotplug.lock();
+        hotplug.non_removable || hotplug.present
+    }
+
+    /// A card detect interrupt was latched, or a change is being debounced: `poll_card_detect`
+    /// should be called again after `CARD_DETECT_DEBOUNCE_US`.
+    pub fn card_detect_pending(&self) -> bool {
+        self.card_detect_int.load(Ordering::Acquire) || self.hotplug.lock().candidate.is_some()
+    }
+
+    /// Sample card detect and report an insertion or a removal once it has been stable for
+    /// `CARD_DETECT_DEBOUNCE_US`.
+    ///
+    /// Call it periodically when polling, or after `handle_irq` when `card_detect_pending`
+    /// returns true. An inserted card is initialised before the event is reported; a removed
+    /// one is powered off. Neither happens under a request in flight, the event is then reported
+    /// by the first call after it.
+    pub fn poll_card_detect(&self) -> Option<CardEvent> {
+        if self.hotplug.lock().non_removable {
+            return None;
+        }
+        self.card_detect_int.store(false, Ordering::Release);
+
+        let stable = self.reg.emmc_card_is_stable();
+        let inserted = self.reg.emmc_card_is_inserted();
+        let now = timer().now();
+        let event = {
+            let mut hotplug = self.hotplug.lock();
+            if !stable || inserted == hotplug.present {
+                hotplug.candidate = None;
+                return None;
+            }
+            match hotplug.candidate {
+                Some((state, since)) if state == inserted => {
+                    if now - since < Duration::from_micros(CARD_DETECT_DEBOUNCE_US) {
+                        return None;
+                    }
+                }
+                _ => {
+                    hotplug.candidate = Some((inserted, now));
+                    return None;
+                }
+            }
+            if self.in_flight() {
+                return None;
+            }
+            hotplug.candidate = None;
+            hotplug.present = inserted;
+            if inserted { CardEvent::Inserted } else { CardEvent::Removed }
+        };
+
+        match event {
+            CardEvent::Inserted => {
+                info!("card inserted");
+                if let Err(err) = self.init() {
+                    warn!("initialising the inserted card failed: {:?}", err);
+                }
+            }
+            CardEvent::Removed => {
+                info!("card removed");
+                self.reg.emmc_disable_sd_clk();
+                self.reg.emmc_pwr_off();
+                *self.card.lock() = Card::empty();
+                *self.tuning.lock() = Tuning::new();
+            }
+        }
+        if let Some(handler) = self.hotplug.lock().handler {
+            handler(event);
+        }
+        Some(event)
+    }
+
+    /// Fail with `MmcError::NoCard` if the slot is known to be empty.
+    pub(crate) fn check_card(&self) -> Result<(), MmcError> {
+        if self.card_present() { Ok(()) } else { Err(MmcError::NoCard) }
+    }
+
+    /// Card detect of a removable slot currently reads empty, without debouncing.
+    pub(crate) fn card_is_gone(&self) -> bool {
+        !self.hotplug.lock().non_removable && !self.reg.emmc_card_is_inserted()
+    }
+
+    /// Wait for card detect to settle after a reset and record whether a card is present.
+    pub(crate) fn detect_card(&self) -> Result<(), MmcError> {
+        if self.hotplug.lock().non_removable {
+            return Ok(());
+        }
+        crate::wait_timeout("card detect stable", CARD_STABLE_TIMEOUT_US, || self.reg.emmc_card_is_stable())?;
+        let inserted = self.reg.emmc_card_is_inserted();
+        let mut hotplug = self.hotplug.lock();
+        hotplug.present = inserted;
+        hotplug.candidate = None;
+        if inserted { Ok(()) } else { Err(MmcError::NoCard) }
+    }
+}
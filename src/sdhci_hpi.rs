@@ -0,0 +1,146 @@
+use core::sync::atomic::Ordering;
+
+use log::{info, warn};
+
+use crate::poll_timeout;
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, Request, SDHCI, status_is_ready};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_resp_type::{MMC_RESP_R1, MMC_RESP_R1B};
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+/// Time to leave an interrupted operation when OUT_OF_INTERRUPT_TIME is not defined.
+pub const DEFAULT_OUT_OF_INTERRUPT_TIME_US: u64 = 100_000;
+
+impl Card {
+    /// Whether the device supports the High Priority Interrupt, from HPI_FEATURES.
+    pub fn hpi_supported(&self) -> bool {
+        self.card_type == CardType::Mmc && self.ext_csd.byte(EXT_CSD_HPI_FEATURES) & EXT_CSD_HPI_SUPPORT != 0
+    }
+
+    /// HPI is enabled with HPI_MGMT.
+    pub fn hpi_enabled(&self) -> bool {
+        self.ext_csd.byte(EXT_CSD_HPI_MGMT) & 0x01 != 0
+    }
+
+    /// Time the device takes to leave an operation interrupted with HPI, from OUT_OF_INTERRUPT_TIME.
+    pub fn out_of_interrupt_timeout_us(&self) -> u64 {
+        match self.ext_csd.byte(EXT_CSD_OUT_OF_INTERRUPT_TIME) {
+            0 => DEFAULT_OUT_OF_INTERRUPT_TIME_US,
+            time => time as u64 * 10_000,
+        }
+    }
+
+    /// Sectors of the last write interrupted with HPI the device programmed, from
+    /// CORRECTLY_PRG_SECTORS_NUM.
+    pub fn correctly_programmed_sectors(&self) -> u32 {
+        self.ext_csd.u32(EXT_CSD_CORRECTLY_PRG_SECTORS_NUM)
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Interrupt the long operation the device is busy with, returning whether there was one.
+    ///
+    /// Meant for latency-sensitive I/O that must not wait behind a write or an erase, e.g. from
+    /// the idle function while they are waited for, see `set_idle`. The waiting `write_blocks` or
+    /// `erase` sends HPI at its next CMD13 poll and fails with `MmcError::Interrupted` once the
+    /// device is out of the operation, within OUT_OF_INTERRUPT_TIME. Background operations
+    /// started with `start_bkops` are interrupted at once, unless a request is in flight which
+    /// leaves them to the next command. Nothing is interrupted without HPI.
+    pub fn interrupt_current_operation(&self) -> Result<bool, MmcError> {
+        self.check_card()?;
+        if !self.card.lock().hpi_supported() {
+            return Ok(false);
+        }
+        if self.long_op.load(Ordering::Acquire) {
+            self.hpi_request.store(true, Ordering::Release);
+            return Ok(true);
+        }
+        if self.in_flight() {
+            return Ok(false);
+        }
+        self.interrupt_bkops()
+    }
+
+    /// Finish a `write_blocks` of `buf` at `lba` interrupted with `MmcError::Interrupted`,
+    /// writing again the blocks past CORRECTLY_PRG_SECTORS_NUM. Returns the blocks written.
+    pub fn resume_write(&self, lba: u32, buf: &[u8]) -> Result<usize, MmcError> {
+        self.check_card()?;
+        if self.card.lock().card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        }
+        let ext_csd = self.with_recovery(|| self.mmc_send_ext_csd())?;
+        let done = {
+            let mut card = self.card.lock();
+            card.ext_csd = ext_csd;
+            card.correctly_programmed_sectors() as usize * card.data_sector_size() / BLOCK_SIZE
+        };
+        let rest = &buf[(done * BLOCK_SIZE).min(buf.len())..];
+        info!("resuming the write at {:#x} after {} blocks", lba, done);
+        if !rest.is_empty() {
+            self.write_blocks(lba + done as u32, rest)?;
+        }
+        Ok(rest.len() / BLOCK_SIZE)
+    }
+
+    /// Poll CMD13 like `wait_ready`, sending HPI and failing with `MmcError::Interrupted` once
+    /// `interrupt_current_operation` asks for it.
+    pub(crate) fn wait_ready_interruptible(&self, timeout_us: u64) -> Result<(), MmcError> {
+        self.hpi_request.store(false, Ordering::Release);
+        self.long_op.store(true, Ordering::Release);
+        let result = poll_timeout("transfer state (CMD13)", timeout_us, || match self.sdhci_send_status() {
+            Ok(status) if !status_is_ready(status) => {
+                if self.hpi_request.swap(false, Ordering::AcqRel) {
+                    return Some(Err(MmcError::Interrupted));
+                }
+                (self.idle)();
+                None
+            }
+            result => Some(result.map(|_| ())),
+        });
+        self.long_op.store(false, Ordering::Release);
+
+        match result? {
+            Err(MmcError::Interrupted) => {
+                warn!("operation interrupted with HPI");
+                self.send_hpi()?;
+                Err(MmcError::Interrupted)
+            }
+            result => result,
+        }
+    }
+
+    /// Enable HPI with HPI_MGMT if the device supports it, returning whether it is enabled.
+    pub(crate) fn hpi_enable(&self) -> Result<bool, MmcError> {
+        let (supported, enabled) = {
+            let card = self.card.lock();
+            (card.hpi_supported(), card.hpi_enabled())
+        };
+        if supported && !enabled {
+            self.mmc_switch(EXT_CSD_HPI_MGMT, 1)?;
+        }
+        Ok(supported)
+    }
+
+    /// Send HPI, CMD12 or CMD13 with the HPI bit as HPI_FEATURES asks, and wait until the
+    /// device has left the interrupted operation.
+    pub(crate) fn send_hpi(&self) -> Result<(), MmcError> {
+        let (mut req, timeout) = self.hpi_request();
+        self.execute(&mut req)?;
+        self.wait_ready(timeout)
+    }
+
+    /// Build the HPI request, with the time the device then takes to leave the operation.
+    pub(crate) fn hpi_request(&self) -> (Request<'static>, u64) {
+        let card = self.card.lock();
+        let arg = (card.rca as u32) << 16 | MMC_HPI_BIT;
+        let timeout = card.out_of_interrupt_timeout_us();
+        let req = if card.ext_csd.byte(EXT_CSD_HPI_FEATURES) & EXT_CSD_HPI_IMPL_CMD12 != 0 {
+            Request::new(MMC_STOP_TRANSMISSION, 0, MMC_RESP_R1B, arg).with_busy_timeout(timeout)
+        } else {
+            Request::new(MMC_SEND_STATUS, 0, MMC_RESP_R1, arg)
+        };
+        (req, timeout)
+    }
+}
@@ -0,0 +1,126 @@
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::sdhci_reg::Mmio;
+
+/// One register access seen by `MockMmio`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read { addr: u64, width: u8, value: u32 },
+    Write { addr: u64, width: u8, value: u32 },
+}
+
+type WriteHook = Rc<dyn Fn(&MockMmio, u32)>;
+
+/// MMIO backend for host tests, enabled with the `mock` feature.
+///
+/// Registers are kept as a sparse byte array, so accesses of different widths to the same
+/// register see each other's values. Unwritten bytes read as zero. On top of that:
+///
+/// - `on_read` queues values returned by the next reads of a register, in place of its content
+/// - `on_write` runs a hook after each write to a register, e.g. to raise CMD_COMPLETE once the
+///   command register is written
+/// - `write_one_to_clear` makes writes clear the set bits, like the interrupt status registers
+///
+/// Every access is recorded and can be inspected with `accesses` and `writes_to`.
+#[derive(Default)]
+pub struct MockMmio {
+    regs: RefCell<BTreeMap<u64, u8>>,
+    reads: RefCell<BTreeMap<u64, VecDeque<u32>>>,
+    hooks: RefCell<BTreeMap<u64, WriteHook>>,
+    w1c: RefCell<BTreeSet<u64>>,
+    log: RefCell<Vec<Access>>,
+}
+
+impl MockMmio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the content of the `width` bytes at `addr`, without recording an access.
+    pub fn set_reg(&self, addr: u64, width: u8, value: u32) {
+        let mut regs = self.regs.borrow_mut();
+        for i in 0..width as u64 {
+            regs.insert(addr + i, (value >> (8 * i)) as u8);
+        }
+    }
+
+    /// Return the content of the `width` bytes at `addr`, without recording an access.
+    pub fn get_reg(&self, addr: u64, width: u8) -> u32 {
+        let regs = self.regs.borrow();
+        (0..width as u64).fold(0, |value, i| value | (*regs.get(&(addr + i)).unwrap_or(&0) as u32) << (8 * i))
+    }
+
+    /// Set bits of the register at `addr`, e.g. to raise an interrupt status bit from a hook.
+    pub fn set_bits(&self, addr: u64, width: u8, bits: u32) {
+        self.set_reg(addr, width, self.get_reg(addr, width) | bits);
+    }
+
+    /// Return `values` from the next reads of `addr`, then its content again.
+    pub fn on_read(&self, addr: u64, values: &[u32]) {
+        self.reads.borrow_mut().entry(addr).or_default().extend(values);
+    }
+
+    /// Call `hook` with the written value after each write to `addr`.
+    pub fn on_write(&self, addr: u64, hook: impl Fn(&MockMmio, u32) + 'static) {
+        self.hooks.borrow_mut().insert(addr, Rc::new(hook) as WriteHook);
+    }
+
+    /// Make writes to the register at `addr` clear the bits set in the written value.
+    pub fn write_one_to_clear(&self, addr: u64) {
+        self.w1c.borrow_mut().insert(addr);
+    }
+
+    /// Return every access since the last `clear_accesses`, oldest first.
+    pub fn accesses(&self) -> Vec<Access> {
+        self.log.borrow().clone()
+    }
+
+    /// Return the values written to `addr`, oldest first.
+    pub fn writes_to(&self, addr: u64) -> Vec<u32> {
+        self.log
+            .borrow()
+            .iter()
+            .filter_map(|access| match *access {
+                Access::Write { addr: a, value, .. } if a == addr => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn clear_accesses(&self) {
+        self.log.borrow_mut().clear();
+    }
+
+    fn read(&self, addr: u64, width: u8) -> u32 {
+        let scripted = self.reads.borrow_mut().get_mut(&addr).and_then(|queue| queue.pop_front());
+        let value = scripted.unwrap_or_else(|| self.get_reg(addr, width));
+        self.log.borrow_mut().push(Access::Read { addr, width, value });
+        value
+    }
+
+    fn write(&self, addr: u64, width: u8, value: u32) {
+        self.log.borrow_mut().push(Access::Write { addr, width, value });
+        if self.w1c.borrow().contains(&addr) {
+            self.set_reg(addr, width, self.get_reg(addr, width) & !value);
+        } else {
+            self.set_reg(addr, width, value);
+        }
+        // The hook may register further hooks, so the map is not borrowed while it runs.
+        let hook = self.hooks.borrow().get(&addr).cloned();
+        if let Some(hook) = hook {
+            hook(self, value);
+        }
+    }
+}
+
+impl Mmio for MockMmio {
+    fn read32(&self, addr: u64) -> u32 { self.read(addr, 4) }
+    fn write32(&self, addr: u64, value: u32) { self.write(addr, 4, value) }
+    fn read16(&self, addr: u64) -> u16 { self.read(addr, 2) as u16 }
+    fn write16(&self, addr: u64, value: u16) { self.write(addr, 2, value as u32) }
+    fn read8(&self, addr: u64) -> u8 { self.read(addr, 1) as u8 }
+    fn write8(&self, addr: u64, value: u8) { self.write(addr, 1, value as u32) }
+}
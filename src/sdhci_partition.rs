@@ -0,0 +1,233 @@
+use core::ops::Range;
+
+use log::{info, warn};
+
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, SDHCI};
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+/// Largest value of the 3-byte size fields.
+const SIZE_MULT_MAX: u32 = 0xff_ffff;
+
+/// Use of a general purpose partition, its field of EXT_PARTITIONS_ATTRIBUTE.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GpAttribute {
+    #[default]
+    Default,
+    /// Rarely updated data such as the system image.
+    SystemCode,
+    /// Temporary data that need not survive a power loss.
+    NonPersistent,
+}
+
+/// A general purpose partition to make, none if `blocks` is 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GpPartition {
+    /// Blocks of `BLOCK_SIZE` bytes, rounded up to `Card::partition_unit_blocks`.
+    pub blocks: u32,
+    /// Enhanced memory, e.g. pSLC.
+    pub enhanced: bool,
+    pub attribute: GpAttribute,
+}
+
+/// Partitions of the device to make with `SDHCI::provision_partitions`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartitionLayout {
+    /// GP1 to GP4.
+    pub gp: [GpPartition; 4],
+    /// Blocks of the user data area to make enhanced, empty for none. The start must be aligned
+    /// to `Card::partition_unit_blocks` and the end is rounded up to it.
+    pub enhanced_user: Range<u32>,
+}
+
+/// The partitioning attributes computed from a `PartitionLayout`, as written to the EXT_CSD.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartitionSettings {
+    /// Blocks in the unit of the sizes, HC_WP_GRP_SIZE × HC_ERASE_GRP_SIZE × 512 KiB.
+    pub unit_blocks: u32,
+    pub gp_size_mult: [u32; 4],
+    pub enh_start_addr: u32,
+    pub enh_size_mult: u32,
+    pub partitions_attribute: u8,
+    pub ext_partitions_attribute: u16,
+    /// Units of enhanced memory in all partitions, at most `max_enh_size_mult`.
+    pub enh_units: u32,
+    pub max_enh_size_mult: u32,
+}
+
+impl PartitionSettings {
+    /// The EXT_CSD fields to write as offset, value and length in bytes, in order,
+    /// PARTITION_SETTING_COMPLETED aside.
+    fn fields(&self) -> impl Iterator<Item = (usize, u32, usize)> {
+        let (enh, ext) = (self.enh_size_mult != 0, self.ext_partitions_attribute != 0);
+        let gp_size_mult = self.gp_size_mult;
+        let gp = (0..4).map(move |n| (EXT_CSD_GP_SIZE_MULT + 3 * n, gp_size_mult[n], 3));
+        [(EXT_CSD_ENH_START_ADDR, self.enh_start_addr, 4), (EXT_CSD_ENH_SIZE_MULT, self.enh_size_mult, 3)]
+            .into_iter()
+            .filter(move |_| enh)
+            .chain(gp)
+            .chain([(EXT_CSD_PARTITIONS_ATTRIBUTE, self.partitions_attribute as u32, 1)])
+            .chain([(EXT_CSD_EXT_PARTITIONS_ATTRIBUTE, self.ext_partitions_attribute as u32, 2)].into_iter().filter(move |_| ext))
+    }
+
+    /// The bytes of the fields, least significant first.
+    fn writes(&self) -> impl Iterator<Item = (usize, u8)> {
+        self.fields().flat_map(|(offset, value, len)| (0..len).map(move |i| (offset + i, (value >> (8 * i)) as u8)))
+    }
+}
+
+impl Card {
+    /// Blocks of `BLOCK_SIZE` bytes in the unit of the partition sizes, `None` if the device
+    /// cannot be partitioned.
+    pub fn partition_unit_blocks(&self) -> Option<u32> {
+        if self.card_type != CardType::Mmc
+            || self.ext_csd.byte(EXT_CSD_PARTITIONING_SUPPORT) & EXT_CSD_PARTITIONING_EN == 0
+        {
+            return None;
+        }
+        let wp_groups = self.ext_csd.byte(EXT_CSD_HC_WP_GRP_SIZE) as u32;
+        let erase_groups = self.ext_csd.byte(EXT_CSD_HC_ERASE_GRP_SIZE) as u32;
+        let unit = wp_groups * erase_groups * 1024;
+        (unit != 0).then_some(unit)
+    }
+
+    /// Whether the partitioning was made final with PARTITION_SETTING_COMPLETED.
+    pub fn partitioning_completed(&self) -> bool {
+        self.ext_csd.byte(EXT_CSD_PARTITION_SETTING_COMPLETED) & 0x01 != 0
+    }
+
+    /// The partitions the device has, or will have after the next power cycle once the
+    /// partitioning is completed. `None` if it cannot be partitioned.
+    pub fn partition_layout(&self) -> Option<PartitionLayout> {
+        let unit = self.partition_unit_blocks()?;
+        let attribute = self.ext_csd.byte(EXT_CSD_PARTITIONS_ATTRIBUTE);
+        let ext = self.ext_csd.byte(EXT_CSD_EXT_PARTITIONS_ATTRIBUTE) as u16
+            | (self.ext_csd.byte(EXT_CSD_EXT_PARTITIONS_ATTRIBUTE + 1) as u16) << 8;
+        let gp = core::array::from_fn(|n| GpPartition {
+            blocks: self.size_mult(EXT_CSD_GP_SIZE_MULT + 3 * n) * unit,
+            enhanced: attribute & 1 << (n + 1) != 0,
+            attribute: match ext >> (4 * n) & 0x0f {
+                1 => GpAttribute::SystemCode,
+                2 => GpAttribute::NonPersistent,
+                _ => GpAttribute::Default,
+            },
+        });
+        let start = self.ext_csd.u32(EXT_CSD_ENH_START_ADDR);
+        let start = if self.high_capacity { start } else { start / BLOCK_SIZE as u32 };
+        let enhanced_user = match attribute & 0x01 {
+            0 => 0..0,
+            _ => start..start + self.size_mult(EXT_CSD_ENH_SIZE_MULT) * unit,
+        };
+        Some(PartitionLayout { gp, enhanced_user })
+    }
+
+    /// The 3-byte size field at `offset`.
+    fn size_mult(&self, offset: usize) -> u32 {
+        u32::from_le_bytes([self.ext_csd.byte(offset), self.ext_csd.byte(offset + 1), self.ext_csd.byte(offset + 2), 0])
+    }
+
+    /// Compute the attributes of `layout`, checking the device supports it.
+    fn partition_settings(&self, layout: &PartitionLayout) -> Result<PartitionSettings, MmcError> {
+        let unit = self.partition_unit_blocks().ok_or(MmcError::Unsupported)?;
+        if self.partitioning_completed() {
+            return Err(MmcError::PartitioningCompleted);
+        }
+        let support = self.ext_csd.byte(EXT_CSD_PARTITIONING_SUPPORT);
+        let enhanced = !layout.enhanced_user.is_empty() || layout.gp.iter().any(|gp| gp.blocks != 0 && gp.enhanced);
+        let attributes = layout.gp.iter().any(|gp| gp.blocks != 0 && gp.attribute != GpAttribute::Default);
+        if enhanced && support & EXT_CSD_ENH_ATTRIBUTE_EN == 0 || attributes && support & EXT_CSD_EXT_ATTRIBUTE_EN == 0 {
+            return Err(MmcError::Unsupported);
+        }
+
+        let mut settings = PartitionSettings {
+            unit_blocks: unit,
+            gp_size_mult: [0; 4],
+            enh_start_addr: 0,
+            enh_size_mult: 0,
+            partitions_attribute: 0,
+            ext_partitions_attribute: 0,
+            enh_units: 0,
+            max_enh_size_mult: self.size_mult(EXT_CSD_MAX_ENH_SIZE_MULT),
+        };
+        let mut gp_blocks = 0u64;
+        for (n, gp) in layout.gp.iter().enumerate().filter(|(_, gp)| gp.blocks != 0) {
+            let mult = gp.blocks.div_ceil(unit);
+            settings.gp_size_mult[n] = mult;
+            gp_blocks += mult as u64 * unit as u64;
+            if gp.enhanced {
+                settings.partitions_attribute |= 1 << (n + 1);
+                settings.enh_units += mult;
+            }
+            let attribute = match gp.attribute {
+                GpAttribute::Default => 0,
+                GpAttribute::SystemCode => 1,
+                GpAttribute::NonPersistent => 2,
+            };
+            settings.ext_partitions_attribute |= attribute << (4 * n);
+        }
+
+        // The general purpose partitions are taken from the user data area.
+        let user_blocks = self.blocks.checked_sub(gp_blocks).ok_or(MmcError::InvalidArgument)?;
+        let enh = &layout.enhanced_user;
+        if !enh.is_empty() {
+            let mult = (enh.end - enh.start).div_ceil(unit);
+            if !enh.start.is_multiple_of(unit) || enh.start as u64 + mult as u64 * unit as u64 > user_blocks {
+                return Err(MmcError::InvalidArgument);
+            }
+            settings.enh_start_addr = if self.high_capacity { enh.start } else { enh.start * BLOCK_SIZE as u32 };
+            settings.enh_size_mult = mult;
+            settings.partitions_attribute |= 0x01;
+            settings.enh_units += mult;
+        }
+
+        if settings.gp_size_mult.iter().any(|&mult| mult > SIZE_MULT_MAX)
+            || settings.enh_size_mult > SIZE_MULT_MAX
+            || settings.enh_units > settings.max_enh_size_mult
+        {
+            return Err(MmcError::InvalidArgument);
+        }
+        Ok(settings)
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Compute the partitioning attributes of `layout` without writing anything, for a dry run
+    /// of `provision_partitions`.
+    ///
+    /// Fails with `MmcError::PartitioningCompleted` once the device is partitioned, with
+    /// `MmcError::Unsupported` if it cannot make such partitions, and with
+    /// `MmcError::InvalidArgument` if the layout does not fit, e.g. because it has more enhanced
+    /// memory than MAX_ENH_SIZE_MULT allows.
+    pub fn plan_partitions(&self, layout: &PartitionLayout) -> Result<PartitionSettings, MmcError> {
+        self.check_card()?;
+        self.card.lock().partition_settings(layout)
+    }
+
+    /// Partition the device as `layout` asks, once and for all.
+    ///
+    /// The attributes computed by `plan_partitions` are written with ERASE_GROUP_DEF set and read
+    /// back before PARTITION_SETTING_COMPLETED makes them final. The device applies them with the
+    /// next power cycle, after which `init` must run again. The content of the user data area is
+    /// lost then.
+    pub fn provision_partitions(&self, layout: &PartitionLayout) -> Result<PartitionSettings, MmcError> {
+        let settings = self.plan_partitions(layout)?;
+        info!("provisioning partitions: {:?}", settings);
+
+        self.mmc_switch(EXT_CSD_ERASE_GROUP_DEF, 0x01)?;
+        for (index, value) in settings.writes() {
+            self.mmc_switch(index, value)?;
+        }
+        let ext_csd = self.mmc_send_ext_csd()?;
+        if let Some((index, value)) = settings.writes().find(|&(index, value)| ext_csd.byte(index) != value) {
+            warn!("EXT_CSD[{}] reads back {:#x} instead of {:#x}", index, ext_csd.byte(index), value);
+            self.card.lock().ext_csd = ext_csd;
+            return Err(MmcError::VerifyFailed);
+        }
+        self.card.lock().ext_csd = ext_csd;
+
+        self.mmc_switch_verify(EXT_CSD_PARTITION_SETTING_COMPLETED, 0x01)?;
+        info!("partitioning completed, it takes effect with the next power cycle");
+        Ok(settings)
+    }
+}
@@ -0,0 +1,99 @@
+use log::info;
+
+use crate::sdhci_reg::Mmio;
+
+/// PCI class code of an SD host controller: base class 0x08 (system peripheral), sub-class 0x05.
+const PCI_CLASS_SDHCI: u32 = 0x0805;
+
+const PCI_VENDOR_ID: u64 = 0x00;
+const PCI_COMMAND: u64 = 0x04;
+const PCI_CLASS_REVISION: u64 = 0x08;
+const PCI_HEADER_TYPE: u64 = 0x0e;
+const PCI_BAR0: u64 = 0x10;
+const PCI_BAR1: u64 = 0x14;
+
+const PCI_COMMAND_MEMORY: u16 = 0x01 << 1;
+const PCI_COMMAND_MASTER: u16 = 0x01 << 2;
+const PCI_HEADER_MULTI_FUNCTION: u8 = 0x01 << 7;
+const PCI_BAR_IO: u32 = 0x01;
+const PCI_BAR_TYPE_64: u32 = 0x02 << 1;
+const PCI_BAR_FLAGS_MASK: u32 = 0x0f;
+
+/// An SD host controller found on a PCI bus, with BAR0 assigned and memory decoding enabled.
+#[derive(Clone, Copy, Debug)]
+pub struct PciSdhci {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    /// Bus address of the SDHCI registers.
+    pub bar0: u64,
+    /// Size of the BAR0 window in bytes.
+    pub bar0_size: u64,
+}
+
+/// Find the first SD host controller behind the ECAM configuration space of bus 0 mapped at `ecam`.
+///
+/// Without firmware, as on QEMU `virt`, the BARs are left unassigned: BAR0 is then placed at the
+/// first suitably aligned address from `mem_base`, which must be inside the memory window of the
+/// host bridge. Returns `None` if there is no such controller.
+pub fn probe<M: Mmio>(mmio: &M, ecam: u64, mem_base: u64) -> Option<PciSdhci> {
+    for device in 0..32 {
+        for function in 0..8 {
+            let cfg = ecam | (device as u64) << 15 | (function as u64) << 12;
+            if mmio.read32(cfg + PCI_VENDOR_ID) & 0xffff == 0xffff {
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+
+            if mmio.read32(cfg + PCI_CLASS_REVISION) >> 16 == PCI_CLASS_SDHCI {
+                let (bar0, bar0_size) = assign_bar0(mmio, cfg, mem_base)?;
+                let command = mmio.read16(cfg + PCI_COMMAND);
+                mmio.write16(cfg + PCI_COMMAND, command | PCI_COMMAND_MEMORY | PCI_COMMAND_MASTER);
+                info!("pci sdhci at 00:{:02x}.{}, bar0 {:#x} size {:#x}", device, function, bar0, bar0_size);
+                return Some(PciSdhci { bus: 0, device, function, bar0, bar0_size });
+            }
+
+            if function == 0 && mmio.read8(cfg + PCI_HEADER_TYPE) & PCI_HEADER_MULTI_FUNCTION == 0 {
+                break;
+            }
+        }
+    }
+    None
+}
+
+/// Size BAR0 and assign it from `mem_base` if the firmware has not, returning its address and size.
+fn assign_bar0<M: Mmio>(mmio: &M, cfg: u64, mem_base: u64) -> Option<(u64, u64)> {
+    let orig = mmio.read32(cfg + PCI_BAR0);
+    if orig & PCI_BAR_IO != 0 {
+        return None;
+    }
+    let is_64 = orig & PCI_BAR_TYPE_64 != 0;
+    let orig_high = if is_64 { mmio.read32(cfg + PCI_BAR1) } else { 0 };
+
+    // Memory decoding must be off while the BAR holds the sizing pattern.
+    let command = mmio.read16(cfg + PCI_COMMAND);
+    mmio.write16(cfg + PCI_COMMAND, command & !PCI_COMMAND_MEMORY);
+    mmio.write32(cfg + PCI_BAR0, 0xffff_ffff);
+    let mut mask = (mmio.read32(cfg + PCI_BAR0) & !PCI_BAR_FLAGS_MASK) as u64;
+    if is_64 {
+        mmio.write32(cfg + PCI_BAR1, 0xffff_ffff);
+        mask |= (mmio.read32(cfg + PCI_BAR1) as u64) << 32;
+    } else {
+        mask |= 0xffff_ffff << 32;
+    }
+    let size = (!mask).wrapping_add(1);
+
+    // An unimplemented BAR reads back all zeros, leave it as it was.
+    let implemented = mask as u32 != 0 || is_64 && mask >> 32 != 0;
+    let assigned = (orig_high as u64) << 32 | (orig & !PCI_BAR_FLAGS_MASK) as u64;
+    let bar0 = if assigned != 0 || !implemented { assigned } else { mem_base.next_multiple_of(size) };
+    mmio.write32(cfg + PCI_BAR0, bar0 as u32 | (orig & PCI_BAR_FLAGS_MASK));
+    if is_64 {
+        mmio.write32(cfg + PCI_BAR1, (bar0 >> 32) as u32);
+    }
+    mmio.write16(cfg + PCI_COMMAND, command);
+
+    implemented.then_some((bar0, size))
+}
@@ -0,0 +1,291 @@
+use log::{debug, warn};
+use rk3568_clk::cru::cru_clksel_con28_bits::CRU_CLKSEL_CCLK_EMMC_SOC0_375K;
+
+use crate::delay_us;
+use crate::sdhci::{CardType, HostKind, IDENT_CLK_HZ, SDHCI, status_is_ready};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_r1_bits::*;
+use crate::sdhci_cmd::mmc_resp_type::*;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_cmd_bits::EMMC_CMD_TYPE_ABORT;
+use crate::sdhci_sd::SdTiming;
+
+/// Retries at the same bus settings before the ladder lowers the clock or the bus width.
+pub const RECOVERY_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled for each further one.
+pub const RECOVERY_BACKOFF_US: u64 = 1000;
+/// CMD13 polls, 1 ms apart, waiting for the device to return to the transfer state.
+pub(crate) const RECOVERY_STATUS_POLLS: u32 = 100;
+
+/// How often each step of the recovery ladder ran since `SDHCI::new`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryStats {
+    /// CMD and DAT line resets.
+    pub line_resets: u32,
+    /// CMD12 aborts sent.
+    pub aborts: u32,
+    /// Requests retried after a transient error.
+    pub retries: u32,
+    /// Clock or bus width reductions.
+    pub downgrades: u32,
+    /// Full re-initialisations of the controller and the device.
+    pub reinits: u32,
+    /// Requests that failed after the whole ladder.
+    pub failures: u32,
+}
+
+/// Next step of `recover_bus` once the device answered CMD13.
+pub(crate) enum RecoveryState {
+    /// The device is back in the transfer state.
+    Ready,
+    /// The device still sends or receives data.
+    Abort,
+    /// The device is still busy, or did not answer.
+    Wait,
+}
+
+/// Settings a request may depend on that `init` resets, restored after re-initialising.
+struct BusSettings {
+    /// Partition access bits of PARTITION_CONFIG.
+    partition_access: u8,
+    bus_width: u8,
+    sd_timing: SdTiming,
+}
+
+impl RecoveryStats {
+    pub(crate) const fn new() -> Self {
+        Self { line_resets: 0, aborts: 0, retries: 0, downgrades: 0, reinits: 0, failures: 0 }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Return the recovery counters.
+    pub fn recovery_stats(&self) -> RecoveryStats {
+        *self.recovery.lock()
+    }
+
+    /// Run `op` and walk up the recovery ladder each time it fails with a transient error.
+    pub(crate) fn with_recovery<T>(&self, mut op: impl FnMut() -> Result<T, MmcError>) -> Result<T, MmcError> {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    attempt += 1;
+                    self.recover(err, attempt)?;
+                }
+            }
+        }
+    }
+
+    /// Take the recovery step for the `attempt`th consecutive failure of a request.
+    ///
+    /// 1. The first `RECOVERY_RETRIES` attempts reset the CMD and DAT lines, abort with CMD12,
+    ///    poll CMD13 until the device is back in the transfer state and retry after a backoff.
+    /// 2. The next one lowers the bus width to 1 bit, or the clock to 375 kHz.
+    /// 3. The last one re-initialises the controller and the device, then restores the partition
+    ///    access, the bus width and the timing the request ran with. The request fails if they
+    ///    cannot be restored.
+    ///
+    /// # Returns
+    ///
+    /// - Ok if the request should be retried
+    /// - `err` if it is not transient or the ladder is exhausted
+    pub fn recover(&self, err: MmcError, attempt: u32) -> Result<(), MmcError> {
+        if !err.is_transient() {
+            return Err(err);
+        }
+        warn!("emmc request failed: {:?}, recovery attempt {}", err, attempt);
+        // A drifted sampling point shows as CRC errors.
+        if matches!(err, MmcError::CmdCrc | MmcError::DataCrc | MmcError::Tuning) {
+            self.request_retune();
+        }
+
+        if attempt <= RECOVERY_RETRIES {
+            self.recover_bus();
+            let backoff = RECOVERY_BACKOFF_US << (attempt - 1);
+            warn!("emmc recovery: retry in {} us", backoff);
+            delay_us(backoff);
+            self.recovery.lock().retries += 1;
+            return Ok(());
+        }
+
+        if attempt == RECOVERY_RETRIES + 1 {
+            self.recover_bus();
+            if self.downgrade_bus() {
+                return Ok(());
+            }
+        }
+
+        if attempt <= RECOVERY_RETRIES + 2 {
+            warn!("emmc recovery: re-initialising");
+            self.recovery.lock().reinits += 1;
+            let settings = self.bus_settings();
+            match self.init().and_then(|()| self.restore_bus_settings(&settings)) {
+                Ok(()) => return Ok(()),
+                Err(init_err) => warn!("emmc recovery: re-initialisation failed: {:?}", init_err),
+            }
+        }
+
+        warn!("emmc recovery: giving up on {:?}", err);
+        self.recovery.lock().failures += 1;
+        Err(err)
+    }
+
+    /// Reset the CMD and DAT lines, abort the transfer and bring the device back to the
+    /// transfer state.
+    fn recover_bus(&self) {
+        if !self.recovery_reset_lines() {
+            return;
+        }
+
+        self.abort();
+        for _ in 0..RECOVERY_STATUS_POLLS {
+            match self.recovery_state(self.sdhci_send_status()) {
+                RecoveryState::Ready => return,
+                RecoveryState::Abort => self.abort(),
+                RecoveryState::Wait => {}
+            }
+            delay_us(1000);
+        }
+        warn!("emmc recovery: card did not return to the transfer state");
+    }
+
+    /// Reset the CMD and DAT lines for `recover_bus`, returning whether it worked.
+    pub(crate) fn recovery_reset_lines(&self) -> bool {
+        warn!("emmc recovery: resetting CMD and DAT lines");
+        self.recovery.lock().line_resets += 1;
+        if let Err(err) = self.reset_lines() {
+            warn!("emmc recovery: line reset failed: {:?}", err);
+            return false;
+        }
+        true
+    }
+
+    /// Tell from the answer to the CMD13 of `recover_bus` what to do next, resetting the lines
+    /// if it did not get through.
+    pub(crate) fn recovery_state(&self, status: Result<u32, MmcError>) -> RecoveryState {
+        match status {
+            Ok(status) if status_is_ready(status) => return RecoveryState::Ready,
+            Ok(status) => {
+                let state = mmc_r1_current_state(status);
+                debug!("emmc recovery: card state {}", state);
+                if state == MMC_R1_STATE_DATA || state == MMC_R1_STATE_RCV {
+                    return RecoveryState::Abort;
+                }
+            }
+            // Error bits of the card status are cleared once they have been reported.
+            Err(MmcError::CardStatus(status)) => debug!("emmc recovery: card status {:#x}", status),
+            Err(_) => {
+                let _ = self.reset_lines();
+            }
+        }
+        RecoveryState::Wait
+    }
+
+    fn abort(&self) {
+        self.note_abort();
+        // The device rejects CMD12 if no transfer was in progress.
+        if let Err(err) = self.sdhci_send_cmd(MMC_STOP_TRANSMISSION, EMMC_CMD_TYPE_ABORT, MMC_RESP_R1B, 0) {
+            debug!("emmc recovery: CMD12 failed: {:?}", err);
+            let _ = self.reset_lines();
+        }
+    }
+
+    pub(crate) fn note_abort(&self) {
+        warn!("emmc recovery: aborting with CMD12");
+        self.recovery.lock().aborts += 1;
+    }
+
+    fn bus_settings(&self) -> BusSettings {
+        let card = self.card.lock();
+        BusSettings {
+            partition_access: card.ext_csd.byte(EXT_CSD_PARTITION_CONFIG) & 0x07,
+            bus_width: card.bus_width,
+            sd_timing: card.sd_timing,
+        }
+    }
+
+    /// Bring the device re-initialised by `init` back to `settings`.
+    fn restore_bus_settings(&self, settings: &BusSettings) -> Result<(), MmcError> {
+        let (card_type, config, bus_width, sd_timing) = {
+            let card = self.card.lock();
+            (card.card_type, card.ext_csd.byte(EXT_CSD_PARTITION_CONFIG), card.bus_width, card.sd_timing)
+        };
+        // The SD timing follows from what the card and the controller support, so a different
+        // one means the card did not come back the same.
+        if card_type == CardType::Sd && sd_timing != settings.sd_timing {
+            warn!("emmc recovery: card came back at {:?} instead of {:?}", sd_timing, settings.sd_timing);
+            return Err(MmcError::Unsupported);
+        }
+        if bus_width != settings.bus_width {
+            warn!("emmc recovery: restoring bus width {}", settings.bus_width);
+            self.set_bus_width(card_type, settings.bus_width)?;
+        }
+        if card_type == CardType::Mmc && config & 0x07 != settings.partition_access {
+            warn!("emmc recovery: restoring partition access {}", settings.partition_access);
+            self.mmc_switch(EXT_CSD_PARTITION_CONFIG, config & !0x07 | settings.partition_access)?;
+        }
+        Ok(())
+    }
+
+    /// Set the data bus width of the card, then the one of the controller.
+    fn set_bus_width(&self, card_type: CardType, width: u8) -> Result<(), MmcError> {
+        match card_type {
+            CardType::Sd => self.sd_set_bus_width(width),
+            _ => self.mmc_set_bus_width(width),
+        }
+    }
+
+    /// Lower the bus width, or the clock if the bus is already 1 bit wide. An eMMC device is
+    /// brought back to legacy timing with the clock.
+    ///
+    /// Returns false if both are already at their minimum.
+    fn downgrade_bus(&self) -> bool {
+        let (card_type, bus_width, hs_timing) = {
+            let card = self.card.lock();
+            (card.card_type, card.bus_width, card.ext_csd.byte(EXT_CSD_HS_TIMING))
+        };
+        if bus_width > 1 {
+            warn!("emmc recovery: lowering bus width from {} to 1 bit", bus_width);
+            if let Err(err) = self.set_bus_width(card_type, 1) {
+                warn!("emmc recovery: switching bus width failed: {:?}", err);
+                return false;
+            }
+            self.recovery.lock().downgrades += 1;
+            return true;
+        }
+
+        let hs_timing = card_type == CardType::Mmc && hs_timing != 0;
+        let clock_high = match &self.clk {
+            Some(clk) => clk.cru_clksel_get_cclk_emmc() != CRU_CLKSEL_CCLK_EMMC_SOC0_375K,
+            None => self.host_kind() == HostKind::Generic && self.reg.emmc_get_freq() < self.clk_divider(IDENT_CLK_HZ),
+        };
+        if !hs_timing && !clock_high {
+            return false;
+        }
+
+        warn!("emmc recovery: lowering clock to the identification rate");
+        if hs_timing {
+            if let Err(err) = self.mmc_switch(EXT_CSD_HS_TIMING, 0) {
+                warn!("emmc recovery: switching to legacy timing failed: {:?}", err);
+                return false;
+            }
+            self.reg.emmc_disable_high_speed();
+        }
+        match &self.clk {
+            Some(clk) => clk.cru_clksel_set_cclk_emmc(CRU_CLKSEL_CCLK_EMMC_SOC0_375K),
+            None if clock_high => {
+                if let Err(err) = self.change_clk_divider(IDENT_CLK_HZ) {
+                    warn!("emmc recovery: lowering the clock failed: {:?}", err);
+                    return false;
+                }
+            }
+            None => {}
+        }
+        self.recovery.lock().downgrades += 1;
+        true
+    }
+}
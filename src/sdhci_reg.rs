@@ -1,42 +1,210 @@
+/// Access to the memory mapped registers, so `Reg` can run against something other than
+/// the hardware, e.g. `sdhci_mock::MockMmio` in host tests.
+///
+/// Addresses are absolute, i.e. the base address given to `Reg` plus the register offset.
+pub trait Mmio {
+    fn read32(&self, addr: u64) -> u32;
+    fn write32(&self, addr: u64, value: u32);
+    fn read16(&self, addr: u64) -> u16;
+    fn write16(&self, addr: u64, value: u16);
+    fn read8(&self, addr: u64) -> u8;
+    fn write8(&self, addr: u64, value: u8);
+}
+
+impl<T: Mmio + ?Sized> Mmio for &T {
+    fn read32(&self, addr: u64) -> u32 { (**self).read32(addr) }
+    fn write32(&self, addr: u64, value: u32) { (**self).write32(addr, value) }
+    fn read16(&self, addr: u64) -> u16 { (**self).read16(addr) }
+    fn write16(&self, addr: u64, value: u16) { (**self).write16(addr, value) }
+    fn read8(&self, addr: u64) -> u8 { (**self).read8(addr) }
+    fn write8(&self, addr: u64, value: u8) { (**self).write8(addr, value) }
+}
+
+/// Volatile accesses through raw pointers, the backend used on hardware.
+#[derive(Clone, Copy, Default)]
+pub struct MmioPtr;
+
+impl Mmio for MmioPtr {
+    /// 安全读取 MMIO 寄存器
+    fn read32(&self, addr: u64) -> u32 {
+        unsafe { core::ptr::read_volatile(addr as *const u32) }
+    }
+
+    /// 安全写入 MMIO 寄存器
+    fn write32(&self, addr: u64, value: u32) {
+        unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+    }
+
+    /// 安全读取 MMIO 寄存器
+    fn read16(&self, addr: u64) -> u16 {
+        unsafe { core::ptr::read_volatile(addr as *const u16) }
+    }
+
+    /// 安全写入 MMIO 寄存器
+    fn write16(&self, addr: u64, value: u16) {
+        unsafe { core::ptr::write_volatile(addr as *mut u16, value) }
+    }
+
+    /// 安全读取 MMIO 寄存器
+    fn read8(&self, addr: u64) -> u8 {
+        unsafe { core::ptr::read_volatile(addr as *const u8) }
+    }
+
+    /// 安全写入 MMIO 寄存器
+    fn write8(&self, addr: u64, value: u8) {
+        unsafe { core::ptr::write_volatile(addr as *mut u8, value) }
+    }
+}
+
 /// 寄存器 外设封装
-pub struct Reg {
+pub struct Reg<M: Mmio = MmioPtr> {
     base_addr: u64, // 寄存器基地址
+    mmio: M,
 }
 
 impl Reg {
     /// 创建实例（无 unsafe 标记，因地址由调用方保证）
     pub const fn new(base_addr: u64) -> Self {
-        Self { base_addr }
+        Self { base_addr, mmio: MmioPtr }
+    }
+}
+
+impl<M: Mmio> Reg<M> {
+    /// Create an instance accessing the registers through `mmio`.
+    pub const fn with_mmio(base_addr: u64, mmio: M) -> Self {
+        Self { base_addr, mmio }
+    }
+
+    /// Return the MMIO backend.
+    pub fn mmio(&self) -> &M {
+        &self.mmio
     }
 
     /// 安全写入 MMIO 寄存器
     fn write_reg(&self, addr: u64, value: u32) {
-        unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+        self.mmio.write32(addr, value)
     }
 
     /// 安全读取 MMIO 寄存器
     fn read_reg(&self, addr: u64) -> u32 {
-        unsafe { core::ptr::read_volatile(addr as *const u32) }
+        self.mmio.read32(addr)
     }
 
     /// 安全写入 MMIO 寄存器
     fn write_reg16(&self, addr: u64, value: u16) {
-        unsafe { core::ptr::write_volatile(addr as *mut u16, value) }
+        self.mmio.write16(addr, value)
     }
 
     /// 安全读取 MMIO 寄存器
     fn read_reg16(&self, addr: u64) -> u16 {
-        unsafe { core::ptr::read_volatile(addr as *const u16) }
+        self.mmio.read16(addr)
     }
 
     /// 安全写入 MMIO 寄存器
     fn write_reg8(&self, addr: u64, value: u8) {
-        unsafe { core::ptr::write_volatile(addr as *mut u8, value) }
+        self.mmio.write8(addr, value)
     }
 
     /// 安全读取 MMIO 寄存器
     fn read_reg8(&self, addr: u64) -> u8 {
-        unsafe { core::ptr::read_volatile(addr as *const u8) }
+        self.mmio.read8(addr)
+    }
+}
+
+/// This module contains the offset position of the `EMMC_SDMASA` register and the definitions of its individual bits.
+/// The `EMMC_SDMASA` register is a 32-bit read-write register that contains the SDMA system address or the Argument2 of Auto CMD23.
+pub mod emmc_sdmasa_bits {
+    /// the offset of the `EMMC_SDMASA` register from the base address of the SDHCI controller.
+    pub const EMMC_SDMASA_OFFSET: u64 = 0x00;
+    /// SDMA System Address / Argument2
+    pub const EMMC_BLOCKCNT_SDMASA_POS: u32 = 0;
+    pub const EMMC_BLOCKCNT_SDMASA_MASK: u32 = 0xffffffff << EMMC_BLOCKCNT_SDMASA_POS;
+    pub const EMMC_BLOCKCNT_SDMASA: u32 = EMMC_BLOCKCNT_SDMASA_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_SDMASA` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_sdmasa_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_SDMASA` register.
+    pub fn emmc_get_sdmasa(&self) -> u32 {
+        let addr = self.base_addr + emmc_sdmasa_bits::EMMC_SDMASA_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Set the entire value of the `EMMC_SDMASA` register.
+    ///
+    /// When Host Version 4 is disabled this is the SDMA system address, otherwise it holds
+    /// the 32-bit block count used by Auto CMD23.
+    pub fn emmc_set_sdmasa(&self, sdmasa: u32) {
+        let addr = self.base_addr + emmc_sdmasa_bits::EMMC_SDMASA_OFFSET;
+        self.write_reg(addr, sdmasa);
+    }
+}
+
+/// This module contains the offset position of the `EMMC_BLOCKSIZE` register and the definitions of its individual bits.
+/// The `EMMC_BLOCKSIZE` register is a 16-bit read-write register that contains the block size of data transfers.
+pub mod emmc_blocksize_bits {
+    /// the offset of the `EMMC_BLOCKSIZE` register from the base address of the SDHCI controller.
+    pub const EMMC_BLOCKSIZE_OFFSET: u64 = 0x04;
+    /// Transfer Block Size
+    pub const EMMC_XFER_BLOCK_SIZE_POS: u16 = 0;
+    pub const EMMC_XFER_BLOCK_SIZE_MASK: u16 = 0xfff << EMMC_XFER_BLOCK_SIZE_POS;
+    pub const EMMC_XFER_BLOCK_SIZE: u16 = EMMC_XFER_BLOCK_SIZE_MASK;
+    /// SDMA Buffer Boundary
+    pub const EMMC_SDMA_BUF_BDARY_POS: u16 = 12;
+    pub const EMMC_SDMA_BUF_BDARY_MASK: u16 = 0x07 << EMMC_SDMA_BUF_BDARY_POS;
+    pub const EMMC_SDMA_BUF_BDARY: u16 = EMMC_SDMA_BUF_BDARY_MASK;
+    pub const EMMC_SDMA_BUF_BDARY_4K: u16 = 0x00 << EMMC_SDMA_BUF_BDARY_POS;
+    pub const EMMC_SDMA_BUF_BDARY_512K: u16 = 0x07 << EMMC_SDMA_BUF_BDARY_POS;
+}
+
+/// This module implements read and write operations for the `EMMC_BLOCKSIZE` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_blocksize_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_BLOCKSIZE` register.
+    pub fn emmc_get_blocksize(&self) -> u16 {
+        let addr = self.base_addr + emmc_blocksize_bits::EMMC_BLOCKSIZE_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Set the entire value of the `EMMC_BLOCKSIZE` register.
+    pub fn emmc_set_blocksize(&self, blocksize: u16) {
+        let addr = self.base_addr + emmc_blocksize_bits::EMMC_BLOCKSIZE_OFFSET;
+        self.write_reg16(addr, blocksize);
+    }
+
+    /// Set the transfer block size in bytes, from 1 to 2048.
+    pub fn emmc_set_xfer_block_size(&self, block_size: u16) {
+        let addr = self.base_addr + emmc_blocksize_bits::EMMC_BLOCKSIZE_OFFSET;
+        let value = self.read_reg16(addr);
+        self.write_reg16(addr, (value & !emmc_blocksize_bits::EMMC_XFER_BLOCK_SIZE_MASK) | (block_size & emmc_blocksize_bits::EMMC_XFER_BLOCK_SIZE_MASK));
+    }
+}
+
+/// This module contains the offset position of the `EMMC_BLOCKCOUNT` register and the definitions of its individual bits.
+/// The `EMMC_BLOCKCOUNT` register is a 16-bit read-write register that contains the number of blocks to transfer.
+pub mod emmc_blockcount_bits {
+    /// the offset of the `EMMC_BLOCKCOUNT` register from the base address of the SDHCI controller.
+    pub const EMMC_BLOCKCOUNT_OFFSET: u64 = 0x06;
+    /// 16-bit Block Count
+    pub const EMMC_BLOCK_CNT_POS: u16 = 0;
+    pub const EMMC_BLOCK_CNT_MASK: u16 = 0xffff << EMMC_BLOCK_CNT_POS;
+    pub const EMMC_BLOCK_CNT: u16 = EMMC_BLOCK_CNT_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_BLOCKCOUNT` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_blockcount_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the number of blocks left to transfer.
+    pub fn emmc_get_blockcount(&self) -> u16 {
+        let addr = self.base_addr + emmc_blockcount_bits::EMMC_BLOCKCOUNT_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Set the number of blocks to transfer. Only used when Block Count Enable is set in `EMMC_XFER_MODE`.
+    pub fn emmc_set_blockcount(&self, blockcount: u16) {
+        let addr = self.base_addr + emmc_blockcount_bits::EMMC_BLOCKCOUNT_OFFSET;
+        self.write_reg16(addr, blockcount);
     }
 }
 
@@ -53,7 +221,7 @@ pub mod emmc_argument_bits {
 
 /// This module implements read and write operations for the `EMMC_ARGUMENT` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_argument_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_ARGUMENT` register.
     ///
     /// These bits specify the SD/eMMC command argument that is 
@@ -90,6 +258,72 @@ impl Reg {
 
 }
 
+/// This module contains the offset position of the `EMMC_XFER_MODE` register and the definitions of its individual bits.
+/// The `EMMC_XFER_MODE` register is a 16-bit read-write register that controls the operation of data transfers.
+pub mod emmc_xfer_mode_bits {
+    /// the offset of the `EMMC_XFER_MODE` register from the base address of the SDHCI controller.
+    pub const EMMC_XFER_MODE_OFFSET: u64 = 0x0c;
+    /// DMA Enable
+    pub const EMMC_DMA_ENABLE_POS: u16 = 0;
+    pub const EMMC_DMA_ENABLE_MASK: u16 = 0x01 << EMMC_DMA_ENABLE_POS;
+    pub const EMMC_DMA_ENABLE: u16 = EMMC_DMA_ENABLE_MASK;
+    /// Block Count Enable
+    pub const EMMC_BLOCK_COUNT_ENABLE_POS: u16 = 1;
+    pub const EMMC_BLOCK_COUNT_ENABLE_MASK: u16 = 0x01 << EMMC_BLOCK_COUNT_ENABLE_POS;
+    pub const EMMC_BLOCK_COUNT_ENABLE: u16 = EMMC_BLOCK_COUNT_ENABLE_MASK;
+    /// Auto Command Enable, the command the controller issues by itself around a multiple block transfer
+    pub const EMMC_AUTO_CMD_ENABLE_POS: u16 = 2;
+    pub const EMMC_AUTO_CMD_ENABLE_MASK: u16 = 0x03 << EMMC_AUTO_CMD_ENABLE_POS;
+    pub const EMMC_AUTO_CMD_ENABLE: u16 = EMMC_AUTO_CMD_ENABLE_MASK;
+    pub const EMMC_AUTO_CMD_DISABLED: u16 = 0x00 << EMMC_AUTO_CMD_ENABLE_POS;
+    /// CMD12 once the last block is transferred
+    pub const EMMC_AUTO_CMD12_ENABLED: u16 = 0x01 << EMMC_AUTO_CMD_ENABLE_POS;
+    /// CMD23 with the argument in `EMMC_SDMASA` before the command, from version 3.00
+    pub const EMMC_AUTO_CMD23_ENABLED: u16 = 0x02 << EMMC_AUTO_CMD_ENABLE_POS;
+    /// Data Transfer Direction Select, set for card to host (read)
+    pub const EMMC_DATA_XFER_DIR_POS: u16 = 4;
+    pub const EMMC_DATA_XFER_DIR_MASK: u16 = 0x01 << EMMC_DATA_XFER_DIR_POS;
+    pub const EMMC_DATA_XFER_DIR: u16 = EMMC_DATA_XFER_DIR_MASK;
+    pub const EMMC_DATA_XFER_DIR_WRITE: u16 = 0x00 << EMMC_DATA_XFER_DIR_POS;
+    pub const EMMC_DATA_XFER_DIR_READ: u16 = 0x01 << EMMC_DATA_XFER_DIR_POS;
+    /// Multi/Single Block Select
+    pub const EMMC_MULTI_BLK_SEL_POS: u16 = 5;
+    pub const EMMC_MULTI_BLK_SEL_MASK: u16 = 0x01 << EMMC_MULTI_BLK_SEL_POS;
+    pub const EMMC_MULTI_BLK_SEL: u16 = EMMC_MULTI_BLK_SEL_MASK;
+    /// Response Type R1/R5, set for R5 (SDIO)
+    pub const EMMC_RESP_TYPE_R5_POS: u16 = 6;
+    pub const EMMC_RESP_TYPE_R5_MASK: u16 = 0x01 << EMMC_RESP_TYPE_R5_POS;
+    pub const EMMC_RESP_TYPE_R5: u16 = EMMC_RESP_TYPE_R5_MASK;
+    /// Response Error Check Enable
+    pub const EMMC_RESP_ERR_CHK_ENABLE_POS: u16 = 7;
+    pub const EMMC_RESP_ERR_CHK_ENABLE_MASK: u16 = 0x01 << EMMC_RESP_ERR_CHK_ENABLE_POS;
+    pub const EMMC_RESP_ERR_CHK_ENABLE: u16 = EMMC_RESP_ERR_CHK_ENABLE_MASK;
+    /// Response Interrupt Disable
+    pub const EMMC_RESP_INT_DISABLE_POS: u16 = 8;
+    pub const EMMC_RESP_INT_DISABLE_MASK: u16 = 0x01 << EMMC_RESP_INT_DISABLE_POS;
+    pub const EMMC_RESP_INT_DISABLE: u16 = EMMC_RESP_INT_DISABLE_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_XFER_MODE` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_xfer_mode_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_XFER_MODE` register.
+    pub fn emmc_get_xfer_mode(&self) -> u16 {
+        let addr = self.base_addr + emmc_xfer_mode_bits::EMMC_XFER_MODE_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Set the entire value of the `EMMC_XFER_MODE` register.
+    ///
+    /// # Note
+    ///
+    /// This register must be written before `EMMC_CMD`, writing the command starts the transfer.
+    pub fn emmc_set_xfer_mode(&self, xfer_mode: u16) {
+        let addr = self.base_addr + emmc_xfer_mode_bits::EMMC_XFER_MODE_OFFSET;
+        self.write_reg16(addr, xfer_mode);
+    }
+}
+
 /// This module contains the offset position of the `EMMC_CMD` register and the definitions of its individual bits.
 /// The `EMMC_CMD` register is a 32-bit read-write register that contains the command.
 pub mod emmc_cmd_bits {
@@ -135,7 +369,7 @@ pub mod emmc_cmd_bits {
 
 /// This module implements read and write operations for the `EMMC_CMD` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_cmd_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_CMD` register.
     ///
     /// # Arguments
@@ -376,7 +610,7 @@ pub mod emmc_resp01_bits {
     pub const EMMC_RESP01: u32 = EMMC_RESP01_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_get_resp01(&self) -> u32 {
         let addr = self.base_addr + emmc_resp01_bits::EMMC_RESP01_OFFSET;
         self.read_reg(addr)
@@ -391,7 +625,7 @@ pub mod emmc_resp23_bits {
     pub const EMMC_RESP23: u32 = EMMC_RESP23_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_get_resp23(&self) -> u32 {
         let addr = self.base_addr + emmc_resp23_bits::EMMC_RESP23_OFFSET;
         self.read_reg(addr)
@@ -399,14 +633,14 @@ impl Reg {
 }
 
 pub mod emmc_resp45_bits {
-    pub const EMMC_RESP45_OFFSET: u64 = 0x14;
+    pub const EMMC_RESP45_OFFSET: u64 = 0x18;
 
     pub const EMMC_RESP45_POS: u32 = 0;
     pub const EMMC_RESP45_MASK: u32 = 0x0ffffffff << EMMC_RESP45_POS;
     pub const EMMC_RESP45: u32 = EMMC_RESP45_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_get_resp45(&self) -> u32 {
         let addr = self.base_addr + emmc_resp45_bits::EMMC_RESP45_OFFSET;
         self.read_reg(addr)
@@ -414,20 +648,45 @@ impl Reg {
 }
 
 pub mod emmc_resp67_bits {
-    pub const EMMC_RESP67_OFFSET: u64 = 0x14;
+    pub const EMMC_RESP67_OFFSET: u64 = 0x1c;
 
     pub const EMMC_RESP67_POS: u32 = 0;
     pub const EMMC_RESP67_MASK: u32 = 0x0ffffffff << EMMC_RESP67_POS;
     pub const EMMC_RESP67: u32 = EMMC_RESP67_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_get_resp67(&self) -> u32 {
         let addr = self.base_addr + emmc_resp67_bits::EMMC_RESP67_OFFSET;
         self.read_reg(addr)
     }
 }
 
+/// This module contains the offset position of the `EMMC_BUF_DATA` register and the definitions of its individual bits.
+/// The `EMMC_BUF_DATA` register is a 32-bit read-write register used to access the packet buffer.
+pub mod emmc_buf_data_bits {
+    /// the offset of the `EMMC_BUF_DATA` register from the base address of the SDHCI controller.
+    pub const EMMC_BUF_DATA_OFFSET: u64 = 0x20;
+    /// Buffer Data
+    pub const EMMC_BUF_DATA_POS: u32 = 0;
+    pub const EMMC_BUF_DATA_MASK: u32 = 0xffffffff << EMMC_BUF_DATA_POS;
+    pub const EMMC_BUF_DATA: u32 = EMMC_BUF_DATA_MASK;
+}
+
+impl<M: Mmio> Reg<M> {
+    /// Read one word from the packet buffer.
+    pub fn emmc_read_buf_data(&self) -> u32 {
+        let addr = self.base_addr + emmc_buf_data_bits::EMMC_BUF_DATA_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Write one word to the packet buffer.
+    pub fn emmc_write_buf_data(&self, data: u32) {
+        let addr = self.base_addr + emmc_buf_data_bits::EMMC_BUF_DATA_OFFSET;
+        self.write_reg(addr, data);
+    }
+}
+
 pub mod emmc_pstate_bits {
     pub const EMMC_PSTATE_OFFSET: u64 = 0x24;
 
@@ -443,6 +702,12 @@ pub mod emmc_pstate_bits {
     pub const EMMC_DATA_LINE7_4_LEVEL_POS: u32 = 4;
     pub const EMMC_DATA_LINE7_4_LEVEL_MASK: u32 = 0x0f << EMMC_DATA_LINE7_4_LEVEL_POS;
     pub const EMMC_DATA_LINE7_4_LEVEL: u32 = EMMC_DATA_LINE7_4_LEVEL_MASK;
+    pub const EMMC_BUF_WR_ENABLE_POS: u32 = 10;
+    pub const EMMC_BUF_WR_ENABLE_MASK: u32 = 0x01 << EMMC_BUF_WR_ENABLE_POS;
+    pub const EMMC_BUF_WR_ENABLE: u32 = EMMC_BUF_WR_ENABLE_MASK;
+    pub const EMMC_BUF_RD_ENABLE_POS: u32 = 11;
+    pub const EMMC_BUF_RD_ENABLE_MASK: u32 = 0x01 << EMMC_BUF_RD_ENABLE_POS;
+    pub const EMMC_BUF_RD_ENABLE: u32 = EMMC_BUF_RD_ENABLE_MASK;
 
     pub const EMMC_CARD_INSERTED_POS: u32 = 16;
     pub const EMMC_CARD_INSERTED_MASK: u32 = 0x01 << EMMC_CARD_INSERTED_POS;
@@ -454,9 +719,12 @@ pub mod emmc_pstate_bits {
     pub const EMMC_DATA_LINE3_0_LEVEL_POS: u32 = 20;
     pub const EMMC_DATA_LINE3_0_LEVEL_MASK: u32 = 0x0f << EMMC_DATA_LINE3_0_LEVEL_POS;
     pub const EMMC_DATA_LINE3_0_LEVEL: u32 = EMMC_DATA_LINE3_0_LEVEL_MASK;
+    pub const EMMC_CMD_LINE_LEVEL_POS: u32 = 24;
+    pub const EMMC_CMD_LINE_LEVEL_MASK: u32 = 0x01 << EMMC_CMD_LINE_LEVEL_POS;
+    pub const EMMC_CMD_LINE_LEVEL: u32 = EMMC_CMD_LINE_LEVEL_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_cmd_is_ready(&self) -> bool {
         let addr = self.base_addr + emmc_pstate_bits::EMMC_PSTATE_OFFSET;
         self.read_reg(addr) & emmc_pstate_bits::EMMC_CMD_INHIBIT == 0
@@ -478,6 +746,22 @@ impl Reg {
         ((value & emmc_pstate_bits::EMMC_DATA_LINE7_4_LEVEL) | ((value & emmc_pstate_bits::EMMC_DATA_LINE3_0_LEVEL) >> emmc_pstate_bits::EMMC_DATA_LINE3_0_LEVEL_POS)) as u8
     }
 
+    /// Return the signal level of the CMD line, true if it is high.
+    pub fn emmc_get_cmd_line_level(&self) -> bool {
+        let addr = self.base_addr + emmc_pstate_bits::EMMC_PSTATE_OFFSET;
+        self.read_reg(addr) & emmc_pstate_bits::EMMC_CMD_LINE_LEVEL == emmc_pstate_bits::EMMC_CMD_LINE_LEVEL
+    }
+
+    pub fn emmc_buf_wr_is_enabled(&self) -> bool {
+        let addr = self.base_addr + emmc_pstate_bits::EMMC_PSTATE_OFFSET;
+        self.read_reg(addr) & emmc_pstate_bits::EMMC_BUF_WR_ENABLE == emmc_pstate_bits::EMMC_BUF_WR_ENABLE
+    }
+
+    pub fn emmc_buf_rd_is_enabled(&self) -> bool {
+        let addr = self.base_addr + emmc_pstate_bits::EMMC_PSTATE_OFFSET;
+        self.read_reg(addr) & emmc_pstate_bits::EMMC_BUF_RD_ENABLE == emmc_pstate_bits::EMMC_BUF_RD_ENABLE
+    }
+
     pub fn emmc_card_is_inserted(&self) -> bool {
         let addr = self.base_addr + emmc_pstate_bits::EMMC_PSTATE_OFFSET;
         self.read_reg(addr) & emmc_pstate_bits::EMMC_CARD_INSERTED == emmc_pstate_bits::EMMC_CARD_INSERTED
@@ -515,7 +799,7 @@ pub mod emmc_host_ctrl1_bits {
     // pub const EMMC_CARD_DETECT_SIG_SEL: u8 = EMMC_CARD_DETECT_SIG_SEL_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_enable_data_xfer_width_1bit(&self) {
         let addr = self.base_addr + emmc_host_ctrl1_bits::EMMC_HOST_CTRL1_OFFSET;
         let value = self.read_reg8(addr);
@@ -569,11 +853,18 @@ pub mod emmc_pwr_ctrl_bits {
     pub const EMMC_PWR_ON_POS: u8 = 0;
     pub const EMMC_PWR_ON_MASK: u8 = 0x01 << EMMC_PWR_ON_POS;
     pub const EMMC_PWR_ON: u8 = EMMC_PWR_ON_MASK;
+    /// SD Bus Voltage Select for VDD1
+    pub const EMMC_SD_BUS_VOL_VDD1_POS: u8 = 1;
+    pub const EMMC_SD_BUS_VOL_VDD1_MASK: u8 = 0x07 << EMMC_SD_BUS_VOL_VDD1_POS;
+    pub const EMMC_SD_BUS_VOL_VDD1: u8 = EMMC_SD_BUS_VOL_VDD1_MASK;
+    pub const EMMC_SD_BUS_VOL_VDD1_3V3: u8 = 0x07 << EMMC_SD_BUS_VOL_VDD1_POS;
+    pub const EMMC_SD_BUS_VOL_VDD1_3V0: u8 = 0x06 << EMMC_SD_BUS_VOL_VDD1_POS;
+    pub const EMMC_SD_BUS_VOL_VDD1_1V8: u8 = 0x05 << EMMC_SD_BUS_VOL_VDD1_POS;
 }
 
 /// This module implements read and write operations for the `EMMC_PWR_CTRL` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_pwr_ctrl_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_PWR_CTRL` register.
     ///
     /// # Arguments
@@ -634,6 +925,15 @@ impl Reg {
         let value = self.read_reg8(addr);
         self.write_reg8(addr, value & !emmc_pwr_ctrl_bits::EMMC_PWR_ON);
     }
+
+    /// Select the VDD1 bus voltage, one of the `EMMC_SD_BUS_VOL_VDD1_*` values.
+    ///
+    /// Standard SDHCI controllers do not power the card until a supported voltage is selected.
+    pub fn emmc_set_sd_bus_vol_vdd1(&self, vol: u8) {
+        let addr = self.base_addr + emmc_pwr_ctrl_bits::EMMC_PWR_CTRL_OFFSET;
+        let value = self.read_reg8(addr);
+        self.write_reg8(addr, (value & !emmc_pwr_ctrl_bits::EMMC_SD_BUS_VOL_VDD1_MASK) | (vol & emmc_pwr_ctrl_bits::EMMC_SD_BUS_VOL_VDD1_MASK));
+    }
 }
 
 /// This module contains the offset position of the `EMMC_CLK_CTRL` register and the definitions of its individual bits.
@@ -665,7 +965,7 @@ pub mod emmc_clk_ctrl_bits {
 
 /// This module implements read and write operations for the `EMMC_CLK_CTRL` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_clk_ctrl_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_CLK_CTRL` register.
     ///
     /// # Arguments
@@ -817,6 +1117,51 @@ impl Reg {
                                 | ((freq & 0xff) << emmc_clk_ctrl_bits::EMMC_FREQ_POS) 
                                 | (((freq & 0x300) >> emmc_clk_ctrl_bits::EMMC_FREQ_POS) << emmc_clk_ctrl_bits::EMMC_UPPER_FREQ_POS));
     }
+
+    /// Get the frequency
+    ///
+    /// # Arguments
+    /// 
+    /// - None
+    /// 
+    /// # Returns
+    /// 
+    /// - The 10-bit divider set with `emmc_set_freq`.
+    pub fn emmc_get_freq(&self) -> u16 {
+        let addr = self.base_addr + emmc_clk_ctrl_bits::EMMC_CLK_CTRL_OFFSET;
+        let value = self.read_reg16(addr);
+        (value & emmc_clk_ctrl_bits::EMMC_FREQ_MASK) >> emmc_clk_ctrl_bits::EMMC_FREQ_POS
+            | ((value & emmc_clk_ctrl_bits::EMMC_UPPER_FREQ_MASK) >> emmc_clk_ctrl_bits::EMMC_UPPER_FREQ_POS) << 8
+    }
+}
+
+/// This module contains the offset position of the `EMMC_TOUT_CTRL` register and the definitions of its individual bits.
+/// The `EMMC_TOUT_CTRL` register is a 8-bit read-write register that contains the data timeout counter value.
+pub mod emmc_tout_ctrl_bits {
+    /// the offset of the `EMMC_TOUT_CTRL` register from the base address of the SDHCI controller.
+    pub const EMMC_TOUT_CTRL_OFFSET: u64 = 0x2e;
+    /// Data Timeout Counter Value, the timeout is TMCLK x 2^(13 + value)
+    pub const EMMC_TOUT_CNT_POS: u8 = 0;
+    pub const EMMC_TOUT_CNT_MASK: u8 = 0x0f << EMMC_TOUT_CNT_POS;
+    pub const EMMC_TOUT_CNT: u8 = EMMC_TOUT_CNT_MASK;
+    pub const EMMC_TOUT_CNT_MAX: u8 = 0x0e << EMMC_TOUT_CNT_POS;
+}
+
+/// This module implements read and write operations for the `EMMC_TOUT_CTRL` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_tout_ctrl_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_TOUT_CTRL` register.
+    pub fn emmc_get_tout_ctrl(&self) -> u8 {
+        let addr = self.base_addr + emmc_tout_ctrl_bits::EMMC_TOUT_CTRL_OFFSET;
+        self.read_reg8(addr)
+    }
+
+    /// Set the data timeout counter value. `0x0f` is reserved.
+    pub fn emmc_set_tout_cnt(&self, tout_cnt: u8) {
+        let addr = self.base_addr + emmc_tout_ctrl_bits::EMMC_TOUT_CTRL_OFFSET;
+        let value = self.read_reg8(addr);
+        self.write_reg8(addr, (value & !emmc_tout_ctrl_bits::EMMC_TOUT_CNT_MASK) | (tout_cnt & emmc_tout_ctrl_bits::EMMC_TOUT_CNT_MASK));
+    }
 }
 
 /// This module contains the offset position of the `EMMC_SW_RST` register and the definitions of its individual bits.
@@ -837,7 +1182,7 @@ pub mod emmc_sw_rst_bits {
 
 /// This module implements read and write operations for the `EMMC_SW_RST` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_sw_rst_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Do software reset for all
     ///
     /// This reset affects the entire Host Controller except for the card 
@@ -1026,7 +1371,7 @@ pub mod emmc_normal_int_stat_bits {
 
 /// This module implements read and write operations for the `EMMC_NORMAL_INT_STAT` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_normal_int_stat_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_NORMAL_INT_STAT` register.
     ///
     /// # Arguments
@@ -1191,7 +1536,22 @@ pub mod emmc_error_int_stat_bits {
     pub const EMMC_BOOT_ACK_ERR: u16 = EMMC_BOOT_ACK_ERR_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_ERROR_INT_STAT` register.
+    pub fn emmc_get_error_int_stat(&self) -> u16 {
+        let addr = self.base_addr + emmc_error_int_stat_bits::EMMC_ERROR_INT_STAT_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Set the entire value of the `EMMC_ERROR_INT_STAT` register.
+    ///
+    /// All bits are write-1-to-clear, so writing back the value returned by
+    /// `emmc_get_error_int_stat` acknowledges exactly the errors that were observed.
+    pub fn emmc_set_error_int_stat(&self, error_int_stat: u16) {
+        let addr = self.base_addr + emmc_error_int_stat_bits::EMMC_ERROR_INT_STAT_OFFSET;
+        self.write_reg16(addr, error_int_stat);
+    }
+
     pub fn emmc_clear_all_error_int_flags(&self) {
         let addr = self.base_addr + emmc_error_int_stat_bits::EMMC_ERROR_INT_STAT_OFFSET;
         let mut value = self.read_reg16(addr);
@@ -1241,12 +1601,15 @@ pub mod emmc_normal_int_en_bits {
     pub const EMMC_CARD_INTERRUPT_EN_POS: u16 = 8;
     pub const EMMC_CARD_INTERRUPT_EN_MASK: u16 = 0x01 << EMMC_CARD_INTERRUPT_EN_POS;
     pub const EMMC_CARD_INTERRUPT_EN: u16 = EMMC_CARD_INTERRUPT_EN_MASK;
+    pub const EMMC_RE_TUNE_EVENT_EN_POS: u16 = 12;
+    pub const EMMC_RE_TUNE_EVENT_EN_MASK: u16 = 0x01 << EMMC_RE_TUNE_EVENT_EN_POS;
+    pub const EMMC_RE_TUNE_EVENT_EN: u16 = EMMC_RE_TUNE_EVENT_EN_MASK;
     pub const EMMC_CQE_EVENT_EN_POS: u16 = 14;
     pub const EMMC_CQE_EVENT_EN_MASK: u16 = 0x01 << EMMC_CQE_EVENT_EN_POS;
     pub const EMMC_CQE_EVENT_EN: u16 = EMMC_CQE_EVENT_EN_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_normal_int_en(&self, normal_int_en: u16) {
         let addr = self.base_addr + emmc_normal_int_en_bits::EMMC_NORMAL_INT_EN_OFFSET;
         self.write_reg16(addr, normal_int_en);
@@ -1320,7 +1683,7 @@ pub mod emmc_error_int_en_bits {
     pub const EMMC_BOOT_ACK_EN: u16 = EMMC_BOOT_ACK_EN_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_error_int_en(&self, error_int_en: u16) {
         let addr = self.base_addr + emmc_error_int_en_bits::EMMC_ERROR_INT_EN_OFFSET;
         self.write_reg16(addr, error_int_en);
@@ -1390,7 +1753,7 @@ pub mod emmc_normal_int_sig_en_bits {
     pub const EMMC_CQE_EVENT_SIG_EN: u16 = EMMC_CQE_EVENT_SIG_EN_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_normal_int_sig_en(&self, normal_int_sig_en: u16) {
         let addr = self.base_addr + emmc_normal_int_sig_en_bits::EMMC_NORMAL_INT_SIG_EN_OFFSET;
         self.write_reg16(addr, normal_int_sig_en);
@@ -1464,7 +1827,7 @@ pub mod emmc_error_int_sig_en_bits {
     pub const EMMC_BOOT_ACK_SIG_EN: u16 = EMMC_BOOT_ACK_SIG_EN_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_error_int_sig_en(&self, error_int_sig_en: u16) {
         let addr = self.base_addr + emmc_error_int_sig_en_bits::EMMC_ERROR_INT_SIG_EN_OFFSET;
         self.write_reg16(addr, error_int_sig_en);
@@ -1499,9 +1862,352 @@ impl Reg {
     }
 }
 
+/// This module contains the offset position of the `EMMC_AUTO_CMD_STAT` register and the definitions of its individual bits.
+/// The `EMMC_AUTO_CMD_STAT` register is a 16-bit read-only register that tells which error `EMMC_AUTO_CMD_ERR`
+/// of the `EMMC_ERROR_INT_STAT` register reports.
+pub mod emmc_auto_cmd_stat_bits {
+    /// the offset of the `EMMC_AUTO_CMD_STAT` register from the base address of the SDHCI controller.
+    pub const EMMC_AUTO_CMD_STAT_OFFSET: u64 = 0x3c;
+    /// Auto CMD12 Not Executed, an error of the data transfer kept the controller from issuing it
+    pub const EMMC_AUTO_CMD12_NOT_EXEC_POS: u16 = 0;
+    pub const EMMC_AUTO_CMD12_NOT_EXEC_MASK: u16 = 0x01 << EMMC_AUTO_CMD12_NOT_EXEC_POS;
+    pub const EMMC_AUTO_CMD12_NOT_EXEC: u16 = EMMC_AUTO_CMD12_NOT_EXEC_MASK;
+    /// Auto CMD Timeout Error
+    pub const EMMC_AUTO_CMD_TOUT_ERR_POS: u16 = 1;
+    pub const EMMC_AUTO_CMD_TOUT_ERR_MASK: u16 = 0x01 << EMMC_AUTO_CMD_TOUT_ERR_POS;
+    pub const EMMC_AUTO_CMD_TOUT_ERR: u16 = EMMC_AUTO_CMD_TOUT_ERR_MASK;
+    /// Auto CMD CRC Error
+    pub const EMMC_AUTO_CMD_CRC_ERR_POS: u16 = 2;
+    pub const EMMC_AUTO_CMD_CRC_ERR_MASK: u16 = 0x01 << EMMC_AUTO_CMD_CRC_ERR_POS;
+    pub const EMMC_AUTO_CMD_CRC_ERR: u16 = EMMC_AUTO_CMD_CRC_ERR_MASK;
+    /// Auto CMD End Bit Error
+    pub const EMMC_AUTO_CMD_EBIT_ERR_POS: u16 = 3;
+    pub const EMMC_AUTO_CMD_EBIT_ERR_MASK: u16 = 0x01 << EMMC_AUTO_CMD_EBIT_ERR_POS;
+    pub const EMMC_AUTO_CMD_EBIT_ERR: u16 = EMMC_AUTO_CMD_EBIT_ERR_MASK;
+    /// Auto CMD Index Error
+    pub const EMMC_AUTO_CMD_IDX_ERR_POS: u16 = 4;
+    pub const EMMC_AUTO_CMD_IDX_ERR_MASK: u16 = 0x01 << EMMC_AUTO_CMD_IDX_ERR_POS;
+    pub const EMMC_AUTO_CMD_IDX_ERR: u16 = EMMC_AUTO_CMD_IDX_ERR_MASK;
+    /// Auto CMD Response Error, the response check function found an error in the R1 card status
+    pub const EMMC_AUTO_CMD_RESP_ERR_POS: u16 = 5;
+    pub const EMMC_AUTO_CMD_RESP_ERR_MASK: u16 = 0x01 << EMMC_AUTO_CMD_RESP_ERR_POS;
+    pub const EMMC_AUTO_CMD_RESP_ERR: u16 = EMMC_AUTO_CMD_RESP_ERR_MASK;
+    /// Command Not Issued By Auto CMD12 Error, the command following a failed Auto CMD12 was not issued
+    pub const EMMC_CMD_NOT_ISSUED_AUTO_CMD12_POS: u16 = 7;
+    pub const EMMC_CMD_NOT_ISSUED_AUTO_CMD12_MASK: u16 = 0x01 << EMMC_CMD_NOT_ISSUED_AUTO_CMD12_POS;
+    pub const EMMC_CMD_NOT_ISSUED_AUTO_CMD12: u16 = EMMC_CMD_NOT_ISSUED_AUTO_CMD12_MASK;
+}
+
+/// This module implements read operations for the `EMMC_AUTO_CMD_STAT` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_auto_cmd_stat_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_AUTO_CMD_STAT` register.
+    ///
+    /// The value is only meaningful when `EMMC_AUTO_CMD_ERR` was reported, it holds until the next Auto CMD.
+    pub fn emmc_get_auto_cmd_stat(&self) -> u16 {
+        let addr = self.base_addr + emmc_auto_cmd_stat_bits::EMMC_AUTO_CMD_STAT_OFFSET;
+        self.read_reg16(addr)
+    }
+}
+
+/// This module contains the offset position of the `EMMC_HOST_CTRL2` register and the definitions of its individual bits.
+/// The `EMMC_HOST_CTRL2` register is a 16-bit read-write register that selects the UHS-I mode, the signaling
+/// voltage and controls the tuning procedure.
+pub mod emmc_host_ctrl2_bits {
+    /// the offset of the `EMMC_HOST_CTRL2` register from the base address of the SDHCI controller.
+    pub const EMMC_HOST_CTRL2_OFFSET: u64 = 0x3e;
+    /// UHS Mode Select, only effective with 1.8V signaling
+    pub const EMMC_UHS_MODE_SEL_POS: u16 = 0;
+    pub const EMMC_UHS_MODE_SEL_MASK: u16 = 0x07 << EMMC_UHS_MODE_SEL_POS;
+    pub const EMMC_UHS_MODE_SEL: u16 = EMMC_UHS_MODE_SEL_MASK;
+    pub const EMMC_UHS_MODE_SDR12: u16 = 0x00 << EMMC_UHS_MODE_SEL_POS;
+    pub const EMMC_UHS_MODE_SDR25: u16 = 0x01 << EMMC_UHS_MODE_SEL_POS;
+    pub const EMMC_UHS_MODE_SDR50: u16 = 0x02 << EMMC_UHS_MODE_SEL_POS;
+    pub const EMMC_UHS_MODE_SDR104: u16 = 0x03 << EMMC_UHS_MODE_SEL_POS;
+    pub const EMMC_UHS_MODE_DDR50: u16 = 0x04 << EMMC_UHS_MODE_SEL_POS;
+    /// 1.8V Signaling Enable
+    pub const EMMC_SIGNALING_EN_POS: u16 = 3;
+    pub const EMMC_SIGNALING_EN_MASK: u16 = 0x01 << EMMC_SIGNALING_EN_POS;
+    pub const EMMC_SIGNALING_EN: u16 = EMMC_SIGNALING_EN_MASK;
+    /// Driver Strength Select
+    pub const EMMC_DRV_STRENGTH_SEL_POS: u16 = 4;
+    pub const EMMC_DRV_STRENGTH_SEL_MASK: u16 = 0x03 << EMMC_DRV_STRENGTH_SEL_POS;
+    pub const EMMC_DRV_STRENGTH_SEL: u16 = EMMC_DRV_STRENGTH_SEL_MASK;
+    /// Execute Tuning, cleared by the controller when the tuning procedure finished
+    pub const EMMC_EXEC_TUNING_POS: u16 = 6;
+    pub const EMMC_EXEC_TUNING_MASK: u16 = 0x01 << EMMC_EXEC_TUNING_POS;
+    pub const EMMC_EXEC_TUNING: u16 = EMMC_EXEC_TUNING_MASK;
+    /// Sampling Clock Select, set by the controller when the tuning procedure succeeded
+    pub const EMMC_SAMPLE_CLK_SEL_POS: u16 = 7;
+    pub const EMMC_SAMPLE_CLK_SEL_MASK: u16 = 0x01 << EMMC_SAMPLE_CLK_SEL_POS;
+    pub const EMMC_SAMPLE_CLK_SEL: u16 = EMMC_SAMPLE_CLK_SEL_MASK;
+    /// Asynchronous Interrupt Enable
+    pub const EMMC_ASYNC_INT_EN_POS: u16 = 14;
+    pub const EMMC_ASYNC_INT_EN_MASK: u16 = 0x01 << EMMC_ASYNC_INT_EN_POS;
+    pub const EMMC_ASYNC_INT_EN: u16 = EMMC_ASYNC_INT_EN_MASK;
+    /// Preset Value Enable
+    pub const EMMC_PRESET_VAL_EN_POS: u16 = 15;
+    pub const EMMC_PRESET_VAL_EN_MASK: u16 = 0x01 << EMMC_PRESET_VAL_EN_POS;
+    pub const EMMC_PRESET_VAL_EN: u16 = EMMC_PRESET_VAL_EN_MASK;
+}
+
+/// This module implements the read and write operations for the `EMMC_HOST_CTRL2` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_host_ctrl2_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_HOST_CTRL2` register.
+    pub fn emmc_get_host_ctrl2(&self) -> u16 {
+        let addr = self.base_addr + emmc_host_ctrl2_bits::EMMC_HOST_CTRL2_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Select the UHS-I mode, one of the `EMMC_UHS_MODE_*` values.
+    ///
+    /// The SD clock must be stopped while the mode is changed.
+    pub fn emmc_set_uhs_mode(&self, mode: u16) {
+        let addr = self.base_addr + emmc_host_ctrl2_bits::EMMC_HOST_CTRL2_OFFSET;
+        let value = self.read_reg16(addr);
+        self.write_reg16(addr, (value & !emmc_host_ctrl2_bits::EMMC_UHS_MODE_SEL_MASK) | mode);
+    }
+
+    pub fn emmc_enable_1v8_signaling(&self) {
+        let addr = self.base_addr + emmc_host_ctrl2_bits::EMMC_HOST_CTRL2_OFFSET;
+        let value = self.read_reg16(addr);
+        self.write_reg16(addr, value | emmc_host_ctrl2_bits::EMMC_SIGNALING_EN);
+    }
+
+    pub fn emmc_disable_1v8_signaling(&self) {
+        let addr = self.base_addr + emmc_host_ctrl2_bits::EMMC_HOST_CTRL2_OFFSET;
+        let value = self.read_reg16(addr);
+        self.write_reg16(addr, value & !emmc_host_ctrl2_bits::EMMC_SIGNALING_EN);
+    }
+
+    /// Check the 1.8V signaling is enabled, the controller clears it if the regulator did not switch.
+    pub fn emmc_1v8_signaling_is_enabled(&self) -> bool {
+        self.emmc_get_host_ctrl2() & emmc_host_ctrl2_bits::EMMC_SIGNALING_EN == emmc_host_ctrl2_bits::EMMC_SIGNALING_EN
+    }
+
+    /// Start the tuning procedure, clearing the result of the previous one.
+    pub fn emmc_start_tuning(&self) {
+        let addr = self.base_addr + emmc_host_ctrl2_bits::EMMC_HOST_CTRL2_OFFSET;
+        let value = self.read_reg16(addr) & !emmc_host_ctrl2_bits::EMMC_SAMPLE_CLK_SEL;
+        self.write_reg16(addr, value | emmc_host_ctrl2_bits::EMMC_EXEC_TUNING);
+    }
+
+    /// Abandon the tuning procedure and go back to the fixed sampling clock.
+    pub fn emmc_reset_tuning(&self) {
+        let addr = self.base_addr + emmc_host_ctrl2_bits::EMMC_HOST_CTRL2_OFFSET;
+        let value = self.read_reg16(addr);
+        self.write_reg16(addr, value & !(emmc_host_ctrl2_bits::EMMC_EXEC_TUNING | emmc_host_ctrl2_bits::EMMC_SAMPLE_CLK_SEL));
+    }
+
+    pub fn emmc_tuning_is_executing(&self) -> bool {
+        self.emmc_get_host_ctrl2() & emmc_host_ctrl2_bits::EMMC_EXEC_TUNING == emmc_host_ctrl2_bits::EMMC_EXEC_TUNING
+    }
+
+    /// Check the tuned sampling clock is in use.
+    pub fn emmc_sample_clk_is_tuned(&self) -> bool {
+        self.emmc_get_host_ctrl2() & emmc_host_ctrl2_bits::EMMC_SAMPLE_CLK_SEL == emmc_host_ctrl2_bits::EMMC_SAMPLE_CLK_SEL
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CAPABILITIES1` register and the definitions of its individual bits.
+/// The `EMMC_CAPABILITIES1` register is a 32-bit read-only register that describes what the Host Controller supports.
+pub mod emmc_capabilities1_bits {
+    /// the offset of the `EMMC_CAPABILITIES1` register from the base address of the SDHCI controller.
+    pub const EMMC_CAPABILITIES1_OFFSET: u64 = 0x40;
+    /// Timeout Clock Frequency
+    pub const EMMC_TOUT_CLK_FREQ_POS: u32 = 0;
+    pub const EMMC_TOUT_CLK_FREQ_MASK: u32 = 0x3f << EMMC_TOUT_CLK_FREQ_POS;
+    pub const EMMC_TOUT_CLK_FREQ: u32 = EMMC_TOUT_CLK_FREQ_MASK;
+    /// Timeout Clock Unit, MHz when set and KHz otherwise
+    pub const EMMC_TOUT_CLK_UNIT_POS: u32 = 7;
+    pub const EMMC_TOUT_CLK_UNIT_MASK: u32 = 0x01 << EMMC_TOUT_CLK_UNIT_POS;
+    pub const EMMC_TOUT_CLK_UNIT: u32 = EMMC_TOUT_CLK_UNIT_MASK;
+    /// Base Clock Frequency for SD clock in MHz, bits 13:8 before version 3.00
+    pub const EMMC_BASE_CLK_FREQ_POS: u32 = 8;
+    pub const EMMC_BASE_CLK_FREQ_MASK: u32 = 0xff << EMMC_BASE_CLK_FREQ_POS;
+    pub const EMMC_BASE_CLK_FREQ: u32 = EMMC_BASE_CLK_FREQ_MASK;
+    pub const EMMC_BASE_CLK_FREQ_V2_MASK: u32 = 0x3f << EMMC_BASE_CLK_FREQ_POS;
+    /// Max Block Length, 512 << value bytes
+    pub const EMMC_MAX_BLK_LEN_POS: u32 = 16;
+    pub const EMMC_MAX_BLK_LEN_MASK: u32 = 0x03 << EMMC_MAX_BLK_LEN_POS;
+    pub const EMMC_MAX_BLK_LEN: u32 = EMMC_MAX_BLK_LEN_MASK;
+    /// 8-bit Support for Embedded Device
+    pub const EMMC_EMBEDDED_8_BIT_POS: u32 = 18;
+    pub const EMMC_EMBEDDED_8_BIT_MASK: u32 = 0x01 << EMMC_EMBEDDED_8_BIT_POS;
+    pub const EMMC_EMBEDDED_8_BIT: u32 = EMMC_EMBEDDED_8_BIT_MASK;
+    /// ADMA2 Support
+    pub const EMMC_ADMA2_SUPPORT_POS: u32 = 19;
+    pub const EMMC_ADMA2_SUPPORT_MASK: u32 = 0x01 << EMMC_ADMA2_SUPPORT_POS;
+    pub const EMMC_ADMA2_SUPPORT: u32 = EMMC_ADMA2_SUPPORT_MASK;
+    /// High Speed Support
+    pub const EMMC_HIGH_SPEED_SUPPORT_POS: u32 = 21;
+    pub const EMMC_HIGH_SPEED_SUPPORT_MASK: u32 = 0x01 << EMMC_HIGH_SPEED_SUPPORT_POS;
+    pub const EMMC_HIGH_SPEED_SUPPORT: u32 = EMMC_HIGH_SPEED_SUPPORT_MASK;
+    /// SDMA Support
+    pub const EMMC_SDMA_SUPPORT_POS: u32 = 22;
+    pub const EMMC_SDMA_SUPPORT_MASK: u32 = 0x01 << EMMC_SDMA_SUPPORT_POS;
+    pub const EMMC_SDMA_SUPPORT: u32 = EMMC_SDMA_SUPPORT_MASK;
+    /// Voltage Support for 3.3V
+    pub const EMMC_VOLT_33_POS: u32 = 24;
+    pub const EMMC_VOLT_33_MASK: u32 = 0x01 << EMMC_VOLT_33_POS;
+    pub const EMMC_VOLT_33: u32 = EMMC_VOLT_33_MASK;
+    /// Voltage Support for 3.0V
+    pub const EMMC_VOLT_30_POS: u32 = 25;
+    pub const EMMC_VOLT_30_MASK: u32 = 0x01 << EMMC_VOLT_30_POS;
+    pub const EMMC_VOLT_30: u32 = EMMC_VOLT_30_MASK;
+    /// Voltage Support for 1.8V
+    pub const EMMC_VOLT_18_POS: u32 = 26;
+    pub const EMMC_VOLT_18_MASK: u32 = 0x01 << EMMC_VOLT_18_POS;
+    pub const EMMC_VOLT_18: u32 = EMMC_VOLT_18_MASK;
+    /// Slot Type
+    pub const EMMC_SLOT_TYPE_POS: u32 = 30;
+    pub const EMMC_SLOT_TYPE_MASK: u32 = 0x03 << EMMC_SLOT_TYPE_POS;
+    pub const EMMC_SLOT_TYPE: u32 = EMMC_SLOT_TYPE_MASK;
+    pub const EMMC_SLOT_TYPE_REMOVABLE: u32 = 0x00 << EMMC_SLOT_TYPE_POS;
+    pub const EMMC_SLOT_TYPE_EMBEDDED: u32 = 0x01 << EMMC_SLOT_TYPE_POS;
+}
+
+/// This module implements read operations for the `EMMC_CAPABILITIES1` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_capabilities1_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CAPABILITIES1` register.
+    pub fn emmc_get_capabilities1(&self) -> u32 {
+        let addr = self.base_addr + emmc_capabilities1_bits::EMMC_CAPABILITIES1_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Return the base clock frequency of the SD clock in MHz, 0 if it has to be obtained another way.
+    ///
+    /// The field is 6 bits wide before version 3.00 of the specification.
+    pub fn emmc_get_base_clk_freq(&self) -> u32 {
+        let mask = if self.emmc_get_spec_version() >= emmc_host_ctrl_ver_bits::EMMC_SPEC_VERSION_V300 {
+            emmc_capabilities1_bits::EMMC_BASE_CLK_FREQ_MASK
+        } else {
+            emmc_capabilities1_bits::EMMC_BASE_CLK_FREQ_V2_MASK
+        };
+        (self.emmc_get_capabilities1() & mask) >> emmc_capabilities1_bits::EMMC_BASE_CLK_FREQ_POS
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CAPABILITIES2` register and the definitions of its individual bits.
+/// The `EMMC_CAPABILITIES2` register is a 32-bit read-only register that describes the UHS-I support of the Host Controller.
+pub mod emmc_capabilities2_bits {
+    /// the offset of the `EMMC_CAPABILITIES2` register from the base address of the SDHCI controller.
+    pub const EMMC_CAPABILITIES2_OFFSET: u64 = 0x44;
+    /// SDR50 Support
+    pub const EMMC_SDR50_SUPPORT_POS: u32 = 0;
+    pub const EMMC_SDR50_SUPPORT_MASK: u32 = 0x01 << EMMC_SDR50_SUPPORT_POS;
+    pub const EMMC_SDR50_SUPPORT: u32 = EMMC_SDR50_SUPPORT_MASK;
+    /// SDR104 Support
+    pub const EMMC_SDR104_SUPPORT_POS: u32 = 1;
+    pub const EMMC_SDR104_SUPPORT_MASK: u32 = 0x01 << EMMC_SDR104_SUPPORT_POS;
+    pub const EMMC_SDR104_SUPPORT: u32 = EMMC_SDR104_SUPPORT_MASK;
+    /// DDR50 Support
+    pub const EMMC_DDR50_SUPPORT_POS: u32 = 2;
+    pub const EMMC_DDR50_SUPPORT_MASK: u32 = 0x01 << EMMC_DDR50_SUPPORT_POS;
+    pub const EMMC_DDR50_SUPPORT: u32 = EMMC_DDR50_SUPPORT_MASK;
+    /// Timer Count for Re-Tuning, 2^(n-1) seconds, 0 if disabled
+    pub const EMMC_RETUNE_CNT_POS: u32 = 8;
+    pub const EMMC_RETUNE_CNT_MASK: u32 = 0x0f << EMMC_RETUNE_CNT_POS;
+    pub const EMMC_RETUNE_CNT: u32 = EMMC_RETUNE_CNT_MASK;
+    /// Use Tuning for SDR50
+    pub const EMMC_USE_TUNING_SDR50_POS: u32 = 13;
+    pub const EMMC_USE_TUNING_SDR50_MASK: u32 = 0x01 << EMMC_USE_TUNING_SDR50_POS;
+    pub const EMMC_USE_TUNING_SDR50: u32 = EMMC_USE_TUNING_SDR50_MASK;
+    /// Re-Tuning Modes
+    pub const EMMC_RE_TUNING_MODES_POS: u32 = 14;
+    pub const EMMC_RE_TUNING_MODES_MASK: u32 = 0x03 << EMMC_RE_TUNING_MODES_POS;
+    pub const EMMC_RE_TUNING_MODES: u32 = EMMC_RE_TUNING_MODES_MASK;
+    /// Clock Multiplier, 0 if the programmable clock mode is not supported
+    pub const EMMC_CLK_MUL_POS: u32 = 16;
+    pub const EMMC_CLK_MUL_MASK: u32 = 0xff << EMMC_CLK_MUL_POS;
+    pub const EMMC_CLK_MUL: u32 = EMMC_CLK_MUL_MASK;
+}
+
+/// This module implements read operations for the `EMMC_CAPABILITIES2` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_capabilities2_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CAPABILITIES2` register.
+    pub fn emmc_get_capabilities2(&self) -> u32 {
+        let addr = self.base_addr + emmc_capabilities2_bits::EMMC_CAPABILITIES2_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Return the re-tuning period in seconds, 0 if the timer is not used.
+    pub fn emmc_get_retune_period(&self) -> u32 {
+        match (self.emmc_get_capabilities2() & emmc_capabilities2_bits::EMMC_RETUNE_CNT) >> emmc_capabilities2_bits::EMMC_RETUNE_CNT_POS {
+            count @ 1..=0x0b => 1 << (count - 1),
+            _ => 0,
+        }
+    }
+}
+
+/* TODO
+ *
+ * offset 0x48 - 0x52
+*/
+
+/// This module contains the offset position of the `EMMC_ADMA_ERR_STAT` register and the definitions of its individual bits.
+/// The `EMMC_ADMA_ERR_STAT` register is a 8-bit read-only register that reports the state of the ADMA engine when an ADMA error occurred.
+pub mod emmc_adma_err_stat_bits {
+    /// the offset of the `EMMC_ADMA_ERR_STAT` register from the base address of the SDHCI controller.
+    pub const EMMC_ADMA_ERR_STAT_OFFSET: u64 = 0x54;
+    /// ADMA Error States
+    pub const EMMC_ADMA_ERR_STATES_POS: u8 = 0;
+    pub const EMMC_ADMA_ERR_STATES_MASK: u8 = 0x03 << EMMC_ADMA_ERR_STATES_POS;
+    pub const EMMC_ADMA_ERR_STATES: u8 = EMMC_ADMA_ERR_STATES_MASK;
+    pub const EMMC_ADMA_ERR_STATES_ST_STOP: u8 = 0x00 << EMMC_ADMA_ERR_STATES_POS;
+    pub const EMMC_ADMA_ERR_STATES_ST_FDS: u8 = 0x01 << EMMC_ADMA_ERR_STATES_POS;
+    pub const EMMC_ADMA_ERR_STATES_ST_TFR: u8 = 0x03 << EMMC_ADMA_ERR_STATES_POS;
+    /// ADMA Length Mismatch Error
+    pub const EMMC_ADMA_LEN_ERR_POS: u8 = 2;
+    pub const EMMC_ADMA_LEN_ERR_MASK: u8 = 0x01 << EMMC_ADMA_LEN_ERR_POS;
+    pub const EMMC_ADMA_LEN_ERR: u8 = EMMC_ADMA_LEN_ERR_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_ADMA_ERR_STAT` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_adma_err_stat_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_ADMA_ERR_STAT` register.
+    pub fn emmc_get_adma_err_stat(&self) -> u8 {
+        let addr = self.base_addr + emmc_adma_err_stat_bits::EMMC_ADMA_ERR_STAT_OFFSET;
+        self.read_reg8(addr)
+    }
+}
+
+/// This module contains the offset positions of the `EMMC_ADMA_SA_LOW` and `EMMC_ADMA_SA_HIGH` registers.
+/// They are 32-bit read-write registers that hold the lower and upper half of the ADMA2 descriptor table address.
+pub mod emmc_adma_sa_bits {
+    /// the offset of the `EMMC_ADMA_SA_LOW` register from the base address of the SDHCI controller.
+    pub const EMMC_ADMA_SA_LOW_OFFSET: u64 = 0x58;
+    /// the offset of the `EMMC_ADMA_SA_HIGH` register from the base address of the SDHCI controller.
+    pub const EMMC_ADMA_SA_HIGH_OFFSET: u64 = 0x5c;
+}
+
+/// This module implements read and write operations for the `EMMC_ADMA_SA_LOW` and `EMMC_ADMA_SA_HIGH` registers.
+/// - The definition of the offsets is in the `emmc_adma_sa_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the address of the ADMA2 descriptor being processed, or of the failed one after an ADMA error.
+    pub fn emmc_get_adma_sa(&self) -> u64 {
+        let addr = self.base_addr + emmc_adma_sa_bits::EMMC_ADMA_SA_LOW_OFFSET;
+        let low = self.read_reg(addr) as u64;
+        let addr = self.base_addr + emmc_adma_sa_bits::EMMC_ADMA_SA_HIGH_OFFSET;
+        (self.read_reg(addr) as u64) << 32 | low
+    }
+
+    /// Set the physical address of the ADMA2 descriptor table.
+    pub fn emmc_set_adma_sa(&self, adma_sa: u64) {
+        let addr = self.base_addr + emmc_adma_sa_bits::EMMC_ADMA_SA_LOW_OFFSET;
+        self.write_reg(addr, adma_sa as u32);
+        let addr = self.base_addr + emmc_adma_sa_bits::EMMC_ADMA_SA_HIGH_OFFSET;
+        self.write_reg(addr, (adma_sa >> 32) as u32);
+    }
+}
+
 /* TODO
  *
- * offset 0x50 - 0x6e
+ * offset 0x60 - 0x6e
 */
 
 /// This module contains the offset position of the `EMMC_ADMA_ID` register and the definitions of its individual bits.
@@ -1517,7 +2223,7 @@ pub mod emmc_adma_id_bits {
 
 /// This module implements read and write operations for the `EMMC_ADMA_ID` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_adma_id_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_ADMA_ID` register.
     ///
     /// These bits indicate the 32-bit of the ADMA Integrated Descriptor 
@@ -1565,7 +2271,7 @@ pub mod emmc_slot_int_status_bits {
 
 /// This module implements read operations for the `EMMC_SLOT_INTR_STATUS` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_slot_int_status_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_SLOT_INTR_STATUS` register. 
     ///
     /// Host Controller support single card slot. This register shall always return 0.
@@ -1606,7 +2312,7 @@ pub mod emmc_host_ctrl_ver_bits {
 
 /// This module implements read operations for the `EMMC_HOST_CNTRL_VERS` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_host_ctrl_ver_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_HOST_CNTRL_VERS` register. 
     ///
     /// # Arguments
@@ -1657,10 +2363,466 @@ impl Reg {
     }
 }
 
-/* TODO
- *
- * offset 0x0180 - 0x01dc
-*/
+/// This module contains the offset position of the `EMMC_CQVER` register and the definitions of its individual bits.
+/// The `EMMC_CQVER` register is a 32-bit read-only register that contains the version of the command queuing interface.
+pub mod emmc_cqver_bits {
+    /// the offset of the `EMMC_CQVER` register from the base address of the SDHCI controller.
+    pub const EMMC_CQVER_OFFSET: u64 = 0x180;
+    /// eMMC Version Suffix
+    pub const EMMC_CQVER_SUFFIX_POS: u32 = 0;
+    pub const EMMC_CQVER_SUFFIX_MASK: u32 = 0x0f << EMMC_CQVER_SUFFIX_POS;
+    /// eMMC Minor Version Number
+    pub const EMMC_CQVER_MINOR_POS: u32 = 4;
+    pub const EMMC_CQVER_MINOR_MASK: u32 = 0x0f << EMMC_CQVER_MINOR_POS;
+    /// eMMC Major Version Number
+    pub const EMMC_CQVER_MAJOR_POS: u32 = 8;
+    pub const EMMC_CQVER_MAJOR_MASK: u32 = 0x0f << EMMC_CQVER_MAJOR_POS;
+}
+
+/// This module implements read and write operations for the `EMMC_CQVER` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_cqver_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CQVER` register, e.g. 0x510 for eMMC 5.1.
+    pub fn emmc_get_cqver(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqver_bits::EMMC_CQVER_OFFSET;
+        self.read_reg(addr)
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQCAP` register and the definitions of its individual bits.
+/// The `EMMC_CQCAP` register is a 32-bit read-only register that contains the command queuing capabilities.
+pub mod emmc_cqcap_bits {
+    /// the offset of the `EMMC_CQCAP` register from the base address of the SDHCI controller.
+    pub const EMMC_CQCAP_OFFSET: u64 = 0x184;
+    /// Internal Timer Clock Frequency Value
+    pub const EMMC_ITCFVAL_POS: u32 = 0;
+    pub const EMMC_ITCFVAL_MASK: u32 = 0x3ff << EMMC_ITCFVAL_POS;
+    /// Internal Timer Clock Frequency Multiplier
+    pub const EMMC_ITCFMUL_POS: u32 = 12;
+    pub const EMMC_ITCFMUL_MASK: u32 = 0x0f << EMMC_ITCFMUL_POS;
+    /// Crypto Support
+    pub const EMMC_CRYPTO_SUPPORT_POS: u32 = 28;
+    pub const EMMC_CRYPTO_SUPPORT_MASK: u32 = 0x01 << EMMC_CRYPTO_SUPPORT_POS;
+    pub const EMMC_CRYPTO_SUPPORT: u32 = EMMC_CRYPTO_SUPPORT_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_CQCAP` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_cqcap_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CQCAP` register.
+    pub fn emmc_get_cqcap(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqcap_bits::EMMC_CQCAP_OFFSET;
+        self.read_reg(addr)
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQCFG` register and the definitions of its individual bits.
+/// The `EMMC_CQCFG` register is a 32-bit read-write register that contains the command queuing configuration.
+pub mod emmc_cqcfg_bits {
+    /// the offset of the `EMMC_CQCFG` register from the base address of the SDHCI controller.
+    pub const EMMC_CQCFG_OFFSET: u64 = 0x188;
+    /// Command Queuing Enable
+    pub const EMMC_CQ_EN_POS: u32 = 0;
+    pub const EMMC_CQ_EN_MASK: u32 = 0x01 << EMMC_CQ_EN_POS;
+    pub const EMMC_CQ_EN: u32 = EMMC_CQ_EN_MASK;
+    /// Task Descriptor Size, 0 for 64-bit and 1 for 128-bit task descriptors
+    pub const EMMC_TASK_DESC_SIZE_POS: u32 = 8;
+    pub const EMMC_TASK_DESC_SIZE_MASK: u32 = 0x01 << EMMC_TASK_DESC_SIZE_POS;
+    pub const EMMC_TASK_DESC_SIZE: u32 = EMMC_TASK_DESC_SIZE_MASK;
+    /// Direct Command (DCMD) Enable, slot 31 is then reserved for direct commands
+    pub const EMMC_DCMD_EN_POS: u32 = 12;
+    pub const EMMC_DCMD_EN_MASK: u32 = 0x01 << EMMC_DCMD_EN_POS;
+    pub const EMMC_DCMD_EN: u32 = EMMC_DCMD_EN_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_CQCFG` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_cqcfg_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CQCFG` register.
+    pub fn emmc_get_cqcfg(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqcfg_bits::EMMC_CQCFG_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Set the entire value of the `EMMC_CQCFG` register.
+    ///
+    /// The task descriptor list address and the other configuration registers must only be
+    /// changed while `EMMC_CQ_EN` is clear.
+    pub fn emmc_set_cqcfg(&self, cqcfg: u32) {
+        let addr = self.base_addr + emmc_cqcfg_bits::EMMC_CQCFG_OFFSET;
+        self.write_reg(addr, cqcfg);
+    }
+
+    /// Check whether the command queuing engine is enabled.
+    pub fn emmc_cqe_is_enabled(&self) -> bool {
+        let addr = self.base_addr + emmc_cqcfg_bits::EMMC_CQCFG_OFFSET;
+        self.read_reg(addr) & emmc_cqcfg_bits::EMMC_CQ_EN != 0
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQCTL` register and the definitions of its individual bits.
+/// The `EMMC_CQCTL` register is a 32-bit read-write register that controls the command queuing engine.
+pub mod emmc_cqctl_bits {
+    /// the offset of the `EMMC_CQCTL` register from the base address of the SDHCI controller.
+    pub const EMMC_CQCTL_OFFSET: u64 = 0x18c;
+    /// Halt, reads 1 once the engine has actually halted
+    pub const EMMC_CQ_HALT_POS: u32 = 0;
+    pub const EMMC_CQ_HALT_MASK: u32 = 0x01 << EMMC_CQ_HALT_POS;
+    pub const EMMC_CQ_HALT: u32 = EMMC_CQ_HALT_MASK;
+    /// Clear All Tasks, only allowed while halted
+    pub const EMMC_CQ_CLR_ALL_TASKS_POS: u32 = 8;
+    pub const EMMC_CQ_CLR_ALL_TASKS_MASK: u32 = 0x01 << EMMC_CQ_CLR_ALL_TASKS_POS;
+    pub const EMMC_CQ_CLR_ALL_TASKS: u32 = EMMC_CQ_CLR_ALL_TASKS_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_CQCTL` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_cqctl_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CQCTL` register.
+    pub fn emmc_get_cqctl(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqctl_bits::EMMC_CQCTL_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Set the entire value of the `EMMC_CQCTL` register.
+    pub fn emmc_set_cqctl(&self, cqctl: u32) {
+        let addr = self.base_addr + emmc_cqctl_bits::EMMC_CQCTL_OFFSET;
+        self.write_reg(addr, cqctl);
+    }
+
+    /// Check whether the command queuing engine has halted.
+    pub fn emmc_cqe_is_halted(&self) -> bool {
+        let addr = self.base_addr + emmc_cqctl_bits::EMMC_CQCTL_OFFSET;
+        self.read_reg(addr) & emmc_cqctl_bits::EMMC_CQ_HALT != 0
+    }
+
+    /// Check whether the clear all tasks operation has completed.
+    pub fn emmc_cqe_clear_all_tasks_is_finished(&self) -> bool {
+        let addr = self.base_addr + emmc_cqctl_bits::EMMC_CQCTL_OFFSET;
+        self.read_reg(addr) & emmc_cqctl_bits::EMMC_CQ_CLR_ALL_TASKS == 0
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQIS` register and the definitions of its individual bits.
+/// The `EMMC_CQIS` register is a 32-bit write-1-to-clear register that contains the command queuing interrupt status.
+///
+/// `EMMC_CQISTE` (status enable), `EMMC_CQISGE` (signal enable) use the same bit layout.
+pub mod emmc_cqis_bits {
+    /// the offset of the `EMMC_CQIS` register from the base address of the SDHCI controller.
+    pub const EMMC_CQIS_OFFSET: u64 = 0x190;
+    /// the offset of the `EMMC_CQISTE` register from the base address of the SDHCI controller.
+    pub const EMMC_CQISTE_OFFSET: u64 = 0x194;
+    /// the offset of the `EMMC_CQISGE` register from the base address of the SDHCI controller.
+    pub const EMMC_CQISGE_OFFSET: u64 = 0x198;
+    /// Halt Complete
+    pub const EMMC_CQ_HAC_POS: u32 = 0;
+    pub const EMMC_CQ_HAC_MASK: u32 = 0x01 << EMMC_CQ_HAC_POS;
+    pub const EMMC_CQ_HAC: u32 = EMMC_CQ_HAC_MASK;
+    /// Task Complete
+    pub const EMMC_CQ_TCC_POS: u32 = 1;
+    pub const EMMC_CQ_TCC_MASK: u32 = 0x01 << EMMC_CQ_TCC_POS;
+    pub const EMMC_CQ_TCC: u32 = EMMC_CQ_TCC_MASK;
+    /// Response Error Detected
+    pub const EMMC_CQ_RED_POS: u32 = 2;
+    pub const EMMC_CQ_RED_MASK: u32 = 0x01 << EMMC_CQ_RED_POS;
+    pub const EMMC_CQ_RED: u32 = EMMC_CQ_RED_MASK;
+    /// Task Cleared
+    pub const EMMC_CQ_TCL_POS: u32 = 3;
+    pub const EMMC_CQ_TCL_MASK: u32 = 0x01 << EMMC_CQ_TCL_POS;
+    pub const EMMC_CQ_TCL: u32 = EMMC_CQ_TCL_MASK;
+    /// General Crypto Error
+    pub const EMMC_CQ_GCE_POS: u32 = 4;
+    pub const EMMC_CQ_GCE_MASK: u32 = 0x01 << EMMC_CQ_GCE_POS;
+    pub const EMMC_CQ_GCE: u32 = EMMC_CQ_GCE_MASK;
+    /// Invalid Crypto Configuration Error
+    pub const EMMC_CQ_ICCE_POS: u32 = 5;
+    pub const EMMC_CQ_ICCE_MASK: u32 = 0x01 << EMMC_CQ_ICCE_POS;
+    pub const EMMC_CQ_ICCE: u32 = EMMC_CQ_ICCE_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_CQIS`, `EMMC_CQISTE` and `EMMC_CQISGE` registers.
+/// - The definition of the bit is in the `emmc_cqis_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CQIS` register.
+    pub fn emmc_get_cqis(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqis_bits::EMMC_CQIS_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Write the `EMMC_CQIS` register. Bits written as 1 are cleared.
+    pub fn emmc_set_cqis(&self, cqis: u32) {
+        let addr = self.base_addr + emmc_cqis_bits::EMMC_CQIS_OFFSET;
+        self.write_reg(addr, cqis);
+    }
+
+    /// Set which `EMMC_CQIS` bits are latched.
+    pub fn emmc_set_cqiste(&self, cqiste: u32) {
+        let addr = self.base_addr + emmc_cqis_bits::EMMC_CQISTE_OFFSET;
+        self.write_reg(addr, cqiste);
+    }
+
+    /// Set which `EMMC_CQIS` bits raise the `EMMC_CQE_EVENT` interrupt.
+    pub fn emmc_set_cqisge(&self, cqisge: u32) {
+        let addr = self.base_addr + emmc_cqis_bits::EMMC_CQISGE_OFFSET;
+        self.write_reg(addr, cqisge);
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQIC` register and the definitions of its individual bits.
+/// The `EMMC_CQIC` register is a 32-bit read-write register that controls interrupt coalescing.
+pub mod emmc_cqic_bits {
+    /// the offset of the `EMMC_CQIC` register from the base address of the SDHCI controller.
+    pub const EMMC_CQIC_OFFSET: u64 = 0x19c;
+    /// Interrupt Coalescing Timeout Value
+    pub const EMMC_CQ_ICTOVAL_POS: u32 = 0;
+    pub const EMMC_CQ_ICTOVAL_MASK: u32 = 0x7f << EMMC_CQ_ICTOVAL_POS;
+    /// Interrupt Coalescing Timeout Value Write Enable
+    pub const EMMC_CQ_ICTOVALWEN_POS: u32 = 7;
+    pub const EMMC_CQ_ICTOVALWEN_MASK: u32 = 0x01 << EMMC_CQ_ICTOVALWEN_POS;
+    pub const EMMC_CQ_ICTOVALWEN: u32 = EMMC_CQ_ICTOVALWEN_MASK;
+    /// Interrupt Coalescing Counter Threshold
+    pub const EMMC_CQ_ICCTH_POS: u32 = 8;
+    pub const EMMC_CQ_ICCTH_MASK: u32 = 0x1f << EMMC_CQ_ICCTH_POS;
+    /// Interrupt Coalescing Counter Threshold Write Enable
+    pub const EMMC_CQ_ICCTHWEN_POS: u32 = 15;
+    pub const EMMC_CQ_ICCTHWEN_MASK: u32 = 0x01 << EMMC_CQ_ICCTHWEN_POS;
+    pub const EMMC_CQ_ICCTHWEN: u32 = EMMC_CQ_ICCTHWEN_MASK;
+    /// Counter and Timer Reset
+    pub const EMMC_CQ_ICCTR_POS: u32 = 16;
+    pub const EMMC_CQ_ICCTR_MASK: u32 = 0x01 << EMMC_CQ_ICCTR_POS;
+    pub const EMMC_CQ_ICCTR: u32 = EMMC_CQ_ICCTR_MASK;
+    /// Interrupt Coalescing Enable/Disable
+    pub const EMMC_CQ_ICENDIS_POS: u32 = 31;
+    pub const EMMC_CQ_ICENDIS_MASK: u32 = 0x01 << EMMC_CQ_ICENDIS_POS;
+    pub const EMMC_CQ_ICENDIS: u32 = EMMC_CQ_ICENDIS_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_CQIC` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_cqic_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Set the entire value of the `EMMC_CQIC` register.
+    pub fn emmc_set_cqic(&self, cqic: u32) {
+        let addr = self.base_addr + emmc_cqic_bits::EMMC_CQIC_OFFSET;
+        self.write_reg(addr, cqic);
+    }
+}
+
+/// This module contains the offset positions of the `EMMC_CQTDLBA` and `EMMC_CQTDLBAU` registers.
+/// They are 32-bit read-write registers that hold the lower and upper half of the task descriptor list base address.
+pub mod emmc_cqtdlba_bits {
+    /// the offset of the `EMMC_CQTDLBA` register from the base address of the SDHCI controller.
+    pub const EMMC_CQTDLBA_OFFSET: u64 = 0x1a0;
+    /// the offset of the `EMMC_CQTDLBAU` register from the base address of the SDHCI controller.
+    pub const EMMC_CQTDLBAU_OFFSET: u64 = 0x1a4;
+}
+
+/// This module implements read and write operations for the `EMMC_CQTDLBA` and `EMMC_CQTDLBAU` registers.
+/// - The definition of the offsets is in the `emmc_cqtdlba_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Set the physical base address of the task descriptor list, aligned to 1 KiB.
+    pub fn emmc_set_cqtdlba(&self, tdlba: u64) {
+        let addr = self.base_addr + emmc_cqtdlba_bits::EMMC_CQTDLBA_OFFSET;
+        self.write_reg(addr, tdlba as u32);
+        let addr = self.base_addr + emmc_cqtdlba_bits::EMMC_CQTDLBAU_OFFSET;
+        self.write_reg(addr, (tdlba >> 32) as u32);
+    }
+}
+
+/// This module contains the offset positions of the per-task command queuing registers.
+/// Each of them is a 32-bit register with bit `n` standing for task `n`.
+pub mod emmc_cq_task_bits {
+    /// Task Doorbell: setting a bit hands the task over to the engine.
+    pub const EMMC_CQTDBR_OFFSET: u64 = 0x1a8;
+    /// Task Completion Notification: write-1-to-clear.
+    pub const EMMC_CQTCN_OFFSET: u64 = 0x1ac;
+    /// Device Queue Status: the device reported the task ready for execution.
+    pub const EMMC_CQDQS_OFFSET: u64 = 0x1b0;
+    /// Device Pending Tasks: the task is queued in the device.
+    pub const EMMC_CQDPT_OFFSET: u64 = 0x1b4;
+    /// Task Clear: only allowed while halted, a bit reads 0 once the task is cleared.
+    pub const EMMC_CQTCLR_OFFSET: u64 = 0x1b8;
+}
+
+/// This module implements read and write operations for the per-task command queuing registers.
+/// - The definition of the offsets is in the `emmc_cq_task_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the tasks handed over to the engine and not completed yet.
+    pub fn emmc_get_cqtdbr(&self) -> u32 {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQTDBR_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Ring the doorbell of the tasks set in `tasks`. Writing 0 to a bit has no effect.
+    pub fn emmc_set_cqtdbr(&self, tasks: u32) {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQTDBR_OFFSET;
+        self.write_reg(addr, tasks);
+    }
+
+    /// Return the tasks completed since the notification was last cleared.
+    pub fn emmc_get_cqtcn(&self) -> u32 {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQTCN_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Clear the completion notification of the tasks set in `tasks`.
+    pub fn emmc_set_cqtcn(&self, tasks: u32) {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQTCN_OFFSET;
+        self.write_reg(addr, tasks);
+    }
+
+    /// Return the tasks the device reported ready for execution.
+    pub fn emmc_get_cqdqs(&self) -> u32 {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQDQS_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Return the tasks queued in the device.
+    pub fn emmc_get_cqdpt(&self) -> u32 {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQDPT_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Return the tasks whose clear is still in progress.
+    pub fn emmc_get_cqtclr(&self) -> u32 {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQTCLR_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Clear the tasks set in `tasks`.
+    pub fn emmc_set_cqtclr(&self, tasks: u32) {
+        let addr = self.base_addr + emmc_cq_task_bits::EMMC_CQTCLR_OFFSET;
+        self.write_reg(addr, tasks);
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQSSC1` and `EMMC_CQSSC2` registers and the definitions of their individual bits.
+/// They are 32-bit read-write registers that configure the CMD13 polling of the device queue status.
+pub mod emmc_cqssc_bits {
+    /// the offset of the `EMMC_CQSSC1` register from the base address of the SDHCI controller.
+    pub const EMMC_CQSSC1_OFFSET: u64 = 0x1c0;
+    /// Send Status Command Idle Timer
+    pub const EMMC_CQ_CIT_POS: u32 = 0;
+    pub const EMMC_CQ_CIT_MASK: u32 = 0xffff << EMMC_CQ_CIT_POS;
+    /// Send Status Command Block Counter
+    pub const EMMC_CQ_CBC_POS: u32 = 16;
+    pub const EMMC_CQ_CBC_MASK: u32 = 0x0f << EMMC_CQ_CBC_POS;
+    /// the offset of the `EMMC_CQSSC2` register from the base address of the SDHCI controller.
+    pub const EMMC_CQSSC2_OFFSET: u64 = 0x1c4;
+    /// Send Queue Status RCA
+    pub const EMMC_CQ_SQSRCA_POS: u32 = 0;
+    pub const EMMC_CQ_SQSRCA_MASK: u32 = 0xffff << EMMC_CQ_SQSRCA_POS;
+}
+
+/// This module implements read and write operations for the `EMMC_CQSSC1` and `EMMC_CQSSC2` registers.
+/// - The definition of the bit is in the `emmc_cqssc_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Set the entire value of the `EMMC_CQSSC1` register.
+    pub fn emmc_set_cqssc1(&self, cqssc1: u32) {
+        let addr = self.base_addr + emmc_cqssc_bits::EMMC_CQSSC1_OFFSET;
+        self.write_reg(addr, cqssc1);
+    }
+
+    /// Set the RCA the engine sends with CMD13 when polling the device queue.
+    pub fn emmc_set_cqssc2_rca(&self, rca: u16) {
+        let addr = self.base_addr + emmc_cqssc_bits::EMMC_CQSSC2_OFFSET;
+        self.write_reg(addr, (rca as u32) << emmc_cqssc_bits::EMMC_CQ_SQSRCA_POS);
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQCRDCT` register.
+/// The `EMMC_CQCRDCT` register is a 32-bit read-only register that holds the response of a direct command.
+pub mod emmc_cqcrdct_bits {
+    /// the offset of the `EMMC_CQCRDCT` register from the base address of the SDHCI controller.
+    pub const EMMC_CQCRDCT_OFFSET: u64 = 0x1c8;
+}
+
+/// This module implements read operations for the `EMMC_CQCRDCT` register.
+impl<M: Mmio> Reg<M> {
+    /// Return the response of the last direct command.
+    pub fn emmc_get_cqcrdct(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqcrdct_bits::EMMC_CQCRDCT_OFFSET;
+        self.read_reg(addr)
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQRMEM` register.
+/// The `EMMC_CQRMEM` register is a 32-bit read-write register that selects which R1 status bits are treated as errors.
+pub mod emmc_cqrmem_bits {
+    /// the offset of the `EMMC_CQRMEM` register from the base address of the SDHCI controller.
+    pub const EMMC_CQRMEM_OFFSET: u64 = 0x1d0;
+    /// Reset value of the response mode error mask.
+    pub const EMMC_CQRMEM_DEFAULT: u32 = 0xfdf9a080;
+}
+
+/// This module implements read and write operations for the `EMMC_CQRMEM` register.
+impl<M: Mmio> Reg<M> {
+    /// Set the R1 status bits that raise `EMMC_CQ_RED`.
+    pub fn emmc_set_cqrmem(&self, cqrmem: u32) {
+        let addr = self.base_addr + emmc_cqrmem_bits::EMMC_CQRMEM_OFFSET;
+        self.write_reg(addr, cqrmem);
+    }
+}
+
+/// This module contains the offset position of the `EMMC_CQTERRI` register and the definitions of its individual bits.
+/// The `EMMC_CQTERRI` register is a 32-bit read-only register that identifies the task and command of the last error.
+pub mod emmc_cqterri_bits {
+    /// the offset of the `EMMC_CQTERRI` register from the base address of the SDHCI controller.
+    pub const EMMC_CQTERRI_OFFSET: u64 = 0x1d4;
+    /// Response Mode Error Command Index
+    pub const EMMC_CQ_RMECI_POS: u32 = 0;
+    pub const EMMC_CQ_RMECI_MASK: u32 = 0x3f << EMMC_CQ_RMECI_POS;
+    /// Response Mode Error Task ID
+    pub const EMMC_CQ_RMETID_POS: u32 = 8;
+    pub const EMMC_CQ_RMETID_MASK: u32 = 0x1f << EMMC_CQ_RMETID_POS;
+    /// Response Mode Error Fields Valid
+    pub const EMMC_CQ_RMEFV_POS: u32 = 15;
+    pub const EMMC_CQ_RMEFV_MASK: u32 = 0x01 << EMMC_CQ_RMEFV_POS;
+    pub const EMMC_CQ_RMEFV: u32 = EMMC_CQ_RMEFV_MASK;
+    /// Data Transfer Error Command Index
+    pub const EMMC_CQ_DTECI_POS: u32 = 16;
+    pub const EMMC_CQ_DTECI_MASK: u32 = 0x3f << EMMC_CQ_DTECI_POS;
+    /// Data Transfer Error Task ID
+    pub const EMMC_CQ_DTETID_POS: u32 = 24;
+    pub const EMMC_CQ_DTETID_MASK: u32 = 0x1f << EMMC_CQ_DTETID_POS;
+    /// Data Transfer Error Fields Valid
+    pub const EMMC_CQ_DTEFV_POS: u32 = 31;
+    pub const EMMC_CQ_DTEFV_MASK: u32 = 0x01 << EMMC_CQ_DTEFV_POS;
+    pub const EMMC_CQ_DTEFV: u32 = EMMC_CQ_DTEFV_MASK;
+}
+
+/// This module implements read operations for the `EMMC_CQTERRI` register.
+/// - The definition of the bit is in the `emmc_cqterri_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_CQTERRI` register.
+    pub fn emmc_get_cqterri(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqterri_bits::EMMC_CQTERRI_OFFSET;
+        self.read_reg(addr)
+    }
+}
+
+/// This module contains the offset positions of the `EMMC_CQCRI` and `EMMC_CQCRA` registers.
+/// They are 32-bit read-only registers holding the index and argument of the last command response received.
+pub mod emmc_cqcr_bits {
+    /// the offset of the `EMMC_CQCRI` register from the base address of the SDHCI controller.
+    pub const EMMC_CQCRI_OFFSET: u64 = 0x1d8;
+    /// the offset of the `EMMC_CQCRA` register from the base address of the SDHCI controller.
+    pub const EMMC_CQCRA_OFFSET: u64 = 0x1dc;
+}
+
+/// This module implements read operations for the `EMMC_CQCRI` and `EMMC_CQCRA` registers.
+impl<M: Mmio> Reg<M> {
+    /// Return the index of the last command response received by the engine.
+    pub fn emmc_get_cqcri(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqcr_bits::EMMC_CQCRI_OFFSET;
+        self.read_reg(addr)
+    }
+
+    /// Return the argument of the last command response received by the engine.
+    pub fn emmc_get_cqcra(&self) -> u32 {
+        let addr = self.base_addr + emmc_cqcr_bits::EMMC_CQCRA_OFFSET;
+        self.read_reg(addr)
+    }
+}
 
 /// This module contains the offset position of the `EMMC_VER_ID` register and the definitions of its individual bits.
 /// The `EMMC_VER_ID` register is a 32-bit read-only register that contains the current version number.
@@ -1675,7 +2837,7 @@ pub mod emmc_ver_id_bits {
 
 /// This module implements read operations for the `EMMC_VER_ID` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_ver_id_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_VER_ID` register. 
     ///
     /// # Arguments
@@ -1704,7 +2866,7 @@ pub mod emmc_ver_type_bits {
 
 /// This module implements read operations for the `EMMC_VER_TYPE` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_ver_type_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_VER_TYPE` register. 
     ///
     /// # Arguments
@@ -1737,7 +2899,7 @@ pub mod emmc_host_ctrl3_bits {
 
 /// This module implements read and write operations for the `EMMC_HOST_CTRL3` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_host_ctrl3_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_HOST_CTRL3` register. 
     ///
     /// # Arguments
@@ -1851,10 +3013,102 @@ impl Reg {
     }
 }
 
+/// This module contains the offset position of the `EMMC_EMMC_CTRL` register and the definitions of its individual bits.
+/// The `EMMC_EMMC_CTRL` register is a 16-bit read-write vendor register that contains the eMMC specific settings.
+pub mod emmc_emmc_ctrl_bits {
+    /// the offset of the `EMMC_EMMC_CTRL` register from the base address of the SDHCI controller.
+    pub const EMMC_EMMC_CTRL_OFFSET: u64 = 0x52c;
+    /// eMMC Card present, required for the boot operations
+    pub const EMMC_CARD_IS_EMMC_POS: u16 = 0;
+    pub const EMMC_CARD_IS_EMMC_MASK: u16 = 0x01 << EMMC_CARD_IS_EMMC_POS;
+    pub const EMMC_CARD_IS_EMMC: u16 = EMMC_CARD_IS_EMMC_MASK;
+    /// Disable Data CRC Check
+    pub const EMMC_DISABLE_DATA_CRC_CHK_POS: u16 = 1;
+    pub const EMMC_DISABLE_DATA_CRC_CHK_MASK: u16 = 0x01 << EMMC_DISABLE_DATA_CRC_CHK_POS;
+    pub const EMMC_DISABLE_DATA_CRC_CHK: u16 = EMMC_DISABLE_DATA_CRC_CHK_MASK;
+    /// eMMC Device Reset signal, active low
+    pub const EMMC_RST_N_POS: u16 = 2;
+    pub const EMMC_RST_N_MASK: u16 = 0x01 << EMMC_RST_N_POS;
+    pub const EMMC_RST_N: u16 = EMMC_RST_N_MASK;
+    /// Output Enable of the eMMC Device Reset signal
+    pub const EMMC_RST_N_OE_POS: u16 = 3;
+    pub const EMMC_RST_N_OE_MASK: u16 = 0x01 << EMMC_RST_N_OE_POS;
+    pub const EMMC_RST_N_OE: u16 = EMMC_RST_N_OE_MASK;
+}
+
+/// This module implements read and write operations for the `EMMC_EMMC_CTRL` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_emmc_ctrl_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_EMMC_CTRL` register.
+    pub fn emmc_get_emmc_ctrl(&self) -> u16 {
+        let addr = self.base_addr + emmc_emmc_ctrl_bits::EMMC_EMMC_CTRL_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Tell the controller an eMMC device is attached, as the boot operations require.
+    pub fn emmc_enable_card_is_emmc(&self) {
+        let addr = self.base_addr + emmc_emmc_ctrl_bits::EMMC_EMMC_CTRL_OFFSET;
+        let value = self.read_reg16(addr);
+        self.write_reg16(addr, value | emmc_emmc_ctrl_bits::EMMC_CARD_IS_EMMC);
+    }
+}
+
+/// This module contains the offset position of the `EMMC_BOOT_CTRL` register and the definitions of its individual bits.
+/// The `EMMC_BOOT_CTRL` register is a 16-bit read-write vendor register that controls the boot operations of an eMMC device.
+pub mod emmc_boot_ctrl_bits {
+    /// the offset of the `EMMC_BOOT_CTRL` register from the base address of the SDHCI controller.
+    pub const EMMC_BOOT_CTRL_OFFSET: u64 = 0x52e;
+    /// Mandatory Boot Enable, the CMD line is held low while it is set
+    pub const EMMC_MAN_BOOT_EN_POS: u16 = 0;
+    pub const EMMC_MAN_BOOT_EN_MASK: u16 = 0x01 << EMMC_MAN_BOOT_EN_POS;
+    pub const EMMC_MAN_BOOT_EN: u16 = EMMC_MAN_BOOT_EN_MASK;
+    /// Validate Mandatory Boot Enable, `EMMC_MAN_BOOT_EN` is only taken with this bit set
+    pub const EMMC_VALIDATE_BOOT_POS: u16 = 7;
+    pub const EMMC_VALIDATE_BOOT_MASK: u16 = 0x01 << EMMC_VALIDATE_BOOT_POS;
+    pub const EMMC_VALIDATE_BOOT: u16 = EMMC_VALIDATE_BOOT_MASK;
+    /// Boot Acknowledge Enable, check the 0-1-0 boot acknowledge pattern in both boot operations
+    pub const EMMC_BOOT_ACK_ENABLE_POS: u16 = 8;
+    pub const EMMC_BOOT_ACK_ENABLE_MASK: u16 = 0x01 << EMMC_BOOT_ACK_ENABLE_POS;
+    pub const EMMC_BOOT_ACK_ENABLE: u16 = EMMC_BOOT_ACK_ENABLE_MASK;
+    /// Boot Ack Timeout Counter Value, TMCLK x 2^(13 + n)
+    pub const EMMC_BOOT_TOUT_CNT_POS: u16 = 12;
+    pub const EMMC_BOOT_TOUT_CNT_MASK: u16 = 0x0f << EMMC_BOOT_TOUT_CNT_POS;
+    pub const EMMC_BOOT_TOUT_CNT: u16 = EMMC_BOOT_TOUT_CNT_MASK;
+    pub const EMMC_BOOT_TOUT_CNT_MAX: u16 = 0x0e << EMMC_BOOT_TOUT_CNT_POS;
+}
+
+/// This module implements read and write operations for the `EMMC_BOOT_CTRL` register itself as well as its individual bits.
+/// - The definition of the bit is in the `emmc_boot_ctrl_bits` module.
+impl<M: Mmio> Reg<M> {
+    /// Return the entire value of the `EMMC_BOOT_CTRL` register.
+    pub fn emmc_get_boot_ctrl(&self) -> u16 {
+        let addr = self.base_addr + emmc_boot_ctrl_bits::EMMC_BOOT_CTRL_OFFSET;
+        self.read_reg16(addr)
+    }
+
+    /// Set the entire value of the `EMMC_BOOT_CTRL` register.
+    pub fn emmc_set_boot_ctrl(&self, boot_ctrl: u16) {
+        let addr = self.base_addr + emmc_boot_ctrl_bits::EMMC_BOOT_CTRL_OFFSET;
+        self.write_reg16(addr, boot_ctrl);
+    }
+
+    /// Start the boot operation by holding the CMD line low.
+    ///
+    /// The transfer registers must be programmed for the boot data first.
+    pub fn emmc_start_mandatory_boot(&self) {
+        let value = self.emmc_get_boot_ctrl() & !emmc_boot_ctrl_bits::EMMC_MAN_BOOT_EN;
+        self.emmc_set_boot_ctrl(value | emmc_boot_ctrl_bits::EMMC_VALIDATE_BOOT | emmc_boot_ctrl_bits::EMMC_MAN_BOOT_EN);
+    }
+
+    /// End the boot operation by releasing the CMD line.
+    pub fn emmc_stop_mandatory_boot(&self) {
+        let value = self.emmc_get_boot_ctrl() & !emmc_boot_ctrl_bits::EMMC_MAN_BOOT_EN;
+        self.emmc_set_boot_ctrl(value | emmc_boot_ctrl_bits::EMMC_VALIDATE_BOOT);
+    }
+}
+
 /* TODO
  *
- * EMMC_EMMC_CTRL 0x052C HW 0x0000000C EMMC Control Register 
- * EMMC_BOOT_CTRL 0x052E HW 0x00000000 Boot Control Register 
  * EMMC_AT_CTRL 0x0540 W 0x00000000 Boot Control Register 
  * EMMC_AT_STAT 
 */
@@ -1889,7 +3143,7 @@ pub mod emmc_dll_ctrl_bits {
 
 /// This module implements read and write operations for the `EMMC_DLL_CTRL` register itself as well as its individual bits.
 /// - The definition of the bit is in the `emmc_dll_ctrl_bits` module.
-impl Reg {
+impl<M: Mmio> Reg<M> {
     /// Return the entire value of the `EMMC_DLL_CTRL` register. 
     ///
     /// # Arguments
@@ -2074,7 +3328,7 @@ pub mod emmc_dll_rxclk_bits {
     pub const EMMC_RX_CLK_SRC_SEL: u32 = EMMC_RX_CLK_SRC_SEL_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_dll_rxclk(&self, dll_rxclk: u32) {
         let addr = self.base_addr + emmc_dll_rxclk_bits::EMMC_DLL_RXCLK_OFFSET;
         self.write_reg(addr, dll_rxclk);
@@ -2130,7 +3384,7 @@ pub mod emmc_dll_txclk_bits {
     pub const EMMC_TX_CLK_OUT_SEL: u32 = EMMC_TX_CLK_OUT_SEL_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_dll_txclk(&self, dll_txclk: u32) {
         let addr = self.base_addr + emmc_dll_txclk_bits::EMMC_DLL_TXCLK_OFFSET;
         self.write_reg(addr, dll_txclk);
@@ -2186,7 +3440,7 @@ pub mod emmc_dll_strbin_bits {
     pub const EMMC_STRBIN_DELAY_EN: u32 = EMMC_STRBIN_DELAY_EN_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_set_dll_strbin(&self, dll_strbin: u32) {
         let addr = self.base_addr + emmc_dll_strbin_bits::EMMC_DLL_STRBINCLK_OFFSET;
         self.write_reg(addr, dll_strbin);
@@ -2230,7 +3484,7 @@ pub mod emmc_dll_status0_bits {
     pub const EMMC_DLL_LOCK_TIMEOUT: u32 = EMMC_DLL_LOCK_TIMEOUT_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_get_dll_status0(&self) -> u32 {
         let addr = self.base_addr + emmc_dll_status0_bits::EMMC_DLL_STATUS0_OFFSET;
         self.read_reg(addr)
@@ -2266,7 +3520,7 @@ pub mod emmc_dll_status1_bits {
     pub const EMMC_STRBIN_DELAY_VALUE: u32 = EMMC_STRBIN_DELAY_VALUE_MASK;
 }
 
-impl Reg {
+impl<M: Mmio> Reg<M> {
     pub fn emmc_get_dll_status1(&self) -> u32 {
         let addr = self.base_addr + emmc_dll_status1_bits::EMMC_DLL_STATUS1_OFFSET;
         self.read_reg(addr)
@@ -0,0 +1,60 @@
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, SDHCI};
+use crate::sdhci_cmd::mmc_cmd_idx::{MMC_CMD23_FORCED_PRG, MMC_CMD23_REL_WRITE};
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+impl Card {
+    /// Sectors written reliably as one unit in the legacy mode, from REL_WR_SEC_C, 0 without
+    /// reliable write.
+    pub fn reliable_write_sectors(&self) -> u32 {
+        if self.card_type == CardType::Mmc { self.ext_csd.byte(EXT_CSD_REL_WR_SEC_C) as u32 } else { 0 }
+    }
+
+    /// Any write is reliable whatever its size and alignment, EN_REL_WR of WR_REL_PARAM.
+    pub fn enhanced_reliable_write(&self) -> bool {
+        self.ext_csd.byte(EXT_CSD_WR_REL_PARAM) & EXT_CSD_EN_REL_WR != 0
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Write `buf.len() / BLOCK_SIZE` blocks starting at `lba` with reliable write: after a
+    /// power loss every sector holds either its old or its new content.
+    ///
+    /// In the legacy mode only a single sector, or REL_WR_SEC_C sectors aligned on that unit,
+    /// are written reliably, and the request is split accordingly. With the volatile cache
+    /// enabled the data also bypasses it, as with `write_blocks_fua`.
+    pub fn write_blocks_reliable(&self, lba: u32, buf: &[u8]) -> Result<(), MmcError> {
+        let (unit, enhanced, cache, forced) = {
+            let card = self.card.lock();
+            let cache = (card.cache_enabled(), card.supports_forced_programming());
+            (card.reliable_write_sectors(), card.enhanced_reliable_write(), cache.0, cache.1)
+        };
+        if unit == 0 {
+            return Err(MmcError::Unsupported);
+        }
+        if buf.is_empty() || !buf.len().is_multiple_of(BLOCK_SIZE) {
+            return Err(MmcError::InvalidArgument);
+        }
+
+        let flags = if cache && forced { MMC_CMD23_REL_WRITE | MMC_CMD23_FORCED_PRG } else { MMC_CMD23_REL_WRITE };
+        let blocks = (buf.len() / BLOCK_SIZE) as u32;
+        let mut done = 0;
+        while done < blocks {
+            let at = lba + done;
+            let left = blocks - done;
+            let count = if enhanced {
+                left.min(u16::MAX as u32)
+            } else if at.is_multiple_of(unit) && left >= unit {
+                unit
+            } else {
+                1
+            };
+            let chunk = &buf[done as usize * BLOCK_SIZE..(done + count) as usize * BLOCK_SIZE];
+            self.with_recovery(|| self.write_blocks_sbc(at, chunk, flags))?;
+            done += count;
+        }
+
+        if cache && !forced { self.flush_cache() } else { Ok(()) }
+    }
+}
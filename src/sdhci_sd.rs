@@ -0,0 +1,549 @@
+use log::{debug, info, warn};
+
+use crate::{delay_us, poll_timeout};
+use crate::sdhci::{BLOCK_SIZE, CardType, Data, HostKind, OCR_TIMEOUT_US, Request, SDHCI, csd_bits};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_ocr_bits::*;
+use crate::sdhci_cmd::mmc_resp_type::*;
+use crate::sdhci_cmd::sd_cmd_idx::*;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_capabilities1_bits::{EMMC_HIGH_SPEED_SUPPORT, EMMC_VOLT_18};
+use crate::sdhci_reg::emmc_capabilities2_bits::*;
+use crate::sdhci_reg::emmc_host_ctrl2_bits::*;
+use crate::sdhci_reg::emmc_host_ctrl_ver_bits::EMMC_SPEC_VERSION_V300;
+
+/// SD clock in default speed mode.
+pub const SD_DEFAULT_CLK_HZ: u32 = 25_000_000;
+/// SD clock in high speed mode.
+pub const SD_HS_CLK_HZ: u32 = 50_000_000;
+/// SD clock in SDR50 mode.
+pub const SD_SDR50_CLK_HZ: u32 = 100_000_000;
+/// SD clock in SDR104 mode.
+pub const SD_SDR104_CLK_HZ: u32 = 208_000_000;
+/// Time the card and the regulator get to settle at 1.8V with the clock stopped.
+const VOLTAGE_SWITCH_DELAY_US: u64 = 5000;
+/// Time the card gets to release DAT[3:0] once the clock runs again at 1.8V.
+const VOLTAGE_SWITCH_RELEASE_US: u64 = 1000;
+/// SDHC cards hold at most 32 GiB.
+const SDHC_MAX_BLOCKS: u64 = 1 << 26;
+
+/// Capacity class of an SD memory card, which decides how it is addressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdCapacity {
+    /// Standard capacity, up to 2 GiB and byte addressed.
+    Sdsc,
+    /// High capacity, up to 32 GiB and block addressed.
+    Sdhc,
+    /// Extended capacity, up to 2 TiB and block addressed.
+    Sdxc,
+}
+
+impl SdCapacity {
+    pub(crate) fn new(high_capacity: bool, blocks: u64) -> Self {
+        match (high_capacity, blocks) {
+            (false, _) => SdCapacity::Sdsc,
+            (true, blocks) if blocks <= SDHC_MAX_BLOCKS => SdCapacity::Sdhc,
+            _ => SdCapacity::Sdxc,
+        }
+    }
+}
+
+/// Bus speed mode of an SD card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdTiming {
+    /// Default speed at 3.3V, up to 25 MHz.
+    Default,
+    /// High speed at 3.3V, up to 50 MHz.
+    HighSpeed,
+    /// UHS-I modes at 1.8V.
+    Sdr12,
+    Sdr25,
+    Sdr50,
+    Sdr104,
+    Ddr50,
+}
+
+impl SdTiming {
+    /// Fastest SD clock of the mode.
+    pub fn clock_hz(self) -> u32 {
+        match self {
+            SdTiming::Default | SdTiming::Sdr12 => SD_DEFAULT_CLK_HZ,
+            SdTiming::HighSpeed | SdTiming::Sdr25 | SdTiming::Ddr50 => SD_HS_CLK_HZ,
+            SdTiming::Sdr50 => SD_SDR50_CLK_HZ,
+            SdTiming::Sdr104 => SD_SDR104_CLK_HZ,
+        }
+    }
+
+    /// The mode uses 1.8V signaling.
+    pub fn is_uhs(self) -> bool {
+        !matches!(self, SdTiming::Default | SdTiming::HighSpeed)
+    }
+
+    /// CMD6 access mode function of a UHS-I mode.
+    fn access_mode(self) -> u8 {
+        match self {
+            SdTiming::Default | SdTiming::Sdr12 => SD_SWITCH_ACCESS_MODE_SDR12,
+            SdTiming::HighSpeed | SdTiming::Sdr25 => SD_SWITCH_ACCESS_MODE_SDR25,
+            SdTiming::Sdr50 => SD_SWITCH_ACCESS_MODE_SDR50,
+            SdTiming::Sdr104 => SD_SWITCH_ACCESS_MODE_SDR104,
+            SdTiming::Ddr50 => SD_SWITCH_ACCESS_MODE_DDR50,
+        }
+    }
+
+    /// UHS Mode Select value of the Host Control 2 register.
+    fn uhs_mode(self) -> u16 {
+        match self {
+            SdTiming::Default | SdTiming::Sdr12 => EMMC_UHS_MODE_SDR12,
+            SdTiming::HighSpeed | SdTiming::Sdr25 => EMMC_UHS_MODE_SDR25,
+            SdTiming::Sdr50 => EMMC_UHS_MODE_SDR50,
+            SdTiming::Sdr104 => EMMC_UHS_MODE_SDR104,
+            SdTiming::Ddr50 => EMMC_UHS_MODE_DDR50,
+        }
+    }
+}
+
+/// The 64-bit SD Configuration Register read with ACMD51, most significant byte first.
+#[derive(Clone)]
+pub struct Scr(pub [u8; 8]);
+
+impl Scr {
+    pub const fn empty() -> Self {
+        Self([0; 8])
+    }
+
+    /// SD_SPEC, 0 for version 1.0, 1 for 1.10 and 2 for 2.00 and later.
+    pub fn sd_spec(&self) -> u8 {
+        be_bits(&self.0, 56, 4) as u8
+    }
+
+    /// Major version of the Physical Layer Specification, from SD_SPEC, SD_SPEC3, SD_SPEC4 and SD_SPECX.
+    pub fn version(&self) -> u8 {
+        let spec3 = be_bits(&self.0, 47, 1);
+        let spec4 = be_bits(&self.0, 42, 1);
+        let specx = be_bits(&self.0, 38, 4) as u8;
+        match (self.sd_spec(), spec3, spec4, specx) {
+            (0 | 1, ..) => 1,
+            (2, 0, ..) => 2,
+            (2, 1, 0, 0) => 3,
+            (2, 1, _, 0) => 4,
+            (2, 1, _, specx) => 4 + specx,
+            _ => 0,
+        }
+    }
+
+    /// The data bits after an erase are all ones.
+    pub fn data_stat_after_erase(&self) -> bool {
+        be_bits(&self.0, 55, 1) != 0
+    }
+
+    /// SD_BUS_WIDTHS, bit 0 for 1-bit and bit 2 for 4-bit.
+    pub fn bus_widths(&self) -> u8 {
+        be_bits(&self.0, 48, 4) as u8
+    }
+
+    pub fn supports_4bit(&self) -> bool {
+        self.bus_widths() & 0x04 != 0
+    }
+
+    /// CMD_SUPPORT, bit 0 for CMD20, bit 1 for CMD23, bit 2 for CMD48/49 and bit 3 for CMD58/59.
+    pub fn cmd_support(&self) -> u8 {
+        be_bits(&self.0, 32, 4) as u8
+    }
+}
+
+/// The 512-bit SD Status read with ACMD13, most significant byte first.
+#[derive(Clone)]
+pub struct SdStatus(pub [u8; 64]);
+
+impl SdStatus {
+    pub const fn empty() -> Self {
+        Self([0; 64])
+    }
+
+    /// DAT_BUS_WIDTH, the width in use: 1 or 4.
+    pub fn bus_width(&self) -> u8 {
+        if be_bits(&self.0, 510, 2) == 2 { 4 } else { 1 }
+    }
+
+    pub fn secured_mode(&self) -> bool {
+        be_bits(&self.0, 509, 1) != 0
+    }
+
+    /// SD_CARD_TYPE, 0 for a regular read/write card.
+    pub fn card_type(&self) -> u16 {
+        be_bits(&self.0, 480, 16) as u16
+    }
+
+    /// SPEED_CLASS as the class number: 0, 2, 4, 6 or 10.
+    pub fn speed_class(&self) -> u8 {
+        match be_bits(&self.0, 440, 8) {
+            1 => 2,
+            2 => 4,
+            3 => 6,
+            4 => 10,
+            _ => 0,
+        }
+    }
+
+    /// UHS_SPEED_GRADE, 0, 1 or 3.
+    pub fn uhs_speed_grade(&self) -> u8 {
+        be_bits(&self.0, 396, 4) as u8
+    }
+
+    /// VIDEO_SPEED_CLASS, e.g. 10 for V10.
+    pub fn video_speed_class(&self) -> u8 {
+        be_bits(&self.0, 384, 8) as u8
+    }
+
+    /// Allocation unit size in bytes, 0 if not defined.
+    pub fn au_size(&self) -> u32 {
+        match be_bits(&self.0, 428, 4) {
+            0 => 0,
+            au @ 1..=10 => (8 * 1024) << au,
+            11 => 12 << 20,
+            12 => 16 << 20,
+            13 => 24 << 20,
+            14 => 32 << 20,
+            _ => 64 << 20,
+        }
+    }
+
+    /// ERASE_SIZE, the number of AUs the ERASE_TIMEOUT applies to, 0 if the timeout is not supported.
+    pub fn erase_size(&self) -> u16 {
+        be_bits(&self.0, 408, 16) as u16
+    }
+
+    /// ERASE_TIMEOUT in seconds for ERASE_SIZE AUs.
+    pub fn erase_timeout(&self) -> u8 {
+        be_bits(&self.0, 402, 6) as u8
+    }
+
+    /// ERASE_OFFSET in seconds, added to the erase timeout.
+    pub fn erase_offset(&self) -> u8 {
+        be_bits(&self.0, 400, 2) as u8
+    }
+
+    /// DISCARD_SUPPORT, CMD38 accepts `SD_DISCARD_ARG`.
+    pub fn discard_support(&self) -> bool {
+        be_bits(&self.0, 313, 1) != 0
+    }
+}
+
+/// The 512-bit status returned by CMD6, most significant byte first.
+#[derive(Clone)]
+pub struct SwitchStatus(pub [u8; 64]);
+
+impl SwitchStatus {
+    pub const fn empty() -> Self {
+        Self([0; 64])
+    }
+
+    /// Maximum current consumption in mA with the checked or selected functions, 0 on error.
+    pub fn max_current(&self) -> u16 {
+        be_bits(&self.0, 496, 16) as u16
+    }
+
+    /// The function `func` of function group `group`, 1 to 6, is supported.
+    pub fn supports(&self, group: u8, func: u8) -> bool {
+        be_bits(&self.0, 400 + 16 * (group as usize - 1), 16) & 1 << func != 0
+    }
+
+    /// The function of group `group` selected by the command, 0xf if it could not be.
+    pub fn selected(&self, group: u8) -> u8 {
+        be_bits(&self.0, 376 + 4 * (group as usize - 1), 4) as u8
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Identify the SD memory card, configure the widest bus and the fastest timing both sides
+    /// support, and bring it to the transfer state.
+    ///
+    /// `if_cond` is set if the card answered CMD8, i.e. it is compliant with version 2.00 or later
+    /// and may be high capacity. With `uhs` the card is asked to switch to 1.8V signaling if the
+    /// host supports a UHS-I mode; when that switch fails the card is power cycled and identified
+    /// again without it.
+    pub(crate) fn init_sd(&self, if_cond: bool, uhs: bool) -> Result<(), MmcError> {
+        let uhs = uhs && if_cond && self.host_supports_uhs();
+        let hcs = if if_cond { SD_OCR_CCS } else { 0 };
+        let s18r = if uhs { SD_OCR_S18R } else { 0 };
+        let ocr = poll_timeout("card power up (ACMD41)", OCR_TIMEOUT_US, || {
+            let mut req = Request::new(SD_APP_SEND_OP_COND, 0, MMC_RESP_R3, hcs | s18r | MMC_OCR_VDD_27_36);
+            match self.sd_app_cmd(0, &mut req) {
+                Ok(ocr) if ocr & MMC_OCR_BUSY == 0 => {
+                    delay_us(1000);
+                    None
+                }
+                result => Some(result),
+            }
+        })??;
+        info!("ACMD41 response: {:#x}", ocr);
+
+        // S18A is only valid from a high capacity card.
+        if uhs && ocr & (SD_OCR_CCS | SD_OCR_S18R) == SD_OCR_CCS | SD_OCR_S18R
+            && let Err(err) = self.sd_switch_voltage()
+        {
+            warn!("sd: switching to 1.8V failed: {:?}, power cycling the card", err);
+            self.sd_power_cycle()?;
+            return self.init_sd(if_cond, false);
+        }
+
+        self.sdhci_send_cmd(MMC_ALL_SEND_CID, 0, MMC_RESP_R2, 0)?;
+        let cid = self.sdhci_get_resp136();
+        let rca = (self.execute(&mut Request::new(SD_SEND_RELATIVE_ADDR, 0, MMC_RESP_R6, 0).unchecked())? >> 16) as u16;
+        self.sdhci_send_cmd(MMC_SEND_CSD, 0, MMC_RESP_R2, (rca as u32) << 16)?;
+        let csd = self.sdhci_get_resp136();
+        self.sdhci_send_cmd(MMC_SELECT_CARD, 0, MMC_RESP_R1B, (rca as u32) << 16)?;
+
+        let high_capacity = ocr & SD_OCR_CCS != 0;
+        let blocks = match csd_bits(&csd, 126, 2) {
+            // CSD version 2.0, C_SIZE in units of 512 KiB.
+            1 => (csd_bits(&csd, 48, 22) as u64 + 1) * 1024,
+            _ => {
+                let c_size = csd_bits(&csd, 62, 12) as u64;
+                let c_size_mult = csd_bits(&csd, 47, 3);
+                let read_bl_len = csd_bits(&csd, 80, 4);
+                ((c_size + 1) << (c_size_mult + 2 + read_bl_len)) / BLOCK_SIZE as u64
+            }
+        };
+        let capacity = SdCapacity::new(high_capacity, blocks);
+        info!("sd cid: {:08x?}, rca: {:#x}, {:?}, blocks: {}", cid, rca, capacity, blocks);
+
+        {
+            let mut card = self.card.lock();
+            card.card_type = CardType::Sd;
+            card.rca = rca;
+            card.high_capacity = high_capacity;
+            card.cid = cid;
+            card.csd = csd;
+            card.ext_csd.0.fill(0);
+            card.blocks = blocks;
+            card.bus_width = 1;
+            card.sd_timing = SdTiming::Default;
+        }
+
+        let scr = self.sd_send_scr()?;
+        info!("sd scr: {:02x?}, spec version {}", scr.0, scr.version());
+        self.card.lock().scr = scr.clone();
+
+        if scr.supports_4bit() {
+            self.sd_set_bus_width(4)?;
+        }
+        self.set_bus_clock(SD_DEFAULT_CLK_HZ)?;
+
+        let sd_status = self.sd_send_status()?;
+        info!("sd status: bus width {}, speed class {}, au size {:#x}",
+              sd_status.bus_width(), sd_status.speed_class(), sd_status.au_size());
+        self.card.lock().sd_status = sd_status;
+
+        if self.reg.emmc_1v8_signaling_is_enabled() {
+            return self.sd_set_uhs_timing();
+        }
+        // CMD6 is mandatory from version 1.10 on.
+        if scr.sd_spec() >= 1 && self.reg.emmc_get_capabilities1() & EMMC_HIGH_SPEED_SUPPORT != 0
+            && let Err(err) = self.sd_set_high_speed()
+        {
+            warn!("sd: staying at default speed: {:?}", err);
+        }
+        Ok(())
+    }
+
+    /// The controller has 1.8V signaling and at least one UHS-I mode above SDR25.
+    fn host_supports_uhs(&self) -> bool {
+        self.reg.emmc_get_spec_version() >= EMMC_SPEC_VERSION_V300
+            && self.reg.emmc_get_capabilities1() & EMMC_VOLT_18 != 0
+            && self.reg.emmc_get_capabilities2() & (EMMC_SDR50_SUPPORT | EMMC_SDR104_SUPPORT | EMMC_DDR50_SUPPORT) != 0
+    }
+
+    /// Switch the signaling voltage of the card and of the controller to 1.8V with CMD11.
+    ///
+    /// The card drives CMD and DAT[3:0] low once it accepted CMD11, and releases them when the
+    /// clock runs again after the switch.
+    fn sd_switch_voltage(&self) -> Result<(), MmcError> {
+        self.sdhci_send_cmd(SD_VOLTAGE_SWITCH, 0, MMC_RESP_R1, 0)?;
+
+        self.reg.emmc_disable_sd_clk();
+        if self.reg.emmc_get_cmd_line_level() || self.reg.emmc_get_data_line_level() & 0x0f != 0 {
+            return Err(MmcError::VoltageSwitch);
+        }
+
+        self.reg.emmc_enable_1v8_signaling();
+        delay_us(VOLTAGE_SWITCH_DELAY_US);
+        if !self.reg.emmc_1v8_signaling_is_enabled() {
+            return Err(MmcError::VoltageSwitch);
+        }
+
+        self.reg.emmc_enable_sd_clk();
+        delay_us(VOLTAGE_SWITCH_RELEASE_US);
+        if !self.reg.emmc_get_cmd_line_level() || self.reg.emmc_get_data_line_level() & 0x0f != 0x0f {
+            return Err(MmcError::VoltageSwitch);
+        }
+        info!("sd: switched to 1.8V signaling");
+        Ok(())
+    }
+
+    /// Power the card off and on at 3.3V signaling, leaving it in the idle state.
+    fn sd_power_cycle(&self) -> Result<(), MmcError> {
+        self.reg.emmc_disable_sd_clk();
+        self.reg.emmc_disable_1v8_signaling();
+        self.reg.emmc_pwr_off();
+        delay_us(VOLTAGE_SWITCH_DELAY_US);
+        self.reg.emmc_pwr_on();
+        self.reg.emmc_enable_sd_clk();
+        delay_us(VOLTAGE_SWITCH_RELEASE_US);
+
+        self.sdhci_send_cmd(MMC_GO_IDLE_STATE, 0, MMC_RESP_NONE, 0)?;
+        // R7 echoes the interface condition, which is no card status to check.
+        let if_cond = SD_IF_COND_VHS_27_36 | SD_IF_COND_CHECK_PATTERN;
+        let resp = self.execute(&mut Request::new(SD_SEND_IF_COND, 0, MMC_RESP_R7, if_cond).unchecked())?;
+        if resp & 0xfff != if_cond {
+            info!("CMD8 response {:#x} does not echo the interface condition", resp);
+            return Err(MmcError::Response);
+        }
+        Ok(())
+    }
+
+    /// Select the fastest UHS-I mode both the card and the controller support, and tune the
+    /// sampling clock when the mode needs it.
+    fn sd_set_uhs_timing(&self) -> Result<(), MmcError> {
+        let caps2 = self.reg.emmc_get_capabilities2();
+        let status = self.sd_switch(false, SD_SWITCH_GROUP_ACCESS_MODE, 0x0f)?;
+        let candidates = [
+            (SdTiming::Sdr104, caps2 & EMMC_SDR104_SUPPORT != 0),
+            (SdTiming::Ddr50, caps2 & EMMC_DDR50_SUPPORT != 0),
+            (SdTiming::Sdr50, caps2 & EMMC_SDR50_SUPPORT != 0),
+            (SdTiming::Sdr25, true),
+            (SdTiming::Sdr12, true),
+        ];
+        let Some(timing) = candidates.into_iter().find_map(|(timing, host)| {
+            (host && status.supports(SD_SWITCH_GROUP_ACCESS_MODE, timing.access_mode())).then_some(timing)
+        }) else {
+            return Err(MmcError::Unsupported);
+        };
+
+        let status = self.sd_switch(true, SD_SWITCH_GROUP_ACCESS_MODE, timing.access_mode())?;
+        if status.selected(SD_SWITCH_GROUP_ACCESS_MODE) != timing.access_mode() {
+            return Err(MmcError::Unsupported);
+        }
+
+        self.reg.emmc_disable_sd_clk();
+        self.reg.emmc_set_uhs_mode(timing.uhs_mode());
+        if timing != SdTiming::Sdr12 {
+            self.reg.emmc_enable_high_speed();
+        }
+        self.reg.emmc_enable_sd_clk();
+        self.set_bus_clock(timing.clock_hz())?;
+        self.card.lock().sd_timing = timing;
+        info!("sd: {:?}", timing);
+
+        let sdr50_tuning = timing == SdTiming::Sdr50 && caps2 & EMMC_USE_TUNING_SDR50 != 0;
+        if timing == SdTiming::Sdr104 || sdr50_tuning {
+            self.execute_tuning(SD_SEND_TUNING_BLOCK)?;
+        }
+        Ok(())
+    }
+
+    /// Issue the application specific command `req`, preceded by CMD55 to the card at `rca`.
+    pub(crate) fn sd_app_cmd(&self, rca: u16, req: &mut Request) -> Result<u32, MmcError> {
+        self.sdhci_send_cmd(SD_APP_CMD, 0, MMC_RESP_R1, (rca as u32) << 16)?;
+        self.execute(req)
+    }
+
+    /// Read the SD Configuration Register with ACMD51.
+    pub fn sd_send_scr(&self) -> Result<Scr, MmcError> {
+        let mut scr = Scr::empty();
+        let rca = self.card.lock().rca;
+        let mut req = Request::new(SD_APP_SEND_SCR, 0, MMC_RESP_R1, 0)
+            .with_data(Data::Read(&mut scr.0))
+            .with_block_size(8);
+        self.sd_app_cmd(rca, &mut req)?;
+        Ok(scr)
+    }
+
+    /// Read the SD Status with ACMD13.
+    pub fn sd_send_status(&self) -> Result<SdStatus, MmcError> {
+        let mut sd_status = SdStatus::empty();
+        let rca = self.card.lock().rca;
+        let mut req = Request::new(SD_APP_SD_STATUS, 0, MMC_RESP_R1, 0)
+            .with_data(Data::Read(&mut sd_status.0))
+            .with_block_size(64);
+        self.sd_app_cmd(rca, &mut req)?;
+        Ok(sd_status)
+    }
+
+    /// Set the data bus width of the card with ACMD6, then the one of the controller.
+    ///
+    /// Only 1 and 4 are valid for SD cards.
+    pub fn sd_set_bus_width(&self, width: u8) -> Result<(), MmcError> {
+        let arg = match width {
+            1 => SD_BUS_WIDTH_1,
+            4 => SD_BUS_WIDTH_4,
+            _ => return Err(MmcError::InvalidArgument),
+        };
+        let rca = self.card.lock().rca;
+        self.sd_app_cmd(rca, &mut Request::new(SD_APP_SET_BUS_WIDTH, 0, MMC_RESP_R1, arg))?;
+        if width == 4 {
+            self.reg.emmc_enable_data_xfer_width_4bit();
+        } else {
+            self.reg.emmc_enable_data_xfer_width_1bit();
+        }
+        self.card.lock().bus_width = width;
+        debug!("sd bus width: {}", width);
+        Ok(())
+    }
+
+    /// Check, or with `set` switch to, function `func` of function group `group` with CMD6.
+    ///
+    /// Function 0xf leaves a group unchanged. Whether the function is supported and was
+    /// selected is reported in the returned status, not as an error.
+    pub fn sd_switch(&self, set: bool, group: u8, func: u8) -> Result<SwitchStatus, MmcError> {
+        if !(1..=6).contains(&group) || func > 0xf {
+            return Err(MmcError::InvalidArgument);
+        }
+        let shift = 4 * (group as u32 - 1);
+        let mut arg = 0x00ff_ffff & !(0xf << shift) | (func as u32) << shift;
+        if set {
+            arg |= SD_SWITCH_MODE_SWITCH;
+        }
+
+        let mut status = SwitchStatus::empty();
+        let mut req = Request::new(SD_SWITCH_FUNC, 0, MMC_RESP_R1, arg)
+            .with_data(Data::Read(&mut status.0))
+            .with_block_size(64);
+        self.execute(&mut req)?;
+        Ok(status)
+    }
+
+    /// Switch the card, then the controller, to high speed timing.
+    fn sd_set_high_speed(&self) -> Result<(), MmcError> {
+        let status = self.sd_switch(false, SD_SWITCH_GROUP_ACCESS_MODE, SD_SWITCH_ACCESS_MODE_HS)?;
+        if !status.supports(SD_SWITCH_GROUP_ACCESS_MODE, SD_SWITCH_ACCESS_MODE_HS) {
+            return Err(MmcError::Unsupported);
+        }
+        let status = self.sd_switch(true, SD_SWITCH_GROUP_ACCESS_MODE, SD_SWITCH_ACCESS_MODE_HS)?;
+        if status.selected(SD_SWITCH_GROUP_ACCESS_MODE) != SD_SWITCH_ACCESS_MODE_HS {
+            return Err(MmcError::Unsupported);
+        }
+
+        self.reg.emmc_enable_high_speed();
+        self.set_bus_clock(SD_HS_CLK_HZ)?;
+        self.card.lock().sd_timing = SdTiming::HighSpeed;
+        info!("sd: high speed");
+        Ok(())
+    }
+
+    /// Card clock of the data transfer phase on hosts whose clock the driver controls.
+    pub(crate) fn set_bus_clock(&self, hz: u32) -> Result<(), MmcError> {
+        // The RK3568 card clock stays at the CRU rate chosen by `init`.
+        if self.host_kind() == HostKind::Generic {
+            self.change_clk_divider(hz)?;
+        }
+        Ok(())
+    }
+}
+
+/// Extract `len` bits starting at bit `start` of a register sent most significant byte first.
+fn be_bits(reg: &[u8], start: usize, len: usize) -> u32 {
+    let bits = reg.len() * 8;
+    (start..start + len).rev().fold(0, |value, bit| value << 1 | (reg[(bits - 1 - bit) / 8] >> (bit % 8) & 1) as u32)
+}
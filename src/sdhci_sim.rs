@@ -0,0 +1,2362 @@
+//! Behavioural model of the DWC MSHC controller and of an eMMC device or SD memory card, enabled
+//! with the `sim` feature, so the whole driver runs in host tests.
+//!
+//! The model is synchronous: a command completes, and a DMA transfer moves all of its data,
+//! during the register write that issues it. It covers what the driver uses, i.e. the SDHCI
+//! register file, the command and data paths through the buffer data port, SDMA and 32-bit
+//! ADMA2 against a `SimMemory`, the boot and RPMB partitions, erase and fault injection. The
+//! command queuing engine is not modelled.
+
+use std::boxed::Box;
+use std::cell::{RefCell, RefMut};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::vec;
+use std::vec::Vec;
+
+use crate::sdhci::BLOCK_SIZE;
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_ocr_bits::*;
+use crate::sdhci_cmd::mmc_r1_bits::*;
+use crate::sdhci_cmd::sd_cmd_idx::*;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_adma_err_stat_bits::*;
+use crate::sdhci_reg::emmc_adma_sa_bits::*;
+use crate::sdhci_reg::emmc_argument_bits::EMMC_ARGUMENT_OFFSET;
+use crate::sdhci_reg::emmc_auto_cmd_stat_bits::*;
+use crate::sdhci_reg::emmc_blockcount_bits::EMMC_BLOCKCOUNT_OFFSET;
+use crate::sdhci_reg::emmc_boot_ctrl_bits::*;
+use crate::sdhci_reg::emmc_blocksize_bits::*;
+use crate::sdhci_reg::emmc_buf_data_bits::EMMC_BUF_DATA_OFFSET;
+use crate::sdhci_reg::emmc_capabilities1_bits::*;
+use crate::sdhci_reg::emmc_capabilities2_bits::*;
+use crate::sdhci_reg::emmc_host_ctrl2_bits::*;
+use crate::sdhci_reg::emmc_clk_ctrl_bits::*;
+use crate::sdhci_reg::emmc_cmd_bits::*;
+use crate::sdhci_reg::emmc_cqver_bits::EMMC_CQVER_OFFSET;
+use crate::sdhci_reg::emmc_error_int_en_bits::EMMC_ERROR_INT_EN_OFFSET;
+use crate::sdhci_reg::emmc_error_int_sig_en_bits::EMMC_ERROR_INT_SIG_EN_OFFSET;
+use crate::sdhci_reg::emmc_error_int_stat_bits::*;
+use crate::sdhci_reg::emmc_host_ctrl1_bits::*;
+use crate::sdhci_reg::emmc_host_ctrl_ver_bits::*;
+use crate::sdhci_reg::emmc_normal_int_en_bits::EMMC_NORMAL_INT_EN_OFFSET;
+use crate::sdhci_reg::emmc_normal_int_sig_en_bits::EMMC_NORMAL_INT_SIG_EN_OFFSET;
+use crate::sdhci_reg::emmc_normal_int_stat_bits::*;
+use crate::sdhci_reg::emmc_pstate_bits::*;
+use crate::sdhci_reg::emmc_pwr_ctrl_bits::*;
+use crate::sdhci_reg::emmc_resp01_bits::EMMC_RESP01_OFFSET;
+use crate::sdhci_reg::emmc_resp67_bits::EMMC_RESP67_OFFSET;
+use crate::sdhci_reg::emmc_sdmasa_bits::EMMC_SDMASA_OFFSET;
+use crate::sdhci_reg::emmc_sw_rst_bits::*;
+use crate::sdhci_reg::emmc_ver_id_bits::EMMC_VER_ID_OFFSET;
+use crate::sdhci_reg::emmc_xfer_mode_bits::*;
+
+/// Size of the register space of the controller, up to the DLL registers.
+const SIM_REG_SPACE: usize = 0x900;
+/// Blocks in each boot partition, a BOOT_SIZE_MULT of 1.
+pub const SIM_BOOT_BLOCKS: u64 = 256;
+/// 256-byte half sectors in the RPMB partition, a RPMB_SIZE_MULT of 1.
+pub const SIM_RPMB_HALF_SECTORS: u64 = 512;
+/// Blocks in an erase group, for both the legacy and the high capacity definition.
+pub const SIM_ERASE_GROUP_BLOCKS: u64 = 1024;
+/// Blocks in a write protect group, one erase group for both definitions.
+pub const SIM_WP_GROUP_BLOCKS: u64 = SIM_ERASE_GROUP_BLOCKS;
+/// Enhanced memory of the device in write protect groups.
+pub const SIM_MAX_ENH_SIZE_MULT: u8 = 2;
+/// CMD13 polls answered in the programming state after SANITIZE_START before the sanitize is done.
+pub const SIM_SANITIZE_POLLS: u32 = 3;
+/// CMD13 polls answered in the programming state after BKOPS_START before the background
+/// operations are done.
+pub const SIM_BKOPS_POLLS: u32 = 3;
+/// Argument of the CMD25 downloading firmware in FFU mode, FFU_ARG.
+pub const SIM_FFU_ARG: u32 = 0xc0de_0000;
+/// Devices above 2 GiB are sector addressed.
+const SIM_SECTOR_MODE_BLOCKS: u64 = 1 << 22;
+/// CMD1 polls answered before the device reports the end of its power up.
+const SIM_POWER_UP_POLLS: u32 = 1;
+/// RCA published by an SD card with CMD3.
+pub const SIM_SD_RCA: u16 = 0xb368;
+/// Base clock advertised in the capabilities, in MHz.
+const SIM_BASE_CLK_MHZ: u32 = 200;
+/// Tuning blocks the controller needs to find a sampling point.
+pub const SIM_TUNING_LOOPS: u32 = 8;
+
+/// Tuning block of a 4-bit bus, sent with CMD19.
+const SD_TUNING_PATTERN: [u8; 64] = [
+    0xff, 0x0f, 0xff, 0x00, 0xff, 0xcc, 0xc3, 0xcc, 0xc3, 0x3c, 0xcc, 0xff, 0xfe, 0xff, 0xfe, 0xef,
+    0xff, 0xdf, 0xff, 0xdd, 0xff, 0xfb, 0xff, 0xfb, 0xbf, 0xff, 0x7f, 0xff, 0x77, 0xf7, 0xbd, 0xef,
+    0xff, 0xf0, 0xff, 0xf0, 0x0f, 0xfc, 0xcc, 0x3c, 0xcc, 0x33, 0xcc, 0xcf, 0xff, 0xef, 0xff, 0xee,
+    0xff, 0xfd, 0xff, 0xfd, 0xdf, 0xff, 0xbf, 0xff, 0xbb, 0xff, 0xf7, 0xff, 0xf7, 0x7f, 0x7b, 0xde,
+];
+/// ADMA2 descriptors walked before the table is considered to be endless.
+const SIM_ADMA_MAX_DESCS: usize = 4096;
+/// Granularity of `SimMemory`.
+const SIM_PAGE_SIZE: u64 = 4096;
+
+/// The EXT_CSD fields from here on are the read-only properties segment.
+const EXT_CSD_PROPERTIES_START: usize = EXT_CSD_REV;
+
+/// Storage behind the user data area of a `SimCard`.
+pub trait SimStorage {
+    /// Capacity in blocks of `BLOCK_SIZE` bytes.
+    fn blocks(&self) -> u64;
+    fn read_block(&mut self, lba: u64, buf: &mut [u8]);
+    fn write_block(&mut self, lba: u64, buf: &[u8]);
+}
+
+impl SimStorage for Vec<u8> {
+    fn blocks(&self) -> u64 {
+        (self.len() / BLOCK_SIZE) as u64
+    }
+
+    fn read_block(&mut self, lba: u64, buf: &mut [u8]) {
+        buf.copy_from_slice(&self[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE]);
+    }
+
+    fn write_block(&mut self, lba: u64, buf: &[u8]) {
+        self[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE].copy_from_slice(buf);
+    }
+}
+
+/// A raw disk image file, e.g. `firmware/uboot.disk`. Writes go straight to the file.
+pub struct ImageFile {
+    file: File,
+    blocks: u64,
+}
+
+impl ImageFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let blocks = file.metadata()?.len() / BLOCK_SIZE as u64;
+        Ok(Self { file, blocks })
+    }
+}
+
+impl SimStorage for ImageFile {
+    fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_block(&mut self, lba: u64, buf: &mut [u8]) {
+        self.file.seek(SeekFrom::Start(lba * BLOCK_SIZE as u64)).expect("image file seek");
+        self.file.read_exact(buf).expect("image file read");
+    }
+
+    fn write_block(&mut self, lba: u64, buf: &[u8]) {
+        self.file.seek(SeekFrom::Start(lba * BLOCK_SIZE as u64)).expect("image file seek");
+        self.file.write_all(buf).expect("image file write");
+    }
+}
+
+/// The partition selected by PARTITION_ACCESS in EXT_CSD PARTITION_CONFIG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    User,
+    Boot1,
+    Boot2,
+    Rpmb,
+}
+
+/// A fault injected into the next matching command, see `Simulator::inject_fault`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The device does not respond.
+    CmdTimeout,
+    /// The command is executed but its response fails the CRC check.
+    CmdCrc,
+    /// The data phase never starts.
+    DataTimeout,
+    /// The first data block fails the CRC check. A written block is not programmed.
+    DataCrc,
+    /// The device loses power, after half of the blocks of a write.
+    PowerLoss,
+}
+
+impl Fault {
+    fn needs_data(self) -> bool {
+        matches!(self, Fault::DataTimeout | Fault::DataCrc)
+    }
+}
+
+/// Response of the device to a command.
+enum Reply {
+    None,
+    Short(u32),
+    /// 136-bit response with the CRC stripped, `[0]` holding bits 127:96.
+    Long([u32; 4]),
+}
+
+/// Source or destination of the data phase the device is in.
+enum CardXfer {
+    ExtCsd,
+    /// A register sent as data, e.g. the SD SCR.
+    Register(Vec<u8>),
+    /// Blocks of a partition, `forced` past the volatile cache with the forced programming bit of CMD23.
+    Blocks { part: Partition, lba: u64, left: Option<u16>, forced: bool },
+    Rpmb { left: u16 },
+    /// Firmware downloaded in FFU mode.
+    Firmware { left: Option<u16> },
+}
+
+/// What keeps the device in the programming state.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Busy {
+    Sanitize,
+    Bkops,
+    /// A write of this many blocks.
+    Program(u32),
+    Erase,
+}
+
+/// State of the replay protected memory block partition.
+///
+/// Frames are handled as specified, except that MACs are neither checked nor generated.
+struct Rpmb {
+    data: Vec<u8>,
+    key: Option<[u8; 32]>,
+    counter: u32,
+    result: u16,
+    /// Request type of the last authenticated write, for the result read request.
+    last_write: u16,
+    /// Frame returned by the next read of the partition.
+    response: Option<[u8; BLOCK_SIZE]>,
+}
+
+/// Offsets and values of the RPMB data frame.
+mod rpmb_frame {
+    pub const KEY_MAC: usize = 196;
+    pub const DATA: usize = 228;
+    pub const DATA_SIZE: usize = 256;
+    pub const NONCE: usize = 484;
+    pub const WRITE_COUNTER: usize = 500;
+    pub const ADDRESS: usize = 504;
+    pub const BLOCK_COUNT: usize = 506;
+    pub const RESULT: usize = 508;
+    pub const REQ_RESP: usize = 510;
+
+    pub const REQ_PROGRAM_KEY: u16 = 0x0001;
+    pub const REQ_READ_COUNTER: u16 = 0x0002;
+    pub const REQ_AUTH_WRITE: u16 = 0x0003;
+    pub const REQ_AUTH_READ: u16 = 0x0004;
+    pub const REQ_RESULT_READ: u16 = 0x0005;
+
+    pub const RESULT_OK: u16 = 0x0000;
+    pub const RESULT_GENERAL_FAILURE: u16 = 0x0001;
+    pub const RESULT_COUNTER_FAILURE: u16 = 0x0003;
+    pub const RESULT_ADDRESS_FAILURE: u16 = 0x0004;
+    pub const RESULT_WRITE_FAILURE: u16 = 0x0005;
+    pub const RESULT_NO_KEY: u16 = 0x0007;
+}
+
+impl Rpmb {
+    fn new() -> Self {
+        Self {
+            data: vec![0; SIM_RPMB_HALF_SECTORS as usize * rpmb_frame::DATA_SIZE],
+            key: None,
+            counter: 0,
+            result: rpmb_frame::RESULT_OK,
+            last_write: 0,
+            response: None,
+        }
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) {
+        use rpmb_frame::*;
+
+        let be16 = |offset: usize| u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        let request = be16(REQ_RESP);
+        let address = be16(ADDRESS) as u64;
+        let mut response = [0u8; BLOCK_SIZE];
+        response[NONCE..NONCE + 16].copy_from_slice(&frame[NONCE..NONCE + 16]);
+
+        let result = match request {
+            REQ_PROGRAM_KEY => {
+                self.last_write = request;
+                if self.key.is_some() {
+                    RESULT_WRITE_FAILURE
+                } else {
+                    self.key = Some(frame[KEY_MAC..KEY_MAC + 32].try_into().unwrap());
+                    RESULT_OK
+                }
+            }
+            REQ_AUTH_WRITE => {
+                self.last_write = request;
+                let counter = u32::from_be_bytes(frame[WRITE_COUNTER..WRITE_COUNTER + 4].try_into().unwrap());
+                if self.key.is_none() {
+                    RESULT_NO_KEY
+                } else if be16(BLOCK_COUNT) != 1 {
+                    RESULT_GENERAL_FAILURE
+                } else if counter != self.counter {
+                    RESULT_COUNTER_FAILURE
+                } else if address >= SIM_RPMB_HALF_SECTORS {
+                    RESULT_ADDRESS_FAILURE
+                } else {
+                    let offset = address as usize * DATA_SIZE;
+                    self.data[offset..offset + DATA_SIZE].copy_from_slice(&frame[DATA..DATA + DATA_SIZE]);
+                    self.counter += 1;
+                    RESULT_OK
+                }
+            }
+            REQ_READ_COUNTER | REQ_RESULT_READ => {
+                if self.key.is_none() { RESULT_NO_KEY } else { RESULT_OK }
+            }
+            REQ_AUTH_READ => {
+                if self.key.is_none() {
+                    RESULT_NO_KEY
+                } else if address >= SIM_RPMB_HALF_SECTORS {
+                    RESULT_ADDRESS_FAILURE
+                } else {
+                    let offset = address as usize * DATA_SIZE;
+                    response[DATA..DATA + DATA_SIZE].copy_from_slice(&self.data[offset..offset + DATA_SIZE]);
+                    RESULT_OK
+                }
+            }
+            _ => RESULT_GENERAL_FAILURE,
+        };
+
+        match request {
+            REQ_PROGRAM_KEY | REQ_AUTH_WRITE => self.result = result,
+            REQ_READ_COUNTER | REQ_AUTH_READ | REQ_RESULT_READ => {
+                let (resp_type, result) = match request {
+                    REQ_RESULT_READ => (self.last_write << 8, self.result),
+                    _ => (request << 8, result),
+                };
+                response[WRITE_COUNTER..WRITE_COUNTER + 4].copy_from_slice(&self.counter.to_be_bytes());
+                response[ADDRESS..ADDRESS + 2].copy_from_slice(&(address as u16).to_be_bytes());
+                response[BLOCK_COUNT..BLOCK_COUNT + 2].copy_from_slice(&1u16.to_be_bytes());
+                response[RESULT..RESULT + 2].copy_from_slice(&result.to_be_bytes());
+                response[REQ_RESP..REQ_RESP + 2].copy_from_slice(&resp_type.to_be_bytes());
+                self.response = Some(response);
+            }
+            _ => self.result = result,
+        }
+    }
+
+    fn read_frame(&mut self, buf: &mut [u8]) {
+        match self.response.take() {
+            Some(response) => buf.copy_from_slice(&response),
+            None => {
+                buf.fill(0);
+                buf[rpmb_frame::RESULT..rpmb_frame::RESULT + 2]
+                    .copy_from_slice(&rpmb_frame::RESULT_GENERAL_FAILURE.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Signaling voltage of an SD card.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SdVoltage {
+    V33,
+    /// CMD11 was accepted, DAT[3:0] are held low until the clock runs again at 1.8V after
+    /// it was stopped.
+    Switching { clock_stopped: bool },
+    V18,
+}
+
+/// State of an SD memory card, on top of what it shares with an eMMC device.
+struct SdState {
+    /// Physical layer 1.x card, which does not know CMD8.
+    v1: bool,
+    /// The card supports the UHS-I modes.
+    uhs: bool,
+    /// CMD8 was answered since the last CMD0.
+    if_cond: bool,
+    /// The previous command was CMD55.
+    app_cmd: bool,
+    /// The last ACMD41 accepted the switch to 1.8V.
+    s18a: bool,
+    /// CMD11 is accepted but the card never releases DAT[3:0].
+    switch_fails: bool,
+    voltage: SdVoltage,
+    bus_width: u8,
+    /// Function of the access mode group selected with CMD6.
+    access_mode: u8,
+    /// Tuning blocks sent with CMD19.
+    tuning_blocks: u32,
+}
+
+/// An eMMC 5.1 device with a user data area, two boot partitions and an RPMB partition, or an
+/// SD memory card with a user data area only.
+///
+/// `cid`, `csd`, `ext_csd`, `scr`, `sd_status` and `health_report` may be changed before the
+/// card is handed to the `Simulator`, e.g. to advertise other features.
+pub struct SimCard {
+    pub cid: [u32; 4],
+    pub csd: [u32; 4],
+    pub ext_csd: [u8; EXT_CSD_SIZE],
+    /// SD Configuration Register, most significant byte first.
+    pub scr: [u8; 8],
+    /// SD Status, most significant byte first. DAT_BUS_WIDTH is filled in when it is read.
+    pub sd_status: [u8; 64],
+    /// Data block read with CMD56, whatever the argument.
+    pub health_report: [u8; BLOCK_SIZE],
+    sd: Option<SdState>,
+    /// An eMMC device sets APP_CMD in its reply to CMD55.
+    mmc_app_cmd: bool,
+    user: Box<dyn SimStorage>,
+    boot: [Vec<u8>; 2],
+    rpmb: Rpmb,
+    high_capacity: bool,
+    state: u32,
+    rca: u16,
+    power_up_polls: u32,
+    /// Error bits reported with the next R1 response.
+    pending: u32,
+    /// Block count set with CMD23 for the next read or write.
+    block_count: Option<u16>,
+    /// CMD23 asked for forced programming of the next write.
+    forced_prg: bool,
+    /// CMD23 asked for a reliable write.
+    reliable: bool,
+    reliable_writes: u32,
+    /// Blocks of the user data area written to the volatile cache and not yet written back.
+    cache: BTreeMap<u64, Vec<u8>>,
+    barriers: u32,
+    xfer: Option<CardXfer>,
+    erase_start: Option<u64>,
+    erase_end: Option<u64>,
+    /// CMD13 polls left before the programming state ends.
+    busy_polls: u32,
+    busy: Option<Busy>,
+    /// CMD13 polls answered in the programming state after a write or an erase, see `slow_programming`.
+    program_polls: u32,
+    /// Blocks received by the write in progress.
+    received: u32,
+    sanitizes: u32,
+    /// Protected write protect groups of the user data area, with the type CMD31 reports.
+    wp: BTreeMap<u64, u8>,
+    /// Firmware downloaded in FFU mode, installed with MODE_OPERATION_CODES or the next power
+    /// cycle. Its first 8 bytes become FIRMWARE_VERSION.
+    firmware: Vec<u8>,
+}
+
+impl SimCard {
+    /// A card whose user data area holds `blocks` blocks in memory.
+    pub fn new(blocks: u64) -> Self {
+        Self::with_storage(Box::new(vec![0u8; blocks as usize * BLOCK_SIZE]))
+    }
+
+    /// A card whose user data area is the disk image at `path`.
+    pub fn with_image(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::with_storage(Box::new(ImageFile::open(path)?)))
+    }
+
+    pub fn with_storage(user: Box<dyn SimStorage>) -> Self {
+        let blocks = user.blocks();
+        let high_capacity = blocks > SIM_SECTOR_MODE_BLOCKS;
+
+        let mut cid = [0u32; 4];
+        set_bits(&mut cid, 120, 8, 0xfe); // MID
+        set_bits(&mut cid, 112, 2, 0x01); // CBX, BGA
+        for (i, c) in b"SIMMC0".iter().enumerate() {
+            set_bits(&mut cid, 96 - 8 * i, 8, *c as u32); // PNM
+        }
+        set_bits(&mut cid, 48, 8, 0x10); // PRV
+        set_bits(&mut cid, 16, 32, 0x1234_5678); // PSN
+
+        let mut csd = [0u32; 4];
+        set_bits(&mut csd, 126, 2, 3); // CSD_STRUCTURE, in EXT_CSD
+        set_bits(&mut csd, 122, 4, 4); // SPEC_VERS
+        set_bits(&mut csd, 112, 8, 0x27); // TAAC, 1.5 ms
+        set_bits(&mut csd, 96, 8, 0x32); // TRAN_SPEED, 26 MHz
+        set_bits(&mut csd, 42, 5, 31); // ERASE_GRP_SIZE
+        set_bits(&mut csd, 37, 5, 31); // ERASE_GRP_MULT
+        set_bits(&mut csd, 31, 1, 1); // WP_GRP_ENABLE, WP_GRP_SIZE of one erase group
+        set_bits(&mut csd, 26, 3, 2); // R2W_FACTOR
+        set_bits(&mut csd, 22, 4, 9); // WRITE_BL_LEN
+        if high_capacity {
+            set_bits(&mut csd, 80, 4, 9);
+            set_bits(&mut csd, 62, 12, 0xfff);
+            set_bits(&mut csd, 47, 3, 7);
+        } else {
+            // (C_SIZE + 1) << (C_SIZE_MULT + 2 + READ_BL_LEN) bytes, rounded down.
+            let read_bl_len = if blocks >> 9 <= 4096 { 9 } else { 10 };
+            let c_size = ((blocks * BLOCK_SIZE as u64) >> (9 + read_bl_len)).max(1) - 1;
+            set_bits(&mut csd, 80, 4, read_bl_len as u32);
+            set_bits(&mut csd, 62, 12, c_size as u32);
+            set_bits(&mut csd, 47, 3, 7);
+        }
+
+        let mut ext_csd = [0u8; EXT_CSD_SIZE];
+        ext_csd[EXT_CSD_RPMB_SIZE_MULT] = 1;
+        ext_csd[EXT_CSD_REV] = 8;
+        ext_csd[EXT_CSD_CARD_TYPE] = 0x03; // HS 26 and 52 MHz
+        if high_capacity {
+            ext_csd[EXT_CSD_SEC_COUNT..EXT_CSD_SEC_COUNT + 4].copy_from_slice(&(blocks as u32).to_le_bytes());
+        }
+        ext_csd[EXT_CSD_ERASE_TIMEOUT_MULT] = 1;
+        ext_csd[EXT_CSD_HC_ERASE_GRP_SIZE] = 1;
+        ext_csd[EXT_CSD_HC_WP_GRP_SIZE] = 1;
+        ext_csd[EXT_CSD_PARTITIONING_SUPPORT] = EXT_CSD_PARTITIONING_EN | EXT_CSD_ENH_ATTRIBUTE_EN | EXT_CSD_EXT_ATTRIBUTE_EN;
+        ext_csd[EXT_CSD_MAX_ENH_SIZE_MULT] = SIM_MAX_ENH_SIZE_MULT;
+        ext_csd[EXT_CSD_SEC_TRIM_MULT] = 2;
+        ext_csd[EXT_CSD_SEC_ERASE_MULT] = 2;
+        ext_csd[EXT_CSD_SEC_FEATURE_SUPPORT] = EXT_CSD_SEC_ER_EN | EXT_CSD_SEC_GB_CL_EN | EXT_CSD_SEC_SANITIZE;
+        ext_csd[EXT_CSD_TRIM_MULT] = 1;
+        ext_csd[EXT_CSD_BOOT_SIZE_MULT] = 1;
+        ext_csd[EXT_CSD_GENERIC_CMD6_TIME] = 10;
+        ext_csd[EXT_CSD_CACHE_SIZE..EXT_CSD_CACHE_SIZE + 4].copy_from_slice(&1024u32.to_le_bytes());
+        ext_csd[EXT_CSD_BARRIER_SUPPORT] = 1;
+        ext_csd[EXT_CSD_OUT_OF_INTERRUPT_TIME] = 10;
+        ext_csd[EXT_CSD_HPI_FEATURES] = EXT_CSD_HPI_SUPPORT | EXT_CSD_HPI_IMPL_CMD12;
+        ext_csd[EXT_CSD_BKOPS_SUPPORT] = 1;
+        ext_csd[EXT_CSD_REL_WR_SEC_C] = 1;
+        ext_csd[EXT_CSD_PRE_EOL_INFO] = 1;
+        ext_csd[EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_A] = 1;
+        ext_csd[EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_B] = 1;
+        ext_csd[EXT_CSD_WR_REL_PARAM] = EXT_CSD_EN_REL_WR;
+        ext_csd[EXT_CSD_FIRMWARE_VERSION..EXT_CSD_FIRMWARE_VERSION + 8].copy_from_slice(b"SIMFW001");
+        ext_csd[EXT_CSD_FFU_ARG..EXT_CSD_FFU_ARG + 4].copy_from_slice(&SIM_FFU_ARG.to_le_bytes());
+        ext_csd[EXT_CSD_OPERATION_CODE_TIMEOUT] = 1;
+        ext_csd[EXT_CSD_FFU_FEATURES] = 1;
+        ext_csd[EXT_CSD_SUPPORTED_MODES] = 1;
+
+        let boot_size = SIM_BOOT_BLOCKS as usize * BLOCK_SIZE;
+        Self {
+            cid,
+            csd,
+            ext_csd,
+            scr: [0; 8],
+            sd_status: [0; 64],
+            health_report: [0; BLOCK_SIZE],
+            sd: None,
+            mmc_app_cmd: false,
+            user,
+            boot: [vec![0; boot_size], vec![0; boot_size]],
+            rpmb: Rpmb::new(),
+            high_capacity,
+            state: MMC_R1_STATE_IDLE,
+            rca: 0,
+            power_up_polls: SIM_POWER_UP_POLLS,
+            pending: 0,
+            block_count: None,
+            forced_prg: false,
+            reliable: false,
+            reliable_writes: 0,
+            cache: BTreeMap::new(),
+            barriers: 0,
+            xfer: None,
+            erase_start: None,
+            erase_end: None,
+            busy_polls: 0,
+            busy: None,
+            program_polls: 0,
+            received: 0,
+            sanitizes: 0,
+            wp: BTreeMap::new(),
+            firmware: Vec::new(),
+        }
+    }
+
+    /// An SD card whose user data area holds `blocks` blocks in memory.
+    pub fn new_sd(blocks: u64) -> Self {
+        Self::sd_with_storage(Box::new(vec![0u8; blocks as usize * BLOCK_SIZE]))
+    }
+
+    /// An SD card of version 4.00, SDSC up to 2 GiB, SDHC up to 32 GiB and SDXC above, with a
+    /// 4-bit bus and high speed.
+    pub fn sd_with_storage(user: Box<dyn SimStorage>) -> Self {
+        let mut card = Self::with_storage(user);
+        let blocks = card.user.blocks();
+
+        let mut cid = [0u32; 4];
+        set_bits(&mut cid, 120, 8, 0x1b); // MID
+        set_bits(&mut cid, 104, 16, u16::from_be_bytes(*b"SM") as u32); // OID
+        for (i, c) in b"SIMSD".iter().enumerate() {
+            set_bits(&mut cid, 96 - 8 * i, 8, *c as u32); // PNM
+        }
+        set_bits(&mut cid, 56, 8, 0x10); // PRV
+        set_bits(&mut cid, 24, 32, 0x8765_4321); // PSN
+        set_bits(&mut cid, 8, 12, 0x18a); // MDT, 2024-10
+
+        let mut csd = [0u32; 4];
+        set_bits(&mut csd, 112, 8, 0x0e); // TAAC, 1 ms
+        set_bits(&mut csd, 96, 8, 0x32); // TRAN_SPEED, 25 MHz
+        set_bits(&mut csd, 84, 12, 0x5b5); // CCC
+        set_bits(&mut csd, 80, 4, 9); // READ_BL_LEN
+        set_bits(&mut csd, 26, 3, 2); // R2W_FACTOR
+        set_bits(&mut csd, 22, 4, 9); // WRITE_BL_LEN
+        if card.high_capacity {
+            set_bits(&mut csd, 126, 2, 1); // CSD_STRUCTURE, version 2.0
+            set_bits(&mut csd, 48, 22, (blocks / 1024).max(1) as u32 - 1); // C_SIZE, 512 KiB units
+        } else {
+            set_bits(&mut csd, 126, 2, 0);
+            let read_bl_len = if blocks >> 9 <= 4096 { 9 } else { 10 };
+            let c_size = ((blocks * BLOCK_SIZE as u64) >> (9 + read_bl_len)).max(1) - 1;
+            set_bits(&mut csd, 80, 4, read_bl_len as u32);
+            set_bits(&mut csd, 62, 12, c_size as u32);
+            set_bits(&mut csd, 47, 3, 7);
+        }
+
+        let mut scr = [0u8; 8];
+        set_be_bits(&mut scr, 56, 4, 2); // SD_SPEC, 2.00 and later
+        set_be_bits(&mut scr, 52, 3, if card.high_capacity { 3 } else { 2 }); // SD_SECURITY
+        set_be_bits(&mut scr, 48, 4, 0b0101); // SD_BUS_WIDTHS, 1 and 4 bits
+        set_be_bits(&mut scr, 47, 1, 1); // SD_SPEC3
+        set_be_bits(&mut scr, 42, 1, 1); // SD_SPEC4
+        set_be_bits(&mut scr, 32, 4, 0b0010); // CMD_SUPPORT, CMD23
+
+        let mut sd_status = [0u8; 64];
+        set_be_bits(&mut sd_status, 440, 8, 4); // SPEED_CLASS, class 10
+        set_be_bits(&mut sd_status, 428, 4, 9); // AU_SIZE, 4 MiB
+        set_be_bits(&mut sd_status, 408, 16, 1); // ERASE_SIZE
+        set_be_bits(&mut sd_status, 402, 6, 1); // ERASE_TIMEOUT
+        set_be_bits(&mut sd_status, 400, 2, 1); // ERASE_OFFSET
+        set_be_bits(&mut sd_status, 396, 4, 1); // UHS_SPEED_GRADE
+        set_be_bits(&mut sd_status, 313, 1, 1); // DISCARD_SUPPORT
+        set_be_bits(&mut sd_status, 384, 8, 10); // VIDEO_SPEED_CLASS
+
+        card.cid = cid;
+        card.csd = csd;
+        card.ext_csd = [0; EXT_CSD_SIZE];
+        card.scr = scr;
+        card.sd_status = sd_status;
+        card.sd = Some(SdState {
+            v1: false,
+            uhs: false,
+            if_cond: false,
+            app_cmd: false,
+            s18a: false,
+            switch_fails: false,
+            voltage: SdVoltage::V33,
+            bus_width: 1,
+            access_mode: 0,
+            tuning_blocks: 0,
+        });
+        card
+    }
+
+    /// Turn an SD card into one of version 1.10, which does not answer CMD8 and cannot be high capacity.
+    pub fn sd_v1(mut self) -> Self {
+        assert!(!self.high_capacity, "version 1.x SD cards are at most 2 GiB");
+        if let Some(sd) = &mut self.sd {
+            sd.v1 = true;
+            set_be_bits(&mut self.scr, 56, 4, 1);
+            set_be_bits(&mut self.scr, 32, 16, 0);
+        }
+        self
+    }
+
+    /// Have an eMMC device set APP_CMD when it answers CMD55, as an SD card does. It still does
+    /// not know ACMD41.
+    pub fn mmc_app_cmd(mut self) -> Self {
+        self.mmc_app_cmd = true;
+        self
+    }
+
+    /// Keep the device in the programming state for `polls` CMD13 polls after each write and
+    /// erase, as one busy with large ones. A write interrupted with HPI reports half of its
+    /// blocks in CORRECTLY_PRG_SECTORS_NUM.
+    pub fn slow_programming(mut self, polls: u32) -> Self {
+        self.program_polls = polls;
+        self
+    }
+
+    /// Support command queuing with `depth` tasks. The controller does not model the engine.
+    pub fn command_queue(mut self, depth: u8) -> Self {
+        assert!((1..=32).contains(&depth), "queue depth out of range");
+        self.ext_csd[EXT_CSD_CMDQ_SUPPORT] = 0x01;
+        self.ext_csd[EXT_CSD_CMDQ_DEPTH] = depth - 1;
+        self
+    }
+
+    /// Command queuing was enabled with CMDQ_MODE_EN.
+    pub fn cmdq_mode(&self) -> bool {
+        self.ext_csd[EXT_CSD_CMDQ_MODE_EN] & 0x01 != 0
+    }
+
+    /// Data bus width selected with ACMD6, `None` for an eMMC device.
+    pub fn sd_bus_width(&self) -> Option<u8> {
+        self.sd.as_ref().map(|sd| sd.bus_width)
+    }
+
+    /// Turn a high capacity SD card into one supporting the UHS-I modes up to SDR104.
+    pub fn uhs_i(mut self) -> Self {
+        assert!(self.high_capacity, "only high capacity SD cards switch to 1.8V");
+        if let Some(sd) = &mut self.sd {
+            sd.uhs = true;
+        }
+        self
+    }
+
+    /// Make the switch to 1.8V of a UHS-I card fail, as one whose regulator does not come up.
+    pub fn failing_voltage_switch(mut self) -> Self {
+        if let Some(sd) = &mut self.sd {
+            sd.switch_fails = true;
+        }
+        self
+    }
+
+    /// High speed was selected with CMD6, at 3.3V.
+    pub fn sd_high_speed(&self) -> bool {
+        self.sd.as_ref().is_some_and(|sd| sd.access_mode == 1 && sd.voltage == SdVoltage::V33)
+    }
+
+    /// Function of the access mode group selected with CMD6, `None` for an eMMC device.
+    pub fn sd_access_mode(&self) -> Option<u8> {
+        self.sd.as_ref().map(|sd| sd.access_mode)
+    }
+
+    /// The card signals at 1.8V.
+    pub fn sd_1v8(&self) -> bool {
+        self.sd.as_ref().is_some_and(|sd| sd.voltage == SdVoltage::V18)
+    }
+
+    /// Blocks held in the volatile cache, lost on power off unless flushed.
+    pub fn cached_blocks(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Cache barriers set with FLUSH_CACHE since the card was created.
+    pub fn barriers(&self) -> u32 {
+        self.barriers
+    }
+
+    /// Reliable writes accepted since the card was created.
+    pub fn reliable_writes(&self) -> u32 {
+        self.reliable_writes
+    }
+
+    /// Sanitize operations completed since the card was created.
+    pub fn sanitizes(&self) -> u32 {
+        self.sanitizes
+    }
+
+    /// The device needs background operations with urgency `level`, as BKOPS_STATUS, raising the
+    /// urgent BKOPS exception event from 2 until they are done.
+    pub fn set_bkops_status(&mut self, level: u8) {
+        self.ext_csd[EXT_CSD_BKOPS_STATUS] = level;
+        if level >= 2 {
+            self.ext_csd[EXT_CSD_EXCEPTION_EVENTS_STATUS] |= EXT_CSD_URGENT_BKOPS;
+        }
+    }
+
+    /// Tuning blocks sent with CMD19 since the card was created.
+    pub fn sd_tuning_blocks(&self) -> u32 {
+        self.sd.as_ref().map_or(0, |sd| sd.tuning_blocks)
+    }
+
+    /// Capacity of `part` in blocks.
+    pub fn blocks(&self, part: Partition) -> u64 {
+        match part {
+            Partition::User => self.user.blocks(),
+            Partition::Boot1 | Partition::Boot2 => SIM_BOOT_BLOCKS,
+            Partition::Rpmb => SIM_RPMB_HALF_SECTORS / 2,
+        }
+    }
+
+    /// Read a block of `part` directly, without going through the bus.
+    pub fn read_block(&mut self, part: Partition, lba: u64, buf: &mut [u8]) {
+        match part {
+            Partition::User => self.user.read_block(lba, buf),
+            Partition::Boot1 | Partition::Boot2 => {
+                let boot = &self.boot[(part == Partition::Boot2) as usize];
+                buf.copy_from_slice(&boot[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE]);
+            }
+            Partition::Rpmb => buf.copy_from_slice(&self.rpmb.data[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE]),
+        }
+    }
+
+    /// Write a block of `part` directly, without going through the bus.
+    pub fn write_block(&mut self, part: Partition, lba: u64, buf: &[u8]) {
+        match part {
+            Partition::User => self.user.write_block(lba, buf),
+            Partition::Boot1 | Partition::Boot2 => {
+                let boot = &mut self.boot[(part == Partition::Boot2) as usize];
+                boot[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE].copy_from_slice(buf);
+            }
+            Partition::Rpmb => self.rpmb.data[lba as usize * BLOCK_SIZE..][..BLOCK_SIZE].copy_from_slice(buf),
+        }
+    }
+
+    /// The write counter of the RPMB partition.
+    pub fn rpmb_counter(&self) -> u32 {
+        self.rpmb.counter
+    }
+
+    /// The partition selected by PARTITION_ACCESS.
+    pub fn partition(&self) -> Partition {
+        match self.ext_csd[EXT_CSD_PARTITION_CONFIG] & 0x07 {
+            1 => Partition::Boot1,
+            2 => Partition::Boot2,
+            3 => Partition::Rpmb,
+            _ => Partition::User,
+        }
+    }
+
+    /// Current state, as reported in the CURRENT_STATE field of the card status.
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    /// Drop the power of the device. It needs to be initialised again afterwards.
+    pub fn power_off(&mut self) {
+        self.cache.clear();
+        if self.ext_csd[EXT_CSD_MODE_CONFIG] == 0 && !self.firmware.is_empty() {
+            self.install_firmware();
+        }
+        self.ext_csd[EXT_CSD_MODE_CONFIG] = 0;
+        self.ext_csd[EXT_CSD_BKOPS_EN] &= !EXT_CSD_AUTO_BKOPS_EN;
+        self.ext_csd[EXT_CSD_BOOT_CONFIG_PROT] &= !EXT_CSD_PWR_BOOT_CONFIG_PROT;
+        // Power-on write protection ends, as does the disabling of it.
+        self.wp.retain(|_, wp| *wp != 2);
+        self.ext_csd[EXT_CSD_USER_WP] &= !(EXT_CSD_US_PWR_WP_EN | EXT_CSD_US_PWR_WP_DIS);
+        self.ext_csd[EXT_CSD_BOOT_WP] &= !(EXT_CSD_B_PWR_WP_EN | EXT_CSD_B_PWR_WP_DIS);
+        let status = self.ext_csd[EXT_CSD_BOOT_WP_STATUS];
+        for shift in [0, 2] {
+            if status >> shift & 0x03 == 1 {
+                self.ext_csd[EXT_CSD_BOOT_WP_STATUS] &= !(0x03 << shift);
+            }
+        }
+        self.reset();
+        if let Some(sd) = &mut self.sd {
+            sd.voltage = SdVoltage::V33;
+        }
+    }
+
+    /// The SD clock was started or stopped, `v18` telling whether the host signals at 1.8V.
+    fn clock(&mut self, running: bool, v18: bool) {
+        let Some(sd) = &mut self.sd else {
+            return;
+        };
+        sd.voltage = match sd.voltage {
+            SdVoltage::Switching { .. } if !running => SdVoltage::Switching { clock_stopped: true },
+            SdVoltage::Switching { clock_stopped: true } if v18 && !sd.switch_fails => SdVoltage::V18,
+            voltage => voltage,
+        };
+    }
+
+    /// The card holds CMD and DAT[3:0] low.
+    fn lines_low(&self) -> bool {
+        self.sd.as_ref().is_some_and(|sd| matches!(sd.voltage, SdVoltage::Switching { .. }))
+    }
+
+    /// Reset to the idle state, as after power up or CMD0.
+    fn reset(&mut self) {
+        self.flush_cache();
+        self.ext_csd[EXT_CSD_CACHE_CTRL] = 0;
+        self.ext_csd[EXT_CSD_BARRIER_CTRL] = 0;
+        self.state = MMC_R1_STATE_IDLE;
+        self.rca = 0;
+        self.power_up_polls = SIM_POWER_UP_POLLS;
+        self.pending = 0;
+        self.block_count = None;
+        self.forced_prg = false;
+        self.xfer = None;
+        self.erase_start = None;
+        self.erase_end = None;
+        self.busy_polls = 0;
+        self.busy = None;
+        self.ext_csd[EXT_CSD_PARTITION_CONFIG] &= !0x07;
+        self.ext_csd[EXT_CSD_EXCEPTION_EVENTS_CTRL..][..2].fill(0);
+        self.ext_csd[EXT_CSD_BUS_WIDTH] = 0;
+        self.ext_csd[EXT_CSD_HS_TIMING] = 0;
+        if let Some(sd) = &mut self.sd {
+            sd.if_cond = false;
+            sd.app_cmd = false;
+            sd.s18a = false;
+            sd.bus_width = 1;
+            sd.access_mode = 0;
+        }
+    }
+
+    /// Start a boot operation from the idle state, sending the partition enabled in
+    /// PARTITION_CONFIG from its first block until CMD0 or the end of the boot operation.
+    fn boot(&mut self) {
+        let part = match self.ext_csd[EXT_CSD_PARTITION_CONFIG] >> 3 & 0x07 {
+            1 => Partition::Boot1,
+            2 => Partition::Boot2,
+            7 => Partition::User,
+            _ => return,
+        };
+        if self.state == MMC_R1_STATE_IDLE {
+            self.state = MMC_R1_STATE_DATA;
+            self.xfer = Some(CardXfer::Blocks { part, lba: 0, left: None, forced: false });
+        }
+    }
+
+    /// The CMD line was released, ending a boot operation.
+    fn end_boot(&mut self) {
+        if self.state == MMC_R1_STATE_DATA && self.rca == 0 {
+            self.state = MMC_R1_STATE_IDLE;
+            self.xfer = None;
+        }
+    }
+
+    /// The device sends the boot acknowledge pattern.
+    fn boot_ack(&self) -> bool {
+        self.ext_csd[EXT_CSD_PARTITION_CONFIG] & EXT_CSD_BOOT_ACK != 0
+    }
+
+    /// Return the card status for an R1 response and clear the error bits it reports.
+    fn status(&mut self) -> u32 {
+        let ready = if self.state == MMC_R1_STATE_PRG { 0 } else { MMC_R1_READY_FOR_DATA };
+        let mut status = self.pending | self.state << MMC_R1_CURRENT_STATE_POS | ready;
+        let events = &self.ext_csd[EXT_CSD_EXCEPTION_EVENTS_STATUS..];
+        let enabled = &self.ext_csd[EXT_CSD_EXCEPTION_EVENTS_CTRL..];
+        // URGENT_BKOPS has no enable bit, it is always reported.
+        if events[0] & (enabled[0] | EXT_CSD_URGENT_BKOPS) != 0 || events[1] & enabled[1] != 0 {
+            status |= MMC_R1_EXCEPTION_EVENT;
+        }
+        self.pending = 0;
+        status
+    }
+
+    /// Replace the firmware with the one downloaded, reporting the result in FFU_STATUS.
+    fn install_firmware(&mut self) {
+        let firmware = core::mem::take(&mut self.firmware);
+        if firmware.len() < 8 {
+            self.ext_csd[EXT_CSD_FFU_STATUS] = 0x12; // download error
+        } else {
+            self.ext_csd[EXT_CSD_FIRMWARE_VERSION..EXT_CSD_FIRMWARE_VERSION + 8].copy_from_slice(&firmware[..8]);
+            self.ext_csd[EXT_CSD_FFU_STATUS] = 0;
+        }
+        self.ext_csd[EXT_CSD_MODE_CONFIG] = 0;
+    }
+
+    /// Write the volatile cache back to the user data area.
+    fn flush_cache(&mut self) {
+        for (lba, block) in core::mem::take(&mut self.cache) {
+            self.user.write_block(lba, &block);
+        }
+    }
+
+    /// One CMD13 poll of a device busy programming, e.g. sanitizing.
+    fn program(&mut self) {
+        if self.state != MMC_R1_STATE_PRG {
+            return;
+        }
+        self.busy_polls = self.busy_polls.saturating_sub(1);
+        if self.busy_polls == 0 {
+            self.state = MMC_R1_STATE_TRAN;
+            match self.busy.take() {
+                Some(Busy::Sanitize) => self.sanitizes += 1,
+                Some(Busy::Bkops) => {
+                    self.ext_csd[EXT_CSD_BKOPS_STATUS] = 0;
+                    self.ext_csd[EXT_CSD_EXCEPTION_EVENTS_STATUS] &= !EXT_CSD_URGENT_BKOPS;
+                }
+                Some(Busy::Program(_) | Busy::Erase) | None => {}
+            }
+        }
+    }
+
+    /// CMD12 or CMD13 with the HPI bit, interrupting the programming state if HPI is enabled.
+    fn hpi(&mut self) -> Reply {
+        if self.ext_csd[EXT_CSD_HPI_MGMT] & 0x01 == 0 {
+            self.pending |= MMC_R1_ILLEGAL_COMMAND;
+            return Reply::None;
+        }
+        let status = self.status();
+        if let Some(Busy::Program(blocks)) = self.busy.take() {
+            self.ext_csd[EXT_CSD_CORRECTLY_PRG_SECTORS_NUM..][..4].copy_from_slice(&(blocks / 2).to_le_bytes());
+        }
+        self.busy_polls = 0;
+        self.state = MMC_R1_STATE_TRAN;
+        Reply::Short(status)
+    }
+
+    /// Stay in the programming state for the polls asked with `slow_programming`, after a write
+    /// or an erase.
+    fn start_programming(&mut self, busy: Busy) {
+        if self.program_polls > 0 {
+            self.busy_polls = self.program_polls;
+            self.busy = Some(busy);
+            self.state = MMC_R1_STATE_PRG;
+        }
+    }
+
+    fn fail(&mut self, error: u32) -> Reply {
+        self.pending |= error;
+        Reply::Short(self.status())
+    }
+
+    fn addressed(&self, arg: u32) -> bool {
+        self.rca != 0 && arg >> 16 == self.rca as u32
+    }
+
+    /// Convert a data address to a block address.
+    fn lba(&self, arg: u32) -> Option<u64> {
+        if self.high_capacity {
+            Some(arg as u64)
+        } else if (arg as usize).is_multiple_of(BLOCK_SIZE) {
+            Some((arg as usize / BLOCK_SIZE) as u64)
+        } else {
+            None
+        }
+    }
+
+    fn command(&mut self, idx: u16, arg: u32) -> Reply {
+        if let Some(reply) = self.sd_command(idx, arg) {
+            return reply;
+        }
+        match (idx, self.state) {
+            (MMC_GO_IDLE_STATE, MMC_R1_STATE_IDLE) if arg == MMC_BOOT_INITIATION_ARG => {
+                self.boot();
+                Reply::None
+            }
+            (MMC_GO_IDLE_STATE, _) => {
+                if arg == 0 {
+                    self.reset();
+                }
+                Reply::None
+            }
+            (SD_APP_CMD, _) if self.rca == 0 || self.addressed(arg) => {
+                let app_cmd = if self.mmc_app_cmd { MMC_R1_APP_CMD } else { 0 };
+                Reply::Short(self.status() | app_cmd)
+            }
+            (MMC_SEND_OP_COND, MMC_R1_STATE_IDLE | MMC_R1_STATE_READY) => {
+                let mut ocr = MMC_OCR_VDD_27_36 | MMC_OCR_VDD_170_195;
+                if self.high_capacity {
+                    ocr |= MMC_OCR_ACCESS_MODE_SECTOR;
+                }
+                if self.power_up_polls > 0 {
+                    self.power_up_polls -= 1;
+                } else {
+                    ocr |= MMC_OCR_BUSY;
+                    self.state = MMC_R1_STATE_READY;
+                }
+                Reply::Short(ocr)
+            }
+            (MMC_ALL_SEND_CID, MMC_R1_STATE_READY) => {
+                self.state = MMC_R1_STATE_IDENT;
+                Reply::Long(self.cid)
+            }
+            (MMC_SET_RELATIVE_ADDR, MMC_R1_STATE_IDENT) => {
+                let status = self.status();
+                self.rca = (arg >> 16) as u16;
+                self.state = MMC_R1_STATE_STBY;
+                Reply::Short(status)
+            }
+            (MMC_SEND_CSD, MMC_R1_STATE_STBY) if self.addressed(arg) => Reply::Long(self.csd),
+            (MMC_SELECT_CARD, MMC_R1_STATE_STBY | MMC_R1_STATE_TRAN) => {
+                if self.addressed(arg) {
+                    let status = self.status();
+                    self.state = MMC_R1_STATE_TRAN;
+                    Reply::Short(status)
+                } else {
+                    self.state = MMC_R1_STATE_STBY;
+                    Reply::None
+                }
+            }
+            (MMC_SEND_STATUS, MMC_R1_STATE_PRG) if self.addressed(arg) && arg & MMC_HPI_BIT != 0 => self.hpi(),
+            (MMC_SEND_STATUS, _) if self.addressed(arg) => {
+                let status = self.status();
+                self.program();
+                Reply::Short(status)
+            }
+            (MMC_SEND_EXT_CSD, MMC_R1_STATE_TRAN) => {
+                let status = self.status();
+                self.state = MMC_R1_STATE_DATA;
+                self.xfer = Some(CardXfer::ExtCsd);
+                Reply::Short(status)
+            }
+            (MMC_SWITCH, MMC_R1_STATE_TRAN) => {
+                let status = self.status();
+                // A rejected switch is reported with the next status.
+                self.switch(arg);
+                Reply::Short(status)
+            }
+            (MMC_STOP_TRANSMISSION, MMC_R1_STATE_PRG) if self.addressed(arg) && arg & MMC_HPI_BIT != 0 => self.hpi(),
+            (MMC_STOP_TRANSMISSION, MMC_R1_STATE_DATA | MMC_R1_STATE_RCV) => {
+                let status = self.status();
+                self.xfer = None;
+                self.state = MMC_R1_STATE_TRAN;
+                Reply::Short(status)
+            }
+            (MMC_SET_BLOCKLEN, MMC_R1_STATE_TRAN) if arg != BLOCK_SIZE as u32 => self.fail(MMC_R1_BLOCK_LEN_ERROR),
+            (MMC_SET_BLOCKLEN, MMC_R1_STATE_TRAN) => Reply::Short(self.status()),
+            (MMC_SET_BLOCK_COUNT, MMC_R1_STATE_TRAN) => {
+                self.block_count = Some(arg as u16);
+                self.forced_prg = arg & MMC_CMD23_FORCED_PRG != 0;
+                self.reliable = arg & MMC_CMD23_REL_WRITE != 0;
+                Reply::Short(self.status())
+            }
+            (MMC_READ_SINGLE_BLOCK | MMC_READ_MULTIPLE_BLOCK | MMC_WRITE_BLOCK | MMC_WRITE_MULTIPLE_BLOCK,
+             MMC_R1_STATE_TRAN) => self.start_xfer(idx, arg),
+            (MMC_ERASE_GROUP_START | MMC_ERASE_GROUP_END, MMC_R1_STATE_TRAN) => {
+                self.erase_bound(idx == MMC_ERASE_GROUP_START, arg)
+            }
+            (MMC_ERASE, MMC_R1_STATE_TRAN) => self.erase(arg),
+            (MMC_GEN_CMD, MMC_R1_STATE_TRAN) if arg & MMC_GEN_CMD_RD != 0 => {
+                Reply::Short(self.send_register(self.health_report.to_vec()))
+            }
+            (MMC_SET_WRITE_PROT | MMC_CLR_WRITE_PROT, MMC_R1_STATE_TRAN) => {
+                self.write_protect(idx == MMC_SET_WRITE_PROT, arg)
+            }
+            (MMC_SEND_WRITE_PROT | MMC_SEND_WRITE_PROT_TYPE, MMC_R1_STATE_TRAN) => {
+                let Some(lba) = self.lba(arg) else {
+                    return self.fail(MMC_R1_ADDRESS_ERROR);
+                };
+                let first = lba / SIM_WP_GROUP_BLOCKS;
+                let wp = |i: u64| self.wp.get(&(first + i)).copied().unwrap_or(0) as u64;
+                let data = if idx == MMC_SEND_WRITE_PROT {
+                    let bits = (0..32).fold(0u32, |bits, i| bits | ((wp(i) != 0) as u32) << i);
+                    bits.to_be_bytes().to_vec()
+                } else {
+                    (0..32).fold(0u64, |bits, i| bits | wp(i) << (2 * i)).to_be_bytes().to_vec()
+                };
+                Reply::Short(self.send_register(data))
+            }
+            _ => {
+                // Illegal commands are not answered and reported with the next status.
+                self.pending |= MMC_R1_ILLEGAL_COMMAND;
+                Reply::None
+            }
+        }
+    }
+
+    /// Handle the commands of an SD card that differ from the eMMC ones.
+    ///
+    /// Returns `None` for an eMMC device and for the commands both have in common.
+    fn sd_command(&mut self, idx: u16, arg: u32) -> Option<Reply> {
+        let sd = self.sd.as_mut()?;
+        let (v1, uhs, if_cond, s18a) = (sd.v1, sd.uhs, sd.if_cond, sd.s18a);
+        let (voltage, access_mode) = (sd.voltage, sd.access_mode);
+        let app = core::mem::take(&mut sd.app_cmd);
+
+        let reply = match (idx, self.state, app) {
+            (SD_SEND_IF_COND, MMC_R1_STATE_IDLE, false) if !v1 => {
+                if arg >> 8 & 0x0f != 1 {
+                    // Unsupported voltage range.
+                    return Some(Reply::None);
+                }
+                self.sd_mut().if_cond = true;
+                Reply::Short(arg & 0xfff)
+            }
+            (SD_APP_CMD, _, _) if self.rca == 0 || self.addressed(arg) => {
+                self.sd_mut().app_cmd = true;
+                Reply::Short(self.status() | MMC_R1_APP_CMD)
+            }
+            // An inquiry, which does not start the power up.
+            (SD_APP_SEND_OP_COND, MMC_R1_STATE_IDLE | MMC_R1_STATE_READY, true) if arg & MMC_OCR_VDD_27_36 == 0 => {
+                Reply::Short(MMC_OCR_VDD_27_36)
+            }
+            (SD_APP_SEND_OP_COND, MMC_R1_STATE_IDLE | MMC_R1_STATE_READY, true) => {
+                let mut ocr = MMC_OCR_VDD_27_36;
+                // A high capacity card stays busy for a host that does not support it.
+                let accepted = !self.high_capacity || (if_cond && arg & SD_OCR_CCS != 0);
+                if self.power_up_polls > 0 {
+                    self.power_up_polls -= 1;
+                } else if accepted {
+                    ocr |= MMC_OCR_BUSY;
+                    if self.high_capacity {
+                        ocr |= SD_OCR_CCS;
+                    }
+                    // A card already at 1.8V does not switch again.
+                    let switch = uhs && arg & SD_OCR_S18R != 0 && voltage == SdVoltage::V33;
+                    if switch {
+                        ocr |= SD_OCR_S18R;
+                    }
+                    self.sd_mut().s18a = switch;
+                    self.state = MMC_R1_STATE_READY;
+                }
+                Reply::Short(ocr)
+            }
+            (SD_VOLTAGE_SWITCH, MMC_R1_STATE_READY, false) if s18a => {
+                self.sd_mut().voltage = SdVoltage::Switching { clock_stopped: false };
+                Reply::Short(self.status())
+            }
+            (SD_SEND_TUNING_BLOCK, MMC_R1_STATE_TRAN, false)
+                if voltage == SdVoltage::V18
+                    && matches!(access_mode, SD_SWITCH_ACCESS_MODE_SDR50 | SD_SWITCH_ACCESS_MODE_SDR104) =>
+            {
+                self.sd_mut().tuning_blocks += 1;
+                Reply::Short(self.send_register(SD_TUNING_PATTERN.to_vec()))
+            }
+            (SD_SEND_RELATIVE_ADDR, MMC_R1_STATE_IDENT | MMC_R1_STATE_STBY, false) => {
+                let status = self.status();
+                self.rca = SIM_SD_RCA;
+                self.state = MMC_R1_STATE_STBY;
+                // R6 carries bits 23, 22, 19 and 12:0 of the card status.
+                let bits = (status >> 8 & 0xc000) | (status >> 6 & 0x2000) | (status & 0x1fff);
+                Reply::Short((self.rca as u32) << 16 | bits)
+            }
+            (SD_APP_SET_BUS_WIDTH, MMC_R1_STATE_TRAN, true) => match arg & 0x03 {
+                0 | 2 => {
+                    self.sd_mut().bus_width = if arg & 0x03 == 2 { 4 } else { 1 };
+                    Reply::Short(self.status() | MMC_R1_APP_CMD)
+                }
+                _ => self.fail(MMC_R1_ERROR),
+            },
+            (SD_APP_SD_STATUS, MMC_R1_STATE_TRAN, true) => {
+                let mut sd_status = self.sd_status;
+                let width = if self.sd_mut().bus_width == 4 { 2 } else { 0 };
+                set_be_bits(&mut sd_status, 510, 2, width);
+                Reply::Short(self.send_register(sd_status.to_vec()) | MMC_R1_APP_CMD)
+            }
+            (SD_APP_SEND_SCR, MMC_R1_STATE_TRAN, true) => {
+                Reply::Short(self.send_register(self.scr.to_vec()) | MMC_R1_APP_CMD)
+            }
+            (SD_ERASE_WR_BLK_START | SD_ERASE_WR_BLK_END, MMC_R1_STATE_TRAN, false) => {
+                self.erase_bound(idx == SD_ERASE_WR_BLK_START, arg)
+            }
+            (SD_SWITCH_FUNC, MMC_R1_STATE_TRAN, false) => {
+                let status = self.switch_function(arg);
+                Reply::Short(self.send_register(status.to_vec()))
+            }
+            (MMC_SEND_OP_COND | SD_SEND_IF_COND | SD_VOLTAGE_SWITCH | SD_SEND_TUNING_BLOCK | MMC_ERASE_GROUP_START
+            | MMC_ERASE_GROUP_END, _, _) => {
+                self.pending |= MMC_R1_ILLEGAL_COMMAND;
+                return Some(Reply::None);
+            }
+            _ => return None,
+        };
+        Some(reply)
+    }
+
+    fn sd_mut(&mut self) -> &mut SdState {
+        self.sd.as_mut().expect("SD card")
+    }
+
+    /// Start sending `data` as the single block of a read, returning the card status.
+    fn send_register(&mut self, data: Vec<u8>) -> u32 {
+        let status = self.status();
+        self.state = MMC_R1_STATE_DATA;
+        self.xfer = Some(CardXfer::Register(data));
+        status
+    }
+
+    /// CMD6 of an SD card: check or switch the functions of the argument and return the status.
+    ///
+    /// Only the access mode group has functions besides the default one: high speed, and at
+    /// 1.8V the UHS-I modes of a UHS-I card.
+    fn switch_function(&mut self, arg: u32) -> [u8; 64] {
+        let switch = arg & SD_SWITCH_MODE_SWITCH != 0;
+        let mut status = [0u8; 64];
+        set_be_bits(&mut status, 496, 16, 200); // Maximum current, mA
+        set_be_bits(&mut status, 368, 8, 1); // Data structure version
+
+        let sd = self.sd_mut();
+        let access_modes = if sd.uhs && sd.voltage == SdVoltage::V18 { 0x801f } else { 0x8003 };
+        let access_mode = sd.access_mode;
+        let mut selected = [0u8; 6];
+        for (group, selected) in selected.iter_mut().enumerate() {
+            let supported = if group == 0 { access_modes } else { 0x8001 };
+            set_be_bits(&mut status, 400 + 16 * group, 16, supported);
+            let current = if group == 0 { access_mode } else { 0 };
+            *selected = match (arg >> (4 * group) & 0x0f) as u8 {
+                0x0f => current,
+                func if supported & 1 << func != 0 => func,
+                _ => 0x0f,
+            };
+            set_be_bits(&mut status, 376 + 4 * group, 4, *selected as u32);
+        }
+        if switch && !selected.contains(&0x0f) {
+            self.sd_mut().access_mode = selected[0];
+        }
+        status
+    }
+
+    fn switch(&mut self, arg: u32) {
+        let mode = arg >> 24 & 0x03;
+        let index = (arg >> 16 & 0xff) as usize;
+        let value = (arg >> 8) as u8;
+
+        let old = self.ext_csd[index];
+        let new = match mode {
+            MMC_SWITCH_MODE_SET_BITS => old | value,
+            MMC_SWITCH_MODE_CLEAR_BITS => old & !value,
+            MMC_SWITCH_MODE_WRITE_BYTE => value,
+            _ => {
+                self.pending |= MMC_R1_SWITCH_ERROR;
+                return;
+            }
+        };
+        let partitioned = self.ext_csd[EXT_CSD_PARTITION_SETTING_COMPLETED] & 0x01 != 0;
+        let valid = match index {
+            EXT_CSD_BUS_WIDTH => matches!(new, 0 | 1 | 2 | 5 | 6),
+            EXT_CSD_HS_TIMING => new & 0x0f <= 3,
+            EXT_CSD_PARTITION_CONFIG => new & 0x07 <= 3,
+            EXT_CSD_BOOT_BUS_CONDITIONS => new & !0x1f == 0 && new & 0x03 != 3 && new >> 3 != 3,
+            EXT_CSD_BOOT_CONFIG_PROT => new & !(EXT_CSD_PWR_BOOT_CONFIG_PROT | EXT_CSD_PERM_BOOT_CONFIG_PROT) == 0,
+            EXT_CSD_BOOT_WP_STATUS | EXT_CSD_FFU_STATUS | EXT_CSD_EXCEPTION_EVENTS_STATUS..=55 => false,
+            EXT_CSD_BKOPS_EN => self.ext_csd[EXT_CSD_BKOPS_SUPPORT] & 0x01 != 0 && new & !0x03 == 0,
+            // Bit 0 is reserved, URGENT_BKOPS cannot be masked.
+            EXT_CSD_EXCEPTION_EVENTS_CTRL => new & EXT_CSD_URGENT_BKOPS == 0,
+            EXT_CSD_BKOPS_START => self.ext_csd[EXT_CSD_BKOPS_EN] & EXT_CSD_MANUAL_BKOPS_EN != 0 && new == 1,
+            EXT_CSD_MODE_CONFIG => {
+                new <= 1 && self.ext_csd[EXT_CSD_SUPPORTED_MODES] & 0x01 != 0 && self.ext_csd[EXT_CSD_FFU_CONFIG] & 0x01 == 0
+            }
+            EXT_CSD_MODE_OPERATION_CODES => {
+                self.ext_csd[EXT_CSD_MODE_CONFIG] == 1
+                    && self.ext_csd[EXT_CSD_FFU_FEATURES] & 0x01 != 0
+                    && matches!(new, EXT_CSD_FFU_INSTALL | EXT_CSD_FFU_ABORT)
+            }
+            // The partitioning attributes are one-time.
+            EXT_CSD_EXT_PARTITIONS_ATTRIBUTE..=53 | EXT_CSD_ENH_START_ADDR..=EXT_CSD_PARTITIONS_ATTRIBUTE => {
+                !partitioned && (index != EXT_CSD_PARTITION_SETTING_COMPLETED || new == 1)
+            }
+            EXT_CSD_CMDQ_MODE_EN => self.ext_csd[EXT_CSD_CMDQ_SUPPORT] & 0x01 != 0 || new == 0,
+            EXT_CSD_SANITIZE_START => self.ext_csd[EXT_CSD_SEC_FEATURE_SUPPORT] & EXT_CSD_SEC_SANITIZE != 0 && new == 1,
+            EXT_CSD_CACHE_CTRL => self.ext_csd[EXT_CSD_CACHE_SIZE..EXT_CSD_CACHE_SIZE + 4] != [0; 4] || new == 0,
+            EXT_CSD_BARRIER_CTRL => self.ext_csd[EXT_CSD_BARRIER_SUPPORT] & 0x01 != 0 || new == 0,
+            EXT_CSD_FLUSH_CACHE => match new {
+                EXT_CSD_FLUSH => true,
+                EXT_CSD_BARRIER => self.ext_csd[EXT_CSD_BARRIER_CTRL] & 0x01 != 0,
+                _ => false,
+            },
+            _ => index < EXT_CSD_PROPERTIES_START,
+        };
+        if !valid {
+            self.pending |= MMC_R1_SWITCH_ERROR;
+            return;
+        }
+        // A locked boot configuration is left as it is, only the partition access changes.
+        let boot_locked = self.ext_csd[EXT_CSD_BOOT_CONFIG_PROT] != 0;
+        match index {
+            EXT_CSD_PARTITION_CONFIG if boot_locked => self.ext_csd[index] = old & !0x07 | new & 0x07,
+            EXT_CSD_BOOT_BUS_CONDITIONS if boot_locked => {}
+            // Protection bits are only cleared by a power cycle, or never.
+            EXT_CSD_BOOT_CONFIG_PROT => self.ext_csd[index] = old | new,
+            EXT_CSD_USER_WP => self.ext_csd[index] = new | old & (EXT_CSD_US_PWR_WP_DIS | EXT_CSD_US_PERM_WP_DIS),
+            EXT_CSD_BOOT_WP => self.boot_wp(new | old & (EXT_CSD_B_PWR_WP_DIS | EXT_CSD_B_PERM_WP_DIS)),
+            // Entering FFU mode starts a new download.
+            EXT_CSD_MODE_CONFIG => {
+                if new == 1 && old == 0 {
+                    self.firmware.clear();
+                    self.ext_csd[EXT_CSD_NUMBER_OF_FW_SECTORS_CORRECTLY_PROGRAMMED..][..4].fill(0);
+                }
+                self.ext_csd[index] = new;
+            }
+            // The operation code is not kept, and the device is back in the normal mode.
+            EXT_CSD_MODE_OPERATION_CODES if new == EXT_CSD_FFU_INSTALL => self.install_firmware(),
+            EXT_CSD_MODE_OPERATION_CODES => {
+                self.firmware.clear();
+                self.ext_csd[EXT_CSD_MODE_CONFIG] = 0;
+            }
+            // MANUAL_EN is one-time programmable.
+            EXT_CSD_BKOPS_EN => self.ext_csd[index] = new | old & EXT_CSD_MANUAL_BKOPS_EN,
+            EXT_CSD_BKOPS_START => {
+                self.busy_polls = SIM_BKOPS_POLLS;
+                self.busy = Some(Busy::Bkops);
+                self.state = MMC_R1_STATE_PRG;
+            }
+            EXT_CSD_SANITIZE_START => {
+                // Busy until the sanitize is done, SANITIZE_START reads back as 0.
+                self.busy_polls = SIM_SANITIZE_POLLS;
+                self.busy = Some(Busy::Sanitize);
+                self.state = MMC_R1_STATE_PRG;
+            }
+            // Writing the cache back is immediate, and a barrier only orders it.
+            EXT_CSD_FLUSH_CACHE if new == EXT_CSD_FLUSH => self.flush_cache(),
+            EXT_CSD_FLUSH_CACHE => self.barriers += 1,
+            EXT_CSD_CACHE_CTRL if new & 0x01 == 0 => {
+                self.flush_cache();
+                self.ext_csd[index] = new;
+            }
+            _ => self.ext_csd[index] = new,
+        }
+    }
+
+    fn start_xfer(&mut self, idx: u16, arg: u32) -> Reply {
+        let read = idx == MMC_READ_SINGLE_BLOCK || idx == MMC_READ_MULTIPLE_BLOCK;
+        let single = idx == MMC_READ_SINGLE_BLOCK || idx == MMC_WRITE_BLOCK;
+        let count = self.block_count.take();
+        let forced = core::mem::take(&mut self.forced_prg);
+        let reliable = core::mem::take(&mut self.reliable);
+        let part = self.partition();
+
+        let xfer = if self.ext_csd[EXT_CSD_MODE_CONFIG] == 1 {
+            // In FFU mode only the firmware download to FFU_ARG is accepted.
+            if read || arg != SIM_FFU_ARG {
+                return self.fail(MMC_R1_ERROR);
+            }
+            CardXfer::Firmware { left: if single { Some(1) } else { count } }
+        } else if part == Partition::Rpmb {
+            // Every RPMB access is a CMD18 or CMD25 of a number of frames set with CMD23.
+            match count {
+                Some(count) if !single && count > 0 => CardXfer::Rpmb { left: count },
+                _ => return self.fail(MMC_R1_ERROR),
+            }
+        } else {
+            let count = if single { Some(1) } else { count };
+            let Some(lba) = self.lba(arg) else {
+                return self.fail(MMC_R1_ADDRESS_ERROR);
+            };
+            if lba + count.unwrap_or(1) as u64 > self.blocks(part) {
+                return self.fail(MMC_R1_OUT_OF_RANGE);
+            }
+            if !read && (lba..lba + count.unwrap_or(1) as u64).any(|lba| self.protected(part, lba)) {
+                return self.fail(MMC_R1_WP_VIOLATION);
+            }
+            if reliable && !read {
+                // The legacy mode only writes one sector or an aligned REL_WR_SEC_C of them reliably.
+                let unit = self.ext_csd[EXT_CSD_REL_WR_SEC_C] as u64;
+                let legacy = self.ext_csd[EXT_CSD_WR_REL_PARAM] & EXT_CSD_EN_REL_WR == 0;
+                let count = count.unwrap_or(0) as u64;
+                if unit == 0 || legacy && count != 1 && (count != unit || !lba.is_multiple_of(unit)) {
+                    return self.fail(MMC_R1_ERROR);
+                }
+                self.reliable_writes += 1;
+            }
+            CardXfer::Blocks { part, lba, left: count, forced }
+        };
+
+        let status = self.status();
+        self.state = if read { MMC_R1_STATE_DATA } else { MMC_R1_STATE_RCV };
+        self.xfer = Some(xfer);
+        self.received = 0;
+        Reply::Short(status)
+    }
+
+    /// Send the next block of the data phase. Returns false if the device is not sending.
+    fn send_block(&mut self, buf: &mut [u8]) -> bool {
+        if self.state != MMC_R1_STATE_DATA {
+            return false;
+        }
+        let done = match &mut self.xfer {
+            Some(CardXfer::ExtCsd) => {
+                buf.copy_from_slice(&self.ext_csd);
+                true
+            }
+            Some(CardXfer::Register(data)) => {
+                let len = buf.len().min(data.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                true
+            }
+            Some(CardXfer::Rpmb { left }) => {
+                self.rpmb.read_frame(buf);
+                *left -= 1;
+                *left == 0
+            }
+            Some(CardXfer::Blocks { part, lba, left, .. }) => {
+                let (part, block) = (*part, *lba);
+                *lba += 1;
+                if let Some(left) = left {
+                    *left -= 1;
+                }
+                let done = *left == Some(0);
+                if block >= self.blocks(part) {
+                    self.pending |= MMC_R1_OUT_OF_RANGE;
+                    buf.fill(0);
+                } else if let Some(cached) = self.cache.get(&block).filter(|_| part == Partition::User) {
+                    buf.copy_from_slice(cached);
+                } else {
+                    self.read_block(part, block, buf);
+                }
+                done
+            }
+            Some(CardXfer::Firmware { .. }) | None => return false,
+        };
+        if done {
+            self.xfer = None;
+            self.state = MMC_R1_STATE_TRAN;
+        }
+        true
+    }
+
+    /// Program the next block of the data phase. Returns false if the device is not receiving.
+    fn receive_block(&mut self, buf: &[u8]) -> bool {
+        if self.state != MMC_R1_STATE_RCV {
+            return false;
+        }
+        let done = match &mut self.xfer {
+            Some(CardXfer::Rpmb { left }) => {
+                self.rpmb.write_frame(buf);
+                *left -= 1;
+                *left == 0
+            }
+            Some(CardXfer::Firmware { left }) => {
+                if let Some(left) = left {
+                    *left -= 1;
+                }
+                let done = *left == Some(0);
+                self.firmware.extend_from_slice(buf);
+                let sectors = &mut self.ext_csd[EXT_CSD_NUMBER_OF_FW_SECTORS_CORRECTLY_PROGRAMMED..][..4];
+                let count = u32::from_le_bytes(sectors.try_into().unwrap()) + 1;
+                sectors.copy_from_slice(&count.to_le_bytes());
+                done
+            }
+            Some(CardXfer::Blocks { part, lba, left, forced }) => {
+                let (part, block, forced) = (*part, *lba, *forced);
+                *lba += 1;
+                if let Some(left) = left {
+                    *left -= 1;
+                }
+                let done = *left == Some(0);
+                self.received += 1;
+                if block >= self.blocks(part) {
+                    self.pending |= MMC_R1_OUT_OF_RANGE;
+                } else if part == Partition::User && self.ext_csd[EXT_CSD_CACHE_CTRL] & 0x01 != 0 && !forced {
+                    self.cache.insert(block, buf.to_vec());
+                } else {
+                    if part == Partition::User {
+                        self.cache.remove(&block);
+                    }
+                    self.write_block(part, block, buf);
+                }
+                done
+            }
+            _ => return false,
+        };
+        if done {
+            self.xfer = None;
+            self.state = MMC_R1_STATE_TRAN;
+            if self.received > 0 {
+                self.start_programming(Busy::Program(self.received));
+            }
+        }
+        true
+    }
+
+    /// Whether writes to `lba` of `part` are refused.
+    fn protected(&self, part: Partition, lba: u64) -> bool {
+        let status = self.ext_csd[EXT_CSD_BOOT_WP_STATUS];
+        match part {
+            Partition::User => self.wp.contains_key(&(lba / SIM_WP_GROUP_BLOCKS)),
+            Partition::Boot1 => status & 0x03 != 0,
+            Partition::Boot2 => status >> 2 & 0x03 != 0,
+            Partition::Rpmb => false,
+        }
+    }
+
+    /// CMD28 or CMD29 on the write protect group of `arg` in the user data area, CMD28
+    /// protecting as USER_WP asks and CMD29 only clearing a temporary protection.
+    fn write_protect(&mut self, set: bool, arg: u32) -> Reply {
+        let Some(lba) = self.lba(arg) else {
+            return self.fail(MMC_R1_ADDRESS_ERROR);
+        };
+        if lba >= self.blocks(Partition::User) {
+            return self.fail(MMC_R1_OUT_OF_RANGE);
+        }
+        let group = lba / SIM_WP_GROUP_BLOCKS;
+        let current = self.wp.get(&group).copied().unwrap_or(0);
+        let user_wp = self.ext_csd[EXT_CSD_USER_WP];
+        if !set {
+            if current > 1 {
+                return self.fail(MMC_R1_WP_VIOLATION);
+            }
+            self.wp.remove(&group);
+        } else if user_wp & EXT_CSD_US_PERM_WP_EN != 0 {
+            self.wp.insert(group, 3);
+        } else if user_wp & EXT_CSD_US_PWR_WP_EN != 0 {
+            self.wp.insert(group, current.max(2));
+        } else {
+            self.wp.insert(group, current.max(1));
+        }
+        Reply::Short(self.status())
+    }
+
+    /// Write BOOT_WP, protecting the boot areas it selects in BOOT_WP_STATUS unless disabled.
+    fn boot_wp(&mut self, boot_wp: u8) {
+        self.ext_csd[EXT_CSD_BOOT_WP] = boot_wp;
+        let kinds = [
+            (EXT_CSD_B_PWR_WP_EN, EXT_CSD_B_PWR_WP_SEC_SEL, EXT_CSD_B_PWR_WP_DIS, 1),
+            (EXT_CSD_B_PERM_WP_EN, EXT_CSD_B_PERM_WP_SEC_SEL, EXT_CSD_B_PERM_WP_DIS, 2),
+        ];
+        for (enable, select, disabled, wp) in kinds {
+            if boot_wp & enable == 0 || boot_wp & disabled != 0 {
+                continue;
+            }
+            let shifts: &[u8] = match (boot_wp & EXT_CSD_B_SEC_WP_SEL != 0, boot_wp & select != 0) {
+                (false, _) => &[0, 2],
+                (true, false) => &[0],
+                (true, true) => &[2],
+            };
+            for &shift in shifts {
+                let status = self.ext_csd[EXT_CSD_BOOT_WP_STATUS];
+                if status >> shift & 0x03 < wp {
+                    self.ext_csd[EXT_CSD_BOOT_WP_STATUS] = status & !(0x03 << shift) | wp << shift;
+                }
+            }
+        }
+    }
+
+    /// CMD38, erasing the groups set with CMD35 and CMD36, or only the blocks for trim and discard.
+    /// CMD35/CMD36, or CMD32/CMD33 of SD cards, setting the first or the last block to erase.
+    fn erase_bound(&mut self, start: bool, arg: u32) -> Reply {
+        let part = self.partition();
+        match self.lba(arg) {
+            Some(lba) if part != Partition::Rpmb && lba < self.blocks(part) => {
+                if start {
+                    self.erase_start = Some(lba);
+                    self.erase_end = None;
+                } else {
+                    self.erase_end = Some(lba);
+                }
+                Reply::Short(self.status())
+            }
+            _ => {
+                self.erase_start = None;
+                self.erase_end = None;
+                self.fail(MMC_R1_OUT_OF_RANGE)
+            }
+        }
+    }
+
+    fn erase(&mut self, arg: u32) -> Reply {
+        let (Some(start), Some(end)) = (self.erase_start.take(), self.erase_end.take()) else {
+            return self.fail(MMC_R1_ERASE_SEQ_ERROR);
+        };
+        if end < start {
+            return self.fail(MMC_R1_ERASE_PARAM);
+        }
+        let sec = self.ext_csd[EXT_CSD_SEC_FEATURE_SUPPORT];
+        let supported = match (self.sd.is_some(), arg) {
+            (true, SD_ERASE_ARG | SD_DISCARD_ARG) => true,
+            (false, MMC_ERASE_ARG | MMC_DISCARD_ARG) => true,
+            (false, MMC_TRIM_ARG) => sec & EXT_CSD_SEC_GB_CL_EN != 0,
+            (false, MMC_SECURE_ERASE_ARG) => sec & EXT_CSD_SEC_ER_EN != 0,
+            (false, MMC_SECURE_TRIM1_ARG | MMC_SECURE_TRIM2_ARG) => sec & EXT_CSD_SEC_ER_EN != 0 && sec & EXT_CSD_SEC_GB_CL_EN != 0,
+            _ => false,
+        };
+        if !supported {
+            return self.fail(MMC_R1_ERASE_PARAM);
+        }
+        if arg == MMC_SECURE_TRIM2_ARG {
+            // The blocks marked by the first step were already purged.
+            return Reply::Short(self.status());
+        }
+
+        let part = self.partition();
+        let (start, end) = if self.sd.is_none() && arg & 0x03 == 0 {
+            // Erase and secure erase of eMMC devices work on whole erase groups.
+            let group = SIM_ERASE_GROUP_BLOCKS;
+            (start / group * group, ((end / group + 1) * group).min(self.blocks(part)) - 1)
+        } else {
+            (start, end)
+        };
+
+        let fill = if self.ext_csd[EXT_CSD_ERASED_MEM_CONT] == 1 { 0xff } else { 0 };
+        let block = [fill; BLOCK_SIZE];
+        for lba in start..=end {
+            // Protected groups are skipped, which the device reports.
+            if self.protected(part, lba) {
+                self.pending |= MMC_R1_WP_ERASE_SKIP;
+                continue;
+            }
+            if part == Partition::User {
+                self.cache.remove(&lba);
+            }
+            self.write_block(part, lba, &block);
+        }
+        let status = self.status();
+        self.start_programming(Busy::Erase);
+        Reply::Short(status)
+    }
+}
+
+/// Set `len` bits starting at bit `start` of a 128-bit CID/CSD register, `reg[0]` holding bits 127:96.
+fn set_bits(reg: &mut [u32; 4], start: usize, len: usize, value: u32) {
+    for i in 0..len {
+        let bit = start + i;
+        let mask = 1 << (bit % 32);
+        if value >> i & 1 != 0 {
+            reg[3 - bit / 32] |= mask;
+        } else {
+            reg[3 - bit / 32] &= !mask;
+        }
+    }
+}
+
+/// Set `len` bits starting at bit `start` of a register sent most significant byte first.
+fn set_be_bits(reg: &mut [u8], start: usize, len: usize, value: u32) {
+    let bits = reg.len() * 8;
+    for i in 0..len {
+        let bit = start + i;
+        let byte = &mut reg[(bits - 1 - bit) / 8];
+        if value >> i & 1 != 0 {
+            *byte |= 1 << (bit % 8);
+        } else {
+            *byte &= !(1 << (bit % 8));
+        }
+    }
+}
+
+/// Sparse physical memory the DMA engines of the `Simulator` read and write.
+#[derive(Default)]
+pub struct SimMemory {
+    pages: BTreeMap<u64, Box<[u8; SIM_PAGE_SIZE as usize]>>,
+}
+
+impl SimMemory {
+    pub fn read(&self, addr: u64, buf: &mut [u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            let at = addr + done as u64;
+            let offset = (at % SIM_PAGE_SIZE) as usize;
+            let len = (SIM_PAGE_SIZE as usize - offset).min(buf.len() - done);
+            match self.pages.get(&(at / SIM_PAGE_SIZE)) {
+                Some(page) => buf[done..done + len].copy_from_slice(&page[offset..offset + len]),
+                None => buf[done..done + len].fill(0),
+            }
+            done += len;
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: &[u8]) {
+        let mut done = 0;
+        while done < data.len() {
+            let at = addr + done as u64;
+            let offset = (at % SIM_PAGE_SIZE) as usize;
+            let len = (SIM_PAGE_SIZE as usize - offset).min(data.len() - done);
+            let page = self.pages.entry(at / SIM_PAGE_SIZE).or_insert_with(|| Box::new([0; SIM_PAGE_SIZE as usize]));
+            page[offset..offset + len].copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+    }
+}
+
+/// Data phase moving through the buffer data port.
+struct PioXfer {
+    read: bool,
+    /// Blocks left, including the one in `buf`.
+    left: u32,
+    done: u32,
+    /// Bytes in a block, from the block size register.
+    size: usize,
+    buf: [u8; BLOCK_SIZE],
+    pos: usize,
+    fault: Option<Fault>,
+}
+
+/// The controller with the card attached to it.
+struct Controller {
+    regs: Vec<u8>,
+    card: SimCard,
+    memory: SimMemory,
+    xfer: Option<PioXfer>,
+    faults: Vec<(Option<u16>, Fault)>,
+    /// Tuning blocks received since Execute Tuning was set.
+    tuning_loops: u32,
+    /// The card is in the slot.
+    inserted: bool,
+    /// Commands written to the command register.
+    commands: u32,
+    /// Auto CMD12 and Auto CMD23 issued by the controller.
+    auto_cmds: u32,
+}
+
+impl Controller {
+    fn reg8(&self, offset: u64) -> u8 {
+        self.regs[offset as usize]
+    }
+
+    fn reg16(&self, offset: u64) -> u16 {
+        u16::from_le_bytes([self.regs[offset as usize], self.regs[offset as usize + 1]])
+    }
+
+    fn reg32(&self, offset: u64) -> u32 {
+        let o = offset as usize;
+        u32::from_le_bytes(self.regs[o..o + 4].try_into().unwrap())
+    }
+
+    fn set_reg(&mut self, offset: u64, width: usize, value: u32) {
+        let o = offset as usize;
+        self.regs[o..o + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+
+    /// Load the reset values of the registers.
+    fn reset_all(&mut self) {
+        self.regs.fill(0);
+        self.set_reg(EMMC_HOST_CTRL_VER_OFFSET, 2, EMMC_SPEC_VERSION_V420 as u32);
+        self.set_reg(EMMC_VER_ID_OFFSET, 4, 0x3138_302a);
+        self.set_reg(EMMC_CQVER_OFFSET, 4, 0x0510);
+        let caps = SIM_BASE_CLK_MHZ << EMMC_BASE_CLK_FREQ_POS | EMMC_EMBEDDED_8_BIT | EMMC_ADMA2_SUPPORT
+            | EMMC_HIGH_SPEED_SUPPORT | EMMC_SDMA_SUPPORT | EMMC_VOLT_33 | EMMC_VOLT_18;
+        self.set_reg(EMMC_CAPABILITIES1_OFFSET, 4, caps);
+        self.set_reg(EMMC_CAPABILITIES2_OFFSET, 4, EMMC_SDR50_SUPPORT | EMMC_SDR104_SUPPORT | EMMC_DDR50_SUPPORT);
+        self.xfer = None;
+        // Power Control is cleared as well.
+        self.card.power_off();
+        self.update();
+    }
+
+    /// Refresh the registers derived from the state of the model.
+    fn update(&mut self) {
+        let mut pstate = EMMC_CARD_STABLE | EMMC_DATA_LINE7_4_LEVEL;
+        if self.inserted {
+            pstate |= EMMC_CARD_INSERTED;
+        }
+        if !self.card.lines_low() {
+            pstate |= EMMC_CMD_LINE_LEVEL | EMMC_DATA_LINE3_0_LEVEL;
+        }
+        if let Some(xfer) = &self.xfer {
+            pstate |= EMMC_CMD_INHIBIT_DATA | EMMC_DATA_LINE_ACTIVE;
+            pstate |= if xfer.read { EMMC_BUF_RD_ENABLE } else { EMMC_BUF_WR_ENABLE };
+        }
+        self.set_reg(EMMC_PSTATE_OFFSET, 4, pstate);
+
+        let mut normal = self.reg16(EMMC_NORMAL_INT_STAT_OFFSET) & !EMMC_ERROR_INT;
+        if self.reg16(EMMC_ERROR_INT_STAT_OFFSET) != 0 {
+            normal |= EMMC_ERROR_INT;
+        }
+        self.set_reg(EMMC_NORMAL_INT_STAT_OFFSET, 2, normal as u32);
+    }
+
+    /// Set the normal interrupt status bits enabled in NORMAL_INT_STAT_EN.
+    fn raise(&mut self, bits: u16) {
+        let stat = self.reg16(EMMC_NORMAL_INT_STAT_OFFSET) | bits & self.reg16(EMMC_NORMAL_INT_EN_OFFSET);
+        self.set_reg(EMMC_NORMAL_INT_STAT_OFFSET, 2, stat as u32);
+    }
+
+    /// Set the error interrupt status bits enabled in ERROR_INT_STAT_EN.
+    fn raise_error(&mut self, bits: u16) {
+        let stat = self.reg16(EMMC_ERROR_INT_STAT_OFFSET) | bits & self.reg16(EMMC_ERROR_INT_EN_OFFSET);
+        self.set_reg(EMMC_ERROR_INT_STAT_OFFSET, 2, stat as u32);
+    }
+
+    fn read(&mut self, offset: u64, width: usize) -> u32 {
+        if offset == EMMC_BUF_DATA_OFFSET {
+            let value = self.read_buf();
+            self.update();
+            return value;
+        }
+        let o = offset as usize;
+        let mut bytes = [0u8; 4];
+        bytes[..width].copy_from_slice(&self.regs[o..o + width]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn write(&mut self, offset: u64, width: usize, value: u32) {
+        if offset == EMMC_BUF_DATA_OFFSET {
+            self.write_buf(value);
+            self.update();
+            return;
+        }
+
+        let range = offset..offset + width as u64;
+        let tuning = self.reg16(EMMC_HOST_CTRL2_OFFSET) & EMMC_EXEC_TUNING != 0;
+        let int_stat = EMMC_NORMAL_INT_STAT_OFFSET..EMMC_NORMAL_INT_STAT_OFFSET + 4;
+        let read_only = [
+            EMMC_RESP01_OFFSET..EMMC_BUF_DATA_OFFSET,
+            EMMC_PSTATE_OFFSET..EMMC_PSTATE_OFFSET + 4,
+            EMMC_AUTO_CMD_STAT_OFFSET..EMMC_AUTO_CMD_STAT_OFFSET + 2,
+            EMMC_CAPABILITIES1_OFFSET..EMMC_CAPABILITIES2_OFFSET + 4,
+        ];
+        for (i, byte) in value.to_le_bytes()[..width].iter().enumerate() {
+            let o = offset + i as u64;
+            if int_stat.contains(&o) {
+                self.regs[o as usize] &= !byte;
+            } else if !read_only.iter().any(|r| r.contains(&o)) {
+                self.regs[o as usize] = *byte;
+            }
+        }
+
+        if range.contains(&EMMC_SW_RST_OFFSET) {
+            let rst = self.reg8(EMMC_SW_RST_OFFSET);
+            if rst & EMMC_SW_RST_ALL != 0 {
+                self.reset_all();
+            }
+            if rst & EMMC_SW_RST_DATA != 0 {
+                self.xfer = None;
+            }
+            self.set_reg(EMMC_SW_RST_OFFSET, 1, 0);
+        }
+        if range.contains(&EMMC_CLK_CTRL_OFFSET) {
+            let mut clk = self.reg16(EMMC_CLK_CTRL_OFFSET) & !EMMC_INTERNAL_CLK_STABLE;
+            if clk & EMMC_INTERNAL_CLK_EN != 0 {
+                clk |= EMMC_INTERNAL_CLK_STABLE;
+            }
+            self.set_reg(EMMC_CLK_CTRL_OFFSET, 2, clk as u32);
+            let v18 = self.reg16(EMMC_HOST_CTRL2_OFFSET) & EMMC_SIGNALING_EN != 0;
+            self.card.clock(clk & EMMC_SD_CLK_EN != 0, v18);
+        }
+        if range.contains(&EMMC_PWR_CTRL_OFFSET) && self.reg8(EMMC_PWR_CTRL_OFFSET) & EMMC_PWR_ON == 0 {
+            self.card.power_off();
+        }
+        if range.contains(&EMMC_HOST_CTRL2_OFFSET) && !tuning {
+            self.tuning_loops = 0;
+        }
+        if range.contains(&EMMC_BOOT_CTRL_OFFSET) {
+            self.mandatory_boot();
+        }
+        // Writing the upper byte of the command register issues the command.
+        if range.contains(&(EMMC_CMD_OFFSET + 1)) {
+            self.issue();
+        }
+        self.update();
+    }
+
+    fn take_fault(&mut self, idx: u16, data: bool) -> Option<Fault> {
+        let pos = self.faults.iter().position(|&(cmd, fault)| {
+            cmd.is_none_or(|cmd| cmd == idx) && (data || !fault.needs_data())
+        })?;
+        Some(self.faults.remove(pos).1)
+    }
+
+    fn issue(&mut self) {
+        self.commands += 1;
+        let cmd = self.reg16(EMMC_CMD_OFFSET);
+        let idx = cmd >> EMMC_CMD_INDEX_POS & 0x3f;
+        let arg = self.reg32(EMMC_ARGUMENT_OFFSET);
+        let xfer_mode = self.reg16(EMMC_XFER_MODE_OFFSET);
+        let data = cmd & EMMC_DATA_PRESENT != 0;
+        let read = xfer_mode & EMMC_DATA_XFER_DIR_READ != 0;
+        let resp_type = cmd & EMMC_RESP_TYPE;
+
+        // An absent, unpowered or unclocked device does not see the command.
+        let powered = self.reg8(EMMC_PWR_CTRL_OFFSET) & EMMC_PWR_ON != 0;
+        let clocked = self.reg16(EMMC_CLK_CTRL_OFFSET) & EMMC_SD_CLK_EN != 0;
+        if !self.inserted || !powered || !clocked {
+            self.raise_error(EMMC_CMD_TOUT_ERR);
+            return;
+        }
+
+        let auto_cmd = xfer_mode & EMMC_AUTO_CMD_ENABLE;
+        if data && auto_cmd == EMMC_AUTO_CMD23_ENABLED {
+            let count = self.reg32(EMMC_SDMASA_OFFSET);
+            if !self.auto_cmd(MMC_SET_BLOCK_COUNT, count) {
+                return;
+            }
+        }
+
+        let fault = self.take_fault(idx, data);
+        match fault {
+            Some(Fault::CmdTimeout) => {
+                self.raise_error(EMMC_CMD_TOUT_ERR);
+                return;
+            }
+            Some(Fault::PowerLoss) if !data || read => {
+                self.card.power_off();
+                self.raise_error(EMMC_CMD_TOUT_ERR);
+                return;
+            }
+            _ => {}
+        }
+
+        match self.card.command(idx, arg) {
+            Reply::None if resp_type != EMMC_RESP_TYPE_NONE => {
+                self.raise_error(EMMC_CMD_TOUT_ERR);
+                return;
+            }
+            Reply::None => {}
+            Reply::Short(resp) => self.set_reg(EMMC_RESP01_OFFSET, 4, resp),
+            Reply::Long(resp) => {
+                let value = ((resp[0] as u128) << 96 | (resp[1] as u128) << 64 | (resp[2] as u128) << 32
+                    | resp[3] as u128) >> 8;
+                for i in 0..4 {
+                    self.set_reg(EMMC_RESP01_OFFSET + 4 * i, 4, (value >> (32 * i)) as u32);
+                }
+            }
+        }
+        if fault == Some(Fault::CmdCrc) {
+            self.raise_error(EMMC_CMD_CRC_ERR);
+            return;
+        }
+        self.raise(EMMC_CMD_COMPLETE);
+        if idx == MMC_GO_IDLE_STATE && data && !self.boot_acked() {
+            return;
+        }
+
+        if !data {
+            if resp_type == EMMC_RESP_TYPE_LEN_48_CHECK {
+                self.raise(EMMC_XFER_COMPLETE);
+            }
+            return;
+        }
+        if fault == Some(Fault::DataTimeout) {
+            self.raise_error(EMMC_DATA_TOUT_ERR);
+            return;
+        }
+        if self.reg16(EMMC_HOST_CTRL2_OFFSET) & EMMC_EXEC_TUNING != 0 && read {
+            return self.tune();
+        }
+        self.start_data(read, fault);
+    }
+
+    /// Start the data phase set up in the transfer mode register.
+    fn start_data(&mut self, read: bool, fault: Option<Fault>) {
+        let xfer_mode = self.reg16(EMMC_XFER_MODE_OFFSET);
+        let blocks = if xfer_mode & EMMC_MULTI_BLK_SEL == 0 {
+            1
+        } else if xfer_mode & EMMC_BLOCK_COUNT_ENABLE != 0 {
+            self.reg16(EMMC_BLOCKCOUNT_OFFSET) as u32
+        } else {
+            u32::MAX
+        };
+        if xfer_mode & EMMC_DMA_ENABLE != 0 {
+            self.dma(read, blocks, fault);
+        } else {
+            let size = match (self.reg16(EMMC_BLOCKSIZE_OFFSET) & EMMC_XFER_BLOCK_SIZE) as usize {
+                size @ 4..=BLOCK_SIZE => size,
+                _ => BLOCK_SIZE,
+            };
+            self.xfer = Some(PioXfer { read, left: blocks, done: 0, size, buf: [0; BLOCK_SIZE], pos: 0, fault });
+            if read {
+                self.load_block();
+            } else {
+                self.raise(EMMC_BUF_WR_READY);
+            }
+        }
+    }
+
+    /// Start or end a boot operation with the CMD line held low, as asked in BOOT_CTRL.
+    fn mandatory_boot(&mut self) {
+        let boot_ctrl = self.reg16(EMMC_BOOT_CTRL_OFFSET);
+        if boot_ctrl & EMMC_VALIDATE_BOOT == 0 {
+            return;
+        }
+        if boot_ctrl & EMMC_MAN_BOOT_EN == 0 {
+            self.xfer = None;
+            return self.card.end_boot();
+        }
+        let powered = self.reg8(EMMC_PWR_CTRL_OFFSET) & EMMC_PWR_ON != 0;
+        let clocked = self.reg16(EMMC_CLK_CTRL_OFFSET) & EMMC_SD_CLK_EN != 0;
+        if !self.inserted || !powered || !clocked {
+            return self.raise_error(EMMC_DATA_TOUT_ERR);
+        }
+        self.card.boot();
+        if self.boot_acked() {
+            self.start_data(true, None);
+        }
+    }
+
+    /// Check the boot acknowledge pattern if BOOT_CTRL asks for it, raising BOOT_ACK_ERR without it.
+    fn boot_acked(&mut self) -> bool {
+        if self.reg16(EMMC_BOOT_CTRL_OFFSET) & EMMC_BOOT_ACK_ENABLE != 0 && !self.card.boot_ack() {
+            self.raise_error(EMMC_BOOT_ACK_ERR);
+            return false;
+        }
+        true
+    }
+
+    /// Issue CMD12 or CMD23 as an Auto CMD, reporting a failure in AUTO_CMD_STAT.
+    fn auto_cmd(&mut self, idx: u16, arg: u32) -> bool {
+        self.auto_cmds += 1;
+        let error = match self.take_fault(idx, false) {
+            Some(Fault::CmdTimeout) => EMMC_AUTO_CMD_TOUT_ERR,
+            fault => match self.card.command(idx, arg) {
+                Reply::Short(_) if fault == Some(Fault::CmdCrc) => EMMC_AUTO_CMD_CRC_ERR,
+                Reply::Short(resp) => {
+                    self.set_reg(EMMC_RESP67_OFFSET, 4, resp);
+                    self.set_reg(EMMC_AUTO_CMD_STAT_OFFSET, 2, 0);
+                    return true;
+                }
+                _ => EMMC_AUTO_CMD_TOUT_ERR,
+            },
+        };
+        self.set_reg(EMMC_AUTO_CMD_STAT_OFFSET, 2, error as u32);
+        self.raise_error(EMMC_AUTO_CMD_ERR);
+        false
+    }
+
+    /// End the data phase, with Auto CMD12 if it is enabled.
+    fn complete(&mut self) {
+        let auto_cmd12 = self.reg16(EMMC_XFER_MODE_OFFSET) & EMMC_AUTO_CMD_ENABLE == EMMC_AUTO_CMD12_ENABLED;
+        if auto_cmd12 && !self.auto_cmd(MMC_STOP_TRANSMISSION, 0) {
+            return;
+        }
+        self.raise(EMMC_XFER_COMPLETE);
+    }
+
+    /// Sample the tuning block the device sends, finishing the tuning after `SIM_TUNING_LOOPS` of them.
+    fn tune(&mut self) {
+        let mut block = SD_TUNING_PATTERN;
+        if !self.card.send_block(&mut block) {
+            return self.raise_error(EMMC_DATA_TOUT_ERR);
+        }
+        self.raise(EMMC_BUF_RD_READY);
+        self.tuning_loops += 1;
+        if self.tuning_loops == SIM_TUNING_LOOPS {
+            let ctrl2 = self.reg16(EMMC_HOST_CTRL2_OFFSET) & !EMMC_EXEC_TUNING;
+            let tuned = if block == SD_TUNING_PATTERN { EMMC_SAMPLE_CLK_SEL } else { 0 };
+            self.set_reg(EMMC_HOST_CTRL2_OFFSET, 2, (ctrl2 | tuned) as u32);
+        }
+    }
+
+    /// Fill the buffer with the next block sent by the device.
+    fn load_block(&mut self) {
+        let Some(xfer) = &mut self.xfer else {
+            return;
+        };
+        if self.card.send_block(&mut xfer.buf[..xfer.size]) {
+            xfer.pos = 0;
+            self.raise(EMMC_BUF_RD_READY);
+        } else {
+            self.xfer = None;
+            self.raise_error(EMMC_DATA_TOUT_ERR);
+        }
+    }
+
+    fn read_buf(&mut self) -> u32 {
+        let Some(xfer) = self.xfer.as_mut().filter(|xfer| xfer.read && xfer.pos < xfer.size) else {
+            return 0;
+        };
+        let value = u32::from_le_bytes(xfer.buf[xfer.pos..xfer.pos + 4].try_into().unwrap());
+        xfer.pos += 4;
+        if xfer.pos == xfer.size {
+            xfer.left -= 1;
+            xfer.done += 1;
+            if xfer.fault == Some(Fault::DataCrc) {
+                self.xfer = None;
+                self.raise_error(EMMC_DATA_CRC_ERR);
+            } else if xfer.left > 0 {
+                self.load_block();
+            } else {
+                self.xfer = None;
+                self.complete();
+            }
+        }
+        value
+    }
+
+    fn write_buf(&mut self, value: u32) {
+        let Some(xfer) = self.xfer.as_mut().filter(|xfer| !xfer.read && xfer.pos < xfer.size) else {
+            return;
+        };
+        xfer.buf[xfer.pos..xfer.pos + 4].copy_from_slice(&value.to_le_bytes());
+        xfer.pos += 4;
+        if xfer.pos < xfer.size {
+            return;
+        }
+
+        let (buf, size, done, fault) = (xfer.buf, xfer.size, xfer.done, xfer.fault);
+        if let Err(error) = self.program(&buf[..size], done, fault) {
+            self.xfer = None;
+            self.raise_error(error);
+            return;
+        }
+        let Some(xfer) = &mut self.xfer else {
+            return;
+        };
+        xfer.done += 1;
+        xfer.left -= 1;
+        xfer.pos = 0;
+        if xfer.left > 0 {
+            self.raise(EMMC_BUF_WR_READY);
+        } else {
+            self.xfer = None;
+            self.complete();
+        }
+    }
+
+    /// Hand a written block to the device, applying the fault injected into the command.
+    ///
+    /// `done` blocks of the command were programmed before this one.
+    fn program(&mut self, block: &[u8], done: u32, fault: Option<Fault>) -> Result<(), u16> {
+        match fault {
+            Some(Fault::DataCrc) if done == 0 => return Err(EMMC_DATA_CRC_ERR),
+            Some(Fault::PowerLoss) if done >= self.write_len() / 2 => {
+                self.card.power_off();
+                return Err(EMMC_DATA_TOUT_ERR);
+            }
+            _ => {}
+        }
+        if self.card.receive_block(block) { Ok(()) } else { Err(EMMC_DATA_TOUT_ERR) }
+    }
+
+    /// Blocks of the write in progress, as programmed in the block count register.
+    fn write_len(&self) -> u32 {
+        let xfer_mode = self.reg16(EMMC_XFER_MODE_OFFSET);
+        if xfer_mode & EMMC_MULTI_BLK_SEL == 0 { 1 } else { self.reg16(EMMC_BLOCKCOUNT_OFFSET) as u32 }
+    }
+
+    /// Return the segments of the DMA transfer, from SDMASA or the ADMA2 descriptor table.
+    fn dma_segments(&mut self, len: u64) -> Result<Vec<(u64, u64)>, u16> {
+        let dma_sel = self.reg8(EMMC_HOST_CTRL1_OFFSET) & EMMC_DMA_SEL;
+        if dma_sel == EMMC_DMA_SEL_SDMA {
+            return Ok(vec![(self.reg32(EMMC_SDMASA_OFFSET) as u64, len)]);
+        }
+        if dma_sel != EMMC_DMA_SEL_ADMA2 {
+            return Err(EMMC_ADMA_ERR);
+        }
+
+        let mut segs = Vec::new();
+        let mut addr = (self.reg32(EMMC_ADMA_SA_HIGH_OFFSET) as u64) << 32 | self.reg32(EMMC_ADMA_SA_LOW_OFFSET) as u64;
+        for _ in 0..SIM_ADMA_MAX_DESCS {
+            let mut desc = [0u8; 8];
+            self.memory.read(addr, &mut desc);
+            let attr = u16::from_le_bytes([desc[0], desc[1]]);
+            let seg_len = match u16::from_le_bytes([desc[2], desc[3]]) {
+                0 => 0x10000,
+                seg_len => seg_len as u64,
+            };
+            let seg_addr = u32::from_le_bytes(desc[4..8].try_into().unwrap()) as u64;
+
+            if attr & 0x01 == 0 {
+                self.set_reg(EMMC_ADMA_SA_LOW_OFFSET, 4, addr as u32);
+                self.set_reg(EMMC_ADMA_SA_HIGH_OFFSET, 4, (addr >> 32) as u32);
+                self.set_reg(EMMC_ADMA_ERR_STAT_OFFSET, 1, EMMC_ADMA_ERR_STATES_ST_FDS as u32);
+                return Err(EMMC_ADMA_ERR);
+            }
+            match attr >> 4 & 0x03 {
+                // Transfer data
+                0b10 => {
+                    segs.push((seg_addr, seg_len));
+                    addr += 8;
+                }
+                // Link to another descriptor
+                0b11 => addr = seg_addr,
+                _ => addr += 8,
+            }
+            if attr & 0x02 != 0 {
+                return Ok(segs);
+            }
+        }
+        Err(EMMC_ADMA_ERR)
+    }
+
+    /// Move the whole data phase between the device and `memory`.
+    fn dma(&mut self, read: bool, blocks: u32, fault: Option<Fault>) {
+        let len = blocks as u64 * BLOCK_SIZE as u64;
+        let segs = match self.dma_segments(len) {
+            Ok(segs) => segs,
+            Err(error) => return self.raise_error(error),
+        };
+        let seg_len: u64 = segs.iter().map(|seg| seg.1).sum();
+        if blocks != u32::MAX && seg_len != len {
+            self.set_reg(EMMC_ADMA_ERR_STAT_OFFSET, 1, (EMMC_ADMA_LEN_ERR | EMMC_ADMA_ERR_STATES_ST_TFR) as u32);
+            return self.raise_error(EMMC_ADMA_ERR);
+        }
+
+        // Physical address of every byte of the transfer, in order.
+        let mut addrs = segs.iter().flat_map(|&(addr, len)| addr..addr + len);
+        let mut block = [0u8; BLOCK_SIZE];
+        for done in 0..(seg_len / BLOCK_SIZE as u64) as u32 {
+            let block_addrs: Vec<u64> = addrs.by_ref().take(BLOCK_SIZE).collect();
+            if read {
+                if !self.card.send_block(&mut block) {
+                    return self.raise_error(EMMC_DATA_TOUT_ERR);
+                }
+                for (byte, addr) in block.iter().zip(block_addrs) {
+                    self.memory.write(addr, core::slice::from_ref(byte));
+                }
+                if fault == Some(Fault::DataCrc) {
+                    return self.raise_error(EMMC_DATA_CRC_ERR);
+                }
+            } else {
+                for (byte, addr) in block.iter_mut().zip(block_addrs) {
+                    self.memory.read(addr, core::slice::from_mut(byte));
+                }
+                if let Err(error) = self.program(&block, done, fault) {
+                    return self.raise_error(error);
+                }
+            }
+        }
+        self.complete();
+    }
+}
+
+/// The DWC MSHC controller of the RK3568 with a `SimCard` attached, as an `Mmio` backend.
+///
+/// ```ignore
+/// let sim = Simulator::new(BASE, SimCard::new(8192));
+/// let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+/// sdhci.init()?;
+/// ```
+pub struct Simulator {
+    base: u64,
+    ctrl: RefCell<Controller>,
+}
+
+impl Simulator {
+    pub fn new(base: u64, card: SimCard) -> Self {
+        let mut ctrl = Controller {
+            regs: vec![0; SIM_REG_SPACE],
+            card,
+            memory: SimMemory::default(),
+            xfer: None,
+            faults: Vec::new(),
+            tuning_loops: 0,
+            inserted: true,
+            commands: 0,
+            auto_cmds: 0,
+        };
+        ctrl.reset_all();
+        Self { base, ctrl: RefCell::new(ctrl) }
+    }
+
+    /// The attached card, e.g. to inspect its content.
+    pub fn card(&self) -> RefMut<'_, SimCard> {
+        RefMut::map(self.ctrl.borrow_mut(), |ctrl| &mut ctrl.card)
+    }
+
+    /// The memory the DMA engines work on.
+    pub fn memory(&self) -> RefMut<'_, SimMemory> {
+        RefMut::map(self.ctrl.borrow_mut(), |ctrl| &mut ctrl.memory)
+    }
+
+    /// Inject `fault` into the next command with index `cmd`, or into the next command if `None`.
+    ///
+    /// Data faults only match commands with a data phase. Each call injects one fault.
+    pub fn inject_fault(&self, cmd: Option<u16>, fault: Fault) {
+        self.ctrl.borrow_mut().faults.push((cmd, fault));
+    }
+
+    /// Commands the driver issued through the command register.
+    pub fn commands(&self) -> u32 {
+        self.ctrl.borrow().commands
+    }
+
+    /// CMD12 and CMD23 the controller issued by itself as Auto CMD.
+    pub fn auto_cmds(&self) -> u32 {
+        self.ctrl.borrow().auto_cmds
+    }
+
+    /// Pull the card out of the slot, aborting the transfer in progress.
+    pub fn remove_card(&self) {
+        let mut ctrl = self.ctrl.borrow_mut();
+        ctrl.inserted = false;
+        ctrl.card.power_off();
+        ctrl.xfer = None;
+        ctrl.raise(EMMC_CARD_REMOVAL);
+        ctrl.update();
+    }
+
+    /// Put the card back into the slot.
+    pub fn insert_card(&self) {
+        let mut ctrl = self.ctrl.borrow_mut();
+        ctrl.inserted = true;
+        ctrl.raise(EMMC_CARD_INSERTION);
+        ctrl.update();
+    }
+
+    /// Raise a re-tuning event, as a controller whose sampling point drifted does.
+    pub fn retune_event(&self) {
+        let mut ctrl = self.ctrl.borrow_mut();
+        ctrl.raise(EMMC_RE_TUNE_EVENT);
+        ctrl.update();
+    }
+
+    /// Drop the power of the card, aborting the transfer in progress.
+    pub fn power_loss(&self) {
+        let mut ctrl = self.ctrl.borrow_mut();
+        ctrl.card.power_off();
+        ctrl.xfer = None;
+        ctrl.update();
+    }
+
+    /// Whether the interrupt line of the controller is asserted.
+    pub fn irq_pending(&self) -> bool {
+        let ctrl = self.ctrl.borrow();
+        ctrl.reg16(EMMC_NORMAL_INT_STAT_OFFSET) & ctrl.reg16(EMMC_NORMAL_INT_SIG_EN_OFFSET) != 0
+            || ctrl.reg16(EMMC_ERROR_INT_STAT_OFFSET) & ctrl.reg16(EMMC_ERROR_INT_SIG_EN_OFFSET) != 0
+    }
+
+    fn offset(&self, addr: u64, width: usize) -> u64 {
+        let offset = addr.wrapping_sub(self.base);
+        assert!(offset as usize + width <= SIM_REG_SPACE, "access outside of the controller at {:#x}", addr);
+        offset
+    }
+}
+
+impl Mmio for Simulator {
+    fn read32(&self, addr: u64) -> u32 {
+        self.ctrl.borrow_mut().read(self.offset(addr, 4), 4)
+    }
+
+    fn write32(&self, addr: u64, value: u32) {
+        self.ctrl.borrow_mut().write(self.offset(addr, 4), 4, value)
+    }
+
+    fn read16(&self, addr: u64) -> u16 {
+        self.ctrl.borrow_mut().read(self.offset(addr, 2), 2) as u16
+    }
+
+    fn write16(&self, addr: u64, value: u16) {
+        self.ctrl.borrow_mut().write(self.offset(addr, 2), 2, value as u32)
+    }
+
+    fn read8(&self, addr: u64) -> u8 {
+        self.ctrl.borrow_mut().read(self.offset(addr, 1), 1) as u8
+    }
+
+    fn write8(&self, addr: u64, value: u8) {
+        self.ctrl.borrow_mut().write(self.offset(addr, 1), 1, value as u32)
+    }
+}
@@ -0,0 +1,61 @@
+use core::time::Duration;
+
+use aarch64_cpu::registers::{CNTFRQ_EL0, CNTPCT_EL0, Readable};
+use kspin::SpinNoIrq;
+
+/// Monotonic time source used for every delay and timeout of the driver.
+///
+/// The default is `GenericTimer`. Integrators with their own clock source install it
+/// with `set_timer` before calling `SDHCI::init`.
+pub trait Timer: Sync {
+    /// Time elapsed since an arbitrary fixed point, e.g. boot.
+    fn now(&self) -> Duration;
+
+    /// Wait for at least `duration`. The default spins on `now`.
+    fn delay(&self, duration: Duration) {
+        let start = self.now();
+        while self.now() - start < duration {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Frequency of the system counter of the RK3568, used when CNTFRQ_EL0 is not programmed.
+pub const DEFAULT_CNTFRQ_HZ: u64 = 24_000_000;
+
+/// The aarch64 generic timer, read through CNTPCT_EL0 and CNTFRQ_EL0.
+pub struct GenericTimer {
+    fallback_hz: u64,
+}
+
+impl GenericTimer {
+    /// Use `fallback_hz` as the counter frequency if the firmware left CNTFRQ_EL0 at 0.
+    pub const fn new(fallback_hz: u64) -> Self {
+        assert!(fallback_hz != 0);
+        Self { fallback_hz }
+    }
+}
+
+impl Timer for GenericTimer {
+    fn now(&self) -> Duration {
+        let ticks = CNTPCT_EL0.get() as u128;
+        let freq = match CNTFRQ_EL0.get() {
+            0 => self.fallback_hz,
+            freq => freq,
+        } as u128;
+        Duration::from_nanos((ticks * 1_000_000_000 / freq) as u64)
+    }
+}
+
+static GENERIC_TIMER: GenericTimer = GenericTimer::new(DEFAULT_CNTFRQ_HZ);
+static TIMER: SpinNoIrq<&'static dyn Timer> = SpinNoIrq::new(&GENERIC_TIMER);
+
+/// Install the time source used by the driver.
+pub fn set_timer(timer: &'static dyn Timer) {
+    *TIMER.lock() = timer;
+}
+
+/// Return the installed time source.
+pub fn timer() -> &'static dyn Timer {
+    *TIMER.lock()
+}
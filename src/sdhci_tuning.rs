@@ -0,0 +1,154 @@
+use core::time::Duration;
+
+use log::{info, warn};
+
+use crate::Deadline;
+use crate::sdhci::{CMD_TIMEOUT_US, Data, Request, SDHCI};
+use crate::sdhci_cmd::mmc_resp_type::MMC_RESP_R1;
+use crate::sdhci_cmd::sd_cmd_idx::SD_TUNING_BLOCK_SIZE;
+use crate::sdhci_err::MmcError;
+use crate::sdhci_reg::Mmio;
+use crate::sdhci_reg::emmc_normal_int_en_bits::EMMC_RE_TUNE_EVENT_EN;
+use crate::sdhci_reg::emmc_normal_int_stat_bits::{EMMC_BUF_RD_READY, EMMC_RE_TUNE_EVENT};
+use crate::sdhci_timer::timer;
+
+/// Tuning blocks sent before the controller is expected to have found a sampling point.
+pub const TUNING_MAX_LOOPS: u32 = 40;
+/// Whole tuning procedure.
+pub const TUNING_TIMEOUT_US: u64 = 150_000;
+
+/// State of the sampling clock tuning, for re-tuning.
+pub(crate) struct Tuning {
+    /// Command sending the tuning block, `None` if the mode in use is not tuned.
+    opcode: Option<u16>,
+    /// Time of the last successful tuning.
+    tuned_at: Duration,
+    /// Re-tuning timer from the capabilities, zero if unused.
+    period: Duration,
+    /// A CRC error hinted that the sampling point drifted.
+    needed: bool,
+    /// Successful tuning procedures since `init`.
+    count: u32,
+}
+
+impl Tuning {
+    pub(crate) const fn new() -> Self {
+        Self { opcode: None, tuned_at: Duration::ZERO, period: Duration::ZERO, needed: false, count: 0 }
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Run the tuning procedure of the controller with `opcode`, CMD19 for SD cards.
+    ///
+    /// The controller adjusts its sampling point on every tuning block and clears Execute
+    /// Tuning once it is done; Sampling Clock Select then tells whether a point was found.
+    /// The tuning is repeated before the next request on a re-tuning event, when the re-tuning
+    /// timer of the capabilities expires, or after a CRC error.
+    pub fn execute_tuning(&self, opcode: u16) -> Result<(), MmcError> {
+        self.check_legacy()?;
+        let mut inflight = self.claim();
+
+        self.reg.emmc_start_tuning();
+        let deadline = Deadline::after_us(TUNING_TIMEOUT_US);
+        let mut loops = 0;
+        let result = loop {
+            if let Some(result) = self.tuning_result(loops, &deadline) {
+                break result;
+            }
+            loops += 1;
+            if let Err(err) =
+                self.issue_tuning_block(opcode).and_then(|_| self.wait_int("tuning block", EMMC_BUF_RD_READY, CMD_TIMEOUT_US))
+            {
+                break Err(err);
+            }
+        };
+
+        inflight.finished = result.is_ok();
+        self.tuning_done(opcode, loops, result)
+    }
+
+    /// Whether the tuning started after `loops` blocks is over, and how it ended.
+    pub(crate) fn tuning_result(&self, loops: u32, deadline: &Deadline) -> Option<Result<(), MmcError>> {
+        if !self.reg.emmc_tuning_is_executing() {
+            return Some(if self.reg.emmc_sample_clk_is_tuned() { Ok(()) } else { Err(MmcError::Tuning) });
+        }
+        if loops == TUNING_MAX_LOOPS || deadline.expired() {
+            return Some(Err(MmcError::Tuning));
+        }
+        None
+    }
+
+    /// Send the next tuning block with `opcode`, reported with Buffer Read Ready.
+    pub(crate) fn issue_tuning_block(&self, opcode: u16) -> Result<(), MmcError> {
+        // The controller consumes the block itself.
+        let mut block = [0u8; SD_TUNING_BLOCK_SIZE];
+        let mut req = Request::new(opcode, 0, MMC_RESP_R1, 0)
+            .with_data(Data::Read(&mut block))
+            .with_block_size(SD_TUNING_BLOCK_SIZE);
+        self.issue(&mut req)
+    }
+
+    /// Record the outcome of the tuning with `opcode` after `loops` blocks.
+    pub(crate) fn tuning_done(&self, opcode: u16, loops: u32, result: Result<(), MmcError>) -> Result<(), MmcError> {
+        let mut tuning = self.tuning.lock();
+        match result {
+            Ok(()) => {
+                info!("sampling clock tuned after {} blocks", loops);
+                tuning.opcode = Some(opcode);
+                tuning.tuned_at = timer().now();
+                tuning.period = Duration::from_secs(self.reg.emmc_get_retune_period() as u64);
+                tuning.needed = false;
+                tuning.count += 1;
+                self.reg.emmc_set_normal_int_en(self.reg.emmc_get_normal_int_en() | EMMC_RE_TUNE_EVENT_EN);
+                self.apply_int_sig();
+            }
+            Err(err) => {
+                warn!("tuning failed after {} blocks: {:?}", loops, err);
+                self.reg.emmc_reset_tuning();
+            }
+        }
+        result
+    }
+
+    /// Return how often the sampling clock was tuned since `init`.
+    pub fn tuning_count(&self) -> u32 {
+        self.tuning.lock().count
+    }
+
+    /// Tune again before the next request, if the mode in use is tuned.
+    pub(crate) fn request_retune(&self) {
+        let mut tuning = self.tuning.lock();
+        tuning.needed = tuning.opcode.is_some();
+    }
+
+    /// Repeat the tuning if the controller raised a re-tuning event, the re-tuning timer
+    /// expired or a CRC error asked for it.
+    pub(crate) fn retune_if_needed(&self) -> Result<(), MmcError> {
+        match self.retune_opcode() {
+            Some(opcode) => self.execute_tuning(opcode),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the opcode to tune again with if the controller raised a re-tuning event, the
+    /// re-tuning timer expired or a CRC error asked for it.
+    pub(crate) fn retune_opcode(&self) -> Option<u16> {
+        let (opcode, expired, needed) = {
+            let tuning = self.tuning.lock();
+            let opcode = tuning.opcode?;
+            let expired = !tuning.period.is_zero() && timer().now() - tuning.tuned_at >= tuning.period;
+            (opcode, expired, tuning.needed)
+        };
+
+        // The event may already have been latched by `handle_irq` or a previous request.
+        let event = self.reg.emmc_re_tune_event_is_actived() || self.take_int(EMMC_RE_TUNE_EVENT) != 0;
+        if event {
+            self.reg.emmc_set_normal_int_stat(EMMC_RE_TUNE_EVENT);
+        }
+        if !(event || expired || needed) {
+            return None;
+        }
+        info!("re-tuning: event {}, timer expired {}, requested {}", event, expired, needed);
+        Some(opcode)
+    }
+}
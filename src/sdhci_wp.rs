@@ -0,0 +1,247 @@
+use core::ops::Range;
+
+use log::{info, warn};
+
+use crate::sdhci::{BLOCK_SIZE, Card, CardType, Data, Request, SDHCI, csd_bits};
+use crate::sdhci_cmd::mmc_cmd_idx::*;
+use crate::sdhci_cmd::mmc_resp_type::{MMC_RESP_R1, MMC_RESP_R1B};
+use crate::sdhci_err::MmcError;
+use crate::sdhci_ext_csd::ext_csd_bits::*;
+use crate::sdhci_reg::Mmio;
+
+/// Confirms that a permanent write protection is wanted. It can never be cleared again, not
+/// even by the vendor, so it is only made explicitly with `Irreversible::confirm`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Irreversible(());
+
+impl Irreversible {
+    pub const fn confirm() -> Self {
+        Self(())
+    }
+}
+
+/// Write protection to apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteProtect {
+    /// Until cleared with `SDHCI::clear_write_protect`, across power cycles.
+    Temporary,
+    /// Until the device is power cycled.
+    PowerOn,
+    /// For good.
+    Permanent(Irreversible),
+}
+
+/// Write protection of a group or boot area, as reported by the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WpStatus {
+    Unprotected,
+    Temporary,
+    PowerOn,
+    Permanent,
+}
+
+/// Boot areas protected together or on their own through BOOT_WP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootArea {
+    Both,
+    Boot1,
+    Boot2,
+}
+
+impl Card {
+    /// Blocks of `BLOCK_SIZE` bytes in a write protect group, `None` if the device has no
+    /// group write protection.
+    ///
+    /// HC_WP_GRP_SIZE of the EXT_CSD gives the erase groups in a group once ERASE_GROUP_DEF
+    /// is set, WP_GRP_SIZE of the CSD otherwise.
+    pub fn wp_group_blocks(&self) -> Option<u32> {
+        if self.card_type != CardType::Mmc || csd_bits(&self.csd, 31, 1) == 0 {
+            return None;
+        }
+        let groups = if self.ext_csd.byte(EXT_CSD_ERASE_GROUP_DEF) & 0x01 != 0 {
+            self.ext_csd.byte(EXT_CSD_HC_WP_GRP_SIZE) as u32
+        } else {
+            csd_bits(&self.csd, 32, 5) + 1
+        };
+        (groups != 0).then(|| groups * self.erase_group_blocks())
+    }
+
+    /// The protection of boot area 1 and 2, from BOOT_WP_STATUS.
+    pub fn boot_wp(&self) -> [WpStatus; 2] {
+        let status = self.ext_csd.byte(EXT_CSD_BOOT_WP_STATUS);
+        [status & 0x03, status >> 2 & 0x03].map(|area| match area {
+            1 => WpStatus::PowerOn,
+            2 => WpStatus::Permanent,
+            _ => WpStatus::Unprotected,
+        })
+    }
+}
+
+impl<M: Mmio> SDHCI<M> {
+    /// Write protect the groups of `lba` in the user data area with CMD28, USER_WP selecting
+    /// the kind of protection for the time of the commands.
+    ///
+    /// `lba` must be aligned to `Card::wp_group_blocks`. Fails with `MmcError::Unsupported`
+    /// if the device has no group write protection or the kind is disabled in USER_WP.
+    /// Writes to a protected group then fail with `MmcError::WriteProtected`.
+    pub fn set_write_protect(&self, lba: Range<u32>, wp: WriteProtect) -> Result<(), MmcError> {
+        let (group, user_wp) = self.wp_groups(&lba)?;
+        let (enable, disabled) = match wp {
+            WriteProtect::Temporary => (0, 0),
+            WriteProtect::PowerOn => (EXT_CSD_US_PWR_WP_EN, EXT_CSD_US_PWR_WP_DIS),
+            WriteProtect::Permanent(_) => (EXT_CSD_US_PERM_WP_EN, EXT_CSD_US_PERM_WP_DIS),
+        };
+        if user_wp & disabled != 0 {
+            return Err(MmcError::Unsupported);
+        }
+
+        // A power-on or permanent protection left enabled, e.g. by another host, would also
+        // turn a temporary one into it.
+        let cleared = user_wp & !(EXT_CSD_US_PWR_WP_EN | EXT_CSD_US_PERM_WP_EN);
+        if enable != 0 || user_wp != cleared {
+            self.mmc_switch_verify(EXT_CSD_USER_WP, cleared | enable)?;
+        }
+        info!("{:?} write protection of blocks {:#x}..{:#x}", wp, lba.start, lba.end);
+        let result = self.wp_command(MMC_SET_WRITE_PROT, lba, group);
+        // Left set, the next temporary protection would not be temporary.
+        if enable != 0 {
+            self.mmc_switch(EXT_CSD_USER_WP, cleared)?;
+        }
+        result
+    }
+
+    /// Clear the temporary write protection of the groups of `lba` in the user data area with CMD29.
+    ///
+    /// `lba` must be aligned to `Card::wp_group_blocks`. The device refuses to clear a power-on or
+    /// permanent protection with `MmcError::WriteProtected`.
+    pub fn clear_write_protect(&self, lba: Range<u32>) -> Result<(), MmcError> {
+        let (group, _) = self.wp_groups(&lba)?;
+        info!("clear write protection of blocks {:#x}..{:#x}", lba.start, lba.end);
+        self.wp_command(MMC_CLR_WRITE_PROT, lba, group)
+    }
+
+    /// Whether the 32 write protect groups from the one of `lba` are protected, bit 0 for the
+    /// first one, read with CMD30. Groups past the end of the device read as unprotected.
+    pub fn write_protected_groups(&self, lba: u32) -> Result<u32, MmcError> {
+        let mut bits = [0u8; 4];
+        self.wp_query(MMC_SEND_WRITE_PROT, lba, &mut bits)?;
+        Ok(u32::from_be_bytes(bits))
+    }
+
+    /// The protection of the 32 write protect groups from the one of `lba`, read with CMD31.
+    pub fn write_protect_types(&self, lba: u32) -> Result<[WpStatus; 32], MmcError> {
+        let mut bits = [0u8; 8];
+        self.wp_query(MMC_SEND_WRITE_PROT_TYPE, lba, &mut bits)?;
+        let bits = u64::from_be_bytes(bits);
+        Ok(core::array::from_fn(|i| match bits >> (2 * i) & 0x03 {
+            1 => WpStatus::Temporary,
+            2 => WpStatus::PowerOn,
+            3 => WpStatus::Permanent,
+            _ => WpStatus::Unprotected,
+        }))
+    }
+
+    /// Write protect the boot partitions with BOOT_WP, and check BOOT_WP_STATUS reports it.
+    ///
+    /// Boot areas have no temporary protection, `WriteProtect::Temporary` fails with
+    /// `MmcError::InvalidArgument`. A kind disabled in BOOT_WP fails with `MmcError::Unsupported`.
+    pub fn set_boot_write_protect(&self, area: BootArea, wp: WriteProtect) -> Result<(), MmcError> {
+        self.check_card()?;
+        let (card_type, boot_wp) = {
+            let card = self.card.lock();
+            (card.card_type, card.ext_csd.byte(EXT_CSD_BOOT_WP))
+        };
+        if card_type != CardType::Mmc {
+            return Err(MmcError::Unsupported);
+        }
+        let (enable, select, disabled) = match wp {
+            WriteProtect::Temporary => return Err(MmcError::InvalidArgument),
+            WriteProtect::PowerOn => (EXT_CSD_B_PWR_WP_EN, EXT_CSD_B_PWR_WP_SEC_SEL, EXT_CSD_B_PWR_WP_DIS),
+            WriteProtect::Permanent(_) => (EXT_CSD_B_PERM_WP_EN, EXT_CSD_B_PERM_WP_SEC_SEL, EXT_CSD_B_PERM_WP_DIS),
+        };
+        if boot_wp & disabled != 0 {
+            return Err(MmcError::Unsupported);
+        }
+
+        // Only the disable bits are kept, they cannot be cleared anyway.
+        let mut value = boot_wp & (EXT_CSD_B_PWR_WP_DIS | EXT_CSD_B_PERM_WP_DIS) | enable;
+        match area {
+            BootArea::Both => {}
+            BootArea::Boot1 => value |= EXT_CSD_B_SEC_WP_SEL,
+            BootArea::Boot2 => value |= EXT_CSD_B_SEC_WP_SEL | select,
+        }
+        self.mmc_switch(EXT_CSD_BOOT_WP, value)?;
+        let ext_csd = self.mmc_send_ext_csd()?;
+        let status = {
+            let mut card = self.card.lock();
+            card.ext_csd = ext_csd;
+            card.boot_wp()
+        };
+
+        let expected = if enable == EXT_CSD_B_PWR_WP_EN { WpStatus::PowerOn } else { WpStatus::Permanent };
+        let areas: &[usize] = match area {
+            BootArea::Both => &[0, 1],
+            BootArea::Boot1 => &[0],
+            BootArea::Boot2 => &[1],
+        };
+        // A permanent protection covers a power-on one.
+        if areas.iter().any(|&i| status[i] != expected && status[i] != WpStatus::Permanent) {
+            warn!("boot write protection reads {:?} after {:?} of {:?}", status, wp, area);
+            return Err(MmcError::VerifyFailed);
+        }
+        info!("{:?} write protection of boot area {:?}", wp, area);
+        Ok(())
+    }
+
+    /// Check the card has write protect groups and `lba` is aligned to them, returning the
+    /// blocks in a group and USER_WP.
+    fn wp_groups(&self, lba: &Range<u32>) -> Result<(u32, u8), MmcError> {
+        self.check_card()?;
+        let card = self.card.lock();
+        let group = card.wp_group_blocks().ok_or(MmcError::Unsupported)?;
+        if lba.is_empty()
+            || lba.end as u64 > card.blocks
+            || !lba.start.is_multiple_of(group)
+            || !lba.end.is_multiple_of(group)
+        {
+            return Err(MmcError::InvalidArgument);
+        }
+        Ok((group, card.ext_csd.byte(EXT_CSD_USER_WP)))
+    }
+
+    /// Issue CMD28 or CMD29 to each group of `lba`.
+    fn wp_command(&self, idx: u16, lba: Range<u32>, group: u32) -> Result<(), MmcError> {
+        let (high_capacity, timeout) = {
+            let card = self.card.lock();
+            (card.high_capacity, card.write_timeout_us())
+        };
+        self.with_recovery(|| {
+            for start in lba.clone().step_by(group as usize) {
+                let arg = if high_capacity { start } else { start * BLOCK_SIZE as u32 };
+                self.execute(&mut Request::new(idx, 0, MMC_RESP_R1B, arg).with_busy_timeout(timeout))?;
+                self.wait_ready(timeout)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Read the write protection bits of CMD30 or CMD31 for the groups from the one of `lba`.
+    fn wp_query(&self, idx: u16, lba: u32, bits: &mut [u8]) -> Result<(), MmcError> {
+        self.check_card()?;
+        let arg = {
+            let card = self.card.lock();
+            if card.wp_group_blocks().is_none() {
+                return Err(MmcError::Unsupported);
+            }
+            if lba as u64 >= card.blocks {
+                return Err(MmcError::InvalidArgument);
+            }
+            if card.high_capacity { lba } else { lba * BLOCK_SIZE as u32 }
+        };
+        let len = bits.len();
+        self.with_recovery(|| {
+            let mut req = Request::new(idx, 0, MMC_RESP_R1, arg).with_data(Data::Read(bits)).with_block_size(len);
+            self.execute(&mut req).map(|_| ())
+        })
+    }
+}
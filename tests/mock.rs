@@ -0,0 +1,243 @@
+//! Host tests of the command path against `MockMmio`.
+//!
+//! Run with `make test_mock`.
+
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use rk3568_emmc::sdhci::SDHCI;
+use rk3568_emmc::sdhci_cmd::mmc_cmd_idx::*;
+use rk3568_emmc::sdhci_cmd::mmc_resp_type::*;
+use rk3568_emmc::sdhci_err::MmcError;
+use rk3568_emmc::sdhci_mock::MockMmio;
+use rk3568_emmc::sdhci_pci;
+use rk3568_emmc::sdhci_reg::emmc_argument_bits::EMMC_ARGUMENT_OFFSET;
+use rk3568_emmc::sdhci_reg::emmc_cmd_bits::*;
+use rk3568_emmc::sdhci_reg::emmc_error_int_stat_bits::*;
+use rk3568_emmc::sdhci_reg::emmc_normal_int_stat_bits::*;
+use rk3568_emmc::sdhci_reg::emmc_resp01_bits::EMMC_RESP01_OFFSET;
+use rk3568_emmc::sdhci_reg::emmc_sw_rst_bits::EMMC_SW_RST_OFFSET;
+use rk3568_emmc::sdhci_timer::{Timer, set_timer};
+
+const BASE: u64 = 0xfe31_0000;
+
+struct StdTimer;
+
+impl Timer for StdTimer {
+    fn now(&self) -> Duration {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// A mock with write-1-to-clear interrupt status and self-clearing software resets.
+fn mock() -> MockMmio {
+    static TIMER: StdTimer = StdTimer;
+    set_timer(&TIMER);
+
+    let mmio = MockMmio::new();
+    mmio.write_one_to_clear(BASE + EMMC_NORMAL_INT_STAT_OFFSET);
+    mmio.write_one_to_clear(BASE + EMMC_ERROR_INT_STAT_OFFSET);
+    mmio.on_write(BASE + EMMC_SW_RST_OFFSET, |mmio, _| mmio.set_reg(BASE + EMMC_SW_RST_OFFSET, 1, 0));
+    mmio
+}
+
+#[test]
+fn send_cmd_encodes_command_and_returns_response() {
+    let mmio = mock();
+    mmio.on_write(BASE + EMMC_CMD_OFFSET, |mmio, _| {
+        mmio.set_reg(BASE + EMMC_RESP01_OFFSET, 4, 0x900);
+        mmio.set_bits(BASE + EMMC_NORMAL_INT_STAT_OFFSET, 2, EMMC_CMD_COMPLETE as u32);
+    });
+    let sdhci = SDHCI::new_with_mmio(BASE, &mmio);
+
+    let resp = sdhci.sdhci_send_cmd(MMC_SEND_STATUS, EMMC_CMD_TYPE_NORMAL, MMC_RESP_R1, 1 << 16);
+
+    assert_eq!(resp, Ok(0x900));
+    assert_eq!(mmio.writes_to(BASE + EMMC_ARGUMENT_OFFSET), [1 << 16]);
+    let cmd = MMC_SEND_STATUS << EMMC_CMD_INDEX_POS | EMMC_CMD_TYPE_NORMAL | MMC_RESP_R1;
+    assert_eq!(mmio.writes_to(BASE + EMMC_CMD_OFFSET), [cmd as u32]);
+    assert_eq!(mmio.get_reg(BASE + EMMC_NORMAL_INT_STAT_OFFSET, 2), 0);
+}
+
+#[test]
+fn send_cmd_reports_timeout_and_resets_lines() {
+    let mmio = mock();
+    mmio.on_write(BASE + EMMC_CMD_OFFSET, |mmio, _| {
+        mmio.set_bits(BASE + EMMC_ERROR_INT_STAT_OFFSET, 2, EMMC_CMD_TOUT_ERR as u32);
+        mmio.set_bits(BASE + EMMC_NORMAL_INT_STAT_OFFSET, 2, EMMC_ERROR_INT as u32);
+    });
+    let sdhci = SDHCI::new_with_mmio(BASE, &mmio);
+
+    let resp = sdhci.sdhci_send_cmd(MMC_SEND_STATUS, EMMC_CMD_TYPE_NORMAL, MMC_RESP_R1, 1 << 16);
+
+    assert_eq!(resp, Err(MmcError::CmdTimeout));
+    assert!(!mmio.writes_to(BASE + EMMC_SW_RST_OFFSET).is_empty());
+}
+
+#[test]
+fn read_before_init_is_rejected_without_bus_access() {
+    let mmio = mock();
+    let sdhci = SDHCI::new_with_mmio(BASE, &mmio);
+
+    let mut buf = [0u8; 512];
+    assert_eq!(sdhci.read_blocks(0, &mut buf), Err(MmcError::InvalidArgument));
+    assert!(mmio.writes_to(BASE + EMMC_CMD_OFFSET).is_empty());
+}
+
+const ECAM: u64 = 0x4010_0000_0000;
+
+/// A PCI bus 0 with an SD host controller as device 0, whose 32-bit BAR0 decodes `bar0_size` bytes.
+fn pci_bus(bar0_size: u32) -> MockMmio {
+    let mmio = mock();
+    for device in 1..32 {
+        mmio.set_reg(ECAM | device << 15, 4, 0xffff_ffff);
+    }
+    mmio.set_reg(ECAM, 4, 0x0007_1b36);
+    mmio.set_reg(ECAM + 0x08, 4, 0x0805_0100);
+    // The address bits below the size read back as zero.
+    mmio.on_write(ECAM + 0x10, move |mmio, value| {
+        mmio.set_reg(ECAM + 0x10, 4, value & !bar0_size.wrapping_sub(1));
+    });
+    mmio
+}
+
+#[test]
+fn pci_bar0_is_sized_and_assigned() {
+    let mmio = pci_bus(0x1000);
+
+    let pci = sdhci_pci::probe(&mmio, ECAM, 0x1000_0800).unwrap();
+
+    assert_eq!((pci.device, pci.function), (0, 0));
+    assert_eq!((pci.bar0, pci.bar0_size), (0x1000_1000, 0x1000));
+    assert_eq!(mmio.get_reg(ECAM + 0x10, 4), 0x1000_1000);
+    assert_eq!(mmio.get_reg(ECAM + 0x04, 2), 0x06);
+}
+
+#[test]
+fn pci_unimplemented_bar0_is_skipped() {
+    let mmio = pci_bus(0);
+
+    assert!(sdhci_pci::probe(&mmio, ECAM, 0x1000_0800).is_none());
+    assert_eq!(mmio.get_reg(ECAM + 0x10, 4), 0);
+}
+
+/// Command queuing against a simulated card, whose controller does not model the engine. The
+/// engine registers are left to a mock: halting succeeds right away and clearing all tasks
+/// finishes as soon as it is requested.
+#[cfg(feature = "sim")]
+mod cqe {
+    use rk3568_emmc::sdhci::BLOCK_SIZE;
+    use rk3568_emmc::sdhci_cqe::cqe_desc_bits::*;
+    use rk3568_emmc::sdhci_cqe::{CQE_DESC_MEM_SIZE, CQE_MAX_SEGS, CQE_NUM_SLOTS, CQE_SEG_SIZE, CqeCompletion, CqeTask};
+    use rk3568_emmc::sdhci_reg::Mmio;
+    use rk3568_emmc::sdhci_reg::emmc_cq_task_bits::EMMC_CQTDBR_OFFSET;
+    use rk3568_emmc::sdhci_reg::emmc_cqcfg_bits::{EMMC_CQ_EN, EMMC_CQCFG_OFFSET};
+    use rk3568_emmc::sdhci_reg::emmc_cqctl_bits::{EMMC_CQ_CLR_ALL_TASKS, EMMC_CQ_HALT, EMMC_CQCTL_OFFSET};
+    use rk3568_emmc::sdhci_reg::emmc_cqssc_bits::EMMC_CQSSC2_OFFSET;
+    use rk3568_emmc::sdhci_reg::emmc_cqtdlba_bits::EMMC_CQTDLBA_OFFSET;
+    use rk3568_emmc::sdhci_sim::{SimCard, Simulator};
+
+    use super::*;
+
+    const CQ_REGS: core::ops::Range<u64> = BASE + 0x180..BASE + 0x200;
+    const DESC_PHYS: u64 = 0x4000_0000;
+    const TASK_SLOT_LEN: usize = 16;
+    const TRAN_OFFSET: usize = CQE_NUM_SLOTS * TASK_SLOT_LEN;
+
+    struct CqeMmio {
+        sim: Simulator,
+        cq: MockMmio,
+    }
+
+    impl CqeMmio {
+        fn target(&self, addr: u64) -> &dyn Mmio {
+            if CQ_REGS.contains(&addr) { &self.cq } else { &self.sim }
+        }
+    }
+
+    impl Mmio for CqeMmio {
+        fn read32(&self, addr: u64) -> u32 { self.target(addr).read32(addr) }
+        fn write32(&self, addr: u64, value: u32) { self.target(addr).write32(addr, value) }
+        fn read16(&self, addr: u64) -> u16 { self.target(addr).read16(addr) }
+        fn write16(&self, addr: u64, value: u16) { self.target(addr).write16(addr, value) }
+        fn read8(&self, addr: u64) -> u8 { self.target(addr).read8(addr) }
+        fn write8(&self, addr: u64, value: u8) { self.target(addr).write8(addr, value) }
+    }
+
+    fn cqe_mmio(card: SimCard) -> CqeMmio {
+        let cq = mock();
+        cq.on_write(BASE + EMMC_CQCTL_OFFSET, |mmio, value| {
+            mmio.set_reg(BASE + EMMC_CQCTL_OFFSET, 4, value & !EMMC_CQ_CLR_ALL_TASKS);
+        });
+        CqeMmio { sim: Simulator::new(BASE, card), cq }
+    }
+
+    /// Descriptor memory, aligned for the 64-bit descriptors.
+    fn desc_mem() -> &'static mut [u8] {
+        let words = Vec::leak(vec![0u64; CQE_DESC_MEM_SIZE / 8]);
+        unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, CQE_DESC_MEM_SIZE) }
+    }
+
+    /// Read a descriptor the way the engine would, behind the back of the driver owning `mem`.
+    fn desc(mem: *const u8, offset: usize) -> u64 {
+        u64::from_le(unsafe { mem.add(offset).cast::<u64>().read_volatile() })
+    }
+
+    #[test]
+    fn descriptors_are_set_up_and_the_engine_enabled_and_disabled() {
+        let mmio = cqe_mmio(SimCard::new(2048).command_queue(2));
+        let sdhci = SDHCI::new_with_mmio(BASE, &mmio);
+        sdhci.init().unwrap();
+        let rca = sdhci.card().rca;
+
+        let mem = desc_mem();
+        let mem_ptr = mem.as_ptr();
+        sdhci.cqe_enable(mem, DESC_PHYS).unwrap();
+        let mem = mem_ptr;
+
+        assert!(mmio.sim.card().cmdq_mode());
+        assert_eq!(mmio.cq.get_reg(BASE + EMMC_CQCFG_OFFSET, 4), EMMC_CQ_EN);
+        assert_eq!(mmio.cq.get_reg(BASE + EMMC_CQTDLBA_OFFSET, 4), DESC_PHYS as u32);
+        assert_eq!(mmio.cq.get_reg(BASE + EMMC_CQSSC2_OFFSET, 2), rca as u32);
+        for tag in 0..CQE_NUM_SLOTS {
+            let tran = DESC_PHYS + (TRAN_OFFSET + tag * CQE_MAX_SEGS * 8) as u64;
+            assert_eq!(desc(mem, tag * TASK_SLOT_LEN), 0);
+            assert_eq!(desc(mem, tag * TASK_SLOT_LEN + 8), CQE_DESC_VALID | CQE_DESC_ACT_LINK | tran << CQE_DESC_ADDR_POS);
+        }
+
+        // Legacy commands are refused while the engine owns the bus.
+        let mut buf = [0u8; BLOCK_SIZE];
+        assert_eq!(sdhci.read_blocks(0, &mut buf), Err(MmcError::CqeActive));
+
+        // Two segments, the last one partial.
+        let blocks = (CQE_SEG_SIZE / BLOCK_SIZE + 8) as u16;
+        let task = CqeTask { lba: 16, blocks, buf_phys: 0x5000_0000, read: true, reliable: false, forced_prog: false, priority: true };
+        assert_eq!(sdhci.cqe_submit(&task), Ok(0));
+        assert_eq!(desc(mem, 0), CQE_DESC_VALID | CQE_DESC_END | CQE_DESC_INT | CQE_DESC_ACT_TASK
+            | CQE_TASK_DATA_DIR_READ | CQE_TASK_PRIORITY
+            | (blocks as u64) << CQE_TASK_BLK_COUNT_POS | ((16 * BLOCK_SIZE) as u64) << CQE_TASK_BLK_ADDR_POS);
+        assert_eq!(desc(mem, TRAN_OFFSET), CQE_DESC_VALID | CQE_DESC_ACT_TRAN | 0x5000_0000 << CQE_DESC_ADDR_POS);
+        assert_eq!(desc(mem, TRAN_OFFSET + 8), CQE_DESC_VALID | CQE_DESC_END | CQE_DESC_ACT_TRAN
+            | ((8 * BLOCK_SIZE) as u64) << CQE_DESC_LEN_POS | ((0x5000_0000 + CQE_SEG_SIZE) as u64) << CQE_DESC_ADDR_POS);
+
+        let task = CqeTask { lba: 0, blocks: 1, buf_phys: 0x5100_0000, read: false, reliable: true, forced_prog: false, priority: false };
+        assert_eq!(sdhci.cqe_submit(&task), Ok(1));
+        assert_eq!(desc(mem, TASK_SLOT_LEN), CQE_DESC_VALID | CQE_DESC_END | CQE_DESC_INT | CQE_DESC_ACT_TASK
+            | CQE_TASK_REL_WRITE | 1 << CQE_TASK_BLK_COUNT_POS);
+        assert_eq!(mmio.cq.writes_to(BASE + EMMC_CQTDBR_OFFSET), [0b01, 0b10]);
+        // The device queue holds two tasks.
+        assert_eq!(sdhci.cqe_submit(&task), Err(MmcError::QueueFull));
+
+        // Disabling halts the engine and fails the tasks still queued.
+        let returned = sdhci.cqe_disable().unwrap();
+        assert_eq!(returned.as_ptr(), mem_ptr);
+        assert_ne!(mmio.cq.get_reg(BASE + EMMC_CQCTL_OFFSET, 4) & EMMC_CQ_HALT, 0);
+        assert_eq!(mmio.cq.get_reg(BASE + EMMC_CQCFG_OFFSET, 4), 0);
+        assert!(!mmio.sim.card().cmdq_mode());
+        assert_eq!(sdhci.cqe_take_completed(), CqeCompletion { done: 0, failed: 0b11 });
+        assert_eq!(sdhci.cqe_disable().err(), Some(MmcError::Unsupported));
+
+        sdhci.read_blocks(0, &mut buf).unwrap();
+    }
+}
@@ -0,0 +1,1502 @@
+//! Host tests running the whole driver against `Simulator`.
+//!
+//! Run with `make test_sim`.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use rk3568_emmc::sdhci::{BLOCK_SIZE, CardType, HostKind, SDHCI};
+use rk3568_emmc::sdhci_bkops::BkopsStatus;
+use rk3568_emmc::sdhci_boot::{BootBusWidth, BootConfig, BootLock, BootLockStatus, BootMethod, BootMode, BootPartition};
+use rk3568_emmc::sdhci_cmd::mmc_cmd_idx::*;
+use rk3568_emmc::sdhci_cmd::mmc_r1_bits::{MMC_R1_STATE_PRG, MMC_R1_STATE_TRAN};
+use rk3568_emmc::sdhci_cmd::mmc_resp_type::*;
+use rk3568_emmc::sdhci_erase::{ERASE_TIMEOUT_UNIT_US, EraseKind, SANITIZE_POLL_US, SANITIZE_TIMEOUT_US};
+use rk3568_emmc::sdhci_err::{AutoCmdError, MmcError};
+use rk3568_emmc::sdhci_ffu::{FfuOutcome, FfuStatus};
+use rk3568_emmc::sdhci_health::{LifeTime, PreEol};
+use rk3568_emmc::sdhci_hotplug::{CARD_DETECT_DEBOUNCE_US, CardEvent};
+use rk3568_emmc::sdhci_ext_csd::ext_csd_bits::*;
+use rk3568_emmc::sdhci_reg::Reg;
+use rk3568_emmc::sdhci_reg::emmc_auto_cmd_stat_bits::*;
+use rk3568_emmc::sdhci_reg::emmc_cmd_bits::*;
+use rk3568_emmc::sdhci_reg::emmc_error_int_stat_bits::{EMMC_ADMA_ERR, EMMC_AUTO_CMD_ERR};
+use rk3568_emmc::sdhci_reg::emmc_host_ctrl1_bits::{EMMC_DMA_SEL_ADMA2, EMMC_DMA_SEL_SDMA};
+use rk3568_emmc::sdhci_reg::emmc_normal_int_stat_bits::EMMC_XFER_COMPLETE;
+use rk3568_emmc::sdhci_reg::emmc_xfer_mode_bits::*;
+use rk3568_emmc::sdhci_partition::{GpAttribute, GpPartition, PartitionLayout};
+use rk3568_emmc::sdhci_recovery::RECOVERY_RETRIES;
+use rk3568_emmc::sdhci_sd::{SdCapacity, SdTiming};
+use rk3568_emmc::sdhci_sim::{Fault, Partition, SimCard, SimStorage, Simulator, SIM_BKOPS_POLLS, SIM_ERASE_GROUP_BLOCKS, SIM_RPMB_HALF_SECTORS, SIM_SANITIZE_POLLS, SIM_SD_RCA, SIM_TUNING_LOOPS, SIM_WP_GROUP_BLOCKS};
+use rk3568_emmc::sdhci_timer::{Timer, set_timer};
+use rk3568_emmc::sdhci_wp::{BootArea, Irreversible, WpStatus, WriteProtect};
+
+const BASE: u64 = 0xfe31_0000;
+const BLOCKS: u64 = 8192;
+
+struct StdTimer;
+
+impl Timer for StdTimer {
+    fn now(&self) -> Duration {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed()
+    }
+}
+
+fn sim(card: SimCard) -> Simulator {
+    static TIMER: StdTimer = StdTimer;
+    set_timer(&TIMER);
+    Simulator::new(BASE, card)
+}
+
+fn pattern(blocks: usize, seed: u8) -> Vec<u8> {
+    (0..blocks * BLOCK_SIZE).map(|i| (i / 7) as u8 ^ seed).collect()
+}
+
+thread_local! {
+    /// Driver whose operations `interrupt_on_idle` interrupts.
+    static INTERRUPTED: Cell<Option<&'static SDHCI<&'static Simulator>>> = const { Cell::new(None) };
+}
+
+/// Idle function interrupting the operation waited for, as latency-sensitive I/O arriving would.
+fn interrupt_on_idle() {
+    if let Some(sdhci) = INTERRUPTED.with(Cell::get) {
+        sdhci.interrupt_current_operation().unwrap();
+    }
+}
+
+thread_local! {
+    /// Controller and driver between which `deliver_irq` stands in for the interrupt controller.
+    static IRQ: Cell<Option<(&'static Simulator, &'static SDHCI<&'static Simulator>)>> = const { Cell::new(None) };
+    /// Interrupts `handle_irq` took.
+    static IRQS: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Idle function calling `handle_irq` while the interrupt line is asserted, as the handler would.
+fn deliver_irq() {
+    if let Some((sim, sdhci)) = IRQ.with(Cell::get)
+        && sim.irq_pending()
+    {
+        assert!(sdhci.handle_irq());
+        IRQS.with(|irqs| irqs.set(irqs.get() + 1));
+    }
+}
+
+thread_local! {
+    /// Controller and driver of which `remove_on_idle` pulls the card out once.
+    static REMOVED: Cell<Option<(&'static Simulator, &'static SDHCI<&'static Simulator>)>> = const { Cell::new(None) };
+}
+
+/// Idle function pulling the card out under the request waited for, and polling card detect
+/// past the debounce time.
+fn remove_on_idle() {
+    if let Some((sim, sdhci)) = REMOVED.with(Cell::take) {
+        sim.remove_card();
+        assert_eq!(sdhci.poll_card_detect(), None);
+        std::thread::sleep(Duration::from_micros(CARD_DETECT_DEBOUNCE_US + 10_000));
+        // The controller is not reset under the request.
+        assert_eq!(sdhci.poll_card_detect(), None);
+        assert!(sdhci.card_detect_pending());
+    }
+}
+
+/// User data area that only stores the blocks written to it.
+struct Sparse(u64, BTreeMap<u64, Vec<u8>>);
+
+impl Sparse {
+    /// 4 GiB, above the byte addressing limit.
+    fn new() -> Self {
+        Self(1 << 23, BTreeMap::new())
+    }
+}
+
+impl SimStorage for Sparse {
+    fn blocks(&self) -> u64 {
+        self.0
+    }
+
+    fn read_block(&mut self, lba: u64, buf: &mut [u8]) {
+        match self.1.get(&lba) {
+            Some(block) => buf.copy_from_slice(block),
+            None => buf.fill(0),
+        }
+    }
+
+    fn write_block(&mut self, lba: u64, buf: &[u8]) {
+        self.1.insert(lba, buf.to_vec());
+    }
+}
+
+#[test]
+fn init_identifies_byte_addressed_card() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+
+    sdhci.init().unwrap();
+
+    let card = sdhci.card();
+    assert!(!card.high_capacity);
+    assert_eq!(card.blocks, BLOCKS);
+    assert_eq!(card.ext_csd.rev(), 8);
+    assert_eq!(card.cid, sim.card().cid);
+}
+
+#[test]
+fn init_identifies_sector_addressed_card() {
+    let sim = sim(SimCard::with_storage(Box::new(Sparse::new())));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let card = sdhci.card();
+    assert!(card.high_capacity);
+    assert_eq!(card.blocks, 1 << 23);
+
+    let lba = (1 << 23) - 2;
+    let data = pattern(2, 0x5a);
+    sdhci.write_blocks(lba, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(lba, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn write_read_round_trip() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let data = pattern(8, 0x11);
+    sdhci.write_blocks(100, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(100, &mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    let mut block = [0; BLOCK_SIZE];
+    sim.card().read_block(Partition::User, 107, &mut block);
+    assert_eq!(block, data[7 * BLOCK_SIZE..]);
+    assert_eq!(sdhci.read_blocks(BLOCKS as u32, &mut block), Err(MmcError::InvalidArgument));
+}
+
+#[test]
+fn requests_complete_through_handle_irq() {
+    let sim: &'static Simulator = Box::leak(Box::new(sim(SimCard::new(BLOCKS))));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, sim);
+    sdhci.set_idle(deliver_irq);
+    let sdhci: &'static SDHCI<&Simulator> = Box::leak(Box::new(sdhci));
+    sdhci.init().unwrap();
+    assert!(!sdhci.handle_irq());
+
+    // Completion is only seen through `handle_irq` once interrupts are enabled.
+    sdhci.enable_irq();
+    IRQ.with(|cell| cell.set(Some((sim, sdhci))));
+    let data = pattern(4, 0x26);
+    sdhci.write_blocks(300, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(300, &mut buf).unwrap();
+    IRQ.with(|cell| cell.set(None));
+    assert_eq!(buf, data);
+    assert!(IRQS.with(Cell::get) > 0);
+    assert!(!sim.irq_pending());
+
+    sdhci.disable_irq();
+    sdhci.read_blocks(300, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn boot_partition_is_separate() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let data = pattern(1, 0x22);
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 1).unwrap();
+    sdhci.write_blocks(0, &data).unwrap();
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 0).unwrap();
+
+    let mut block = [0; BLOCK_SIZE];
+    sdhci.read_blocks(0, &mut block).unwrap();
+    assert_eq!(block, [0; BLOCK_SIZE]);
+    sim.card().read_block(Partition::Boot1, 0, &mut block);
+    assert_eq!(block, data[..]);
+
+    // Partition 4 is a general purpose partition the card does not have.
+    assert!(matches!(sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 4), Err(MmcError::CardStatus(_))));
+}
+
+#[test]
+fn boot_config_is_written_and_locked() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let boot = BootConfig {
+        partition: BootPartition::Boot1,
+        ack: true,
+        bus_width: BootBusWidth::X8,
+        mode: BootMode::SdrHighSpeed,
+        retain_bus: false,
+    };
+    // The partition accessed is kept.
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 2).unwrap();
+    sdhci.set_boot_config(&boot).unwrap();
+    assert_eq!(sim.card().ext_csd[EXT_CSD_PARTITION_CONFIG], 0x4a);
+    assert_eq!(sim.card().ext_csd[EXT_CSD_BOOT_BUS_CONDITIONS], 0x0a);
+    assert_eq!(sdhci.card().boot_config(), Some(boot));
+
+    sdhci.lock_boot_config(BootLock::UntilPowerCycle).unwrap();
+    assert_eq!(sdhci.card().boot_lock(), BootLockStatus::UntilPowerCycle);
+    let other = BootConfig { partition: BootPartition::Boot2, ..boot };
+    assert_eq!(sdhci.set_boot_config(&other), Err(MmcError::VerifyFailed));
+    assert_eq!(sdhci.card().boot_config(), Some(boot));
+
+    // The lock goes away with the power.
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 0x48).unwrap();
+    sim.power_loss();
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.card().boot_lock(), BootLockStatus::Unlocked);
+    sdhci.set_boot_config(&other).unwrap();
+    assert_eq!(sdhci.card().boot_config(), Some(other));
+
+    // The permanent lock survives it.
+    sdhci.lock_boot_config(BootLock::Permanent(Irreversible::confirm())).unwrap();
+    assert_eq!(sdhci.card().boot_lock(), BootLockStatus::Permanent);
+    sim.power_loss();
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.card().boot_lock(), BootLockStatus::Permanent);
+    assert_eq!(sdhci.set_boot_config(&boot), Err(MmcError::VerifyFailed));
+}
+
+#[test]
+fn boot_read_streams_the_boot_partition() {
+    let mut card = SimCard::new(BLOCKS);
+    let image = pattern(4, 0x5a);
+    for (i, block) in image.chunks(BLOCK_SIZE).enumerate() {
+        card.write_block(Partition::Boot1, i as u64, block);
+    }
+    card.ext_csd[EXT_CSD_PARTITION_CONFIG] = 0x08;
+    let sim = sim(card);
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+
+    for method in [BootMethod::Alternative, BootMethod::CmdLow] {
+        let mut buf = vec![0; image.len()];
+        sdhci.boot_read(method, false, &mut buf).unwrap();
+        assert_eq!(buf, image);
+    }
+    // The device does not send the boot acknowledge pattern.
+    let mut buf = vec![0; BLOCK_SIZE];
+    assert_eq!(sdhci.boot_read(BootMethod::Alternative, true, &mut buf), Err(MmcError::BootAck));
+    sim.card().ext_csd[EXT_CSD_PARTITION_CONFIG] |= EXT_CSD_BOOT_ACK;
+    sdhci.boot_read(BootMethod::CmdLow, true, &mut buf).unwrap();
+    assert_eq!(buf, image[..BLOCK_SIZE]);
+
+    // The device is left idle for the identification.
+    sdhci.init().unwrap();
+    sdhci.read_blocks(0, &mut buf).unwrap();
+}
+
+#[test]
+fn write_protect_groups_and_boot_areas() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let group = SIM_WP_GROUP_BLOCKS as u32;
+    assert_eq!(sdhci.card().wp_group_blocks(), Some(group));
+    assert_eq!(sdhci.set_write_protect(1..group, WriteProtect::Temporary), Err(MmcError::InvalidArgument));
+    sdhci.set_write_protect(group..2 * group, WriteProtect::Temporary).unwrap();
+    sdhci.set_write_protect(2 * group..3 * group, WriteProtect::PowerOn).unwrap();
+    sdhci.set_write_protect(3 * group..4 * group, WriteProtect::Permanent(Irreversible::confirm())).unwrap();
+    assert_eq!(sim.card().ext_csd[EXT_CSD_USER_WP], 0);
+
+    let data = pattern(1, 0x66);
+    sdhci.write_blocks(group - 1, &data).unwrap();
+    assert_eq!(sdhci.write_blocks(group, &data), Err(MmcError::WriteProtected));
+    assert_eq!(sdhci.write_protected_groups(0).unwrap(), 0b1110);
+    let types = sdhci.write_protect_types(group).unwrap();
+    assert_eq!(types[..4], [WpStatus::Temporary, WpStatus::PowerOn, WpStatus::Permanent, WpStatus::Unprotected]);
+
+    // Only the temporary protection can be cleared.
+    sdhci.clear_write_protect(group..2 * group).unwrap();
+    sdhci.write_blocks(group, &data).unwrap();
+    assert_eq!(sdhci.clear_write_protect(2 * group..3 * group), Err(MmcError::WriteProtected));
+
+    sdhci.set_boot_write_protect(BootArea::Boot2, WriteProtect::PowerOn).unwrap();
+    assert_eq!(sdhci.card().boot_wp(), [WpStatus::Unprotected, WpStatus::PowerOn]);
+    assert_eq!(sdhci.set_boot_write_protect(BootArea::Both, WriteProtect::Temporary), Err(MmcError::InvalidArgument));
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 2).unwrap();
+    assert_eq!(sdhci.write_blocks(0, &data), Err(MmcError::WriteProtected));
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 1).unwrap();
+    sdhci.write_blocks(0, &data).unwrap();
+
+    // The power-on protections go away with the power, the permanent one stays.
+    sim.power_loss();
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.write_protected_groups(0).unwrap(), 0b1000);
+    assert_eq!(sdhci.card().boot_wp(), [WpStatus::Unprotected; 2]);
+
+    // A power-on protection left enabled in USER_WP is not applied to a temporary one.
+    sdhci.mmc_switch(EXT_CSD_USER_WP, EXT_CSD_US_PWR_WP_EN).unwrap();
+    sdhci.set_write_protect(group..2 * group, WriteProtect::Temporary).unwrap();
+    assert_eq!(sim.card().ext_csd[EXT_CSD_USER_WP], 0);
+    assert_eq!(sdhci.write_protect_types(group).unwrap()[0], WpStatus::Temporary);
+    sdhci.clear_write_protect(group..2 * group).unwrap();
+}
+
+#[test]
+fn partitions_are_provisioned_once() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let unit = sdhci.card().partition_unit_blocks().unwrap();
+    let mut layout = PartitionLayout {
+        gp: [
+            GpPartition { blocks: unit - 24, enhanced: true, attribute: GpAttribute::SystemCode },
+            GpPartition { blocks: 2 * unit, enhanced: false, attribute: GpAttribute::NonPersistent },
+            GpPartition::default(),
+            GpPartition::default(),
+        ],
+        enhanced_user: unit..2 * unit,
+    };
+    // A dry run writes nothing.
+    let settings = sdhci.plan_partitions(&layout).unwrap();
+    assert_eq!(settings.gp_size_mult, [1, 2, 0, 0]);
+    assert_eq!((settings.enh_start_addr, settings.enh_size_mult), (unit * BLOCK_SIZE as u32, 1));
+    assert_eq!((settings.partitions_attribute, settings.ext_partitions_attribute), (0x03, 0x21));
+    assert_eq!(sim.card().ext_csd[EXT_CSD_PARTITIONS_ATTRIBUTE], 0);
+
+    // More enhanced memory than MAX_ENH_SIZE_MULT.
+    layout.gp[1].enhanced = true;
+    assert_eq!(sdhci.plan_partitions(&layout), Err(MmcError::InvalidArgument));
+    layout.gp[1].enhanced = false;
+
+    assert_eq!(sdhci.provision_partitions(&layout).unwrap(), settings);
+    assert!(sdhci.card().partitioning_completed());
+    assert_eq!(sim.card().ext_csd[EXT_CSD_GP_SIZE_MULT + 3], 2);
+    layout.gp[0].blocks = unit;
+    assert_eq!(sdhci.card().partition_layout(), Some(layout.clone()));
+
+    assert_eq!(sdhci.provision_partitions(&layout), Err(MmcError::PartitioningCompleted));
+    assert!(matches!(sdhci.mmc_switch(EXT_CSD_GP_SIZE_MULT, 3), Err(MmcError::CardStatus(_))));
+}
+
+#[test]
+fn health_reports_wear_and_vendor_report() {
+    let mut card = SimCard::new(BLOCKS);
+    card.health_report[..4].copy_from_slice(b"HLTH");
+    let sim = sim(card);
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let health = sdhci.health().unwrap();
+    assert_eq!((health.pre_eol, health.slc, health.mlc), (PreEol::Normal, LifeTime::Used(10), LifeTime::Used(10)));
+    assert_eq!(health.vendor_report, None);
+
+    // The device wears out while running.
+    sim.card().ext_csd[EXT_CSD_PRE_EOL_INFO] = 3;
+    sim.card().ext_csd[EXT_CSD_DEVICE_LIFE_TIME_EST_TYP_B] = 0x0b;
+    sdhci.set_health_report(Some(0x110005f0));
+    let health = sdhci.health().unwrap();
+    assert!(health.is_critical());
+    assert_eq!(health.pre_eol, PreEol::Urgent);
+    assert_eq!(health.mlc.wear_percent(), Some(110));
+    assert_eq!(health.vendor_report.unwrap()[..4], *b"HLTH");
+}
+
+#[test]
+fn firmware_is_updated_in_the_field() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.card().firmware_version(), *b"SIMFW001");
+
+    let mut firmware = pattern(3, 0x46);
+    assert_eq!(sdhci.ffu(&firmware[..BLOCK_SIZE + 1]), Err(MmcError::InvalidArgument));
+    firmware[..8].copy_from_slice(b"SIMFW002");
+    assert_eq!(sdhci.ffu(&firmware), Ok(FfuOutcome::Installed(*b"SIMFW002")));
+    assert_eq!(sdhci.card().ffu_status(), FfuStatus::Success);
+    assert_eq!(sim.card().ext_csd[EXT_CSD_MODE_CONFIG], 0);
+
+    // Without MODE_OPERATION_CODES the firmware is installed by a power cycle.
+    sim.card().ext_csd[EXT_CSD_FFU_FEATURES] = 0;
+    sdhci.init().unwrap();
+    firmware[..8].copy_from_slice(b"SIMFW003");
+    assert_eq!(sdhci.ffu(&firmware), Ok(FfuOutcome::PowerCycle));
+    assert_eq!(sdhci.card().firmware_version(), *b"SIMFW002");
+    sim.card().power_off();
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.card().firmware_version(), *b"SIMFW003");
+
+    // Updates disabled for good.
+    sim.card().ext_csd[EXT_CSD_FFU_CONFIG] = 1;
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.ffu(&firmware), Err(MmcError::Unsupported));
+}
+
+#[test]
+fn erase_clears_whole_groups() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let group = SIM_ERASE_GROUP_BLOCKS as u32;
+    let data = pattern(2, 0x33);
+    sdhci.write_blocks(group - 1, &data).unwrap();
+
+    let addr = |lba: u32| lba * BLOCK_SIZE as u32;
+    sdhci.sdhci_send_cmd(MMC_ERASE_GROUP_START, 0, MMC_RESP_R1, addr(group + 1)).unwrap();
+    sdhci.sdhci_send_cmd(MMC_ERASE_GROUP_END, 0, MMC_RESP_R1, addr(group + 2)).unwrap();
+    sdhci.sdhci_send_cmd(MMC_ERASE, 0, MMC_RESP_R1B, 0).unwrap();
+
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(group - 1, &mut buf).unwrap();
+    assert_eq!(buf[..BLOCK_SIZE], data[..BLOCK_SIZE]);
+    assert_eq!(buf[BLOCK_SIZE..], [0; BLOCK_SIZE]);
+}
+
+#[test]
+fn erase_kinds_align_to_erase_groups() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let group = SIM_ERASE_GROUP_BLOCKS as u32;
+    let card = sdhci.card();
+    assert_eq!(card.erase_group_blocks(), group);
+    assert_eq!(card.erase_timeout_us(EraseKind::Erase, 2), 2 * card.write_timeout_us());
+    sdhci.mmc_switch(EXT_CSD_ERASE_GROUP_DEF, 1).unwrap();
+    let card = sdhci.card();
+    assert_eq!(card.erase_group_blocks(), group);
+    assert_eq!(card.erase_timeout_us(EraseKind::Erase, 2), 2 * ERASE_TIMEOUT_UNIT_US);
+    assert_eq!(card.erase_timeout_us(EraseKind::SecureErase, 1), 2 * ERASE_TIMEOUT_UNIT_US);
+
+    let mut expected = pattern(3 * group as usize, 0x5a);
+    sdhci.write_blocks(0, &expected).unwrap();
+    let mut erased = |range: std::ops::Range<u32>| expected[range.start as usize * BLOCK_SIZE..range.end as usize * BLOCK_SIZE].fill(0);
+
+    // Only the second group lies inside the range.
+    sdhci.erase(1..2 * group + 1, EraseKind::Erase).unwrap();
+    erased(group..2 * group);
+    sdhci.erase(2..4, EraseKind::Trim).unwrap();
+    erased(2..4);
+    assert_eq!(sdhci.erase(1..group, EraseKind::SecureErase), Err(MmcError::InvalidArgument));
+    sdhci.erase(2 * group..3 * group, EraseKind::SecureErase).unwrap();
+    erased(2 * group..3 * group);
+    sdhci.erase(5..6, EraseKind::SecureTrim).unwrap();
+    erased(5..6);
+
+    let mut buf = vec![0; expected.len()];
+    sdhci.read_blocks(0, &mut buf).unwrap();
+    for (lba, (block, expected)) in buf.chunks(BLOCK_SIZE).zip(expected.chunks(BLOCK_SIZE)).enumerate() {
+        assert_eq!(block, expected, "block {}", lba);
+    }
+}
+
+#[test]
+fn discard_blocks_reaches_the_card() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let data = pattern(4, 0x21);
+    sdhci.write_blocks(100, &data).unwrap();
+    sdhci.discard_blocks(101, 2).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(100, &mut buf).unwrap();
+    assert_eq!(buf[..BLOCK_SIZE], data[..BLOCK_SIZE]);
+    assert_eq!(buf[BLOCK_SIZE..3 * BLOCK_SIZE], [0; 2 * BLOCK_SIZE]);
+    assert_eq!(buf[3 * BLOCK_SIZE..], data[3 * BLOCK_SIZE..]);
+    assert_eq!(sdhci.discard_blocks(BLOCKS as u32 - 1, 2), Err(MmcError::InvalidArgument));
+
+    let sim = self::sim(SimCard::new_sd(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+
+    assert!(sdhci.card().supports_erase(EraseKind::Discard));
+    assert_eq!(sdhci.erase(0..1, EraseKind::Trim), Err(MmcError::Unsupported));
+    sdhci.write_blocks(100, &data).unwrap();
+    sdhci.discard_blocks(101, 2).unwrap();
+    sdhci.erase(103..104, EraseKind::Erase).unwrap();
+    sdhci.read_blocks(100, &mut buf).unwrap();
+    assert_eq!(buf[..BLOCK_SIZE], data[..BLOCK_SIZE]);
+    assert_eq!(buf[BLOCK_SIZE..], [0; 3 * BLOCK_SIZE]);
+}
+
+#[test]
+fn sanitize_runs_to_completion() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let mut polls = 0;
+    let start = Instant::now();
+    sdhci.sanitize(SANITIZE_TIMEOUT_US, || {
+        polls += 1;
+        false
+    })
+    .unwrap();
+    assert_eq!(polls, SIM_SANITIZE_POLLS);
+    assert!(start.elapsed() >= Duration::from_micros(SIM_SANITIZE_POLLS as u64 * SANITIZE_POLL_US));
+    assert_eq!(sim.card().sanitizes(), 1);
+    assert_eq!(sdhci.card().ext_csd.byte(EXT_CSD_HPI_MGMT), 1);
+
+    let sim = self::sim(SimCard::new_sd(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.sanitize(SANITIZE_TIMEOUT_US, || false), Err(MmcError::Unsupported));
+}
+
+#[test]
+fn sanitize_is_interrupted_with_hpi() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    assert_eq!(sdhci.sanitize(SANITIZE_TIMEOUT_US, || true), Err(MmcError::Interrupted));
+    assert_eq!(sim.card().sanitizes(), 0);
+
+    let data = pattern(2, 0x44);
+    sdhci.write_blocks(8, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(8, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn urgent_background_operations_run_when_idle() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    assert!(!sdhci.card().auto_bkops_enabled());
+    sdhci.set_auto_bkops(true);
+    sdhci.init().unwrap();
+    assert!(sdhci.card().auto_bkops_enabled());
+    assert_eq!(sdhci.start_bkops(), Err(MmcError::Unsupported));
+    sdhci.enable_manual_bkops(Irreversible::confirm()).unwrap();
+    assert!(sdhci.card().manual_bkops_enabled());
+
+    // Reported with the next R1 status, and started once idle.
+    sim.card().set_bkops_status(2);
+    assert!(!sdhci.bkops_idle().unwrap());
+    let data = pattern(2, 0x42);
+    sdhci.write_blocks(8, &data).unwrap();
+    assert!(sdhci.bkops_idle().unwrap());
+    assert_eq!(sim.card().state(), MMC_R1_STATE_PRG);
+
+    // A read does not wait for them.
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(8, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(sdhci.card().bkops_status(), BkopsStatus::PerformanceImpacted);
+
+    // Started again as they are still urgent, and run to completion.
+    assert!(sdhci.bkops_idle().unwrap());
+    for _ in 0..=SIM_BKOPS_POLLS {
+        assert!(!sdhci.bkops_idle().unwrap());
+    }
+    assert_eq!(sdhci.card().bkops_status(), BkopsStatus::None);
+    assert_eq!(sim.card().state(), MMC_R1_STATE_TRAN);
+}
+
+#[test]
+fn long_write_and_erase_are_interrupted_with_hpi() {
+    let sim: &'static Simulator = Box::leak(Box::new(sim(SimCard::new(BLOCKS).slow_programming(5))));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, sim);
+    sdhci.set_idle(interrupt_on_idle);
+    let sdhci: &'static SDHCI<&Simulator> = Box::leak(Box::new(sdhci));
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.interrupt_current_operation(), Ok(false));
+
+    INTERRUPTED.with(|cell| cell.set(Some(sdhci)));
+    let data = pattern(8, 0x48);
+    assert_eq!(sdhci.write_blocks(64, &data), Err(MmcError::Interrupted));
+    assert_eq!(sim.card().state(), MMC_R1_STATE_TRAN);
+    let group = SIM_ERASE_GROUP_BLOCKS as u32;
+    assert_eq!(sdhci.erase(group..2 * group, EraseKind::Erase), Err(MmcError::Interrupted));
+    INTERRUPTED.with(|cell| cell.set(None));
+
+    // The device programmed half of the write.
+    assert_eq!(sdhci.resume_write(64, &data), Ok(4));
+    assert_eq!(sdhci.card().correctly_programmed_sectors(), 4);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(64, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn volatile_cache_is_flushed() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    assert!(!sdhci.card().cache_enabled());
+    sdhci.write_blocks(10, &pattern(1, 0x01)).unwrap();
+    assert_eq!(sim.card().cached_blocks(), 0);
+
+    sdhci.set_cache(true);
+    sdhci.init().unwrap();
+    let card = sdhci.card();
+    assert!(card.cache_size() > 0);
+    assert!(card.cache_enabled());
+    assert!(card.barrier_enabled());
+
+    let data = pattern(2, 0x66);
+    sdhci.write_blocks(10, &data).unwrap();
+    assert_eq!(sim.card().cached_blocks(), 2);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(10, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    let mut block = [0; BLOCK_SIZE];
+    sim.card().read_block(Partition::User, 11, &mut block);
+    assert_eq!(block, [0; BLOCK_SIZE]);
+
+    sdhci.cache_barrier().unwrap();
+    assert_eq!(sim.card().barriers(), 1);
+    sdhci.flush_cache().unwrap();
+    assert_eq!(sim.card().cached_blocks(), 0);
+    sim.card().read_block(Partition::User, 11, &mut block);
+    assert_eq!(block, data[BLOCK_SIZE..]);
+
+    // Forced unit access bypasses the cache.
+    let data = pattern(3, 0x67);
+    sdhci.write_blocks_fua(20, &data).unwrap();
+    assert_eq!(sim.card().cached_blocks(), 0);
+    sim.card().read_block(Partition::User, 22, &mut block);
+    assert_eq!(block, data[2 * BLOCK_SIZE..]);
+}
+
+#[test]
+fn multi_block_transfers_use_auto_cmd() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    let data = pattern(4, 0x71);
+    let mut buf = vec![0; data.len()];
+
+    // Auto CMD23: one command per transfer.
+    let (commands, auto_cmds) = (sim.commands(), sim.auto_cmds());
+    sdhci.read_blocks(40, &mut buf).unwrap();
+    assert_eq!((sim.commands() - commands, sim.auto_cmds() - auto_cmds), (1, 1));
+
+    // CMD23 sent by the driver.
+    sdhci.set_auto_cmd(false);
+    sdhci.write_blocks(40, &data).unwrap();
+    let (commands, auto_cmds) = (sim.commands(), sim.auto_cmds());
+    sdhci.read_blocks(40, &mut buf).unwrap();
+    assert_eq!((sim.commands() - commands, sim.auto_cmds() - auto_cmds), (2, 0));
+    assert_eq!(buf, data);
+
+    // Auto CMD12 for a card without CMD23.
+    let sim = self::sim(SimCard::new_sd(BLOCKS).sd_v1());
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    assert!(!sdhci.card().supports_cmd23());
+    sdhci.write_blocks(40, &data).unwrap();
+    let (commands, auto_cmds) = (sim.commands(), sim.auto_cmds());
+    sdhci.read_blocks(40, &mut buf).unwrap();
+    assert_eq!((sim.commands() - commands, sim.auto_cmds() - auto_cmds), (1, 1));
+    assert_eq!(buf, data);
+    assert_eq!(sim.card().state(), MMC_R1_STATE_TRAN);
+}
+
+#[test]
+fn auto_cmd_error_is_decoded_and_retried() {
+    assert_eq!(
+        MmcError::from_error_int_stat(EMMC_AUTO_CMD_ERR, EMMC_AUTO_CMD_CRC_ERR),
+        Some(MmcError::AutoCmd(AutoCmdError::Crc))
+    );
+    assert_eq!(
+        MmcError::from_error_int_stat(EMMC_AUTO_CMD_ERR, EMMC_AUTO_CMD12_NOT_EXEC | EMMC_AUTO_CMD_TOUT_ERR),
+        Some(MmcError::AutoCmd(AutoCmdError::NotExecuted))
+    );
+
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    let data = pattern(4, 0x72);
+    sdhci.write_blocks(48, &data).unwrap();
+
+    sim.inject_fault(Some(MMC_SET_BLOCK_COUNT), Fault::CmdTimeout);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(48, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(sdhci.recovery_stats().retries, 1);
+}
+
+#[test]
+fn reliable_write_splits_on_the_legacy_unit() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    // Enhanced reliable write: one CMD25 whatever the alignment.
+    let data = pattern(20, 0x81);
+    sdhci.write_blocks_reliable(4, &data).unwrap();
+    assert_eq!(sim.card().reliable_writes(), 1);
+
+    let mut card = SimCard::new(BLOCKS);
+    card.ext_csd[EXT_CSD_WR_REL_PARAM] = 0;
+    card.ext_csd[EXT_CSD_REL_WR_SEC_C] = 8;
+    let sim = self::sim(card);
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    assert!(!sdhci.card().enhanced_reliable_write());
+
+    // Legacy mode: 4 single sectors up to the unit boundary, then 2 units.
+    sdhci.write_blocks_reliable(4, &data).unwrap();
+    assert_eq!(sim.card().reliable_writes(), 6);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(4, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn data_crc_error_is_retried() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    let data = pattern(4, 0x44);
+    sdhci.write_blocks(8, &data).unwrap();
+
+    sim.inject_fault(Some(MMC_READ_MULTIPLE_BLOCK), Fault::DataCrc);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(8, &mut buf).unwrap();
+
+    assert_eq!(buf, data);
+    assert_eq!(sdhci.recovery_stats().retries, 1);
+}
+
+#[test]
+fn command_crc_error_is_reported() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    sim.inject_fault(Some(MMC_SEND_STATUS), Fault::CmdCrc);
+    assert_eq!(sdhci.sdhci_send_status(), Err(MmcError::CmdCrc));
+    assert!(sdhci.sdhci_send_status().is_ok());
+}
+
+#[test]
+fn power_loss_during_write_is_recovered() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    sim.inject_fault(Some(MMC_WRITE_MULTIPLE_BLOCK), Fault::PowerLoss);
+    let data = pattern(4, 0x55);
+    sdhci.write_blocks(16, &data).unwrap();
+
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(16, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(sdhci.recovery_stats().reinits, 1);
+}
+
+#[test]
+fn reinit_restores_partition_access() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 1).unwrap();
+
+    sim.inject_fault(Some(MMC_WRITE_MULTIPLE_BLOCK), Fault::PowerLoss);
+    let data = pattern(4, 0x56);
+    sdhci.write_blocks(0, &data).unwrap();
+    assert_eq!(sdhci.recovery_stats().reinits, 1);
+
+    // The write was replayed to the boot partition it was meant for.
+    assert_eq!(sim.card().partition(), Partition::Boot1);
+    let mut block = [0; BLOCK_SIZE];
+    for (lba, expected) in data.chunks(BLOCK_SIZE).enumerate() {
+        sim.card().read_block(Partition::Boot1, lba as u64, &mut block);
+        assert_eq!(block, expected);
+        sim.card().read_block(Partition::User, lba as u64, &mut block);
+        assert_eq!(block, [0; BLOCK_SIZE]);
+    }
+}
+
+#[test]
+fn reinit_restores_the_bus_width() {
+    let sim = sim(SimCard::new_sd(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+    sdhci.sd_set_bus_width(1).unwrap();
+
+    sim.inject_fault(Some(MMC_WRITE_MULTIPLE_BLOCK), Fault::PowerLoss);
+    let data = pattern(4, 0x57);
+    sdhci.write_blocks(8, &data).unwrap();
+
+    assert_eq!(sdhci.recovery_stats().reinits, 1);
+    assert_eq!(sdhci.card().bus_width, 1);
+    assert_eq!(sim.card().sd_bus_width(), Some(1));
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(8, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn recovery_lowers_the_bus_width_then_the_clock_and_timing() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+    let ident_divider = sdhci.reg().emmc_get_freq();
+    sdhci.mmc_set_bus_width(8).unwrap();
+    sdhci.mmc_set_high_speed().unwrap();
+    assert!(sdhci.reg().emmc_get_freq() < ident_divider);
+    assert_eq!(sim.card().ext_csd[EXT_CSD_BUS_WIDTH], 2);
+    assert_eq!(sim.card().ext_csd[EXT_CSD_HS_TIMING], 1);
+
+    let data = pattern(2, 0x5a);
+    sdhci.write_blocks(16, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    for downgrades in 1..=2 {
+        for _ in 0..=RECOVERY_RETRIES {
+            sim.inject_fault(Some(MMC_READ_MULTIPLE_BLOCK), Fault::DataCrc);
+        }
+        sdhci.read_blocks(16, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(sdhci.recovery_stats().downgrades, downgrades);
+    }
+    assert_eq!(sdhci.card().bus_width, 1);
+    assert_eq!(sim.card().ext_csd[EXT_CSD_BUS_WIDTH], 0);
+    assert_eq!(sim.card().ext_csd[EXT_CSD_HS_TIMING], 0);
+    assert_eq!(sdhci.reg().emmc_get_freq(), ident_divider);
+    assert_eq!(sdhci.recovery_stats().reinits, 0);
+}
+
+#[test]
+fn image_file_is_read_and_written() {
+    let path = std::env::temp_dir().join(format!("rk3568_emmc_sim_{}.img", std::process::id()));
+    let data = pattern(BLOCKS as usize, 0x66);
+    std::fs::write(&path, &data).unwrap();
+
+    {
+        let sim = sim(SimCard::with_image(&path).unwrap());
+        let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+        sdhci.init().unwrap();
+
+        let mut buf = vec![0; 2 * BLOCK_SIZE];
+        sdhci.read_blocks(1, &mut buf).unwrap();
+        assert_eq!(buf, data[BLOCK_SIZE..3 * BLOCK_SIZE]);
+        sdhci.write_blocks(0, &[0xa5; BLOCK_SIZE]).unwrap();
+    }
+
+    let image = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(image[..BLOCK_SIZE], [0xa5; BLOCK_SIZE]);
+    assert_eq!(image[BLOCK_SIZE..], data[BLOCK_SIZE..]);
+}
+
+#[test]
+fn sd_card_runs_4bit_high_speed() {
+    let sim = sim(SimCard::new_sd(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+
+    let card = sdhci.card();
+    assert_eq!(card.card_type, CardType::Sd);
+    assert_eq!(card.rca, SIM_SD_RCA);
+    assert_eq!(card.sd_capacity(), Some(SdCapacity::Sdsc));
+    assert_eq!(card.blocks, BLOCKS);
+    assert_eq!(card.bus_width, 4);
+    assert_eq!(card.scr.version(), 4);
+    assert_eq!(card.sd_status.bus_width(), 4);
+    assert_eq!(card.sd_status.speed_class(), 10);
+    assert_eq!(card.sd_status.au_size(), 4 << 20);
+    assert_eq!(sim.card().sd_bus_width(), Some(4));
+    assert!(sim.card().sd_high_speed());
+
+    let data = pattern(4, 0x3c);
+    sdhci.write_blocks(40, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(40, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn sd_high_capacity_is_detected() {
+    let sim = sim(SimCard::sd_with_storage(Box::new(Sparse::new())));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let card = sdhci.card();
+    assert!(card.high_capacity);
+    assert_eq!(card.sd_capacity(), Some(SdCapacity::Sdhc));
+    assert_eq!(card.blocks, 1 << 23);
+
+    let lba = (1 << 23) - 1;
+    let data = pattern(1, 0xa5);
+    sdhci.write_blocks(lba, &data).unwrap();
+    let mut block = [0; BLOCK_SIZE];
+    sim.card().read_block(Partition::User, lba as u64, &mut block);
+    assert_eq!(block[..], data[..]);
+
+    let sim = self::sim(SimCard::sd_with_storage(Box::new(Sparse(1 << 27, BTreeMap::new()))));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.card().sd_capacity(), Some(SdCapacity::Sdxc));
+}
+
+#[test]
+fn emmc_answering_cmd55_is_not_taken_for_an_sd_card() {
+    for card in [SimCard::new(BLOCKS), SimCard::new(BLOCKS).mmc_app_cmd()] {
+        let sim = sim(card);
+        let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+        sdhci.init().unwrap();
+        let card = sdhci.card();
+        assert_eq!(card.card_type, CardType::Mmc);
+        assert_eq!(card.blocks, BLOCKS);
+    }
+}
+
+#[test]
+fn sd_v1_card_without_cmd8() {
+    let sim = sim(SimCard::new_sd(BLOCKS).sd_v1());
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    let card = sdhci.card();
+    assert_eq!(card.card_type, CardType::Sd);
+    assert!(!card.high_capacity);
+    assert_eq!(card.blocks, BLOCKS);
+    assert_eq!(card.scr.version(), 1);
+    // CMD6 is part of version 1.10.
+    assert!(sim.card().sd_high_speed());
+
+    let switch = sdhci.sd_switch(false, 1, 1).unwrap();
+    assert!(switch.supports(1, 1));
+    assert_eq!(switch.selected(1), 1);
+}
+
+#[test]
+fn sd_uhs_card_switches_to_1v8_and_tunes() {
+    let sim = sim(SimCard::sd_with_storage(Box::new(Sparse::new())).uhs_i());
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+
+    let card = sdhci.card();
+    assert_eq!(card.sd_timing, SdTiming::Sdr104);
+    assert_eq!(card.bus_width, 4);
+    assert!(sim.card().sd_1v8());
+    assert_eq!(sim.card().sd_access_mode(), Some(3));
+    assert_eq!(sim.card().sd_tuning_blocks(), SIM_TUNING_LOOPS);
+    assert_eq!(sdhci.tuning_count(), 1);
+
+    let data = pattern(2, 0x77);
+    sdhci.write_blocks(1000, &data).unwrap();
+
+    // The next request re-tunes first.
+    sim.retune_event();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(1000, &mut buf).unwrap();
+    assert_eq!(buf, data);
+    assert_eq!(sdhci.tuning_count(), 2);
+    assert_eq!(sim.card().sd_tuning_blocks(), 2 * SIM_TUNING_LOOPS);
+
+    // A re-initialisation power cycles the card and switches again.
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.card().sd_timing, SdTiming::Sdr104);
+    assert_eq!(sdhci.tuning_count(), 1);
+}
+
+#[test]
+fn sd_failed_voltage_switch_falls_back_to_3v3() {
+    let sim = sim(SimCard::sd_with_storage(Box::new(Sparse::new())).uhs_i().failing_voltage_switch());
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.init().unwrap();
+
+    // The card was power cycled and identified again without asking for 1.8V.
+    assert_eq!(sdhci.card().sd_timing, SdTiming::HighSpeed);
+    assert!(!sim.card().sd_1v8());
+    assert!(sim.card().sd_high_speed());
+    assert_eq!(sdhci.tuning_count(), 0);
+
+    let data = pattern(2, 0x78);
+    sdhci.write_blocks(1000, &data).unwrap();
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(1000, &mut buf).unwrap();
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn sd_card_hot_plug() {
+    static REMOVALS: AtomicU32 = AtomicU32::new(0);
+    fn on_event(event: CardEvent) {
+        if event == CardEvent::Removed {
+            REMOVALS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    fn debounce() {
+        std::thread::sleep(Duration::from_micros(CARD_DETECT_DEBOUNCE_US + 10_000));
+    }
+
+    let sim = sim(SimCard::new_sd(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.set_non_removable(false);
+    sdhci.set_card_event_handler(on_event);
+    sdhci.init().unwrap();
+    assert_eq!(sdhci.poll_card_detect(), None);
+
+    // Requests fail as soon as the slot reads empty, before the removal is debounced.
+    sim.remove_card();
+    let mut block = [0; BLOCK_SIZE];
+    assert_eq!(sdhci.read_blocks(0, &mut block), Err(MmcError::NoCard));
+    assert_eq!(sdhci.poll_card_detect(), None);
+    assert!(sdhci.card_detect_pending());
+    debounce();
+    assert_eq!(sdhci.poll_card_detect(), Some(CardEvent::Removed));
+    assert!(!sdhci.card_present());
+    assert_eq!(REMOVALS.load(Ordering::Relaxed), 1);
+    assert_eq!(sdhci.read_blocks(0, &mut block), Err(MmcError::NoCard));
+    assert_eq!(sdhci.init(), Err(MmcError::NoCard));
+
+    // A bounce shorter than the debounce time is ignored.
+    sim.insert_card();
+    assert_eq!(sdhci.poll_card_detect(), None);
+    sim.remove_card();
+    assert_eq!(sdhci.poll_card_detect(), None);
+    assert!(!sdhci.card_detect_pending());
+
+    sim.insert_card();
+    assert_eq!(sdhci.poll_card_detect(), None);
+    debounce();
+    assert_eq!(sdhci.poll_card_detect(), Some(CardEvent::Inserted));
+    assert_eq!(sdhci.card().card_type, CardType::Sd);
+    sdhci.read_blocks(0, &mut block).unwrap();
+}
+
+#[test]
+fn card_detect_interrupt_outlives_the_next_request() {
+    let sim = sim(SimCard::new_sd(BLOCKS));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.set_non_removable(false);
+    sdhci.init().unwrap();
+    sdhci.enable_irq();
+
+    sim.remove_card();
+    assert!(sdhci.handle_irq());
+    let mut block = [0; BLOCK_SIZE];
+    assert_eq!(sdhci.read_blocks(0, &mut block), Err(MmcError::NoCard));
+    assert!(sdhci.card_detect_pending());
+    assert_eq!(sdhci.poll_card_detect(), None);
+    std::thread::sleep(Duration::from_micros(CARD_DETECT_DEBOUNCE_US + 10_000));
+    assert_eq!(sdhci.poll_card_detect(), Some(CardEvent::Removed));
+    assert!(!sdhci.card_detect_pending());
+}
+
+#[test]
+fn card_detect_waits_for_the_request_in_flight() {
+    let sim: &'static Simulator = Box::leak(Box::new(sim(SimCard::new_sd(BLOCKS))));
+    let mut sdhci = SDHCI::new_with_mmio(BASE, sim);
+    sdhci.set_host_kind(HostKind::Generic);
+    sdhci.set_non_removable(false);
+    sdhci.set_idle(remove_on_idle);
+    let sdhci: &'static SDHCI<&Simulator> = Box::leak(Box::new(sdhci));
+    sdhci.init().unwrap();
+    sdhci.enable_irq();
+
+    REMOVED.with(|cell| cell.set(Some((sim, sdhci))));
+    let mut block = [0; BLOCK_SIZE];
+    assert_eq!(sdhci.read_blocks(0, &mut block), Err(MmcError::NoCard));
+    assert!(REMOVED.with(Cell::get).is_none());
+    assert_eq!(sdhci.poll_card_detect(), Some(CardEvent::Removed));
+    assert!(!sdhci.card_present());
+}
+
+#[test]
+fn non_removable_card_ignores_card_detect() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+
+    sim.remove_card();
+    assert_eq!(sdhci.poll_card_detect(), None);
+    assert!(sdhci.card_present());
+}
+
+/// The `async` request API, woken by `handle_irq` and never blocking the executor.
+/// Issue `idx` straight through the registers, the way a driver using DMA would, moving the
+/// data phase of `blocks` blocks with the DMA engine selected in HOST_CTRL1.
+///
+/// Returns the error interrupt status the command ended with.
+fn dma_cmd(reg: &Reg<&Simulator>, idx: u16, arg: u32, blocks: u16) -> u16 {
+    let read = matches!(idx, MMC_READ_SINGLE_BLOCK | MMC_READ_MULTIPLE_BLOCK);
+    let mut xfer_mode = EMMC_DMA_ENABLE | EMMC_BLOCK_COUNT_ENABLE;
+    if read {
+        xfer_mode |= EMMC_DATA_XFER_DIR_READ;
+    }
+    if blocks > 1 {
+        xfer_mode |= EMMC_MULTI_BLK_SEL | EMMC_AUTO_CMD12_ENABLED;
+    }
+    reg.emmc_set_xfer_block_size(BLOCK_SIZE as u16);
+    reg.emmc_set_blockcount(blocks);
+    reg.emmc_set_xfer_mode(xfer_mode);
+    reg.emmc_set_argument(arg);
+    reg.emmc_set_cmd(idx << EMMC_CMD_INDEX_POS | MMC_RESP_R1 | EMMC_DATA_PRESENT);
+
+    let error = reg.emmc_get_error_int_stat();
+    if error == 0 {
+        assert_ne!(reg.emmc_get_normal_int_stat() & EMMC_XFER_COMPLETE, 0);
+    }
+    reg.emmc_clear_all_normal_int_flags();
+    reg.emmc_clear_all_error_int_flags();
+    error
+}
+
+/// Write ADMA2 transfer descriptors for `segs` at `table`, the last one ending the table.
+fn adma_table(sim: &Simulator, table: u64, segs: &[(u32, u16)]) {
+    for (i, &(addr, len)) in segs.iter().enumerate() {
+        let end = if i + 1 == segs.len() { 0x02 } else { 0 };
+        sim.memory().write(table + 8 * i as u64, &adma_desc(0x21 | end, len, addr));
+    }
+}
+
+fn adma_desc(attr: u16, len: u16, addr: u32) -> [u8; 8] {
+    let mut desc = [0; 8];
+    desc[..2].copy_from_slice(&attr.to_le_bytes());
+    desc[2..4].copy_from_slice(&len.to_le_bytes());
+    desc[4..].copy_from_slice(&addr.to_le_bytes());
+    desc
+}
+
+#[test]
+fn sdma_moves_blocks_between_memory_and_device() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    let reg = Reg::with_mmio(BASE, &sim);
+    reg.emmc_select_dma(EMMC_DMA_SEL_SDMA);
+
+    let data = pattern(4, 0x71);
+    sim.memory().write(0x10_0000, &data);
+    reg.emmc_set_sdmasa(0x10_0000);
+    assert_eq!(dma_cmd(&reg, MMC_WRITE_MULTIPLE_BLOCK, 20 * BLOCK_SIZE as u32, 4), 0);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(20, &mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    let data = pattern(1, 0x72);
+    sdhci.write_blocks(30, &data).unwrap();
+    reg.emmc_set_sdmasa(0x20_0000);
+    assert_eq!(dma_cmd(&reg, MMC_READ_SINGLE_BLOCK, 30 * BLOCK_SIZE as u32, 1), 0);
+    let mut block = [0; BLOCK_SIZE];
+    sim.memory().read(0x20_0000, &mut block);
+    assert_eq!(block, data[..]);
+}
+
+#[test]
+fn adma2_follows_the_descriptor_table() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    let reg = Reg::with_mmio(BASE, &sim);
+    reg.emmc_select_dma(EMMC_DMA_SEL_ADMA2);
+
+    // One block, a link to a second table, then two blocks.
+    let data = pattern(3, 0x73);
+    sim.memory().write(0x40_0000, &data[..BLOCK_SIZE]);
+    sim.memory().write(0x50_0000, &data[BLOCK_SIZE..]);
+    sim.memory().write(0x30_0000, &adma_desc(0x21, BLOCK_SIZE as u16, 0x40_0000));
+    sim.memory().write(0x30_0008, &adma_desc(0x31, 0, 0x31_0000));
+    adma_table(&sim, 0x31_0000, &[(0x50_0000, 2 * BLOCK_SIZE as u16)]);
+    reg.emmc_set_adma_sa(0x30_0000);
+    assert_eq!(dma_cmd(&reg, MMC_WRITE_MULTIPLE_BLOCK, 40 * BLOCK_SIZE as u32, 3), 0);
+    let mut buf = vec![0; data.len()];
+    sdhci.read_blocks(40, &mut buf).unwrap();
+    assert_eq!(buf, data);
+
+    adma_table(&sim, 0x32_0000, &[(0x60_0000, BLOCK_SIZE as u16), (0x70_0000, 2 * BLOCK_SIZE as u16)]);
+    reg.emmc_set_adma_sa(0x32_0000);
+    assert_eq!(dma_cmd(&reg, MMC_READ_MULTIPLE_BLOCK, 40 * BLOCK_SIZE as u32, 3), 0);
+    let mut block = [0; BLOCK_SIZE];
+    sim.memory().read(0x60_0000, &mut block);
+    assert_eq!(block, data[..BLOCK_SIZE]);
+    let mut blocks = vec![0; 2 * BLOCK_SIZE];
+    sim.memory().read(0x70_0000, &mut blocks);
+    assert_eq!(blocks, data[BLOCK_SIZE..]);
+
+    // A descriptor without the valid bit stops the engine, which reports where.
+    sim.memory().write(0x33_0000, &adma_desc(0x20, BLOCK_SIZE as u16, 0x40_0000));
+    reg.emmc_set_adma_sa(0x33_0000);
+    assert_eq!(dma_cmd(&reg, MMC_READ_SINGLE_BLOCK, 0, 1), EMMC_ADMA_ERR);
+    assert_eq!(reg.emmc_get_adma_sa(), 0x33_0000);
+    sdhci.sdhci_send_cmd(MMC_STOP_TRANSMISSION, EMMC_CMD_TYPE_ABORT, MMC_RESP_R1B, 0).unwrap();
+
+    // So does a table shorter than the transfer.
+    adma_table(&sim, 0x34_0000, &[(0x40_0000, BLOCK_SIZE as u16)]);
+    reg.emmc_set_adma_sa(0x34_0000);
+    assert_eq!(dma_cmd(&reg, MMC_WRITE_MULTIPLE_BLOCK, 0, 2), EMMC_ADMA_ERR);
+}
+
+const RPMB_FRAME_ADDR: u64 = 0x80_0000;
+
+/// Send `frame` to the RPMB partition with CMD23 and CMD25, moving it with SDMA.
+fn rpmb_request(sdhci: &SDHCI<&Simulator>, reg: &Reg<&Simulator>, frame: &[u8; BLOCK_SIZE], reliable: bool) {
+    let rel = if reliable { MMC_CMD23_REL_WRITE } else { 0 };
+    sdhci.sdhci_send_cmd(MMC_SET_BLOCK_COUNT, EMMC_CMD_TYPE_NORMAL, MMC_RESP_R1, 1 | rel).unwrap();
+    reg.mmio().memory().write(RPMB_FRAME_ADDR, frame);
+    reg.emmc_set_sdmasa(RPMB_FRAME_ADDR as u32);
+    assert_eq!(dma_cmd(reg, MMC_WRITE_MULTIPLE_BLOCK, 0, 1), 0);
+}
+
+/// Read the response frame of the last RPMB request with CMD23 and CMD18.
+fn rpmb_response(sdhci: &SDHCI<&Simulator>, reg: &Reg<&Simulator>) -> [u8; BLOCK_SIZE] {
+    sdhci.sdhci_send_cmd(MMC_SET_BLOCK_COUNT, EMMC_CMD_TYPE_NORMAL, MMC_RESP_R1, 1).unwrap();
+    reg.emmc_set_sdmasa(RPMB_FRAME_ADDR as u32);
+    assert_eq!(dma_cmd(reg, MMC_READ_MULTIPLE_BLOCK, 0, 1), 0);
+    let mut frame = [0; BLOCK_SIZE];
+    reg.mmio().memory().read(RPMB_FRAME_ADDR, &mut frame);
+    frame
+}
+
+fn rpmb_frame(request: u16, counter: u32, address: u16) -> [u8; BLOCK_SIZE] {
+    let mut frame = [0; BLOCK_SIZE];
+    frame[484..500].copy_from_slice(&[0x5a; 16]);
+    frame[500..504].copy_from_slice(&counter.to_be_bytes());
+    frame[504..506].copy_from_slice(&address.to_be_bytes());
+    frame[506..508].copy_from_slice(&1u16.to_be_bytes());
+    frame[510..512].copy_from_slice(&request.to_be_bytes());
+    frame
+}
+
+/// Result and request/response type of a response frame.
+fn rpmb_result(frame: &[u8; BLOCK_SIZE]) -> (u16, u16) {
+    (u16::from_be_bytes([frame[508], frame[509]]), u16::from_be_bytes([frame[510], frame[511]]))
+}
+
+#[test]
+fn rpmb_frames_program_the_key_and_authenticated_data() {
+    let sim = sim(SimCard::new(BLOCKS));
+    let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+    sdhci.init().unwrap();
+    let reg = Reg::with_mmio(BASE, &sim);
+    reg.emmc_select_dma(EMMC_DMA_SEL_SDMA);
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 3).unwrap();
+    assert_eq!(sim.card().partition(), Partition::Rpmb);
+
+    // Nothing works before the key is programmed.
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0002, 0, 0), false);
+    assert_eq!(rpmb_result(&rpmb_response(&sdhci, &reg)), (0x0007, 0x0200));
+
+    let mut key = rpmb_frame(0x0001, 0, 0);
+    key[196..228].copy_from_slice(&[0xc3; 32]);
+    rpmb_request(&sdhci, &reg, &key, true);
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0005, 0, 0), false);
+    assert_eq!(rpmb_result(&rpmb_response(&sdhci, &reg)), (0x0000, 0x0100));
+    // The key can only be programmed once.
+    rpmb_request(&sdhci, &reg, &key, true);
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0005, 0, 0), false);
+    assert_eq!(rpmb_result(&rpmb_response(&sdhci, &reg)), (0x0005, 0x0100));
+
+    let mut write = rpmb_frame(0x0003, 0, 7);
+    write[228..484].copy_from_slice(&[0x99; 256]);
+    rpmb_request(&sdhci, &reg, &write, true);
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0005, 0, 0), false);
+    assert_eq!(rpmb_result(&rpmb_response(&sdhci, &reg)), (0x0000, 0x0300));
+    assert_eq!(sim.card().rpmb_counter(), 1);
+
+    // A replayed write frame carries a stale counter.
+    rpmb_request(&sdhci, &reg, &write, true);
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0005, 0, 0), false);
+    assert_eq!(rpmb_result(&rpmb_response(&sdhci, &reg)), (0x0003, 0x0300));
+    assert_eq!(sim.card().rpmb_counter(), 1);
+
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0004, 0, 7), false);
+    let read = rpmb_response(&sdhci, &reg);
+    assert_eq!(rpmb_result(&read), (0x0000, 0x0400));
+    assert_eq!(read[228..484], [0x99; 256]);
+    assert_eq!(read[484..500], [0x5a; 16]);
+    assert_eq!(read[500..504], 1u32.to_be_bytes());
+
+    rpmb_request(&sdhci, &reg, &rpmb_frame(0x0004, 0, SIM_RPMB_HALF_SECTORS as u16), false);
+    assert_eq!(rpmb_result(&rpmb_response(&sdhci, &reg)), (0x0004, 0x0400));
+
+    // Frames only travel in CMD23-counted transfers, the device rejects CMD17.
+    let mut block = [0; BLOCK_SIZE];
+    assert_eq!(sdhci.read_blocks(0, &mut block), Err(MmcError::DataTimeout));
+    assert_eq!(sim.card().partition(), Partition::Rpmb);
+
+    sdhci.mmc_switch(EXT_CSD_PARTITION_CONFIG, 0).unwrap();
+    assert_eq!(sim.card().partition(), Partition::User);
+}
+
+#[cfg(feature = "async")]
+mod async_requests {
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use super::*;
+
+    /// Waker counting its wake ups.
+    struct CountingWaker(AtomicU32);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn waker() -> (Arc<CountingWaker>, Waker) {
+        let counter = Arc::new(CountingWaker(AtomicU32::new(0)));
+        (counter.clone(), Waker::from(counter))
+    }
+
+    /// Executor delivering the interrupt of `sim` whenever `future` waits, which must have
+    /// arranged to be woken up.
+    fn block_on<F: Future>(sim: &Simulator, sdhci: &SDHCI<&Simulator>, future: F) -> F::Output {
+        let (wakes, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            if sim.irq_pending() {
+                assert!(sdhci.handle_irq());
+            }
+            assert!(wakes.0.swap(0, Ordering::SeqCst) > 0, "pending future not woken");
+        }
+    }
+
+    #[test]
+    fn requests_are_woken_by_handle_irq() {
+        let sim = sim(SimCard::new(BLOCKS));
+        let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+        sdhci.init().unwrap();
+        sdhci.enable_irq();
+
+        let data = pattern(4, 0x27);
+        block_on(&sim, &sdhci, sdhci.write(40, &data)).unwrap();
+        block_on(&sim, &sdhci, sdhci.flush()).unwrap();
+        let mut buf = vec![0; data.len()];
+        block_on(&sim, &sdhci, sdhci.read(40, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+
+        // A request waiting for the controller sleeps until it is released.
+        let (wakes, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+        let other = pattern(2, 0x72);
+        buf.fill(0);
+        {
+            let mut write = pin!(sdhci.write(80, &other));
+            let mut read = pin!(sdhci.read(40, &mut buf));
+            assert!(write.as_mut().poll(&mut cx).is_pending());
+            assert!(read.as_mut().poll(&mut cx).is_pending());
+            assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+            let mut done = (false, false);
+            while done != (true, true) {
+                assert!(sim.irq_pending() || wakes.0.swap(0, Ordering::SeqCst) > 0);
+                sdhci.handle_irq();
+                if !done.0 && let Poll::Ready(result) = write.as_mut().poll(&mut cx) {
+                    result.unwrap();
+                    done.0 = true;
+                }
+                if !done.1 && let Poll::Ready(result) = read.as_mut().poll(&mut cx) {
+                    result.unwrap();
+                    done.1 = true;
+                }
+            }
+        }
+        assert_eq!(buf, data);
+        let mut block = [0; BLOCK_SIZE];
+        sim.card().read_block(Partition::User, 81, &mut block);
+        assert_eq!(block, other[BLOCK_SIZE..]);
+    }
+
+    #[test]
+    fn background_operations_are_interrupted_before_a_request() {
+        let sim = sim(SimCard::new(BLOCKS));
+        let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+        sdhci.init().unwrap();
+        sdhci.enable_manual_bkops(Irreversible::confirm()).unwrap();
+        sim.card().set_bkops_status(2);
+        let data = pattern(2, 0x49);
+        sdhci.write_blocks(8, &data).unwrap();
+        assert!(sdhci.bkops_idle().unwrap());
+        assert_eq!(sim.card().state(), MMC_R1_STATE_PRG);
+
+        sdhci.enable_irq();
+        let mut buf = vec![0; data.len()];
+        block_on(&sim, &sdhci, sdhci.read(8, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(sim.card().state(), MMC_R1_STATE_TRAN);
+    }
+
+    #[test]
+    fn long_write_is_interrupted_with_hpi() {
+        let sim = sim(SimCard::new(BLOCKS).slow_programming(5));
+        let sdhci = SDHCI::new_with_mmio(BASE, &sim);
+        sdhci.init().unwrap();
+        sdhci.enable_irq();
+
+        // Interrupted from another task, between the CMD13 polls.
+        let (_, waker) = waker();
+        let mut cx = Context::from_waker(&waker);
+        let data = pattern(8, 0x4a);
+        let mut interrupted = false;
+        let mut write = pin!(sdhci.write(64, &data));
+        let result = loop {
+            if let Poll::Ready(result) = write.as_mut().poll(&mut cx) {
+                break result;
+            }
+            interrupted |= sdhci.interrupt_current_operation().unwrap();
+            if sim.irq_pending() {
+                sdhci.handle_irq();
+            }
+        };
+        assert!(interrupted);
+        assert_eq!(result, Err(MmcError::Interrupted));
+        assert_eq!(sim.card().state(), MMC_R1_STATE_TRAN);
+        sdhci.disable_irq();
+        assert_eq!(sdhci.resume_write(64, &data), Ok(4));
+    }
+
+    #[test]
+    fn errors_are_retried_and_retuning_is_awaited() {
+        let sim = sim(SimCard::sd_with_storage(Box::new(Sparse::new())).uhs_i());
+        let mut sdhci = SDHCI::new_with_mmio(BASE, &sim);
+        sdhci.set_host_kind(HostKind::Generic);
+        sdhci.init().unwrap();
+        let data = pattern(2, 0x36);
+        block_on(&sim, &sdhci, sdhci.write(1000, &data)).unwrap();
+
+        // The retry re-tunes first.
+        sim.inject_fault(Some(MMC_READ_MULTIPLE_BLOCK), Fault::DataCrc);
+        let mut buf = vec![0; data.len()];
+        block_on(&sim, &sdhci, sdhci.read(1000, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(sdhci.recovery_stats().retries, 1);
+        assert_eq!(sdhci.tuning_count(), 2);
+
+        // The steps of the ladder after the retries are left to the caller, the next request
+        // still re-tunes.
+        for _ in 0..=RECOVERY_RETRIES {
+            sim.inject_fault(Some(MMC_READ_MULTIPLE_BLOCK), Fault::DataCrc);
+        }
+        assert_eq!(block_on(&sim, &sdhci, sdhci.read(1000, &mut buf)), Err(MmcError::DataCrc));
+        let stats = sdhci.recovery_stats();
+        assert_eq!((stats.retries, stats.downgrades, stats.reinits), (1 + RECOVERY_RETRIES, 0, 0));
+        block_on(&sim, &sdhci, sdhci.read(1000, &mut buf)).unwrap();
+
+        sdhci.enable_irq();
+        sim.retune_event();
+        block_on(&sim, &sdhci, sdhci.read(1000, &mut buf)).unwrap();
+        assert_eq!(buf, data);
+        assert_eq!(sdhci.tuning_count(), 7);
+        assert_eq!(sim.card().sd_tuning_blocks(), 7 * SIM_TUNING_LOOPS);
+    }
+}
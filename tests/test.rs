@@ -8,7 +8,19 @@ extern crate alloc;
 mod tests {
     use bare_test::{globals::{global_val, PlatformInfoKind}, mem::iomap, println};
     use log::info;
-    use rk3568_emmc::sdhci::SDHCI;
+    use rk3568_emmc::sdhci::{BLOCK_SIZE, CardType, SDHCI};
+    use rk3568_emmc::sdhci_pci;
+    use rk3568_emmc::sdhci_reg::MmioPtr;
+
+    /// ECAM configuration space of one bus.
+    const ECAM_BUS_SIZE: usize = 1 << 20;
+    /// Cells of a PCI address in `ranges`: phys.hi with the space code, then the 64-bit address.
+    const PCI_ADDRESS_CELLS: usize = 3;
+    /// Cells of a CPU address in `ranges`, the `#address-cells` of the root on 64-bit machines.
+    const CPU_ADDRESS_CELLS: usize = 2;
+    /// Space code of phys.hi selecting a 32-bit memory window.
+    const PCI_SPACE_MEM32: u32 = 0b10 << 24;
+    const PCI_SPACE_MASK: u32 = 0b11 << 24;
 
     #[test]
     fn test_platform() {
@@ -23,7 +35,7 @@ mod tests {
         } else if fdt_parser.find_compatible(&["pci-host-ecam-generic"]).next().is_some() {
             // QEMU platform detected, run qemu test
             info!("QEMU platform detected, running qemu test");
-            // test_qemu(&fdt_parser);
+            test_qemu(&fdt_parser);
         } else {
             // Unknown platform, output debug information
             println!("Unknown platform, no compatible devices found");
@@ -54,7 +66,75 @@ mod tests {
         let syscon_addr = syscon_addr_ptr.as_ptr() as usize;
         info!("EMMC addr: {:#x}, Clock addr: {:#x}, Syscon addr: {:#x}", emmc_addr, clk_addr, syscon_addr);
 
-        let hdhci = SDHCI::new(emmc_addr as u64, clk_addr as u64);
-        hdhci.init();
+        let mut hdhci = SDHCI::new(emmc_addr as u64, clk_addr as u64);
+        hdhci.set_non_removable(emmc.find_property("non-removable").is_some());
+        hdhci.init().unwrap();
+
+        let mut block = [0u8; 512];
+        hdhci.read_blocks(0, &mut block).unwrap();
+        info!("LBA 0: {:02x?}", &block[..16]);
+    }
+
+    fn test_qemu(fdt: &fdt_parser::Fdt) {
+        let pcie = fdt.find_compatible(&["pci-host-ecam-generic"]).next().unwrap();
+        let ecam_reg = pcie.reg().unwrap().next().unwrap();
+        println!("ECAM reg {:#x}, {:#x}", ecam_reg.address, ecam_reg.size.unwrap());
+
+        let (pci_base, cpu_base) = pci_mem32_window(&pcie).expect("no 32-bit memory window in ranges");
+        println!("PCI memory window {:#x}, CPU address {:#x}", pci_base, cpu_base);
+
+        let ecam = iomap((ecam_reg.address as usize).into(), ECAM_BUS_SIZE).as_ptr() as u64;
+        let pci = sdhci_pci::probe(&MmioPtr, ecam, pci_base).expect("no sdhci-pci controller");
+        let bar0 = pci.bar0 - pci_base + cpu_base;
+        let base = iomap((bar0 as usize).into(), pci.bar0_size as usize).as_ptr() as u64;
+        info!("SDHCI bar0: {:#x}, mapped at {:#x}", pci.bar0, base);
+
+        let sdhci = SDHCI::new_generic(base);
+        sdhci.init().unwrap();
+        let card = sdhci.card();
+        assert_eq!(card.card_type, CardType::Sd);
+        info!("SD card: {} blocks, high capacity: {}", card.blocks, card.high_capacity);
+
+        let mut block = [0u8; BLOCK_SIZE];
+        sdhci.read_blocks(0, &mut block).unwrap();
+        info!("LBA 0: {:02x?}", &block[..16]);
+
+        // Round trip through the last blocks of the image, restoring them afterwards.
+        let lba = (card.blocks - 4) as u32;
+        let mut orig = [0u8; 4 * BLOCK_SIZE];
+        sdhci.read_blocks(lba, &mut orig).unwrap();
+
+        let mut data = [0u8; 4 * BLOCK_SIZE];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i / 3) as u8 ^ 0xa5;
+        }
+        sdhci.write_blocks(lba, &data).unwrap();
+        let mut buf = [0u8; 4 * BLOCK_SIZE];
+        sdhci.read_blocks(lba, &mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        sdhci.write_blocks(lba + 1, &data[..BLOCK_SIZE]).unwrap();
+        sdhci.read_blocks(lba + 1, &mut buf[..BLOCK_SIZE]).unwrap();
+        assert_eq!(buf[..BLOCK_SIZE], data[..BLOCK_SIZE]);
+
+        sdhci.write_blocks(lba, &orig).unwrap();
+        sdhci.read_blocks(lba, &mut buf).unwrap();
+        assert_eq!(buf, orig);
+    }
+
+    /// Find the 32-bit memory window in the `ranges` of the host bridge, where BAR0 is placed.
+    ///
+    /// Returns the PCI bus address of the window and the CPU address it is mapped at.
+    fn pci_mem32_window(pcie: &fdt_parser::Node) -> Option<(u64, u64)> {
+        let ranges = pcie.find_property("ranges")?.raw_value();
+        let size_cells = pcie.find_property("#size-cells").map_or(2, |p| p.u32() as usize);
+        let cells: alloc::vec::Vec<u32> =
+            ranges.chunks_exact(4).map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]])).collect();
+        let cell64 = |c: &[u32]| (c[0] as u64) << 32 | c[1] as u64;
+
+        cells
+            .chunks_exact(PCI_ADDRESS_CELLS + CPU_ADDRESS_CELLS + size_cells)
+            .find(|entry| entry[0] & PCI_SPACE_MASK == PCI_SPACE_MEM32)
+            .map(|entry| (cell64(&entry[1..3]), cell64(&entry[PCI_ADDRESS_CELLS..PCI_ADDRESS_CELLS + 2])))
     }
 }
\ No newline at end of file